@@ -0,0 +1,594 @@
+use serde::Serialize;
+
+// Parameter of a route, as described by the `/api` route.
+#[derive(Serialize)]
+pub struct ApiParam {
+    name: &'static str,
+    // Either `query` or `path`.
+    #[serde(rename = "in")]
+    location: &'static str,
+    required: bool,
+}
+
+impl ApiParam {
+    const fn query(name: &'static str, required: bool) -> Self {
+        Self {
+            name,
+            location: "query",
+            required,
+        }
+    }
+
+    const fn path(name: &'static str) -> Self {
+        Self {
+            name,
+            location: "path",
+            required: true,
+        }
+    }
+}
+
+// Method of a route, as described by the `/api` route.
+#[derive(Serialize)]
+pub struct ApiRoute {
+    path: &'static str,
+    method: &'static str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    params: &'static [ApiParam],
+    // Kind of request body: `json`, `form` or `binary`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body: Option<&'static str>,
+    // Kind of response: `empty`, `text`, `json`, `html`, `sse` or
+    // `prometheus`.
+    response: &'static str,
+    // Kind of response under the API prefix, for the routes also served
+    // there.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    versioned: Option<&'static str>,
+}
+
+// Description of the HTTP API returned by the `/api` route, one entry for
+// each method of each route.
+//
+// The router only accepts the paths listed here, see `documented`, and the
+// routes it registers are checked against this table when building, see
+// `check_routes`. The routes answering JSON are also served under the API
+// prefix, where `/on`, `/off` and `/toggle` answer JSON as well.
+pub const API_ROUTES: [ApiRoute; 45] = [
+    ApiRoute {
+        path: "/",
+        method: "GET",
+        params: &[],
+        body: None,
+        response: "html",
+        versioned: None,
+    },
+    ApiRoute {
+        path: "/on",
+        method: "GET",
+        params: &[
+            ApiParam::query("fade", false),
+            ApiParam::query("duration", false),
+        ],
+        body: None,
+        response: "empty",
+        versioned: Some("json"),
+    },
+    ApiRoute {
+        path: "/off",
+        method: "GET",
+        params: &[ApiParam::query("fade", false)],
+        body: None,
+        response: "empty",
+        versioned: Some("json"),
+    },
+    ApiRoute {
+        path: "/toggle",
+        method: "GET",
+        params: &[],
+        body: None,
+        response: "text",
+        versioned: Some("json"),
+    },
+    ApiRoute {
+        path: "/blink",
+        method: "GET",
+        params: &[ApiParam::query("period", false)],
+        body: None,
+        response: "empty",
+        versioned: None,
+    },
+    ApiRoute {
+        path: "/color",
+        method: "GET",
+        params: &[ApiParam::query("rgb", true)],
+        body: None,
+        response: "empty",
+        versioned: None,
+    },
+    ApiRoute {
+        path: "/morse",
+        method: "GET",
+        params: &[ApiParam::query("msg", true)],
+        body: None,
+        response: "empty",
+        versioned: None,
+    },
+    ApiRoute {
+        path: "/brightness",
+        method: "GET",
+        params: &[
+            ApiParam::query("level", true),
+            ApiParam::query("fade", false),
+        ],
+        body: None,
+        response: "empty",
+        versioned: None,
+    },
+    ApiRoute {
+        path: "/led",
+        method: "POST",
+        params: &[],
+        body: Some("json"),
+        response: "empty",
+        versioned: Some("empty"),
+    },
+    ApiRoute {
+        path: "/led/{id}",
+        method: "GET",
+        params: &[ApiParam::path("id")],
+        body: None,
+        response: "json",
+        versioned: Some("json"),
+    },
+    ApiRoute {
+        path: "/led/{id}/on",
+        method: "GET",
+        params: &[
+            ApiParam::path("id"),
+            ApiParam::query("fade", false),
+            ApiParam::query("duration", false),
+        ],
+        body: None,
+        response: "json",
+        versioned: Some("json"),
+    },
+    ApiRoute {
+        path: "/led/{id}/off",
+        method: "GET",
+        params: &[ApiParam::path("id"), ApiParam::query("fade", false)],
+        body: None,
+        response: "json",
+        versioned: Some("json"),
+    },
+    ApiRoute {
+        path: "/led/{id}/toggle",
+        method: "GET",
+        params: &[ApiParam::path("id")],
+        body: None,
+        response: "json",
+        versioned: Some("json"),
+    },
+    ApiRoute {
+        path: "/gpio",
+        method: "GET",
+        params: &[],
+        body: None,
+        response: "json",
+        versioned: Some("json"),
+    },
+    ApiRoute {
+        path: "/gpio/{name}",
+        method: "GET",
+        params: &[ApiParam::path("name")],
+        body: None,
+        response: "json",
+        versioned: Some("json"),
+    },
+    ApiRoute {
+        path: "/gpio/{name}",
+        method: "POST",
+        params: &[ApiParam::path("name")],
+        body: Some("json"),
+        response: "json",
+        versioned: Some("json"),
+    },
+    ApiRoute {
+        path: "/button",
+        method: "GET",
+        params: &[],
+        body: None,
+        response: "json",
+        versioned: Some("json"),
+    },
+    ApiRoute {
+        path: "/button/reset",
+        method: "POST",
+        params: &[],
+        body: None,
+        response: "empty",
+        versioned: None,
+    },
+    ApiRoute {
+        path: "/history",
+        method: "GET",
+        params: &[ApiParam::query("limit", false)],
+        body: None,
+        response: "json",
+        versioned: Some("json"),
+    },
+    ApiRoute {
+        path: "/setup",
+        method: "GET",
+        params: &[],
+        body: None,
+        response: "html",
+        versioned: None,
+    },
+    ApiRoute {
+        path: "/setup",
+        method: "POST",
+        params: &[],
+        body: Some("form"),
+        response: "text",
+        versioned: None,
+    },
+    ApiRoute {
+        path: "/config",
+        method: "GET",
+        params: &[],
+        body: None,
+        response: "json",
+        versioned: Some("json"),
+    },
+    ApiRoute {
+        path: "/config",
+        method: "POST",
+        params: &[],
+        body: Some("json"),
+        response: "json",
+        versioned: Some("json"),
+    },
+    ApiRoute {
+        path: "/schedule",
+        method: "GET",
+        params: &[],
+        body: None,
+        response: "json",
+        versioned: Some("json"),
+    },
+    ApiRoute {
+        path: "/schedule",
+        method: "POST",
+        params: &[],
+        body: Some("json"),
+        response: "text",
+        versioned: Some("text"),
+    },
+    ApiRoute {
+        path: "/automation",
+        method: "GET",
+        params: &[],
+        body: None,
+        response: "json",
+        versioned: Some("json"),
+    },
+    ApiRoute {
+        path: "/automation",
+        method: "POST",
+        params: &[],
+        body: Some("json"),
+        response: "json",
+        versioned: Some("json"),
+    },
+    ApiRoute {
+        path: "/update",
+        method: "POST",
+        params: &[],
+        body: Some("binary"),
+        response: "text",
+        versioned: None,
+    },
+    ApiRoute {
+        path: "/restart",
+        method: "POST",
+        params: &[ApiParam::query("confirm", false)],
+        body: None,
+        response: "text",
+        versioned: None,
+    },
+    ApiRoute {
+        path: "/sleep",
+        method: "POST",
+        params: &[],
+        body: None,
+        response: "text",
+        versioned: None,
+    },
+    ApiRoute {
+        path: "/health",
+        method: "GET",
+        params: &[],
+        body: None,
+        response: "json",
+        versioned: Some("json"),
+    },
+    ApiRoute {
+        path: "/temperature",
+        method: "GET",
+        params: &[],
+        body: None,
+        response: "json",
+        versioned: Some("json"),
+    },
+    ApiRoute {
+        path: "/adc",
+        method: "GET",
+        params: &[],
+        body: None,
+        response: "json",
+        versioned: Some("json"),
+    },
+    ApiRoute {
+        path: "/touch",
+        method: "GET",
+        params: &[],
+        body: None,
+        response: "json",
+        versioned: Some("json"),
+    },
+    ApiRoute {
+        path: "/info",
+        method: "GET",
+        params: &[],
+        body: None,
+        response: "json",
+        versioned: Some("json"),
+    },
+    ApiRoute {
+        path: "/lastpanic",
+        method: "GET",
+        params: &[],
+        body: None,
+        response: "text",
+        versioned: None,
+    },
+    ApiRoute {
+        path: "/lastpanic",
+        method: "DELETE",
+        params: &[],
+        body: None,
+        response: "empty",
+        versioned: None,
+    },
+    ApiRoute {
+        path: "/logs",
+        method: "GET",
+        params: &[
+            ApiParam::query("level", false),
+            ApiParam::query("clear", false),
+        ],
+        body: None,
+        response: "text",
+        versioned: None,
+    },
+    ApiRoute {
+        path: "/loglevel",
+        method: "GET",
+        params: &[],
+        body: None,
+        response: "json",
+        versioned: Some("json"),
+    },
+    ApiRoute {
+        path: "/loglevel",
+        method: "POST",
+        params: &[],
+        body: Some("json"),
+        response: "json",
+        versioned: Some("json"),
+    },
+    ApiRoute {
+        path: "/metrics",
+        method: "GET",
+        params: &[],
+        body: None,
+        response: "prometheus",
+        versioned: None,
+    },
+    ApiRoute {
+        path: "/stats",
+        method: "GET",
+        params: &[],
+        body: None,
+        response: "text",
+        versioned: None,
+    },
+    ApiRoute {
+        path: "/events",
+        method: "GET",
+        params: &[],
+        body: None,
+        response: "sse",
+        versioned: None,
+    },
+    ApiRoute {
+        path: "/status",
+        method: "GET",
+        params: &[],
+        body: None,
+        response: "json",
+        versioned: Some("json"),
+    },
+    ApiRoute {
+        path: "/api",
+        method: "GET",
+        params: &[],
+        body: None,
+        response: "json",
+        versioned: Some("json"),
+    },
+];
+
+// Whether two strings are equal, in a const context.
+const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+// Path of a route, evaluated at compile time, which fails the build when the
+// path is missing from the API description.
+pub const fn documented(path: &'static str) -> &'static str {
+    let mut i = 0;
+    while i < API_ROUTES.len() {
+        if str_eq(API_ROUTES[i].path, path) {
+            return path;
+        }
+        i += 1;
+    }
+    panic!("route missing from API_ROUTES");
+}
+
+// Route registered by the router: its path, with the path parameters named
+// like in `API_ROUTES`, and its method.
+pub type Route = (&'static str, &'static str);
+
+// Whether the routes contain the given path and method.
+const fn contains(routes: &[Route], path: &str, method: &str) -> bool {
+    let mut i = 0;
+    while i < routes.len() {
+        if str_eq(routes[i].0, path) && str_eq(routes[i].1, method) {
+            return true;
+        }
+        i += 1;
+    }
+    false
+}
+
+// First of the given registered routes missing from the table, if any.
+pub const fn undocumented_route(routes: &[Route]) -> Option<Route> {
+    let mut i = 0;
+    while i < routes.len() {
+        let (path, method) = routes[i];
+        let mut documented = false;
+        let mut j = 0;
+        while j < API_ROUTES.len() {
+            documented |= str_eq(API_ROUTES[j].path, path) && str_eq(API_ROUTES[j].method, method);
+            j += 1;
+        }
+        if !documented {
+            return Some(routes[i]);
+        }
+        i += 1;
+    }
+    None
+}
+
+// First route of the table missing from the given registered routes, if
+// any.
+pub const fn unregistered_route(routes: &[Route]) -> Option<Route> {
+    let mut i = 0;
+    while i < API_ROUTES.len() {
+        let route = &API_ROUTES[i];
+        if !contains(routes, route.path, route.method) {
+            return Some((route.path, route.method));
+        }
+        i += 1;
+    }
+    None
+}
+
+// Checks the routes registered by the router against the table, evaluated
+// at compile time, which fails the build when they differ.
+pub const fn check_routes(routes: &[Route]) {
+    if undocumented_route(routes).is_some() {
+        panic!("registered route missing from API_ROUTES");
+    }
+    if unregistered_route(routes).is_some() {
+        panic!("route of API_ROUTES not registered");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+
+    // Every route of the table, as a router registering all of them would.
+    fn table() -> Vec<Route> {
+        API_ROUTES
+            .iter()
+            .map(|route| (route.path, route.method))
+            .collect()
+    }
+
+    #[test]
+    fn table_has_no_duplicates() {
+        let routes: BTreeSet<_> = table().into_iter().collect();
+        assert_eq!(routes.len(), API_ROUTES.len());
+    }
+
+    #[test]
+    fn documented_paths_are_accepted() {
+        assert_eq!(documented("/led"), "/led");
+        // Path parameters are registered as segments of their own.
+        assert_eq!(documented("/gpio"), "/gpio");
+    }
+
+    #[test]
+    #[should_panic(expected = "route missing from API_ROUTES")]
+    fn undocumented_paths_are_rejected() {
+        documented("/undocumented");
+    }
+
+    #[test]
+    fn routes_of_the_table_pass_the_check() {
+        let mut routes = table();
+        assert_eq!(undocumented_route(&routes), None);
+        assert_eq!(unregistered_route(&routes), None);
+        check_routes(&routes);
+
+        // Routes registered twice, such as under the API prefix, count once.
+        routes.push(("/on", "GET"));
+        check_routes(&routes);
+    }
+
+    #[test]
+    fn unregistered_routes_are_reported() {
+        let routes: Vec<_> = table()
+            .into_iter()
+            .filter(|route| *route != ("/lastpanic", "DELETE"))
+            .collect();
+        assert_eq!(undocumented_route(&routes), None);
+        assert_eq!(unregistered_route(&routes), Some(("/lastpanic", "DELETE")));
+    }
+
+    #[test]
+    fn undocumented_routes_are_reported() {
+        let mut routes = table();
+        routes.push(("/reboot", "POST"));
+        assert_eq!(undocumented_route(&routes), Some(("/reboot", "POST")));
+
+        // A documented path registered with another method is undocumented.
+        let mut routes = table();
+        routes.push(("/update", "GET"));
+        assert_eq!(undocumented_route(&routes), Some(("/update", "GET")));
+    }
+
+    #[test]
+    #[should_panic(expected = "route of API_ROUTES not registered")]
+    fn check_fails_on_unregistered_routes() {
+        check_routes(&table()[1..]);
+    }
+}
@@ -0,0 +1,71 @@
+// Highest version of the HTTP API, the only one served.
+pub const API_VERSION: u32 = 1;
+// Prefix of the routes of the current version of the HTTP API.
+pub const API_PREFIX: &str = "/api/v1";
+// Prefix shared by the routes of every version of the HTTP API.
+const VERSIONS_PREFIX: &str = "/api/v";
+
+// Version requested by the given path, if it is the path of a versioned route.
+//
+// Only plain decimal versions count, so `/api/v01` or `/api/vfoo` are paths
+// like any other.
+pub fn requested_version(path: &str) -> Option<u32> {
+    let rest = path.strip_prefix(VERSIONS_PREFIX)?;
+    let version = rest.split('/').next().unwrap_or(rest);
+    if !version.bytes().all(|byte| byte.is_ascii_digit()) || version.starts_with('0') {
+        return None;
+    }
+    version.parse().ok()
+}
+
+// Route of the current version the given path refers to, with the prefix
+// removed, so a versioned path and its bare alias share their settings.
+pub fn unversioned(path: &str) -> &str {
+    path.strip_prefix(API_PREFIX)
+        .filter(|route| route.starts_with('/'))
+        .unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_are_read_from_the_prefix() {
+        assert_eq!(requested_version("/api/v1"), Some(1));
+        assert_eq!(requested_version("/api/v1/led"), Some(1));
+        assert_eq!(requested_version("/api/v2"), Some(2));
+        assert_eq!(requested_version("/api/v2/x"), Some(2));
+        assert_eq!(requested_version("/api/v10/x/y"), Some(10));
+    }
+
+    #[test]
+    fn other_paths_are_not_versioned() {
+        for path in [
+            "/api/vfoo",
+            "/api/v01",
+            "/api/v0",
+            "/api/v+1",
+            "/api/v",
+            "/api/v/led",
+            "/api/v99999999999",
+            "/api",
+            "/led",
+            "/",
+            "",
+        ] {
+            assert_eq!(requested_version(path), None, "{path}");
+        }
+    }
+
+    #[test]
+    fn current_version_prefix_is_removed() {
+        assert_eq!(unversioned("/api/v1/led"), "/led");
+        assert_eq!(unversioned("/api/v1/led/0"), "/led/0");
+        assert_eq!(unversioned("/led"), "/led");
+        // Only whole path segments are removed.
+        assert_eq!(unversioned("/api/v1"), "/api/v1");
+        assert_eq!(unversioned("/api/v10/led"), "/api/v10/led");
+        assert_eq!(unversioned("/api/v2/led"), "/api/v2/led");
+    }
+}
@@ -0,0 +1,87 @@
+// Exponential backoff with random jitter.
+//
+// The base delay doubles after every failure, from the minimum up to the
+// maximum one. Each returned delay is randomly picked between half the base
+// delay and the base delay, so devices failing together do not retry in
+// lockstep.
+pub struct Backoff {
+    min_ms: u64,
+    max_ms: u64,
+    base_ms: u64,
+    // Consecutive failures since the last reset.
+    failures: u32,
+}
+
+impl Backoff {
+    pub const fn new(min_ms: u64, max_ms: u64) -> Self {
+        Self {
+            min_ms,
+            max_ms,
+            base_ms: min_ms,
+            failures: 0,
+        }
+    }
+
+    // Number of consecutive failures since the last reset.
+    pub const fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    // Records a failure, returning the delay to wait before retrying.
+    //
+    // The jitter is derived from the given random value.
+    pub fn next_delay(&mut self, random: u32) -> u64 {
+        let base_ms = self.base_ms;
+        self.base_ms = base_ms.saturating_mul(2).min(self.max_ms);
+        self.failures = self.failures.saturating_add(1);
+
+        let half_ms = base_ms / 2;
+        base_ms - half_ms + u64::from(random) % (half_ms + 1)
+    }
+
+    // Restarts from the minimum delay, after a success.
+    pub fn reset(&mut self) {
+        self.base_ms = self.min_ms;
+        self.failures = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_doubles_up_to_the_maximum() {
+        let mut backoff = Backoff::new(1000, 60_000);
+
+        // A zero random value picks half the base delay.
+        let delays: Vec<u64> = (0..8).map(|_| backoff.next_delay(0)).collect();
+        assert_eq!(
+            delays,
+            [500, 1000, 2000, 4000, 8000, 16_000, 30_000, 30_000]
+        );
+        assert_eq!(backoff.failures(), 8);
+    }
+
+    #[test]
+    fn jitter_stays_between_half_and_the_whole_base_delay() {
+        for random in [0, 1, 1234, 5000, u32::MAX / 2, u32::MAX] {
+            let mut backoff = Backoff::new(10_000, 60_000);
+            let delay = backoff.next_delay(random);
+            assert!((5000..=10_000).contains(&delay), "{delay}");
+        }
+        assert_eq!(Backoff::new(10_000, 60_000).next_delay(0), 5000);
+    }
+
+    #[test]
+    fn reset_restarts_from_the_minimum() {
+        let mut backoff = Backoff::new(1000, 60_000);
+        for _ in 0..5 {
+            backoff.next_delay(0);
+        }
+
+        backoff.reset();
+        assert_eq!(backoff.failures(), 0);
+        assert_eq!(backoff.next_delay(0), 500);
+    }
+}
@@ -0,0 +1,231 @@
+// Maximum length of the configured user name and password.
+pub const MAX_USER_LEN: usize = 32;
+pub const MAX_PASS_LEN: usize = 64;
+// Maximum length of the decoded Basic credentials, the user name and the
+// password separated by a colon.
+const MAX_CREDENTIALS_LEN: usize = MAX_USER_LEN + MAX_PASS_LEN + 1;
+
+// Compares two keys in a time which does not depend on their common prefix,
+// so the key cannot be guessed one byte at a time.
+pub fn keys_match(given: &[u8], expected: &[u8]) -> bool {
+    let difference = given
+        .iter()
+        .zip(expected)
+        .fold(0, |difference, (given, expected)| {
+            difference | (given ^ expected)
+        });
+    difference == 0 && given.len() == expected.len()
+}
+
+// Value of a base64 digit.
+const fn base64_digit(digit: u8) -> Option<u8> {
+    match digit {
+        b'A'..=b'Z' => Some(digit - b'A'),
+        b'a'..=b'z' => Some(digit - b'a' + 26),
+        b'0'..=b'9' => Some(digit - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+// Decodes padded base64 into the given buffer, returning the decoded bytes.
+//
+// Input which is malformed or does not fit the buffer is rejected.
+fn decode_base64<'a>(encoded: &[u8], buffer: &'a mut [u8]) -> Option<&'a [u8]> {
+    if !encoded.len().is_multiple_of(4) || encoded.len() / 4 * 3 > buffer.len() {
+        return None;
+    }
+
+    let mut len = 0;
+    let quads = encoded.len() / 4;
+    for (index, quad) in encoded.chunks_exact(4).enumerate() {
+        // Padding is only allowed at the end of the last quad.
+        let padding = match quad {
+            [_, _, b'=', b'='] => 2,
+            [_, _, _, b'='] => 1,
+            _ => 0,
+        };
+        if padding > 0 && index + 1 != quads {
+            return None;
+        }
+
+        let mut bits = 0u32;
+        for digit in &quad[..4 - padding] {
+            bits = bits << 6 | u32::from(base64_digit(*digit)?);
+        }
+        bits <<= 6 * padding;
+
+        let bytes = bits.to_be_bytes();
+        let decoded = &bytes[1..4 - padding];
+        buffer[len..len + decoded.len()].copy_from_slice(decoded);
+        len += decoded.len();
+    }
+
+    Some(&buffer[..len])
+}
+
+// Whether an `Authorization` header carries the given credentials.
+pub fn credentials_match(authorization: &[u8], user: &[u8], password: &[u8]) -> bool {
+    let Some(encoded) = authorization.strip_prefix(b"Basic ") else {
+        return false;
+    };
+    // Every quad of base64 digits is decoded into three bytes before the
+    // padding is dropped, so the buffer holds whole triples.
+    let mut buffer = [0; MAX_CREDENTIALS_LEN.next_multiple_of(3)];
+    let Some(credentials) = decode_base64(encoded.trim_ascii(), &mut buffer) else {
+        return false;
+    };
+    let Some(separator) = credentials.iter().position(|byte| *byte == b':') else {
+        return false;
+    };
+
+    let (given_user, given_password) = (&credentials[..separator], &credentials[separator + 1..]);
+    // Both parts are always compared, so the time does not reveal which one
+    // is wrong.
+    keys_match(given_user, user) & keys_match(given_password, password)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGITS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    fn encode(bytes: &[u8]) -> String {
+        let mut encoded = String::new();
+        for chunk in bytes.chunks(3) {
+            let mut triple = [0; 3];
+            triple[..chunk.len()].copy_from_slice(chunk);
+            let bits = u32::from_be_bytes([0, triple[0], triple[1], triple[2]]);
+            for index in 0..4 {
+                if index <= chunk.len() {
+                    let digit = (bits >> (18 - 6 * index)) & 0x3f;
+                    encoded.push(char::from(DIGITS[digit as usize]));
+                } else {
+                    encoded.push('=');
+                }
+            }
+        }
+        encoded
+    }
+
+    fn header(credentials: &[u8]) -> String {
+        format!("Basic {}", encode(credentials))
+    }
+
+    fn decode(encoded: &[u8]) -> Option<Vec<u8>> {
+        let mut buffer = [0; 12];
+        decode_base64(encoded, &mut buffer).map(<[u8]>::to_vec)
+    }
+
+    #[test]
+    fn base64_is_decoded() {
+        assert_eq!(decode(b"dXNlcjpwdw==").as_deref(), Some(&b"user:pw"[..]));
+        assert_eq!(decode(b"dXNlcjpwdzE=").as_deref(), Some(&b"user:pw1"[..]));
+        assert_eq!(decode(b"dXNlcjpwdzEy").as_deref(), Some(&b"user:pw12"[..]));
+        assert_eq!(decode(b"").as_deref(), Some(&b""[..]));
+    }
+
+    #[test]
+    fn padding_is_only_allowed_at_the_end() {
+        assert_eq!(decode(b"dQ==dXNl"), None);
+        assert_eq!(decode(b"dXN=bGVy"), None);
+        assert_eq!(decode(b"d==="), None);
+        assert_eq!(decode(b"dX=l"), None);
+    }
+
+    #[test]
+    fn malformed_base64_is_rejected() {
+        // Not a multiple of 4 digits.
+        assert_eq!(decode(b"dXNlcjpwdw="), None);
+        assert_eq!(decode(b"dXNlc"), None);
+        // Digits outside the alphabet.
+        assert_eq!(decode(b"dXNl*jpw"), None);
+        // Longer than the buffer.
+        assert_eq!(decode(b"dXNlcjpwdzEydXNlcjpw"), None);
+    }
+
+    #[test]
+    fn matching_credentials_are_accepted() {
+        assert!(credentials_match(
+            header(b"admin:secret").as_bytes(),
+            b"admin",
+            b"secret"
+        ));
+        // Whitespace around the credentials is ignored.
+        let padded = format!("Basic  {} ", encode(b"admin:secret"));
+        assert!(credentials_match(padded.as_bytes(), b"admin", b"secret"));
+        // The password may hold colons.
+        assert!(credentials_match(
+            header(b"admin:a:b").as_bytes(),
+            b"admin",
+            b"a:b"
+        ));
+    }
+
+    #[test]
+    fn wrong_credentials_are_rejected() {
+        for credentials in [&b"admin:wrong"[..], b"user:secret", b"admin:secret2"] {
+            assert!(!credentials_match(
+                header(credentials).as_bytes(),
+                b"admin",
+                b"secret"
+            ));
+        }
+        // Another scheme.
+        let bearer = format!("Bearer {}", encode(b"admin:secret"));
+        assert!(!credentials_match(bearer.as_bytes(), b"admin", b"secret"));
+    }
+
+    #[test]
+    fn credentials_without_a_colon_are_rejected() {
+        assert!(!credentials_match(
+            header(b"adminsecret").as_bytes(),
+            b"admin",
+            b"secret"
+        ));
+        assert!(!credentials_match(header(b"").as_bytes(), b"", b""));
+    }
+
+    #[test]
+    fn empty_parts_only_match_empty_credentials() {
+        assert!(!credentials_match(
+            header(b":secret").as_bytes(),
+            b"admin",
+            b"secret"
+        ));
+        assert!(!credentials_match(
+            header(b"admin:").as_bytes(),
+            b"admin",
+            b"secret"
+        ));
+        // A user may have no password.
+        assert!(credentials_match(
+            header(b"admin:").as_bytes(),
+            b"admin",
+            b""
+        ));
+    }
+
+    #[test]
+    fn longest_credentials_round_trip() {
+        let user = [b'u'; MAX_USER_LEN];
+        let password = [b'p'; MAX_PASS_LEN];
+        let credentials = [&user[..], b":", &password[..]].concat();
+        assert!(credentials_match(
+            header(&credentials).as_bytes(),
+            &user,
+            &password
+        ));
+
+        // Longer credentials do not fit the buffer.
+        let password = [b'p'; MAX_PASS_LEN + 3];
+        let credentials = [&user[..], b":", &password[..]].concat();
+        assert!(!credentials_match(
+            header(&credentials).as_bytes(),
+            &user,
+            &password
+        ));
+    }
+}
@@ -0,0 +1,225 @@
+use core::fmt::Write;
+
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::click::Click;
+use crate::led::{LedInput, MAIN_CHANNEL, MAX_BRIGHTNESS, MAX_LED_CHANNELS};
+
+// Size of an encoded action: its kind, its led channel and its brightness.
+const ACTION_SIZE: usize = 3;
+// Size of the encoded button actions, one for every gesture.
+pub const BUTTON_ACTIONS_SIZE: usize = 3 * ACTION_SIZE;
+
+// Action performed when the button emits a gesture.
+//
+// Written as `none`, `mqtt`, `toggle:<channel>`, `off:<channel>`,
+// `blink:<channel>`, `cycle:<channel>` or `brightness:<channel>:<level>`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ButtonAction {
+    // Ignore the gesture.
+    None,
+    // Only report the gesture, over MQTT and the other notifications, leaving
+    // the leds untouched.
+    Mqtt,
+    // Toggle a led channel.
+    Toggle { channel: usize },
+    // Turn a led channel off.
+    Off { channel: usize },
+    // Start blinking a led channel, or stop when it is already blinking.
+    Blink { channel: usize },
+    // Set a led channel to the next brightness preset.
+    Cycle { channel: usize },
+    // Set a led channel to a brightness percentage.
+    Brightness { channel: usize, level: u8 },
+}
+
+impl ButtonAction {
+    // Led channel changed by the action, if any.
+    pub const fn channel(self) -> Option<usize> {
+        match self {
+            Self::None | Self::Mqtt => None,
+            Self::Toggle { channel }
+            | Self::Off { channel }
+            | Self::Blink { channel }
+            | Self::Cycle { channel }
+            | Self::Brightness { channel, .. } => Some(channel),
+        }
+    }
+
+    // Whether the action changes no led channel, or one of the given number
+    // of led channels.
+    pub fn fits(self, channels: usize) -> bool {
+        self.channel().is_none_or(|channel| channel < channels)
+    }
+
+    // Led input sent by the action, if any.
+    pub const fn led_input(self) -> Option<LedInput> {
+        match self {
+            Self::None | Self::Mqtt => None,
+            Self::Toggle { .. } => Some(LedInput::Button),
+            Self::Off { .. } => Some(LedInput::Off { fade_ms: None }),
+            Self::Blink { .. } => Some(LedInput::ToggleBlink),
+            Self::Cycle { .. } => Some(LedInput::CyclePreset),
+            Self::Brightness { level, .. } => Some(LedInput::Brightness {
+                level,
+                fade_ms: None,
+            }),
+        }
+    }
+
+    fn parse(action: &str) -> Option<Self> {
+        let channel = |channel: &str| {
+            channel
+                .parse()
+                .ok()
+                .filter(|channel| *channel < MAX_LED_CHANNELS)
+        };
+        let mut fields = action.split(':');
+        let action = match (fields.next()?, fields.next(), fields.next()) {
+            ("none", None, None) => Self::None,
+            ("mqtt", None, None) => Self::Mqtt,
+            ("toggle", Some(id), None) => Self::Toggle {
+                channel: channel(id)?,
+            },
+            ("off", Some(id), None) => Self::Off {
+                channel: channel(id)?,
+            },
+            ("blink", Some(id), None) => Self::Blink {
+                channel: channel(id)?,
+            },
+            ("cycle", Some(id), None) => Self::Cycle {
+                channel: channel(id)?,
+            },
+            ("brightness", Some(id), Some(level)) => Self::Brightness {
+                channel: channel(id)?,
+                level: level
+                    .parse()
+                    .ok()
+                    .filter(|level| *level <= MAX_BRIGHTNESS)?,
+            },
+            _ => return None,
+        };
+        fields.next().is_none().then_some(action)
+    }
+
+    fn encode(self, bytes: &mut [u8; ACTION_SIZE]) {
+        let (kind, level) = match self {
+            Self::None => (0, 0),
+            Self::Mqtt => (1, 0),
+            Self::Toggle { .. } => (2, 0),
+            Self::Off { .. } => (3, 0),
+            Self::Blink { .. } => (4, 0),
+            Self::Brightness { level, .. } => (5, level),
+            Self::Cycle { .. } => (6, 0),
+        };
+        bytes[0] = kind;
+        // Channels are fewer than `MAX_LED_CHANNELS`, which fits a byte.
+        bytes[1] = self.channel().unwrap_or(MAIN_CHANNEL) as u8;
+        bytes[2] = level;
+    }
+
+    fn decode(bytes: &[u8; ACTION_SIZE]) -> Option<Self> {
+        let channel = usize::from(bytes[1]);
+        if channel >= MAX_LED_CHANNELS {
+            return None;
+        }
+        match bytes[0] {
+            0 => Some(Self::None),
+            1 => Some(Self::Mqtt),
+            2 => Some(Self::Toggle { channel }),
+            3 => Some(Self::Off { channel }),
+            4 => Some(Self::Blink { channel }),
+            5 if bytes[2] <= MAX_BRIGHTNESS => Some(Self::Brightness {
+                channel,
+                level: bytes[2],
+            }),
+            6 => Some(Self::Cycle { channel }),
+            _ => None,
+        }
+    }
+}
+
+impl Serialize for ButtonAction {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut action = heapless::String::<18>::new();
+        // `brightness:<channel>:<level>` is the longest action, which fits.
+        let _ = match *self {
+            Self::None => write!(action, "none"),
+            Self::Mqtt => write!(action, "mqtt"),
+            Self::Toggle { channel } => write!(action, "toggle:{channel}"),
+            Self::Off { channel } => write!(action, "off:{channel}"),
+            Self::Blink { channel } => write!(action, "blink:{channel}"),
+            Self::Cycle { channel } => write!(action, "cycle:{channel}"),
+            Self::Brightness { channel, level } => write!(action, "brightness:{channel}:{level}"),
+        };
+        serializer.serialize_str(&action)
+    }
+}
+
+impl<'de> Deserialize<'de> for ButtonAction {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let action = <&str>::deserialize(deserializer)?;
+        Self::parse(action).ok_or_else(|| D::Error::custom("invalid button action"))
+    }
+}
+
+// Actions performed for every button gesture, stored with the settings.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ButtonActions {
+    pub single: ButtonAction,
+    pub double: ButtonAction,
+    pub long: ButtonAction,
+}
+
+impl ButtonActions {
+    // Actions of the original firmware on the given led channel: a click
+    // toggles it, or cycles its brightness presets when configured so, a
+    // double click makes it blink and a long press turns it off.
+    pub const fn defaults(channel: usize, cycles_presets: bool) -> Self {
+        Self {
+            single: if cycles_presets {
+                ButtonAction::Cycle { channel }
+            } else {
+                ButtonAction::Toggle { channel }
+            },
+            double: ButtonAction::Blink { channel },
+            long: ButtonAction::Off { channel },
+        }
+    }
+
+    pub const fn get(&self, click: Click) -> ButtonAction {
+        match click {
+            Click::Single => self.single,
+            Click::Double => self.double,
+            Click::Long => self.long,
+        }
+    }
+
+    // Whether every action fits the given number of led channels.
+    pub fn fits(&self, channels: usize) -> bool {
+        [self.single, self.double, self.long]
+            .into_iter()
+            .all(|action| action.fits(channels))
+    }
+
+    pub fn encode(&self, bytes: &mut [u8; BUTTON_ACTIONS_SIZE]) {
+        for (action, bytes) in [self.single, self.double, self.long]
+            .into_iter()
+            .zip(bytes.chunks_exact_mut(ACTION_SIZE))
+        {
+            action.encode(bytes.try_into().unwrap());
+        }
+    }
+
+    pub fn decode(bytes: &[u8; BUTTON_ACTIONS_SIZE]) -> Option<Self> {
+        let mut actions = bytes
+            .chunks_exact(ACTION_SIZE)
+            .map(|bytes| ButtonAction::decode(bytes.try_into().ok()?));
+        Some(Self {
+            single: actions.next()??,
+            double: actions.next()??,
+            long: actions.next()??,
+        })
+    }
+}
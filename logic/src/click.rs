@@ -0,0 +1,140 @@
+// Button gestures.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Click {
+    // A short press not followed by another press within the double-click
+    // window.
+    Single,
+    // Two short presses within the double-click window.
+    Double,
+    // A press held for at least the long press duration.
+    Long,
+}
+
+// Classifier states.
+#[derive(Clone, Copy)]
+enum ClickState {
+    // The button is released and no gesture is in progress.
+    Idle,
+    // The button has been pressed at the given timestamp.
+    Pressed { at_ms: u64 },
+    // The button has been held for a long press and is not released yet.
+    Held,
+    // The button has been released at the given timestamp after a short
+    // press, so a second press may still arrive.
+    Released { at_ms: u64 },
+    // The second press of a double-click is not released yet.
+    SecondPressed,
+}
+
+// Classifies debounced button presses and releases into gestures.
+//
+// A single click is emitted only once the double-click window expires, so the
+// caller has to invoke `on_timeout` when the deadline returned by `deadline_ms`
+// is reached.
+pub struct ClickClassifier {
+    long_press_ms: u64,
+    double_click_ms: u64,
+    state: ClickState,
+}
+
+impl ClickClassifier {
+    pub const fn new(long_press_ms: u64, double_click_ms: u64) -> Self {
+        Self {
+            long_press_ms,
+            double_click_ms,
+            state: ClickState::Idle,
+        }
+    }
+
+    // Timestamp at which `on_timeout` must be invoked, if any.
+    pub const fn deadline_ms(&self) -> Option<u64> {
+        match self.state {
+            ClickState::Pressed { at_ms } => Some(at_ms.saturating_add(self.long_press_ms)),
+            ClickState::Released { at_ms } => Some(at_ms.saturating_add(self.double_click_ms)),
+            ClickState::Idle | ClickState::Held | ClickState::SecondPressed => None,
+        }
+    }
+
+    // Feeds a button press.
+    pub fn on_press(&mut self, now_ms: u64) -> Option<Click> {
+        match self.state {
+            ClickState::Released { .. } => {
+                self.state = ClickState::SecondPressed;
+                Some(Click::Double)
+            }
+            _ => {
+                self.state = ClickState::Pressed { at_ms: now_ms };
+                None
+            }
+        }
+    }
+
+    // Feeds a button release.
+    pub fn on_release(&mut self, now_ms: u64) -> Option<Click> {
+        self.state = match self.state {
+            ClickState::Pressed { .. } => ClickState::Released { at_ms: now_ms },
+            _ => ClickState::Idle,
+        };
+        None
+    }
+
+    // Notifies that the deadline has been reached.
+    pub fn on_timeout(&mut self) -> Option<Click> {
+        match self.state {
+            ClickState::Pressed { .. } => {
+                self.state = ClickState::Held;
+                Some(Click::Long)
+            }
+            ClickState::Released { .. } => {
+                self.state = ClickState::Idle;
+                Some(Click::Single)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LONG_PRESS_MS: u64 = 1000;
+    const DOUBLE_CLICK_MS: u64 = 400;
+
+    #[test]
+    fn single_click_waits_for_the_double_click_window() {
+        let mut classifier = ClickClassifier::new(LONG_PRESS_MS, DOUBLE_CLICK_MS);
+
+        assert_eq!(classifier.on_press(0), None);
+        assert_eq!(classifier.deadline_ms(), Some(LONG_PRESS_MS));
+        assert_eq!(classifier.on_release(100), None);
+        assert_eq!(classifier.deadline_ms(), Some(500));
+        assert_eq!(classifier.on_timeout(), Some(Click::Single));
+        assert_eq!(classifier.deadline_ms(), None);
+    }
+
+    #[test]
+    fn two_presses_are_one_double_click() {
+        let mut classifier = ClickClassifier::new(LONG_PRESS_MS, DOUBLE_CLICK_MS);
+
+        classifier.on_press(0);
+        classifier.on_release(100);
+        assert_eq!(classifier.on_press(300), Some(Click::Double));
+        assert_eq!(classifier.on_release(350), None);
+        // The release ends the gesture, without a single click.
+        assert_eq!(classifier.deadline_ms(), None);
+        assert_eq!(classifier.on_timeout(), None);
+    }
+
+    #[test]
+    fn long_press_is_emitted_while_held() {
+        let mut classifier = ClickClassifier::new(LONG_PRESS_MS, DOUBLE_CLICK_MS);
+
+        classifier.on_press(0);
+        assert_eq!(classifier.on_timeout(), Some(Click::Long));
+        assert_eq!(classifier.deadline_ms(), None);
+        // Releasing the button emits nothing else.
+        assert_eq!(classifier.on_release(3000), None);
+        assert_eq!(classifier.deadline_ms(), None);
+    }
+}
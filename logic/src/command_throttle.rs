@@ -0,0 +1,388 @@
+use crate::led::{LedCommand, Source};
+use crate::logic::{Admission, LedLogic, LedStore};
+
+// Keeps the led from following the commands faster than a minimum interval,
+// whatever sends them, such as a flood of MQTT messages.
+//
+// A command arriving within the interval is held until it expires, replacing
+// the one already held, so the most recent command is always the one applied.
+// The interval starts once the logic accepts a command, as reported by
+// `on_admission`. A zero interval disables the throttle.
+//
+// Commands the logic delays, until a relay can switch again, are held the
+// same way, so waiting for the relay never keeps the led task busy.
+pub struct CommandThrottle<T> {
+    interval_ms: u64,
+    // Timestamp of the last command accepted, if any.
+    applied_at_ms: Option<u64>,
+    // Time before which the held command is not released, set only when the
+    // logic delayed it.
+    delayed_until_ms: Option<u64>,
+    // Command waiting for the interval or the delay to expire, if any.
+    held: Option<T>,
+}
+
+// Outcome of a command fed to the throttle.
+pub enum Throttled<T> {
+    // The command can be applied right away.
+    Apply(T),
+    // The command bypasses the throttle, so it is applied right away without
+    // starting the interval.
+    Bypass(T),
+    // The command is held until the interval expires.
+    Hold,
+    // The command is held, replacing the one held before, which is dropped.
+    Coalesce,
+}
+
+impl<T> CommandThrottle<T> {
+    pub const fn new(interval_ms: u64) -> Self {
+        Self {
+            interval_ms,
+            applied_at_ms: None,
+            delayed_until_ms: None,
+            held: None,
+        }
+    }
+
+    // Whether a command received at the given time can be applied right away.
+    pub fn admits(&self, now_ms: u64) -> bool {
+        self.held.is_none() && self.release_at_ms().is_none_or(|at_ms| now_ms >= at_ms)
+    }
+
+    // Feeds a command received at the given time.
+    pub fn on_command(&mut self, command: T, now_ms: u64) -> Throttled<T> {
+        if self.admits(now_ms) {
+            return Throttled::Apply(command);
+        }
+        match self.held.replace(command) {
+            Some(_) => Throttled::Coalesce,
+            None => Throttled::Hold,
+        }
+    }
+
+    // Time at which the next command can be applied, if it cannot be applied
+    // at any time.
+    pub fn release_at_ms(&self) -> Option<u64> {
+        let interval_end_ms = self
+            .applied_at_ms
+            .map(|applied_at_ms| applied_at_ms + self.interval_ms);
+        // `None` is less than any time.
+        interval_end_ms.max(self.delayed_until_ms)
+    }
+
+    // Whether a command is held.
+    pub const fn is_holding(&self) -> bool {
+        self.held.is_some()
+    }
+
+    // Command held, if any.
+    pub const fn held(&self) -> Option<&T> {
+        self.held.as_ref()
+    }
+
+    // Takes the held command, once the interval and the delay expired.
+    pub fn release(&mut self, now_ms: u64) -> Option<T> {
+        if self.release_at_ms().is_some_and(|at_ms| now_ms < at_ms) {
+            return None;
+        }
+        self.delayed_until_ms = None;
+        self.held.take()
+    }
+
+    // Starts the interval once the logic accepted, at the given time, a
+    // command let through. Ignored commands do not start it, so the next
+    // command is applied right away, and neither do delayed ones, until they
+    // are accepted.
+    pub fn on_admission(&mut self, admission: &Admission, now_ms: u64) {
+        if self.interval_ms > 0 && matches!(admission, Admission::Accept) {
+            self.applied_at_ms = Some(now_ms);
+        }
+    }
+}
+
+impl CommandThrottle<LedCommand> {
+    // Feeds a command received by the led channel of the given logic at the
+    // given time.
+    //
+    // The inputs of the firmware itself, such as the auto-off timer, the
+    // network state patterns or the quiet hours, are never throttled. A held
+    // command may be replaced by a later one, so relative inputs, such as
+    // toggles, are held as the absolute input they amount to.
+    pub fn receive<S: LedStore>(
+        &mut self,
+        command: LedCommand,
+        logic: &LedLogic<S>,
+        now_ms: u64,
+    ) -> Throttled<LedCommand> {
+        if matches!(command.source, Source::System | Source::Startup) {
+            return Throttled::Bypass(command);
+        }
+
+        let command = if self.admits(now_ms) {
+            command
+        } else {
+            let held = self.held().map(|held| &held.input);
+            LedCommand {
+                input: logic.resolve(command.input, held),
+                ..command
+            }
+        };
+        self.on_command(command, now_ms)
+    }
+
+    // Holds a command the logic of its led channel delayed until the given
+    // time, replacing the one held, if any. It is held as the absolute input
+    // it amounts to, like the other held commands, and admitted again once
+    // released.
+    pub fn defer<S: LedStore>(&mut self, command: LedCommand, logic: &LedLogic<S>, until_ms: u64) {
+        let held = self.held().map(|held| &held.input);
+        self.held = Some(LedCommand {
+            input: logic.resolve(command.input, held),
+            ..command
+        });
+        self.delayed_until_ms = Some(until_ms);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::led::{LedInput, MAIN_CHANNEL};
+    use crate::logic::{BrightnessPresets, LogicConfig};
+
+    #[derive(Default)]
+    struct TestStore {
+        brightness: u8,
+        preset_index: usize,
+    }
+
+    impl LedStore for TestStore {
+        fn brightness(&self, _channel: usize) -> u8 {
+            self.brightness
+        }
+
+        fn set_brightness(&mut self, _channel: usize, brightness: u8) {
+            self.brightness = brightness;
+        }
+
+        fn preset_index(&self, _channel: usize) -> usize {
+            self.preset_index
+        }
+
+        fn set_preset_index(&mut self, _channel: usize, index: usize) {
+            self.preset_index = index;
+        }
+
+        fn count_switch(&mut self) {}
+    }
+
+    fn logic() -> LedLogic<TestStore> {
+        LedLogic::new(
+            MAIN_CHANNEL,
+            BrightnessPresets::parse("0,50,100"),
+            LogicConfig {
+                fade_ms: 0,
+                manual_override_ms: 0,
+                min_switch_interval_ms: 0,
+                reject_early_switches: false,
+            },
+            TestStore::default(),
+        )
+    }
+
+    fn command(source: Source, input: LedInput) -> LedCommand {
+        LedCommand::new(source, input)
+    }
+
+    fn toggle() -> LedCommand {
+        command(Source::Mqtt, LedInput::Toggle)
+    }
+
+    #[test]
+    fn commands_within_the_interval_are_held() {
+        let logic = logic();
+        let mut throttle = CommandThrottle::new(100);
+
+        assert!(matches!(
+            throttle.receive(toggle(), &logic, 0),
+            Throttled::Apply(_)
+        ));
+        throttle.on_admission(&Admission::Accept, 0);
+        assert!(!throttle.admits(50));
+        assert!(matches!(
+            throttle.receive(toggle(), &logic, 50),
+            Throttled::Hold
+        ));
+        assert!(throttle.is_holding());
+        // A held command keeps the next ones waiting, even past the interval.
+        assert!(!throttle.admits(150));
+    }
+
+    #[test]
+    fn later_commands_replace_the_held_one() {
+        let logic = logic();
+        let mut throttle = CommandThrottle::new(100);
+        throttle.on_admission(&Admission::Accept, 0);
+
+        assert!(matches!(
+            throttle.receive(
+                command(Source::Http, LedInput::Off { fade_ms: None }),
+                &logic,
+                10
+            ),
+            Throttled::Hold
+        ));
+        assert!(matches!(
+            throttle.receive(
+                command(
+                    Source::Mqtt,
+                    LedInput::Brightness {
+                        level: 30,
+                        fade_ms: None
+                    }
+                ),
+                &logic,
+                20
+            ),
+            Throttled::Coalesce
+        ));
+        let released = throttle.release(100).unwrap();
+        assert!(released.source == Source::Mqtt);
+        assert!(matches!(
+            released.input,
+            LedInput::Brightness { level: 30, .. }
+        ));
+    }
+
+    #[test]
+    fn held_commands_are_resolved() {
+        // The led is off, so a held toggle turns it on and a second one,
+        // replacing the first, turns it off again.
+        let logic = logic();
+        let mut throttle = CommandThrottle::new(100);
+        throttle.on_admission(&Admission::Accept, 0);
+
+        throttle.receive(toggle(), &logic, 10);
+        assert!(matches!(
+            throttle.held().unwrap().input,
+            LedInput::On { .. }
+        ));
+        throttle.receive(toggle(), &logic, 20);
+        assert!(matches!(
+            throttle.held().unwrap().input,
+            LedInput::Off { .. }
+        ));
+
+        // Commands let through are not resolved.
+        let mut throttle = CommandThrottle::new(100);
+        let Throttled::Apply(command) = throttle.receive(toggle(), &logic, 0) else {
+            panic!("toggle held");
+        };
+        assert!(matches!(command.input, LedInput::Toggle));
+    }
+
+    #[test]
+    fn held_command_is_released_once_the_interval_expires() {
+        let logic = logic();
+        let mut throttle = CommandThrottle::new(100);
+        throttle.on_admission(&Admission::Accept, 1000);
+        throttle.receive(toggle(), &logic, 1010);
+
+        assert_eq!(throttle.release_at_ms(), Some(1100));
+        assert!(throttle.release(1099).is_none());
+        assert!(throttle.release(1100).is_some());
+        assert!(!throttle.is_holding());
+        assert!(throttle.release(1200).is_none());
+
+        // The released command starts the interval again once admitted.
+        throttle.on_admission(&Admission::Accept, 1100);
+        assert!(!throttle.admits(1150));
+        assert!(throttle.admits(1200));
+    }
+
+    #[test]
+    fn firmware_commands_bypass_the_throttle() {
+        let logic = logic();
+        let mut throttle = CommandThrottle::new(100);
+        throttle.on_admission(&Admission::Accept, 0);
+        throttle.receive(toggle(), &logic, 10);
+
+        for source in [Source::System, Source::Startup] {
+            let received = throttle.receive(command(source, LedInput::Reconcile), &logic, 20);
+            assert!(matches!(received, Throttled::Bypass(_)));
+        }
+        // The held command is kept.
+        assert!(matches!(
+            throttle.held().unwrap().input,
+            LedInput::On { .. }
+        ));
+    }
+
+    #[test]
+    fn zero_interval_never_holds() {
+        let logic = logic();
+        let mut throttle = CommandThrottle::new(0);
+        for now_ms in [0, 0, 1] {
+            assert!(matches!(
+                throttle.receive(toggle(), &logic, now_ms),
+                Throttled::Apply(_)
+            ));
+            throttle.on_admission(&Admission::Accept, now_ms);
+        }
+        assert_eq!(throttle.release_at_ms(), None);
+    }
+
+    #[test]
+    fn delayed_commands_wait_for_the_relay() {
+        let logic = logic();
+        let mut throttle = CommandThrottle::new(100);
+        throttle.on_admission(&Admission::Accept, 0);
+
+        // The relay can switch again 300 ms after the interval starts.
+        let Throttled::Apply(command) = throttle.receive(toggle(), &logic, 200) else {
+            panic!("toggle held");
+        };
+        let until = embassy_time::Instant::from_millis(500);
+        throttle.on_admission(&Admission::Delay(until), 200);
+        throttle.defer(command, &logic, 500);
+        assert_eq!(throttle.release_at_ms(), Some(500));
+
+        // The delayed toggle is held as turning the led on, so a later toggle
+        // replacing it turns the led off.
+        assert!(matches!(
+            throttle.receive(toggle(), &logic, 250),
+            Throttled::Coalesce
+        ));
+        assert!(throttle.release(499).is_none());
+        let released = throttle.release(500).unwrap();
+        assert!(matches!(released.input, LedInput::Off { .. }));
+
+        // The delay ends with the released command, which starts the interval
+        // once accepted.
+        assert_eq!(throttle.release_at_ms(), Some(100));
+        throttle.on_admission(&Admission::Accept, 500);
+        assert_eq!(throttle.release_at_ms(), Some(600));
+    }
+
+    #[test]
+    fn ignored_commands_do_not_start_the_interval() {
+        // The chip is too hot, so the logic ignores a command turning the led
+        // on and the next command is applied right away.
+        let logic = logic();
+        let mut throttle = CommandThrottle::new(100);
+        let Throttled::Apply(command) = throttle.receive(toggle(), &logic, 0) else {
+            panic!("toggle held");
+        };
+        let now = embassy_time::Instant::from_millis(0);
+        let admission = logic.admit(&command.input, command.source, true, None, now);
+        assert!(matches!(admission, Admission::Ignore));
+        throttle.on_admission(&admission, 0);
+
+        assert!(throttle.admits(10));
+        assert!(matches!(
+            throttle.receive(toggle(), &logic, 10),
+            Throttled::Apply(_)
+        ));
+    }
+}
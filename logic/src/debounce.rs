@@ -0,0 +1,80 @@
+// Button debouncer.
+//
+// It is fed with a stream of (pressed, timestamp) samples and reports a new
+// button state only once the samples have been stable for the whole
+// stabilization window, so bouncing contacts never produce spurious presses.
+pub struct Debouncer {
+    window_ms: u64,
+    pressed: bool,
+    // Timestamp of the first sample differing from the stable state.
+    changed_at_ms: Option<u64>,
+}
+
+impl Debouncer {
+    // Creates a debouncer for a released button.
+    pub const fn new(window_ms: u64) -> Self {
+        Self {
+            window_ms,
+            pressed: false,
+            changed_at_ms: None,
+        }
+    }
+
+    // Whether the stable button state is pressed.
+    pub const fn is_pressed(&self) -> bool {
+        self.pressed
+    }
+
+    // Whether the samples differ from the stable state, but have not been
+    // stable for the whole window yet.
+    pub const fn is_settling(&self) -> bool {
+        self.changed_at_ms.is_some()
+    }
+
+    // Feeds a sample, returning the new stable state when it changes.
+    pub fn update(&mut self, pressed: bool, timestamp_ms: u64) -> Option<bool> {
+        if pressed == self.pressed {
+            // The level went back to the stable state, so it was a bounce.
+            self.changed_at_ms = None;
+            return None;
+        }
+
+        let changed_at_ms = *self.changed_at_ms.get_or_insert(timestamp_ms);
+        if timestamp_ms.saturating_sub(changed_at_ms) < self.window_ms {
+            return None;
+        }
+
+        self.pressed = pressed;
+        self.changed_at_ms = None;
+        Some(pressed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounces_shorter_than_the_window_are_ignored() {
+        let mut debouncer = Debouncer::new(20);
+
+        assert_eq!(debouncer.update(true, 0), None);
+        assert!(debouncer.is_settling());
+        assert_eq!(debouncer.update(false, 5), None);
+        assert!(!debouncer.is_settling());
+        assert!(!debouncer.is_pressed());
+    }
+
+    #[test]
+    fn level_stable_for_the_window_changes_the_state() {
+        let mut debouncer = Debouncer::new(20);
+
+        assert_eq!(debouncer.update(true, 0), None);
+        assert_eq!(debouncer.update(true, 10), None);
+        assert_eq!(debouncer.update(true, 20), Some(true));
+        assert!(debouncer.is_pressed());
+        assert_eq!(debouncer.update(true, 30), None);
+        assert_eq!(debouncer.update(false, 40), None);
+        assert_eq!(debouncer.update(false, 60), Some(false));
+    }
+}
@@ -0,0 +1,166 @@
+use crate::basic_auth::keys_match;
+use crate::sha256::{Sha256, DIGEST_SIZE};
+
+// Bytes starting every packet.
+const MAGIC: [u8; 2] = *b"BL";
+// Length of the truncated HMAC-SHA256 tag ending every packet.
+const TAG_LEN: usize = 8;
+// Length of a packet: the magic, the command, the counter and the tag.
+pub const PACKET_LEN: usize = MAGIC.len() + 1 + 4 + TAG_LEN;
+// Size of a SHA-256 block, and of the HMAC keys.
+const HMAC_BLOCK_SIZE: usize = 64;
+// Number of counters a remote reserves in flash at once, so it writes the
+// flash once every that many packets.
+pub const COUNTER_RESERVATION: u32 = 64;
+
+// HMAC-SHA256 of a message.
+pub fn hmac(key: &[u8], message: &[u8]) -> [u8; DIGEST_SIZE] {
+    // Keys longer than a block are hashed first.
+    let mut block_key = [0; HMAC_BLOCK_SIZE];
+    if key.len() > HMAC_BLOCK_SIZE {
+        let mut hasher = Sha256::new();
+        hasher.update(key);
+        block_key[..DIGEST_SIZE].copy_from_slice(&hasher.finalize());
+    } else {
+        block_key[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(&block_key.map(|byte| byte ^ 0x36));
+    inner.update(message);
+    let mut outer = Sha256::new();
+    outer.update(&block_key.map(|byte| byte ^ 0x5c));
+    outer.update(&inner.finalize());
+    outer.finalize()
+}
+
+// Extracts the command and the counter of an authenticated packet, returning
+// `None` when it is malformed or its tag is wrong.
+pub fn parse(packet: &[u8], key: &[u8]) -> Option<(u8, u32)> {
+    let packet: &[u8; PACKET_LEN] = packet.try_into().ok()?;
+    let (message, tag) = packet.split_at(PACKET_LEN - TAG_LEN);
+    if message[..MAGIC.len()] != MAGIC || !keys_match(tag, &hmac(key, message)[..TAG_LEN]) {
+        return None;
+    }
+    let command = message[MAGIC.len()];
+    let counter = u32::from_le_bytes(message[MAGIC.len() + 1..].try_into().ok()?);
+    Some((command, counter))
+}
+
+// Builds the packet carrying a command, authenticated with the shared key.
+pub fn packet(command: u8, counter: u32, key: &[u8]) -> [u8; PACKET_LEN] {
+    let mut packet = [0; PACKET_LEN];
+    let (message, tag) = packet.split_at_mut(PACKET_LEN - TAG_LEN);
+    message[..MAGIC.len()].copy_from_slice(&MAGIC);
+    message[MAGIC.len()] = command;
+    message[MAGIC.len() + 1..].copy_from_slice(&counter.to_le_bytes());
+    tag.copy_from_slice(&hmac(key, message)[..TAG_LEN]);
+    packet
+}
+
+// Reservation a remote must store in flash before sending the given counter,
+// when the counter is past the stored reservation.
+//
+// Every counter sent is at most the stored reservation, so a remote losing
+// its counter resumes from the reservation and its counters keep growing.
+pub fn reservation(counter: u32, reserved: u32) -> Option<u32> {
+    (counter > reserved).then(|| counter.saturating_add(COUNTER_RESERVATION))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(digest: [u8; DIGEST_SIZE]) -> String {
+        digest.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    // Test cases of RFC 4231, except the fifth, whose output is truncated.
+    #[test]
+    fn hmac_short_keys() {
+        assert_eq!(
+            hex(hmac(&[0x0b; 20], b"Hi There")),
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+        );
+        assert_eq!(
+            hex(hmac(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            hex(hmac(&[0xaa; 20], &[0xdd; 50])),
+            "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe"
+        );
+        let key: Vec<u8> = (1..=25).collect();
+        assert_eq!(
+            hex(hmac(&key, &[0xcd; 50])),
+            "82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b"
+        );
+    }
+
+    #[test]
+    fn hmac_keys_longer_than_a_block() {
+        assert_eq!(
+            hex(hmac(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+        assert_eq!(
+            hex(hmac(
+                &[0xaa; 131],
+                b"This is a test using a larger than block-size key and a larger than \
+                  block-size data. The key needs to be hashed before being used by the \
+                  HMAC algorithm."
+            )),
+            "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2"
+        );
+    }
+
+    #[test]
+    fn packets_round_trip() {
+        let packet = packet(0x02, 0x0102_0304, b"secret");
+        assert_eq!(&packet[..7], b"BL\x02\x04\x03\x02\x01");
+        assert_eq!(parse(&packet, b"secret"), Some((0x02, 0x0102_0304)));
+    }
+
+    #[test]
+    fn forged_packets_are_rejected() {
+        let valid = packet(0x01, 7, b"secret");
+        assert_eq!(parse(&valid, b"other"), None);
+
+        // Any changed byte breaks the tag, or the magic.
+        for index in 0..PACKET_LEN {
+            let mut forged = valid;
+            forged[index] ^= 0x01;
+            assert_eq!(parse(&forged, b"secret"), None, "byte {index}");
+        }
+
+        // A packet with another magic is rejected even with a valid tag.
+        let mut other_magic = valid;
+        other_magic[..2].copy_from_slice(b"XX");
+        let tag = hmac(b"secret", &other_magic[..PACKET_LEN - TAG_LEN]);
+        other_magic[PACKET_LEN - TAG_LEN..].copy_from_slice(&tag[..TAG_LEN]);
+        assert_eq!(parse(&other_magic, b"secret"), None);
+    }
+
+    #[test]
+    fn packets_of_another_length_are_rejected() {
+        let valid = packet(0x01, 7, b"secret");
+        assert_eq!(parse(&valid[..PACKET_LEN - 1], b"secret"), None);
+        let mut longer = valid.to_vec();
+        longer.push(0);
+        assert_eq!(parse(&longer, b"secret"), None);
+        assert_eq!(parse(&[], b"secret"), None);
+    }
+
+    #[test]
+    fn counters_are_reserved_ahead() {
+        // Nothing is stored while the counter is within the reservation.
+        assert_eq!(reservation(1, 0), Some(1 + COUNTER_RESERVATION));
+        assert_eq!(reservation(10, 65), None);
+        assert_eq!(reservation(65, 65), None);
+        assert_eq!(reservation(66, 65), Some(66 + COUNTER_RESERVATION));
+        assert_eq!(reservation(u32::MAX, 65), Some(u32::MAX));
+    }
+}
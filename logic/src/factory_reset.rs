@@ -0,0 +1,92 @@
+// Time the button has to be held to reset the device to factory settings.
+const FACTORY_RESET_HOLD_MS: u64 = 10_000;
+// Interval between two feedback updates while the button is held.
+const FEEDBACK_STEP_MS: u64 = 1000;
+// Blinking periods of the feedback, at the long press and right before the
+// reset.
+const SLOWEST_FEEDBACK_PERIOD_MS: u64 = 500;
+const FASTEST_FEEDBACK_PERIOD_MS: u64 = 50;
+
+// Progress of a button hold towards a factory reset.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HoldProgress {
+    // The led blinks with the given period, faster as the reset approaches.
+    Feedback { period_ms: u64 },
+    // The button has been held long enough to reset the device.
+    Reset,
+}
+
+// Button hold in progress.
+#[derive(Clone, Copy)]
+struct Hold {
+    pressed_at_ms: u64,
+    // Timestamp of the next progress update.
+    next_update_ms: u64,
+}
+
+// Tracks how long the button is held, to reset the device once it has been
+// held for 10 seconds. Feedback starts together with the long press.
+//
+// A button pressed at boot may be stuck, so holds are only tracked once the
+// button has been seen released.
+pub struct ResetHold {
+    long_press_ms: u64,
+    armed: bool,
+    hold: Option<Hold>,
+}
+
+impl ResetHold {
+    // Creates the tracker, given whether the button is released at boot.
+    pub const fn new(long_press_ms: u64, released: bool) -> Self {
+        Self {
+            long_press_ms,
+            armed: released,
+            hold: None,
+        }
+    }
+
+    // Timestamp at which `on_timeout` must be invoked, if any.
+    pub fn deadline_ms(&self) -> Option<u64> {
+        self.hold.map(|hold| hold.next_update_ms)
+    }
+
+    // Feeds a button press.
+    pub fn on_press(&mut self, now_ms: u64) {
+        if self.armed {
+            self.hold = Some(Hold {
+                pressed_at_ms: now_ms,
+                next_update_ms: now_ms + self.long_press_ms.min(FACTORY_RESET_HOLD_MS),
+            });
+        }
+    }
+
+    // Feeds a button release, returning whether feedback had started.
+    pub fn on_release(&mut self, now_ms: u64) -> bool {
+        self.armed = true;
+        self.hold
+            .take()
+            .is_some_and(|hold| now_ms - hold.pressed_at_ms >= self.long_press_ms)
+    }
+
+    // Notifies that the deadline has been reached.
+    pub fn on_timeout(&mut self, now_ms: u64) -> Option<HoldProgress> {
+        let hold = self.hold.as_mut()?;
+        let held_ms = now_ms - hold.pressed_at_ms;
+        if held_ms >= FACTORY_RESET_HOLD_MS {
+            self.hold = None;
+            return Some(HoldProgress::Reset);
+        }
+
+        hold.next_update_ms =
+            (now_ms + FEEDBACK_STEP_MS).min(hold.pressed_at_ms + FACTORY_RESET_HOLD_MS);
+
+        // Shorten the period linearly until the reset.
+        let elapsed_ms = held_ms.saturating_sub(self.long_press_ms);
+        let window_ms = FACTORY_RESET_HOLD_MS
+            .saturating_sub(self.long_press_ms)
+            .max(1);
+        let period_ms = SLOWEST_FEEDBACK_PERIOD_MS
+            - (SLOWEST_FEEDBACK_PERIOD_MS - FASTEST_FEEDBACK_PERIOD_MS) * elapsed_ms / window_ms;
+        Some(HoldProgress::Feedback { period_ms })
+    }
+}
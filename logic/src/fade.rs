@@ -0,0 +1,71 @@
+// Linear ramp between two brightness percentages.
+//
+// It yields the brightness of each step, the last one being always the end
+// brightness.
+pub struct FadeRamp {
+    start: u8,
+    end: u8,
+    steps: u32,
+    step: u32,
+}
+
+impl FadeRamp {
+    // Creates a ramp of the given number of steps, at least one.
+    pub fn new(start: u8, end: u8, steps: u32) -> Self {
+        Self {
+            start,
+            end,
+            steps: steps.max(1),
+            step: 0,
+        }
+    }
+
+    // Brightness reached at the end of the ramp.
+    pub const fn end(&self) -> u8 {
+        self.end
+    }
+
+    // Whether all the steps have been yielded.
+    pub const fn is_finished(&self) -> bool {
+        self.step >= self.steps
+    }
+}
+
+impl Iterator for FadeRamp {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.is_finished() {
+            return None;
+        }
+        self.step += 1;
+
+        let start = i64::from(self.start);
+        let end = i64::from(self.end);
+        let brightness = start + (end - start) * i64::from(self.step) / i64::from(self.steps);
+
+        u8::try_from(brightness).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ramp_is_linear_and_ends_at_the_end_brightness() {
+        let ramp = FadeRamp::new(0, 100, 4);
+        assert_eq!(ramp.collect::<Vec<_>>(), [25, 50, 75, 100]);
+
+        let ramp = FadeRamp::new(100, 0, 3);
+        assert_eq!(ramp.collect::<Vec<_>>(), [67, 34, 0]);
+    }
+
+    #[test]
+    fn zero_steps_jump_to_the_end() {
+        let mut ramp = FadeRamp::new(30, 80, 0);
+        assert_eq!(ramp.next(), Some(80));
+        assert!(ramp.is_finished());
+        assert_eq!(ramp.next(), None);
+    }
+}
@@ -0,0 +1,157 @@
+use crate::click::{Click, ClickClassifier};
+use crate::factory_reset::{HoldProgress, ResetHold};
+
+// What the debounced button changes and the expired deadlines lead to.
+#[derive(Clone, Copy, Default)]
+pub struct Gesture {
+    // Gesture recognized, if any.
+    pub click: Option<Click>,
+    // Progress of the hold towards a factory reset, if any.
+    pub progress: Option<HoldProgress>,
+    // Whether the button was released after the factory reset feedback had
+    // started, so the feedback has to stop.
+    pub feedback_ended: bool,
+}
+
+// Decides the gestures and the factory reset of a button, given its debounced
+// presses and releases and the expired deadlines, so the button task only
+// waits for them and performs what they lead to.
+pub struct ButtonGestures {
+    classifier: ClickClassifier,
+    reset_hold: ResetHold,
+}
+
+impl ButtonGestures {
+    // Creates the gestures of a button, given whether it is released at boot.
+    pub const fn new(long_press_ms: u64, double_click_ms: u64, released: bool) -> Self {
+        Self {
+            classifier: ClickClassifier::new(long_press_ms, double_click_ms),
+            reset_hold: ResetHold::new(long_press_ms, released),
+        }
+    }
+
+    // Timestamp at which `on_timeout` must be invoked, if any.
+    pub fn deadline_ms(&self) -> Option<u64> {
+        self.classifier
+            .deadline_ms()
+            .into_iter()
+            .chain(self.reset_hold.deadline_ms())
+            .min()
+    }
+
+    // Feeds a debounced button press or release.
+    pub fn on_change(&mut self, pressed: bool, now_ms: u64) -> Gesture {
+        if pressed {
+            self.reset_hold.on_press(now_ms);
+            Gesture {
+                click: self.classifier.on_press(now_ms),
+                ..Gesture::default()
+            }
+        } else {
+            let feedback_ended = self.reset_hold.on_release(now_ms);
+            Gesture {
+                click: self.classifier.on_release(now_ms),
+                feedback_ended,
+                ..Gesture::default()
+            }
+        }
+    }
+
+    // Notifies that the deadline has been reached, feeding the classifier and
+    // the factory reset whose deadline is due.
+    pub fn on_timeout(&mut self, now_ms: u64) -> Gesture {
+        let is_due = |deadline_ms: Option<u64>| deadline_ms.is_some_and(|ms| ms <= now_ms);
+        let click = if is_due(self.classifier.deadline_ms()) {
+            self.classifier.on_timeout()
+        } else {
+            None
+        };
+        let progress = if is_due(self.reset_hold.deadline_ms()) {
+            self.reset_hold.on_timeout(now_ms)
+        } else {
+            None
+        };
+        Gesture {
+            click,
+            progress,
+            feedback_ended: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LONG_PRESS_MS: u64 = 1000;
+    const DOUBLE_CLICK_MS: u64 = 300;
+
+    fn gestures() -> ButtonGestures {
+        ButtonGestures::new(LONG_PRESS_MS, DOUBLE_CLICK_MS, true)
+    }
+
+    #[test]
+    fn short_press_is_a_single_click_once_the_window_expires() {
+        let mut gestures = gestures();
+
+        assert_eq!(gestures.on_change(true, 0).click, None);
+        assert_eq!(gestures.on_change(false, 100).click, None);
+        assert_eq!(gestures.deadline_ms(), Some(100 + DOUBLE_CLICK_MS));
+        assert_eq!(gestures.on_timeout(400).click, Some(Click::Single));
+        assert_eq!(gestures.deadline_ms(), None);
+    }
+
+    #[test]
+    fn second_press_within_the_window_is_a_double_click() {
+        let mut gestures = gestures();
+
+        gestures.on_change(true, 0);
+        gestures.on_change(false, 100);
+        assert_eq!(gestures.on_change(true, 200).click, Some(Click::Double));
+        assert_eq!(gestures.on_change(false, 300).click, None);
+        assert_eq!(gestures.deadline_ms(), None);
+    }
+
+    #[test]
+    fn held_press_is_a_long_press_followed_by_the_reset_feedback() {
+        let mut gestures = gestures();
+
+        gestures.on_change(true, 0);
+        assert_eq!(gestures.deadline_ms(), Some(LONG_PRESS_MS));
+        let gesture = gestures.on_timeout(LONG_PRESS_MS);
+        assert_eq!(gesture.click, Some(Click::Long));
+        assert_eq!(
+            gesture.progress,
+            Some(HoldProgress::Feedback { period_ms: 500 })
+        );
+
+        // Releasing the button stops the feedback, without another gesture.
+        let gesture = gestures.on_change(false, 2500);
+        assert_eq!(gesture.click, None);
+        assert!(gesture.feedback_ended);
+        assert_eq!(gestures.deadline_ms(), None);
+    }
+
+    #[test]
+    fn button_held_for_ten_seconds_resets_the_device() {
+        let mut gestures = gestures();
+
+        gestures.on_change(true, 0);
+        let mut progress = None;
+        while let Some(deadline_ms) = gestures.deadline_ms() {
+            progress = gestures.on_timeout(deadline_ms).progress;
+        }
+        assert_eq!(progress, Some(HoldProgress::Reset));
+    }
+
+    #[test]
+    fn button_pressed_at_boot_does_not_reset_the_device() {
+        let mut gestures = ButtonGestures::new(LONG_PRESS_MS, DOUBLE_CLICK_MS, false);
+
+        gestures.on_change(true, 0);
+        let gesture = gestures.on_timeout(LONG_PRESS_MS);
+        assert_eq!(gesture.click, Some(Click::Long));
+        assert_eq!(gesture.progress, None);
+        assert_eq!(gestures.deadline_ms(), None);
+    }
+}
@@ -0,0 +1,529 @@
+use core::fmt;
+
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+
+use log::error;
+
+use crate::morse::MorseMessage;
+use crate::pattern::LedPattern;
+
+// Blinking period, in milliseconds, used when no period is requested.
+pub const DEFAULT_BLINK_PERIOD_MS: u64 = 500;
+// Maximum led brightness percentage.
+pub const MAX_BRIGHTNESS: u8 = 100;
+// Channel of the main led, the one controlled by the bare routes and by the
+// subsystems unaware of the channels.
+pub const MAIN_CHANNEL: usize = 0;
+// Maximum number of led channels, the main led included.
+pub const MAX_LED_CHANNELS: usize = 4;
+// Interval between two brightness steps while fading.
+pub const FADE_STEP_MS: u64 = 10;
+
+// Color of an addressable led, written as `RRGGBB`.
+#[derive(Clone, Copy)]
+pub struct Rgb {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Rgb {
+    pub const WHITE: Self = Self {
+        red: 255,
+        green: 255,
+        blue: 255,
+    };
+
+    pub fn parse(rgb: &str) -> Option<Self> {
+        if rgb.len() != 6 || !rgb.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return None;
+        }
+        let channel = |range| u8::from_str_radix(&rgb[range], 16).ok();
+        Some(Self {
+            red: channel(0..2)?,
+            green: channel(2..4)?,
+            blue: channel(4..6)?,
+        })
+    }
+}
+
+impl fmt::Display for Rgb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02X}{:02X}{:02X}", self.red, self.green, self.blue)
+    }
+}
+
+impl<'de> Deserialize<'de> for Rgb {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let rgb = <&str>::deserialize(deserializer)?;
+        Self::parse(rgb).ok_or_else(|| D::Error::custom("invalid color, expected `RRGGBB`"))
+    }
+}
+
+// The led does not support colors.
+pub struct ColorUnsupported;
+
+// Led hardware, driven at a brightness percentage.
+pub trait LedDriver {
+    // Drives the led at the given brightness percentage.
+    fn set_level(&mut self, brightness: u8);
+
+    // Drives the led fully on or off.
+    fn set(&mut self, on: bool) {
+        self.set_level(if on { MAX_BRIGHTNESS } else { 0 });
+    }
+
+    // Changes the color of the led, keeping its brightness.
+    fn set_color(&mut self, color: Rgb) -> Result<(), ColorUnsupported>;
+}
+
+#[derive(Clone, Copy)]
+pub enum LedInput {
+    // Turn the led on, fading for `fade_ms` milliseconds or for the default
+    // duration when missing. The same applies to `Off` and `Brightness`.
+    //
+    // When `auto_off_secs` is set, the led is turned off after that many
+    // seconds, unless another input arrives first.
+    On {
+        fade_ms: Option<u64>,
+        auto_off_secs: Option<u64>,
+    },
+    Off {
+        fade_ms: Option<u64>,
+    },
+    Toggle,
+    Button,
+    // Start blinking with the default period, or stop when already blinking.
+    ToggleBlink,
+    // Set the brightness preset following the current one, starting over
+    // after the last one.
+    CyclePreset,
+    // Blink the led, switching its state every `period_ms` milliseconds.
+    Blink {
+        period_ms: u64,
+    },
+    // Set the led brightness percentage, from 0 (off) to 100.
+    Brightness {
+        level: u8,
+        fade_ms: Option<u64>,
+    },
+    // Temporarily override the led with a network state pattern. Any other
+    // input ends the override.
+    Pattern(LedPattern),
+    // Temporarily override the led to play a message in Morse code. Any other
+    // input, including another message, ends the playback.
+    Morse(MorseMessage),
+    // Change the color of an addressable led, keeping everything else.
+    Color(Rgb),
+    // Drive the led output again, once the quiet hours start or end.
+    Reconcile,
+}
+
+impl LedInput {
+    // Whether the input switches the led on or off right away, given the
+    // brightness it has or is fading to.
+    pub const fn switches(&self, brightness: u8) -> bool {
+        match self {
+            Self::On { .. } => brightness == 0,
+            Self::Off { .. } => brightness > 0,
+            // The logic tells whether cycling switches, given the presets.
+            Self::Toggle | Self::Button | Self::CyclePreset => true,
+            Self::Brightness { level, .. } => (*level > 0) != (brightness > 0),
+            // Blinking switches the led only once its period expires.
+            Self::ToggleBlink
+            | Self::Blink { .. }
+            | Self::Pattern(_)
+            | Self::Morse(_)
+            | Self::Color(_)
+            | Self::Reconcile => false,
+        }
+    }
+
+    // Brightness the input leaves the led at, if it sets one.
+    pub const fn brightness(&self) -> Option<u8> {
+        match self {
+            Self::On { .. } => Some(MAX_BRIGHTNESS),
+            Self::Off { .. } => Some(0),
+            Self::Brightness { level, .. } => Some(*level),
+            Self::Toggle
+            | Self::Button
+            | Self::CyclePreset
+            | Self::ToggleBlink
+            | Self::Blink { .. }
+            | Self::Pattern(_)
+            | Self::Morse(_)
+            | Self::Color(_)
+            | Self::Reconcile => None,
+        }
+    }
+
+    // Whether the input may turn the led on.
+    pub const fn may_light(&self) -> bool {
+        match self {
+            Self::Off { .. } | Self::Color(_) | Self::Reconcile => false,
+            Self::Brightness { level, .. } => *level > 0,
+            Self::On { .. }
+            | Self::Toggle
+            | Self::Button
+            | Self::CyclePreset
+            | Self::ToggleBlink
+            | Self::Blink { .. }
+            | Self::Pattern(_)
+            | Self::Morse(_) => true,
+        }
+    }
+}
+
+// Subsystem which sent a led input.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Button,
+    Encoder,
+    Http,
+    Mqtt,
+    #[cfg(feature = "coap")]
+    Coap,
+    Udp,
+    #[cfg(feature = "ble")]
+    Ble,
+    #[cfg(feature = "espnow")]
+    EspNow,
+    Schedule,
+    Motion,
+    // The boot sequence, such as the provisioning blinking.
+    Startup,
+    // The firmware itself, such as the auto-off timer or the network state
+    // patterns.
+    System,
+}
+
+impl Source {
+    // Source as a lowercase string.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Button => "button",
+            Self::Encoder => "encoder",
+            Self::Http => "http",
+            Self::Mqtt => "mqtt",
+            #[cfg(feature = "coap")]
+            Self::Coap => "coap",
+            Self::Udp => "udp",
+            #[cfg(feature = "ble")]
+            Self::Ble => "ble",
+            #[cfg(feature = "espnow")]
+            Self::EspNow => "espnow",
+            Self::Schedule => "schedule",
+            Self::Motion => "motion",
+            Self::Startup => "startup",
+            Self::System => "system",
+        }
+    }
+
+    // Whether the input is sent by an automation rather than on request.
+    pub const fn is_automation(self) -> bool {
+        matches!(self, Self::Schedule | Self::Motion)
+    }
+
+    // Whether the input is requested by someone, locally or remotely.
+    pub const fn is_manual(self) -> bool {
+        !self.is_automation() && !matches!(self, Self::Startup | Self::System)
+    }
+}
+
+// Led input together with the subsystem which sent it and the led channel it
+// is meant for.
+pub struct LedCommand {
+    pub input: LedInput,
+    pub source: Source,
+    pub channel: usize,
+}
+
+impl LedCommand {
+    // Command for the main led.
+    pub const fn new(source: Source, input: LedInput) -> Self {
+        Self::on_channel(MAIN_CHANNEL, source, input)
+    }
+
+    pub const fn on_channel(channel: usize, source: Source, input: LedInput) -> Self {
+        Self {
+            input,
+            source,
+            channel,
+        }
+    }
+}
+
+// Body of a `/led` request.
+#[derive(Deserialize)]
+struct LedBody<'a> {
+    state: &'a str,
+}
+
+// Errors arising while parsing a `/led` request body.
+#[derive(Debug)]
+pub enum LedBodyError {
+    MalformedJson,
+    UnknownState,
+}
+
+impl LedBodyError {
+    pub const fn message(&self) -> &'static str {
+        match self {
+            Self::MalformedJson => "Malformed JSON body\n",
+            Self::UnknownState => "Unknown led state, expected `on`, `off` or `toggle`\n",
+        }
+    }
+}
+
+// Parses a `/led` request body such as `{"state":"on"}` into a led input.
+pub fn parse_led_body(body: &[u8]) -> Result<LedInput, LedBodyError> {
+    let (led_body, _) =
+        serde_json_core::from_slice::<LedBody>(body).map_err(|_| LedBodyError::MalformedJson)?;
+
+    match led_body.state {
+        "on" => Ok(LedInput::On {
+            fade_ms: None,
+            auto_off_secs: None,
+        }),
+        "off" => Ok(LedInput::Off { fade_ms: None }),
+        "toggle" => Ok(LedInput::Toggle),
+        _ => Err(LedBodyError::UnknownState),
+    }
+}
+
+// Parses the payload of an MQTT command message.
+//
+// The accepted payloads are `ON`, `OFF`, `TOGGLE`, in any case, and a
+// brightness percentage from 0 to 100.
+pub fn parse_command(payload: &[u8]) -> Option<LedInput> {
+    let payload = core::str::from_utf8(payload).ok()?.trim();
+
+    if payload.eq_ignore_ascii_case("on") {
+        Some(LedInput::On {
+            fade_ms: None,
+            auto_off_secs: None,
+        })
+    } else if payload.eq_ignore_ascii_case("off") {
+        Some(LedInput::Off { fade_ms: None })
+    } else if payload.eq_ignore_ascii_case("toggle") {
+        Some(LedInput::Toggle)
+    } else {
+        payload
+            .parse::<u8>()
+            .ok()
+            .filter(|level| *level <= MAX_BRIGHTNESS)
+            .map(|level| LedInput::Brightness {
+                level,
+                fade_ms: None,
+            })
+    }
+}
+
+// Electrical polarity of a PWM led pin.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LedPolarity {
+    // The led is on when the pin is low.
+    ActiveLow,
+    // The led is on when the pin is high.
+    ActiveHigh,
+}
+
+// Extra led channel, as configured.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LedChannelSpec {
+    pub gpio: u8,
+    pub polarity: LedPolarity,
+}
+
+// Parses a `gpio[:high|low]` led channel, active high by default.
+fn parse_led_channel(channel: &str) -> Option<LedChannelSpec> {
+    let mut fields = channel.split(':');
+    let gpio = fields.next()?.parse().ok()?;
+    let polarity = match fields.next() {
+        None | Some("high") => LedPolarity::ActiveHigh,
+        Some("low") => LedPolarity::ActiveLow,
+        Some(_) => return None,
+    };
+    fields
+        .next()
+        .is_none()
+        .then_some(LedChannelSpec { gpio, polarity })
+}
+
+// Parses a comma-separated list of extra led channels.
+//
+// The channels are addressed by their position, so the first invalid one
+// ends the list rather than shifting the following ones.
+pub fn parse_led_channels(
+    channels: &str,
+) -> heapless::Vec<LedChannelSpec, { MAX_LED_CHANNELS - 1 }> {
+    let mut specs = heapless::Vec::new();
+    for channel in channels
+        .split(',')
+        .map(str::trim)
+        .filter(|channel| !channel.is_empty())
+    {
+        let Some(spec) = parse_led_channel(channel) else {
+            error!("Invalid led channel {channel}, expected `gpio[:high|low]`, ignoring the rest");
+            break;
+        };
+        if specs.push(spec).is_err() {
+            error!("Too many led channels, {channel} and the rest are ignored");
+            break;
+        }
+    }
+    specs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors_are_parsed_from_hex() {
+        let color = Rgb::parse("ff8000").unwrap();
+        assert_eq!((color.red, color.green, color.blue), (255, 128, 0));
+        assert_eq!(color.to_string(), "FF8000");
+
+        for invalid in ["", "fff", "ff80000", "gg8000", "+f8000"] {
+            assert!(Rgb::parse(invalid).is_none(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn led_body_is_mapped_to_an_input() {
+        assert!(matches!(
+            parse_led_body(br#"{"state":"on"}"#),
+            Ok(LedInput::On {
+                fade_ms: None,
+                auto_off_secs: None
+            })
+        ));
+        assert!(matches!(
+            parse_led_body(br#"{"state":"off"}"#),
+            Ok(LedInput::Off { fade_ms: None })
+        ));
+        assert!(matches!(
+            parse_led_body(br#" {"state": "toggle"} "#),
+            Ok(LedInput::Toggle)
+        ));
+    }
+
+    #[test]
+    fn invalid_led_bodies_are_rejected() {
+        assert!(matches!(
+            parse_led_body(br#"{"state":"blink"}"#),
+            Err(LedBodyError::UnknownState)
+        ));
+        for body in [&b""[..], b"{", b"state=on", br#"{"state":1}"#, br#"{}"#] {
+            assert!(matches!(
+                parse_led_body(body),
+                Err(LedBodyError::MalformedJson)
+            ));
+        }
+    }
+
+    #[test]
+    fn commands_are_case_insensitive() {
+        for payload in [&b"on"[..], b"ON", b"On", b" on\n"] {
+            assert!(
+                matches!(parse_command(payload), Some(LedInput::On { .. })),
+                "{payload:?}"
+            );
+        }
+        for payload in [&b"off"[..], b"OFF", b"oFf", b"\toff "] {
+            assert!(
+                matches!(parse_command(payload), Some(LedInput::Off { .. })),
+                "{payload:?}"
+            );
+        }
+        for payload in [&b"toggle"[..], b"TOGGLE", b"Toggle", b"  toggle  "] {
+            assert!(
+                matches!(parse_command(payload), Some(LedInput::Toggle)),
+                "{payload:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn brightness_commands_range_from_0_to_100() {
+        for (payload, expected) in [(&b"0"[..], 0), (b"42", 42), (b"100", 100), (b" 7\r\n", 7)] {
+            assert!(
+                matches!(
+                    parse_command(payload),
+                    Some(LedInput::Brightness { level, fade_ms: None }) if level == expected
+                ),
+                "{payload:?}"
+            );
+        }
+        for payload in [&b"101"[..], b"255", b"256", b"-1", b"4 2", b"50%"] {
+            assert!(parse_command(payload).is_none(), "{payload:?}");
+        }
+    }
+
+    #[test]
+    fn invalid_commands_are_rejected() {
+        for payload in [&b""[..], b"   ", b"onn", b"blink", b"\xff\xfeon", b"o\xffn"] {
+            assert!(parse_command(payload).is_none(), "{payload:?}");
+        }
+    }
+
+    #[test]
+    fn only_manual_sources_start_the_override() {
+        assert!(Source::Button.is_manual());
+        assert!(Source::Http.is_manual());
+        assert!(Source::Schedule.is_automation() && !Source::Schedule.is_manual());
+        assert!(Source::Motion.is_automation());
+        assert!(!Source::System.is_manual() && !Source::System.is_automation());
+        assert!(!Source::Startup.is_manual());
+    }
+
+    #[test]
+    fn led_channels_are_parsed() {
+        let specs = parse_led_channels(" 4, 5:low ,6:high");
+        assert_eq!(
+            specs,
+            [
+                LedChannelSpec {
+                    gpio: 4,
+                    polarity: LedPolarity::ActiveHigh
+                },
+                LedChannelSpec {
+                    gpio: 5,
+                    polarity: LedPolarity::ActiveLow
+                },
+                LedChannelSpec {
+                    gpio: 6,
+                    polarity: LedPolarity::ActiveHigh
+                },
+            ]
+        );
+        assert!(parse_led_channels("").is_empty());
+        // Empty entries are skipped.
+        assert_eq!(parse_led_channels(",4,,").len(), 1);
+    }
+
+    #[test]
+    fn invalid_led_channels_are_rejected() {
+        for channel in ["", "low", "4:", "4:up", "4:low:high", "256", "-1", "4 low"] {
+            assert_eq!(parse_led_channel(channel), None, "{channel}");
+        }
+    }
+
+    #[test]
+    fn first_invalid_led_channel_ends_the_list() {
+        // The channels after an invalid one keep their position, unused.
+        let gpios = |channels| {
+            parse_led_channels(channels)
+                .iter()
+                .map(|spec| spec.gpio)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(gpios("4,x,5"), [4]);
+        assert_eq!(gpios("x,4,5"), []);
+        assert_eq!(gpios("4,5:dim,6"), [4]);
+        // Channels past the capacity are dropped too.
+        assert_eq!(gpios("4,5,6,7,8"), [4, 5, 6]);
+    }
+}
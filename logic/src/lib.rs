@@ -0,0 +1,33 @@
+// Logic of the firmware free of any hardware, task or global state, such as
+// the led and button state machines and the pure helpers, so it builds for
+// the host and is tested there with `./test.sh`.
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+
+pub mod api;
+pub mod api_version;
+pub mod backoff;
+pub mod basic_auth;
+pub mod button_actions;
+pub mod click;
+pub mod command_throttle;
+pub mod crc;
+pub mod debounce;
+pub mod espnow;
+pub mod factory_reset;
+pub mod fade;
+pub mod gesture;
+pub mod led;
+pub mod link_local;
+pub mod log_buffer;
+pub mod logic;
+pub mod manual_override;
+pub mod morse;
+pub mod pattern;
+pub mod quadrature;
+pub mod quiet_hours;
+pub mod schedule;
+pub mod settings;
+pub mod sha256;
+pub mod slaac;
+pub mod sntp;
+pub mod touch;
@@ -0,0 +1,375 @@
+use core::net::Ipv6Addr;
+
+// Offsets in an Ethernet frame carrying an IPv6 packet.
+const ETHERTYPE: usize = 12;
+const NEXT_HEADER: usize = 14 + 6;
+const SOURCE: usize = 14 + 8;
+const DESTINATION: usize = 14 + 24;
+const PAYLOAD: usize = 14 + 40;
+const ETHERTYPE_IPV6: [u8; 2] = [0x86, 0xdd];
+
+// Upper-layer protocols whose checksum covers the addresses.
+const TCP: u8 = 6;
+const UDP: u8 = 17;
+const ICMPV6: u8 = 58;
+// Neighbor Discovery messages, and the offset of their target address.
+const NEIGHBOR_SOLICITATION: u8 = 135;
+const NEIGHBOR_ADVERTISEMENT: u8 = 136;
+const TARGET: usize = PAYLOAD + 8;
+// Multicast address of all the nodes on the link, to which the solicitations
+// from the unspecified address are answered.
+const ALL_NODES: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+// Neighbors waiting for an advertisement of the link-local address at once.
+const MAX_SOLICITORS: usize = 4;
+
+// Keeps the link-local address of the device reachable once the network
+// stack, which holds a single IPv6 address, is given the global one.
+//
+// The received packets addressed to the link-local address are translated to
+// the global one, and the packets sent on the link from the global address
+// are translated back, so the neighbors only see the link-local address.
+// Neighbor Discovery is translated too, so the neighbors can still resolve
+// the link-local address.
+#[derive(Clone, Copy)]
+pub struct LinkLocalAlias {
+    link_local: Ipv6Addr,
+    global: Option<Ipv6Addr>,
+    // Neighbors which solicited the link-local address, so the advertisement
+    // of the global address sent to each of them answers it. The oldest one
+    // is dropped when a new one does not fit.
+    solicitors: [Option<Ipv6Addr>; MAX_SOLICITORS],
+}
+
+impl LinkLocalAlias {
+    pub const fn new(link_local: Ipv6Addr) -> Self {
+        Self {
+            link_local,
+            global: None,
+            solicitors: [None; MAX_SOLICITORS],
+        }
+    }
+
+    // Sets the address held by the stack instead of the link-local one, if
+    // any.
+    pub fn set_global(&mut self, global: Option<Ipv6Addr>) {
+        self.global = global;
+        self.solicitors = [None; MAX_SOLICITORS];
+    }
+
+    // Translates a frame received from the link before the stack handles it.
+    pub fn on_received(&mut self, frame: &mut [u8]) {
+        let (Some(global), Some(checksum)) = (self.global, checksum_offset(frame)) else {
+            return;
+        };
+        if address(frame, DESTINATION) == Some(self.link_local) {
+            replace(frame, DESTINATION, global, checksum);
+        }
+        if is_neighbor_discovery(frame, NEIGHBOR_SOLICITATION, self.link_local) {
+            replace(frame, TARGET, global, checksum);
+            let solicitor = match address(frame, SOURCE) {
+                Some(Ipv6Addr::UNSPECIFIED) => ALL_NODES,
+                Some(source) => source,
+                None => return,
+            };
+            self.add_solicitor(solicitor);
+        }
+    }
+
+    // Translates a frame sent by the stack before it reaches the link.
+    pub fn on_sent(&mut self, frame: &mut [u8]) {
+        let (Some(global), Some(checksum)) = (self.global, checksum_offset(frame)) else {
+            return;
+        };
+        let on_link = address(frame, DESTINATION).is_some_and(is_link_scope);
+        if on_link && address(frame, SOURCE) == Some(global) {
+            replace(frame, SOURCE, self.link_local, checksum);
+        }
+        if is_neighbor_discovery(frame, NEIGHBOR_ADVERTISEMENT, global) {
+            let destination = address(frame, DESTINATION);
+            if let Some(solicitor) = self
+                .solicitors
+                .iter_mut()
+                .find(|solicitor| solicitor.is_some() && **solicitor == destination)
+            {
+                *solicitor = None;
+                replace(frame, TARGET, self.link_local, checksum);
+            }
+        }
+    }
+
+    fn add_solicitor(&mut self, solicitor: Ipv6Addr) {
+        let slot = match self.solicitors.iter().position(Option::is_none) {
+            Some(slot) => slot,
+            None => {
+                self.solicitors.rotate_left(1);
+                MAX_SOLICITORS - 1
+            }
+        };
+        self.solicitors[slot] = Some(solicitor);
+    }
+}
+
+// Offset of the checksum of the upper-layer protocol of an IPv6 frame, if
+// the protocol directly follows the header and covers the addresses.
+fn checksum_offset(frame: &[u8]) -> Option<usize> {
+    if frame.get(ETHERTYPE..ETHERTYPE + 2)? != ETHERTYPE_IPV6 {
+        return None;
+    }
+    let offset = match *frame.get(NEXT_HEADER)? {
+        TCP => 16,
+        UDP => 6,
+        ICMPV6 => 2,
+        _ => return None,
+    };
+    let offset = PAYLOAD + offset;
+    (frame.len() >= offset + 2).then_some(offset)
+}
+
+// Address at the given offset of a frame, if the frame holds it.
+fn address(frame: &[u8], offset: usize) -> Option<Ipv6Addr> {
+    let octets: [u8; 16] = frame.get(offset..offset + 16)?.try_into().ok()?;
+    Some(Ipv6Addr::from(octets))
+}
+
+// Whether the address is only reachable on the link.
+fn is_link_scope(address: Ipv6Addr) -> bool {
+    let [first, second, ..] = address.octets();
+    address.is_unicast_link_local() || (first == 0xff && second & 0x0f == 2)
+}
+
+// Whether the frame is a Neighbor Discovery message of the given type about
+// the given target.
+fn is_neighbor_discovery(frame: &[u8], message: u8, target: Ipv6Addr) -> bool {
+    frame.get(NEXT_HEADER) == Some(&ICMPV6)
+        && frame.get(PAYLOAD) == Some(&message)
+        && address(frame, TARGET) == Some(target)
+}
+
+// Replaces an address held by the frame, updating the checksum at the given
+// offset (RFC 1624).
+fn replace(frame: &mut [u8], offset: usize, address: Ipv6Addr, checksum: usize) {
+    let mut sum = u32::from(!u16::from_be_bytes([frame[checksum], frame[checksum + 1]]));
+    let old = &frame[offset..offset + 16];
+    for (old, new) in old.chunks_exact(2).zip(address.octets().chunks_exact(2)) {
+        sum += u32::from(!u16::from_be_bytes([old[0], old[1]]));
+        sum += u32::from(u16::from_be_bytes([new[0], new[1]]));
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    // The carries are folded, so the sum fits. A zero checksum means none
+    // for UDP, so its equivalent is used instead.
+    let updated = match !(sum as u16) {
+        0 => 0xffff,
+        updated => updated,
+    };
+
+    frame[offset..offset + 16].copy_from_slice(&address.octets());
+    frame[checksum..checksum + 2].copy_from_slice(&updated.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINK_LOCAL: Ipv6Addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 0x0211, 0x22ff, 0xfe33, 0x4455);
+    const GLOBAL: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 1, 0x0211, 0x22ff, 0xfe33, 0x4455);
+    const ROUTER: Ipv6Addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+    const REMOTE: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 2, 0, 0, 0, 1);
+    const SOLICITED_NODE: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 1, 0xff33, 0x4455);
+
+    fn alias() -> LinkLocalAlias {
+        let mut alias = LinkLocalAlias::new(LINK_LOCAL);
+        alias.set_global(Some(GLOBAL));
+        alias
+    }
+
+    // Sums the 16 bits words of the bytes, in one's complement.
+    fn sum(bytes: &[u8]) -> u32 {
+        bytes
+            .chunks(2)
+            .map(|word| u32::from(word[0]) << 8 | u32::from(*word.get(1).unwrap_or(&0)))
+            .sum()
+    }
+
+    fn fold(mut sum: u32) -> u16 {
+        while sum > 0xffff {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        sum as u16
+    }
+
+    // Sum of the pseudo-header and of the payload, which is all ones when
+    // the checksum is valid.
+    fn verify(frame: &[u8]) -> u16 {
+        let payload = &frame[PAYLOAD..];
+        let length = payload.len() as u32;
+        fold(
+            sum(&frame[SOURCE..PAYLOAD])
+                + (length >> 16)
+                + (length & 0xffff)
+                + u32::from(frame[NEXT_HEADER])
+                + sum(payload),
+        )
+    }
+
+    // Frame from `source` to `destination` carrying the given payload, with a
+    // valid checksum at the given offset of the payload.
+    fn frame(
+        source: Ipv6Addr,
+        destination: Ipv6Addr,
+        protocol: u8,
+        payload: &[u8],
+        checksum: usize,
+    ) -> Vec<u8> {
+        let mut frame = vec![0; PAYLOAD];
+        frame[ETHERTYPE..ETHERTYPE + 2].copy_from_slice(&ETHERTYPE_IPV6);
+        frame[14] = 0x60;
+        frame[14 + 4..14 + 6].copy_from_slice(&(payload.len() as u16).to_be_bytes());
+        frame[NEXT_HEADER] = protocol;
+        frame[SOURCE..SOURCE + 16].copy_from_slice(&source.octets());
+        frame[DESTINATION..DESTINATION + 16].copy_from_slice(&destination.octets());
+        frame.extend_from_slice(payload);
+        let offset = PAYLOAD + checksum;
+        let checksum = !verify(&frame);
+        frame[offset..offset + 2].copy_from_slice(&checksum.to_be_bytes());
+        frame
+    }
+
+    fn tcp(source: Ipv6Addr, destination: Ipv6Addr) -> Vec<u8> {
+        let mut segment = [0; 24];
+        segment[..4].copy_from_slice(&[0x1f, 0x90, 0xc0, 0x01]);
+        segment[12] = 0x50;
+        segment[20..].copy_from_slice(b"GET ");
+        frame(source, destination, TCP, &segment, 16)
+    }
+
+    fn neighbor_discovery(
+        message: u8,
+        source: Ipv6Addr,
+        destination: Ipv6Addr,
+        target: Ipv6Addr,
+    ) -> Vec<u8> {
+        let mut payload = [0; 24];
+        payload[0] = message;
+        payload[8..].copy_from_slice(&target.octets());
+        frame(source, destination, ICMPV6, &payload, 2)
+    }
+
+    #[test]
+    fn link_local_packets_reach_the_global_address() {
+        let mut alias = alias();
+        let mut received = tcp(ROUTER, LINK_LOCAL);
+        alias.on_received(&mut received);
+        assert_eq!(address(&received, DESTINATION), Some(GLOBAL));
+        assert_eq!(verify(&received), 0xffff);
+
+        let mut sent = tcp(GLOBAL, ROUTER);
+        alias.on_sent(&mut sent);
+        assert_eq!(address(&sent, SOURCE), Some(LINK_LOCAL));
+        assert_eq!(verify(&sent), 0xffff);
+    }
+
+    #[test]
+    fn global_traffic_is_not_translated() {
+        let mut alias = alias();
+        for mut frame in [tcp(REMOTE, GLOBAL), tcp(GLOBAL, REMOTE)] {
+            let original = frame.clone();
+            alias.on_received(&mut frame);
+            alias.on_sent(&mut frame);
+            assert_eq!(frame, original);
+        }
+    }
+
+    #[test]
+    fn link_local_address_is_resolved_by_the_neighbors() {
+        let mut alias = alias();
+        let mut solicitation =
+            neighbor_discovery(NEIGHBOR_SOLICITATION, ROUTER, SOLICITED_NODE, LINK_LOCAL);
+        alias.on_received(&mut solicitation);
+        assert_eq!(address(&solicitation, TARGET), Some(GLOBAL));
+        assert_eq!(verify(&solicitation), 0xffff);
+
+        // The stack answers for the address it holds.
+        let mut advertisement = neighbor_discovery(NEIGHBOR_ADVERTISEMENT, GLOBAL, ROUTER, GLOBAL);
+        alias.on_sent(&mut advertisement);
+        assert_eq!(address(&advertisement, SOURCE), Some(LINK_LOCAL));
+        assert_eq!(address(&advertisement, TARGET), Some(LINK_LOCAL));
+        assert_eq!(verify(&advertisement), 0xffff);
+
+        // A solicitation of the global address is answered as is.
+        let mut advertisement = neighbor_discovery(NEIGHBOR_ADVERTISEMENT, GLOBAL, ROUTER, GLOBAL);
+        alias.on_sent(&mut advertisement);
+        assert_eq!(address(&advertisement, TARGET), Some(GLOBAL));
+    }
+
+    #[test]
+    fn solicitations_are_answered_per_neighbor() {
+        let mut alias = alias();
+        let neighbor = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 2);
+        for (source, target) in [(ROUTER, LINK_LOCAL), (neighbor, GLOBAL)] {
+            let mut solicitation =
+                neighbor_discovery(NEIGHBOR_SOLICITATION, source, SOLICITED_NODE, target);
+            alias.on_received(&mut solicitation);
+        }
+
+        // The neighbor soliciting the global address is answered first, and
+        // keeps resolving it.
+        let mut advertisement =
+            neighbor_discovery(NEIGHBOR_ADVERTISEMENT, GLOBAL, neighbor, GLOBAL);
+        alias.on_sent(&mut advertisement);
+        assert_eq!(address(&advertisement, TARGET), Some(GLOBAL));
+
+        let mut advertisement = neighbor_discovery(NEIGHBOR_ADVERTISEMENT, GLOBAL, ROUTER, GLOBAL);
+        alias.on_sent(&mut advertisement);
+        assert_eq!(address(&advertisement, TARGET), Some(LINK_LOCAL));
+        assert_eq!(verify(&advertisement), 0xffff);
+    }
+
+    #[test]
+    fn duplicate_address_detection_is_answered_to_all_nodes() {
+        let mut alias = alias();
+        let mut solicitation = neighbor_discovery(
+            NEIGHBOR_SOLICITATION,
+            Ipv6Addr::UNSPECIFIED,
+            SOLICITED_NODE,
+            LINK_LOCAL,
+        );
+        alias.on_received(&mut solicitation);
+
+        let mut advertisement =
+            neighbor_discovery(NEIGHBOR_ADVERTISEMENT, GLOBAL, ALL_NODES, GLOBAL);
+        alias.on_sent(&mut advertisement);
+        assert_eq!(address(&advertisement, TARGET), Some(LINK_LOCAL));
+    }
+
+    #[test]
+    fn oldest_solicitor_is_dropped() {
+        let mut alias = alias();
+        let neighbors = (1..=MAX_SOLICITORS as u16 + 1)
+            .map(|host| Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, host))
+            .collect::<Vec<_>>();
+        for &neighbor in &neighbors {
+            let mut solicitation =
+                neighbor_discovery(NEIGHBOR_SOLICITATION, neighbor, SOLICITED_NODE, LINK_LOCAL);
+            alias.on_received(&mut solicitation);
+        }
+
+        for (index, &neighbor) in neighbors.iter().enumerate() {
+            let mut advertisement =
+                neighbor_discovery(NEIGHBOR_ADVERTISEMENT, GLOBAL, neighbor, GLOBAL);
+            alias.on_sent(&mut advertisement);
+            let expected = if index == 0 { GLOBAL } else { LINK_LOCAL };
+            assert_eq!(address(&advertisement, TARGET), Some(expected));
+        }
+    }
+
+    #[test]
+    fn nothing_is_translated_without_a_global_address() {
+        let mut alias = LinkLocalAlias::new(LINK_LOCAL);
+        let mut frame = tcp(ROUTER, LINK_LOCAL);
+        let original = frame.clone();
+        alias.on_received(&mut frame);
+        assert_eq!(frame, original);
+    }
+}
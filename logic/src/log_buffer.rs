@@ -0,0 +1,299 @@
+use log::Level;
+
+// Size of a log line, longer lines are truncated.
+pub const LINE_SIZE: usize = 160;
+// Size of the header preceding each line, its level and length.
+const HEADER_SIZE: usize = 2;
+
+// Log line read from the buffer.
+pub struct LogLine {
+    // Sequence number of the line, increasing with each logged line.
+    pub seq: u32,
+    pub level: Level,
+    pub len: usize,
+}
+
+// Position of a reader in the buffer, so every line is read without
+// skipping over the older ones again.
+pub struct LogCursor {
+    // Sequence number of the next line to read and its position in the
+    // arena, missing before the first read.
+    next: Option<(u32, usize)>,
+}
+
+impl Default for LogCursor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogCursor {
+    // Cursor starting from the oldest line.
+    pub const fn new() -> Self {
+        Self { next: None }
+    }
+}
+
+// Ring buffer of log lines, evicting the oldest lines when full, in an arena
+// of `N` bytes.
+//
+// Lines are stored back to back in a byte arena, each preceded by its level
+// and length, so no memory is allocated for each line.
+pub struct LogBuffer<const N: usize> {
+    arena: [u8; N],
+    // Offset of the oldest line and number of used bytes.
+    start: usize,
+    used: usize,
+    // Sequence numbers of the oldest line and of the next logged line.
+    first_seq: u32,
+    next_seq: u32,
+}
+
+impl<const N: usize> Default for LogBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> LogBuffer<N> {
+    pub const fn new() -> Self {
+        Self {
+            arena: [0; N],
+            start: 0,
+            used: 0,
+            first_seq: 0,
+            next_seq: 0,
+        }
+    }
+
+    fn byte(&self, position: usize) -> u8 {
+        self.arena[position % N]
+    }
+
+    // Size of the line stored at the given position, header included.
+    fn line_size(&self, position: usize) -> usize {
+        HEADER_SIZE + usize::from(self.byte(position + 1))
+    }
+
+    // Removes the oldest line.
+    fn evict(&mut self) {
+        let evicted = self.line_size(self.start);
+        self.start = (self.start + evicted) % N;
+        self.used -= evicted;
+        self.first_seq = self.first_seq.wrapping_add(1);
+    }
+
+    pub fn push(&mut self, level: Level, line: &[u8]) {
+        let line = &line[..line.len().min(LINE_SIZE)];
+        let size = HEADER_SIZE + line.len();
+        while N - self.used < size {
+            self.evict();
+        }
+
+        // The line length fits a byte, since it is bounded by `LINE_SIZE`.
+        let header = [level as u8, line.len() as u8];
+        let end = self.start + self.used;
+        for (i, byte) in header.iter().chain(line).enumerate() {
+            self.arena[(end + i) % N] = *byte;
+        }
+        self.used += size;
+        self.next_seq = self.next_seq.wrapping_add(1);
+    }
+
+    // Copies the line at the cursor, or the oldest one when it has been
+    // evicted, and moves the cursor to the next line.
+    pub fn read(&self, cursor: &mut LogCursor, out: &mut [u8; LINE_SIZE]) -> Option<LogLine> {
+        // The lines are never moved, so the position of a line stays valid
+        // until it is evicted. The position following the newest line does
+        // not change either, evicting only moves the start.
+        let held = self.next_seq.wrapping_sub(self.first_seq);
+        let (seq, position) = match cursor.next {
+            Some((seq, position)) if seq.wrapping_sub(self.first_seq) <= held => (seq, position),
+            _ => (self.first_seq, self.start),
+        };
+        if seq == self.next_seq {
+            cursor.next = Some((seq, position));
+            return None;
+        }
+
+        let level = match self.byte(position) {
+            1 => Level::Error,
+            2 => Level::Warn,
+            3 => Level::Info,
+            4 => Level::Debug,
+            _ => Level::Trace,
+        };
+        let len = usize::from(self.byte(position + 1));
+        for (i, byte) in out[..len].iter_mut().enumerate() {
+            *byte = self.byte(position + HEADER_SIZE + i);
+        }
+
+        let next = (position + HEADER_SIZE + len) % N;
+        cursor.next = Some((seq.wrapping_add(1), next));
+        Some(LogLine { seq, level, len })
+    }
+
+    // Removes the lines older than the given sequence number.
+    pub fn clear_until(&mut self, seq: u32) {
+        while self.first_seq != seq && self.used > 0 {
+            self.evict();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reads every line from the cursor on, with its sequence number.
+    fn read_all<const N: usize>(
+        buffer: &LogBuffer<N>,
+        cursor: &mut LogCursor,
+    ) -> Vec<(u32, String)> {
+        let mut out = [0; LINE_SIZE];
+        let mut lines = Vec::new();
+        while let Some(line) = buffer.read(cursor, &mut out) {
+            lines.push((
+                line.seq,
+                String::from_utf8(out[..line.len].to_vec()).unwrap(),
+            ));
+        }
+        lines
+    }
+
+    fn lines(lines: &[(u32, &str)]) -> Vec<(u32, String)> {
+        lines
+            .iter()
+            .map(|(seq, line)| (*seq, String::from(*line)))
+            .collect()
+    }
+
+    #[test]
+    fn lines_are_read_in_order() {
+        let mut buffer = LogBuffer::<64>::new();
+        buffer.push(Level::Warn, b"first");
+        buffer.push(Level::Info, b"second");
+
+        let mut out = [0; LINE_SIZE];
+        let mut cursor = LogCursor::new();
+        let line = buffer.read(&mut cursor, &mut out).unwrap();
+        assert_eq!(
+            (line.seq, line.level, &out[..line.len]),
+            (0, Level::Warn, &b"first"[..])
+        );
+        assert_eq!(read_all(&buffer, &mut cursor), lines(&[(1, "second")]));
+
+        // The cursor waits for the next line.
+        assert!(buffer.read(&mut cursor, &mut out).is_none());
+        buffer.push(Level::Error, b"third");
+        assert_eq!(read_all(&buffer, &mut cursor), lines(&[(2, "third")]));
+    }
+
+    #[test]
+    fn lines_wrap_around_the_arena_end() {
+        // Lines of 10 bytes, header included, so the fourth one crosses the
+        // end of the 32 byte arena and the oldest lines are evicted.
+        let mut buffer = LogBuffer::<32>::new();
+        for line in [b"aaaaaaaa", b"bbbbbbbb", b"cccccccc", b"dddddddd"] {
+            buffer.push(Level::Info, line);
+        }
+        assert_eq!(
+            read_all(&buffer, &mut LogCursor::new()),
+            lines(&[(1, "bbbbbbbb"), (2, "cccccccc"), (3, "dddddddd")])
+        );
+
+        // Lines keep wrapping once the start moved past the end too.
+        for line in [b"eeeeeeee", b"ffffffff", b"gggggggg"] {
+            buffer.push(Level::Info, line);
+        }
+        assert_eq!(
+            read_all(&buffer, &mut LogCursor::new()),
+            lines(&[(4, "eeeeeeee"), (5, "ffffffff"), (6, "gggggggg")])
+        );
+    }
+
+    #[test]
+    fn evicted_cursor_restarts_from_the_oldest_line() {
+        let mut buffer = LogBuffer::<32>::new();
+        buffer.push(Level::Info, b"aaaaaaaa");
+        buffer.push(Level::Info, b"bbbbbbbb");
+
+        let mut out = [0; LINE_SIZE];
+        let mut cursor = LogCursor::new();
+        assert_eq!(buffer.read(&mut cursor, &mut out).unwrap().seq, 0);
+
+        // The line following the cursor is evicted while it is streaming.
+        for line in [b"cccccccc", b"dddddddd", b"eeeeeeee"] {
+            buffer.push(Level::Info, line);
+        }
+        assert_eq!(
+            read_all(&buffer, &mut cursor),
+            lines(&[(2, "cccccccc"), (3, "dddddddd"), (4, "eeeeeeee")])
+        );
+    }
+
+    #[test]
+    fn cleared_lines_are_not_read_again() {
+        let mut buffer = LogBuffer::<64>::new();
+        for line in [b"one", b"two", b"six"] {
+            buffer.push(Level::Info, line);
+        }
+        buffer.clear_until(2);
+        assert_eq!(
+            read_all(&buffer, &mut LogCursor::new()),
+            lines(&[(2, "six")])
+        );
+
+        // Clearing up to the next line empties the buffer, and clearing past it
+        // stops there.
+        buffer.clear_until(10);
+        assert!(read_all(&buffer, &mut LogCursor::new()).is_empty());
+        buffer.push(Level::Info, b"ten");
+        assert_eq!(
+            read_all(&buffer, &mut LogCursor::new()),
+            lines(&[(3, "ten")])
+        );
+    }
+
+    #[test]
+    fn sequence_numbers_wrap() {
+        let mut buffer = LogBuffer::<32> {
+            first_seq: u32::MAX - 1,
+            next_seq: u32::MAX - 1,
+            ..LogBuffer::new()
+        };
+        let mut cursor = LogCursor::new();
+        buffer.push(Level::Info, b"aaaaaaaa");
+        buffer.push(Level::Info, b"bbbbbbbb");
+        assert_eq!(
+            read_all(&buffer, &mut cursor),
+            lines(&[(u32::MAX - 1, "aaaaaaaa"), (u32::MAX, "bbbbbbbb")])
+        );
+
+        // The cursor stays valid across the wrap, and so does eviction.
+        buffer.push(Level::Info, b"cccccccc");
+        buffer.push(Level::Info, b"dddddddd");
+        assert_eq!(
+            read_all(&buffer, &mut cursor),
+            lines(&[(0, "cccccccc"), (1, "dddddddd")])
+        );
+        assert_eq!(read_all(&buffer, &mut LogCursor::new())[0].0, u32::MAX);
+
+        buffer.clear_until(1);
+        assert_eq!(
+            read_all(&buffer, &mut LogCursor::new()),
+            lines(&[(1, "dddddddd")])
+        );
+    }
+
+    #[test]
+    fn long_lines_are_truncated() {
+        let mut buffer = LogBuffer::<512>::new();
+        buffer.push(Level::Info, &[b'x'; LINE_SIZE + 40]);
+        buffer.push(Level::Info, &[b'y'; LINE_SIZE]);
+
+        let lines = read_all(&buffer, &mut LogCursor::new());
+        assert_eq!(lines[0].1, "x".repeat(LINE_SIZE));
+        assert_eq!(lines[1].1, "y".repeat(LINE_SIZE));
+    }
+}
@@ -0,0 +1,1088 @@
+use embassy_time::{Duration, Instant};
+
+use log::{error, info, warn};
+
+use crate::fade::FadeRamp;
+use crate::led::{
+    LedDriver, LedInput, Source, DEFAULT_BLINK_PERIOD_MS, FADE_STEP_MS, MAIN_CHANNEL,
+    MAX_BRIGHTNESS,
+};
+use crate::manual_override::ManualOverride;
+use crate::morse::{MorseStep, MorseSteps};
+use crate::pattern::PatternOverride;
+
+// Led state shared with the rest of the firmware, read and updated by the
+// logic, which does not know where it is stored.
+pub trait LedStore {
+    // Brightness percentage of a led channel, 0 when it is off.
+    fn brightness(&self, channel: usize) -> u8;
+
+    fn set_brightness(&mut self, channel: usize, brightness: u8);
+
+    // Index of the brightness preset the button cycle of a led channel is at.
+    fn preset_index(&self, channel: usize) -> usize;
+
+    fn set_preset_index(&mut self, channel: usize, index: usize);
+
+    // Records a switch of the main led, which wears a relay wired in its
+    // place.
+    fn count_switch(&mut self);
+}
+
+// Settings of the led logic, from the device configuration.
+#[derive(Clone, Copy)]
+pub struct LogicConfig {
+    // Fade duration of the inputs which do not request one.
+    pub fade_ms: u64,
+    // Window following a manual change, during which the automations are
+    // ignored. Zero disables it.
+    pub manual_override_ms: u64,
+    // Shortest time between two switches of a relay wired in place of the
+    // main led. Zero when there is no relay.
+    pub min_switch_interval_ms: u64,
+    // Whether early switches of the relay are rejected rather than deferred.
+    pub reject_early_switches: bool,
+}
+
+// Set a led channel to the given brightness percentage.
+//
+// The logical led state is the single source of truth, so it is updated
+// together with the duty cycle. Switches of the main led are counted to
+// estimate the wear of a relay wired in its place.
+pub fn set_led(
+    led: &mut impl LedDriver,
+    store: &mut impl LedStore,
+    channel: usize,
+    brightness: u8,
+) {
+    led.set_level(brightness);
+    if channel == MAIN_CHANNEL && (brightness > 0) != (store.brightness(channel) > 0) {
+        store.count_switch();
+    }
+    store.set_brightness(channel, brightness);
+}
+
+// Start fading a led channel towards the given brightness percentage.
+//
+// The first step is applied immediately, so a zero duration sets the
+// brightness at once. Returns the remaining ramp, if any.
+fn fade_led(
+    led: &mut impl LedDriver,
+    store: &mut impl LedStore,
+    channel: usize,
+    brightness: u8,
+    fade_ms: u64,
+) -> Option<FadeRamp> {
+    let mut ramp = FadeRamp::new(
+        store.brightness(channel),
+        brightness,
+        u32::try_from(fade_ms / FADE_STEP_MS).unwrap_or(u32::MAX),
+    );
+    if let Some(level) = ramp.next() {
+        set_led(led, store, channel, level);
+    }
+    (!ramp.is_finished()).then_some(ramp)
+}
+
+// Set a led channel to on.
+fn led_on(
+    led: &mut impl LedDriver,
+    store: &mut impl LedStore,
+    channel: usize,
+    fade_ms: u64,
+) -> Option<FadeRamp> {
+    info!("Led {channel} is on!");
+    fade_led(led, store, channel, MAX_BRIGHTNESS, fade_ms)
+}
+
+// Set a led channel to off.
+fn led_off(
+    led: &mut impl LedDriver,
+    store: &mut impl LedStore,
+    channel: usize,
+    fade_ms: u64,
+) -> Option<FadeRamp> {
+    info!("Led {channel} is off!");
+    fade_led(led, store, channel, 0, fade_ms)
+}
+
+// Switch the state of a led channel, given the brightness it has or is fading
+// to.
+fn toggle_led(
+    led: &mut impl LedDriver,
+    store: &mut impl LedStore,
+    channel: usize,
+    brightness: u8,
+    fade_ms: u64,
+) -> Option<FadeRamp> {
+    if brightness > 0 {
+        led_off(led, store, channel, fade_ms)
+    } else {
+        led_on(led, store, channel, fade_ms)
+    }
+}
+
+// Maximum number of brightness presets.
+const MAX_BRIGHTNESS_PRESETS: usize = 8;
+// Presets used when none of the configured ones is valid.
+const DEFAULT_BRIGHTNESS_PRESETS: [u8; 4] = [0, 25, 50, 100];
+
+// Brightness percentages cycled through by repeated button presses, in the
+// order they are shown.
+#[derive(Clone)]
+pub struct BrightnessPresets {
+    levels: heapless::Vec<u8, MAX_BRIGHTNESS_PRESETS>,
+}
+
+impl BrightnessPresets {
+    // Parses comma-separated percentages, ignoring the invalid ones.
+    pub fn parse(presets: &str) -> Self {
+        let mut levels = heapless::Vec::new();
+        for preset in presets
+            .split(',')
+            .map(str::trim)
+            .filter(|preset| !preset.is_empty())
+        {
+            match preset.parse() {
+                Ok(level) if level <= MAX_BRIGHTNESS => {
+                    if levels.push(level).is_err() {
+                        error!("Too many brightness presets, {preset} and the rest are ignored");
+                        break;
+                    }
+                }
+                _ => error!("Invalid brightness preset {preset}, expected a percentage"),
+            }
+        }
+        if levels.is_empty() {
+            error!("No valid brightness preset, using the default ones");
+            levels = heapless::Vec::from_slice(&DEFAULT_BRIGHTNESS_PRESETS).unwrap_or_default();
+        }
+        Self { levels }
+    }
+
+    // Brightness of the preset at the given index.
+    fn level(&self, index: usize) -> u8 {
+        self.levels.get(index).copied().unwrap_or(0)
+    }
+
+    // Index of the preset nearest to the given brightness, the first one on
+    // ties.
+    fn nearest(&self, brightness: u8) -> usize {
+        self.levels
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, level)| level.abs_diff(brightness))
+            .map_or(0, |(index, _)| index)
+    }
+
+    // Index of the preset following the one at the given index, which the
+    // led shows, starting over after the last one.
+    //
+    // When the led shows another brightness, such as one set through the
+    // `/brightness` route, the cycle starts again from the nearest preset.
+    fn next(&self, index: usize, brightness: u8) -> usize {
+        let index = if self.level(index) == brightness {
+            index
+        } else {
+            self.nearest(brightness)
+        };
+        (index + 1) % self.levels.len()
+    }
+}
+
+// Whether a led input is applied.
+pub enum Admission {
+    Accept,
+    // The input is dropped.
+    Ignore,
+    // The input is applied once the relay can switch again, at the given
+    // time.
+    Delay(Instant),
+}
+
+// Led state machine, deciding what the led shows for every input and every
+// expired step, while the led task only waits for them.
+//
+// It drives the led through `LedDriver` only, so it does not depend on the
+// hardware, nor on where the led state is stored. Every led channel has its
+// own logic.
+pub struct LedLogic<S> {
+    // Led channel driven by the logic.
+    channel: usize,
+    config: LogicConfig,
+    // Led state shared with the rest of the firmware.
+    store: S,
+    // Blinking period, set only while the led is blinking.
+    blink_period_ms: Option<u64>,
+    // Remaining fade steps, set only while the led is fading.
+    fade: Option<FadeRamp>,
+    // Pattern overriding the led, set only while it is shown.
+    pattern: Option<PatternOverride>,
+    // Morse message overriding the led, set only while it is played, with
+    // the step being shown.
+    morse: Option<(MorseSteps, MorseStep)>,
+    // Deadline of the automatic turn off, if any.
+    auto_off_at: Option<Instant>,
+    // Window following a manual change, during which the automations are
+    // ignored.
+    manual_override: ManualOverride,
+    // Brightness presets cycled through by the button.
+    presets: BrightnessPresets,
+}
+
+impl<S: LedStore> LedLogic<S> {
+    pub const fn new(
+        channel: usize,
+        presets: BrightnessPresets,
+        config: LogicConfig,
+        store: S,
+    ) -> Self {
+        Self {
+            channel,
+            config,
+            store,
+            blink_period_ms: None,
+            fade: None,
+            pattern: None,
+            morse: None,
+            auto_off_at: None,
+            manual_override: ManualOverride::new(config.manual_override_ms),
+            presets,
+        }
+    }
+
+    // Led state the logic reads and updates.
+    pub const fn store(&self) -> &S {
+        &self.store
+    }
+
+    // Deadline of the automatic turn off, if any.
+    pub const fn auto_off_at(&self) -> Option<Instant> {
+        self.auto_off_at
+    }
+
+    // Brightness the led has or is fading to.
+    pub fn target_brightness(&self) -> u8 {
+        self.fade
+            .as_ref()
+            .map_or_else(|| self.brightness(), FadeRamp::end)
+    }
+
+    // Brightness the led has.
+    fn brightness(&self) -> u8 {
+        self.store.brightness(self.channel)
+    }
+
+    // Index of the brightness preset cycling sets, given the brightness the
+    // led has or is fading to.
+    fn next_preset(&self, brightness: u8) -> usize {
+        self.presets
+            .next(self.store.preset_index(self.channel), brightness)
+    }
+
+    // Absolute input amounting to a relative one, such as a toggle, once the
+    // given input has been applied, so it keeps its meaning when held and
+    // replaced by a later input. Absolute inputs are returned unchanged.
+    pub fn resolve(&self, led_input: LedInput, previous: Option<&LedInput>) -> LedInput {
+        // Brightness, blinking and brightness preset once the previous input
+        // has been applied, which is absolute since it has been resolved too.
+        let current = (
+            self.target_brightness(),
+            self.blink_period_ms.is_some(),
+            self.store.preset_index(self.channel),
+        );
+        let (brightness, blinking, preset_index) = match previous {
+            Some(LedInput::On { .. }) => {
+                (MAX_BRIGHTNESS, false, self.presets.nearest(MAX_BRIGHTNESS))
+            }
+            Some(LedInput::Off { .. }) => (0, false, self.presets.nearest(0)),
+            Some(LedInput::Brightness { level, .. }) => {
+                (*level, false, self.presets.nearest(*level))
+            }
+            Some(LedInput::Blink { .. }) => (current.0, true, self.presets.nearest(current.0)),
+            // Overrides stop blinking and leave the rest untouched.
+            Some(LedInput::Pattern(_) | LedInput::Morse(_)) => (current.0, false, current.2),
+            None
+            | Some(
+                LedInput::Toggle
+                | LedInput::Button
+                | LedInput::ToggleBlink
+                | LedInput::CyclePreset
+                | LedInput::Color(_)
+                | LedInput::Reconcile,
+            ) => current,
+        };
+
+        match led_input {
+            LedInput::Toggle | LedInput::Button if brightness > 0 => {
+                LedInput::Off { fade_ms: None }
+            }
+            LedInput::Toggle | LedInput::Button => LedInput::On {
+                fade_ms: None,
+                auto_off_secs: None,
+            },
+            LedInput::CyclePreset => LedInput::Brightness {
+                level: self
+                    .presets
+                    .level(self.presets.next(preset_index, brightness)),
+                fade_ms: None,
+            },
+            // Any input stops blinking, the brightness it had is kept.
+            LedInput::ToggleBlink if blinking => LedInput::Brightness {
+                level: brightness,
+                fade_ms: Some(0),
+            },
+            LedInput::ToggleBlink => LedInput::Blink {
+                period_ms: DEFAULT_BLINK_PERIOD_MS,
+            },
+            led_input => led_input,
+        }
+    }
+
+    // Whether the input switches the led on or off right away, given the
+    // brightness it has or is fading to.
+    fn switches(&self, led_input: &LedInput, brightness: u8) -> bool {
+        if matches!(led_input, LedInput::CyclePreset) {
+            let level = self.presets.level(self.next_preset(brightness));
+            (level > 0) != (brightness > 0)
+        } else {
+            led_input.switches(brightness)
+        }
+    }
+
+    // Whether the led drives a relay, which only the main led can.
+    const fn drives_relay(&self) -> bool {
+        self.channel == MAIN_CHANNEL && self.config.min_switch_interval_ms != 0
+    }
+
+    // Blinking period actually used for the requested one, slowed down for a
+    // relay.
+    fn blink_period_ms(&self, period_ms: u64) -> u64 {
+        if self.drives_relay() {
+            period_ms.max(self.config.min_switch_interval_ms)
+        } else {
+            period_ms
+        }
+    }
+
+    // Time until the next step of the Morse message, the pattern, the fade or
+    // the blinking, in this order of precedence, if any is running.
+    pub fn step_delay_ms(&self) -> Option<u64> {
+        if let Some((_, step)) = &self.morse {
+            Some(step.duration_ms)
+        } else if let Some(shown) = &self.pattern {
+            Some(shown.period_ms())
+        } else if self.fade.is_some() {
+            Some(FADE_STEP_MS)
+        } else {
+            self.blink_period_ms
+        }
+    }
+
+    // Moves the running Morse message, pattern, fade or blinking one step
+    // forward, once the step delay has expired.
+    pub fn on_step(&mut self, led: &mut impl LedDriver, now: Instant) {
+        if let Some((steps, step)) = self.morse.as_mut() {
+            if let Some(next) = steps.next() {
+                led.set(next.on);
+                *step = next;
+            } else {
+                info!("Morse message played!");
+                led.set_level(self.brightness());
+                self.morse = None;
+            }
+        } else if let Some(shown) = self.pattern.as_mut() {
+            match shown.switch(now.as_millis()) {
+                Some(on) => led.set(on),
+                None => {
+                    // Give the led back to the normal logic.
+                    led.set_level(self.brightness());
+                    self.pattern = None;
+                }
+            }
+        } else if let Some(ramp) = self.fade.as_mut() {
+            if let Some(level) = ramp.next() {
+                set_led(led, &mut self.store, self.channel, level);
+            }
+            if ramp.is_finished() {
+                self.fade = None;
+            }
+        } else if self.blink_period_ms.is_some() {
+            let brightness = self.brightness();
+            toggle_led(led, &mut self.store, self.channel, brightness, 0);
+        }
+    }
+
+    // Decides whether an input is applied, given the subsystem which sent it,
+    // whether the chip is too hot and the time left before the relay can
+    // switch again.
+    pub fn admit(
+        &self,
+        led_input: &LedInput,
+        source: Source,
+        overheated: bool,
+        switch_delay: Option<Duration>,
+        now: Instant,
+    ) -> Admission {
+        // Colors and reconciliations change how the led looks, not what it
+        // does.
+        if matches!(led_input, LedInput::Color(_) | LedInput::Reconcile) {
+            return Admission::Accept;
+        }
+
+        // The automations do not undo a recent change made by hand.
+        if source.is_automation()
+            && let Some(remaining_ms) = self.manual_override.remaining_ms(now.as_millis())
+        {
+            info!(
+                "Led changed by hand, {} input ignored for {} s!",
+                source.as_str(),
+                remaining_ms.div_ceil(1000)
+            );
+            return Admission::Ignore;
+        }
+
+        // The led stays off while the chip is too hot.
+        if overheated && led_input.may_light() {
+            warn!("Chip is too hot, led input ignored!");
+            return Admission::Ignore;
+        }
+
+        // A relay must not switch faster than its minimum interval, which
+        // patterns and Morse messages would do.
+        if self.drives_relay() {
+            if matches!(led_input, LedInput::Pattern(_) | LedInput::Morse(_)) {
+                return Admission::Ignore;
+            }
+            if self.switches(led_input, self.target_brightness())
+                && let Some(delay) = switch_delay
+            {
+                if self.config.reject_early_switches {
+                    warn!("Led switched too recently, input rejected!");
+                    return Admission::Ignore;
+                }
+                info!(
+                    "Led switched too recently, input delayed by {} ms!",
+                    delay.as_millis()
+                );
+                return Admission::Delay(now + delay);
+            }
+        }
+
+        Admission::Accept
+    }
+
+    // Applies an admitted input, returning whether the led state changed,
+    // which the overrides and the colors never do.
+    pub fn on_input(
+        &mut self,
+        led: &mut impl LedDriver,
+        led_input: LedInput,
+        source: Source,
+        now: Instant,
+    ) -> bool {
+        // Colors do not stop blinking and fading.
+        if let LedInput::Color(color) = led_input {
+            if led.set_color(color).is_ok() {
+                info!("Led color is {color}!");
+            }
+            return false;
+        }
+
+        // Neither do reconciliations, which show the led state again unless
+        // an override is shown.
+        if matches!(led_input, LedInput::Reconcile) {
+            if self.pattern.is_none() && self.morse.is_none() {
+                led.set_level(self.brightness());
+            }
+            return false;
+        }
+
+        // Any new input stops blinking and fading. An interrupted fade
+        // leaves the led at its current brightness, but toggling considers
+        // the brightness it was fading to.
+        let was_blinking = self.blink_period_ms.take().is_some();
+        let brightness = self
+            .fade
+            .take()
+            .map_or_else(|| self.brightness(), |ramp| ramp.end());
+
+        // Any new input also ends the shown pattern or Morse message,
+        // restoring the led.
+        if self.pattern.take().is_some() | self.morse.take().is_some() {
+            led.set_level(brightness);
+        }
+
+        // Any new input but the overrides cancels the pending auto-off.
+        if !matches!(led_input, LedInput::Pattern(_) | LedInput::Morse(_)) {
+            self.auto_off_at = None;
+        }
+
+        let default_fade_ms = self.config.fade_ms;
+        let cycles = matches!(led_input, LedInput::CyclePreset);
+        match led_input {
+            LedInput::On {
+                fade_ms,
+                auto_off_secs,
+            } => {
+                self.fade = led_on(
+                    led,
+                    &mut self.store,
+                    self.channel,
+                    fade_ms.unwrap_or(default_fade_ms),
+                );
+                self.auto_off_at = auto_off_secs.map(|secs| {
+                    info!("Led turns off in {secs} s!");
+                    now + Duration::from_secs(secs)
+                });
+            }
+            LedInput::Off { fade_ms } => {
+                self.fade = led_off(
+                    led,
+                    &mut self.store,
+                    self.channel,
+                    fade_ms.unwrap_or(default_fade_ms),
+                );
+            }
+            LedInput::Toggle | LedInput::Button => {
+                self.fade = toggle_led(
+                    led,
+                    &mut self.store,
+                    self.channel,
+                    brightness,
+                    default_fade_ms,
+                );
+            }
+            LedInput::Blink { period_ms } => {
+                let period_ms = self.blink_period_ms(period_ms);
+                info!("Led is blinking every {period_ms} ms!");
+                self.blink_period_ms = Some(period_ms);
+            }
+            LedInput::Brightness { level, fade_ms } => {
+                info!("Led brightness is {level}%!");
+                self.fade = fade_led(
+                    led,
+                    &mut self.store,
+                    self.channel,
+                    level,
+                    fade_ms.unwrap_or(default_fade_ms),
+                );
+            }
+            LedInput::CyclePreset => {
+                let index = self.next_preset(brightness);
+                let level = self.presets.level(index);
+                info!("Led brightness preset {index} is {level}%!");
+                self.fade = fade_led(led, &mut self.store, self.channel, level, default_fade_ms);
+                self.store.set_preset_index(self.channel, index);
+            }
+            LedInput::ToggleBlink => {
+                if was_blinking {
+                    info!("Led stopped blinking!");
+                } else {
+                    let period_ms = self.blink_period_ms(DEFAULT_BLINK_PERIOD_MS);
+                    info!("Led is blinking every {period_ms} ms!");
+                    self.blink_period_ms = Some(period_ms);
+                }
+            }
+            LedInput::Pattern(next) => {
+                // Patterns leave the led state untouched. An interrupted fade
+                // jumps to its end.
+                self.store.set_brightness(self.channel, brightness);
+                self.pattern = Some(PatternOverride::new(next, now.as_millis()));
+                led.set(true);
+                return false;
+            }
+            // Colors and reconciliations are applied before stopping
+            // blinking and fading.
+            LedInput::Color(_) | LedInput::Reconcile => return false,
+            LedInput::Morse(message) => {
+                // Like patterns, messages leave the led state untouched.
+                self.store.set_brightness(self.channel, brightness);
+                let mut steps = MorseSteps::new(message);
+                // A parsed message always has a symbol.
+                if let Some(step) = steps.next() {
+                    info!("Led is playing a Morse message!");
+                    led.set(true);
+                    self.morse = Some((steps, step));
+                }
+                return false;
+            }
+        }
+
+        // Any other change moves the cycle to the nearest preset, so the next
+        // press continues from what the led shows.
+        if !cycles {
+            let index = self.presets.nearest(self.target_brightness());
+            self.store.set_preset_index(self.channel, index);
+        }
+
+        if source.is_manual() {
+            self.manual_override.on_manual_change(now.as_millis());
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::led::{ColorUnsupported, Rgb};
+    use crate::pattern::LedPattern;
+
+    #[derive(Default)]
+    struct TestLed {
+        level: u8,
+    }
+
+    impl LedDriver for TestLed {
+        fn set_level(&mut self, brightness: u8) {
+            self.level = brightness;
+        }
+
+        fn set_color(&mut self, _color: Rgb) -> Result<(), ColorUnsupported> {
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct TestStore {
+        brightness: [u8; 2],
+        preset_index: [usize; 2],
+        switches: u32,
+    }
+
+    impl LedStore for TestStore {
+        fn brightness(&self, channel: usize) -> u8 {
+            self.brightness[channel]
+        }
+
+        fn set_brightness(&mut self, channel: usize, brightness: u8) {
+            self.brightness[channel] = brightness;
+        }
+
+        fn preset_index(&self, channel: usize) -> usize {
+            self.preset_index[channel]
+        }
+
+        fn set_preset_index(&mut self, channel: usize, index: usize) {
+            self.preset_index[channel] = index;
+        }
+
+        fn count_switch(&mut self) {
+            self.switches += 1;
+        }
+    }
+
+    const CONFIG: LogicConfig = LogicConfig {
+        fade_ms: 0,
+        manual_override_ms: 0,
+        min_switch_interval_ms: 0,
+        reject_early_switches: false,
+    };
+
+    fn logic(config: LogicConfig) -> LedLogic<TestStore> {
+        LedLogic::new(
+            MAIN_CHANNEL,
+            BrightnessPresets::parse("0,25,50,100"),
+            config,
+            TestStore::default(),
+        )
+    }
+
+    fn at(ms: u64) -> Instant {
+        Instant::from_millis(ms)
+    }
+
+    #[test]
+    fn toggle_switches_on_and_off() {
+        let mut logic = logic(CONFIG);
+        let mut led = TestLed::default();
+
+        assert!(logic.on_input(&mut led, LedInput::Toggle, Source::Http, at(0)));
+        assert_eq!(led.level, 100);
+        assert_eq!(logic.store().brightness(MAIN_CHANNEL), 100);
+
+        assert!(logic.on_input(&mut led, LedInput::Button, Source::Button, at(10)));
+        assert_eq!(led.level, 0);
+        assert_eq!(logic.store().brightness(MAIN_CHANNEL), 0);
+
+        assert!(logic.on_input(&mut led, LedInput::Toggle, Source::Http, at(20)));
+        assert_eq!(led.level, 100);
+        assert_eq!(logic.store().switches, 3);
+        assert_eq!(logic.step_delay_ms(), None);
+    }
+
+    #[test]
+    fn toggle_while_fading_considers_the_target() {
+        let mut logic = logic(LogicConfig {
+            fade_ms: 100,
+            ..CONFIG
+        });
+        let mut led = TestLed::default();
+
+        logic.on_input(&mut led, LedInput::Toggle, Source::Http, at(0));
+        // The first of the ten steps is applied right away.
+        assert_eq!(led.level, 10);
+        assert_eq!(logic.target_brightness(), 100);
+        assert_eq!(logic.step_delay_ms(), Some(FADE_STEP_MS));
+
+        // The led is still dim, but toggling turns it off since it was
+        // fading on.
+        logic.on_input(&mut led, LedInput::Toggle, Source::Http, at(10));
+        assert_eq!(logic.target_brightness(), 0);
+        for step in 1..=10 {
+            logic.on_step(&mut led, at(10 + step * FADE_STEP_MS));
+        }
+        assert_eq!(led.level, 0);
+        assert_eq!(logic.step_delay_ms(), None);
+    }
+
+    #[test]
+    fn blinking_switches_every_period_until_another_input() {
+        let mut logic = logic(CONFIG);
+        let mut led = TestLed::default();
+
+        assert!(logic.on_input(
+            &mut led,
+            LedInput::Blink { period_ms: 200 },
+            Source::Http,
+            at(0)
+        ));
+        assert_eq!(logic.step_delay_ms(), Some(200));
+        logic.on_step(&mut led, at(200));
+        assert_eq!(led.level, 100);
+        logic.on_step(&mut led, at(400));
+        assert_eq!(led.level, 0);
+        logic.on_step(&mut led, at(600));
+        assert_eq!(led.level, 100);
+
+        // Turning the led off cancels the blinking.
+        logic.on_input(
+            &mut led,
+            LedInput::Off { fade_ms: None },
+            Source::Http,
+            at(700),
+        );
+        assert_eq!(logic.step_delay_ms(), None);
+        assert_eq!(led.level, 0);
+    }
+
+    #[test]
+    fn toggle_blink_starts_and_stops_blinking() {
+        let mut logic = logic(CONFIG);
+        let mut led = TestLed::default();
+
+        logic.on_input(&mut led, LedInput::ToggleBlink, Source::Button, at(0));
+        assert_eq!(logic.step_delay_ms(), Some(DEFAULT_BLINK_PERIOD_MS));
+        logic.on_step(&mut led, at(DEFAULT_BLINK_PERIOD_MS));
+        assert_eq!(led.level, 100);
+
+        // The led stays as it is when the blinking stops.
+        logic.on_input(&mut led, LedInput::ToggleBlink, Source::Button, at(600));
+        assert_eq!(logic.step_delay_ms(), None);
+        assert_eq!(led.level, 100);
+    }
+
+    #[test]
+    fn overrides_keep_the_logical_state() {
+        let mut logic = logic(CONFIG);
+        let mut led = TestLed::default();
+
+        logic.on_input(
+            &mut led,
+            LedInput::Off { fade_ms: None },
+            Source::Http,
+            at(0),
+        );
+        let pattern = LedInput::Pattern(LedPattern::Connected);
+        assert!(!logic.on_input(&mut led, pattern, Source::System, at(0)));
+        // The pattern lights the led, while the led stays off for the rest
+        // of the firmware.
+        assert_eq!(led.level, 100);
+        assert_eq!(logic.store().brightness(MAIN_CHANNEL), 0);
+
+        // Toggling follows the logical state, not the output.
+        logic.on_input(&mut led, LedInput::Toggle, Source::Http, at(100));
+        assert_eq!(logic.store().brightness(MAIN_CHANNEL), 100);
+        assert_eq!(logic.step_delay_ms(), None);
+    }
+
+    #[test]
+    fn relay_slows_blinking_down() {
+        let mut logic = logic(LogicConfig {
+            min_switch_interval_ms: 1000,
+            ..CONFIG
+        });
+        let mut led = TestLed::default();
+
+        logic.on_input(
+            &mut led,
+            LedInput::Blink { period_ms: 200 },
+            Source::Http,
+            at(0),
+        );
+        assert_eq!(logic.step_delay_ms(), Some(1000));
+    }
+
+    #[test]
+    fn auto_off_is_set_by_on_and_cancelled_by_other_inputs() {
+        let mut logic = logic(CONFIG);
+        let mut led = TestLed::default();
+
+        let on = LedInput::On {
+            fade_ms: None,
+            auto_off_secs: Some(5),
+        };
+        logic.on_input(&mut led, on, Source::Http, at(1000));
+        assert_eq!(logic.auto_off_at(), Some(at(6000)));
+
+        // Overrides keep the deadline.
+        logic.on_input(
+            &mut led,
+            LedInput::Pattern(LedPattern::Connected),
+            Source::System,
+            at(2000),
+        );
+        assert_eq!(logic.auto_off_at(), Some(at(6000)));
+
+        // Any other input cancels it.
+        logic.on_input(
+            &mut led,
+            LedInput::Brightness {
+                level: 50,
+                fade_ms: None,
+            },
+            Source::Http,
+            at(3000),
+        );
+        assert_eq!(logic.auto_off_at(), None);
+        assert_eq!(led.level, 50);
+    }
+
+    #[test]
+    fn auto_off_expiry_turns_the_led_off() {
+        let mut logic = logic(CONFIG);
+        let mut led = TestLed::default();
+
+        let on = LedInput::On {
+            fade_ms: None,
+            auto_off_secs: Some(1),
+        };
+        logic.on_input(&mut led, on, Source::Http, at(0));
+        let deadline = logic.auto_off_at().unwrap();
+
+        // The led task sends `Off` once the deadline expires.
+        assert!(logic.on_input(
+            &mut led,
+            LedInput::Off { fade_ms: None },
+            Source::System,
+            deadline
+        ));
+        assert_eq!(led.level, 0);
+        assert_eq!(logic.auto_off_at(), None);
+    }
+
+    #[test]
+    fn presets_skip_invalid_entries() {
+        let presets = BrightnessPresets::parse("10, abc, 150,, 60");
+        assert_eq!(presets.levels.as_slice(), &[10, 60]);
+
+        let presets = BrightnessPresets::parse("abc");
+        assert_eq!(presets.levels.as_slice(), &DEFAULT_BRIGHTNESS_PRESETS);
+    }
+
+    #[test]
+    fn presets_wrap_around_after_the_last_one() {
+        let presets = BrightnessPresets::parse("0,25,50,100");
+
+        assert_eq!(presets.next(0, 0), 1);
+        assert_eq!(presets.next(2, 50), 3);
+        assert_eq!(presets.next(3, 100), 0);
+    }
+
+    #[test]
+    fn presets_resync_to_the_nearest_one() {
+        let presets = BrightnessPresets::parse("0,25,50,100");
+
+        // The led shows 40%, nearer to 50% than to 25%.
+        assert_eq!(presets.nearest(40), 2);
+        assert_eq!(presets.next(0, 40), 3);
+        // Ties pick the first preset.
+        assert_eq!(presets.nearest(75), 2);
+        assert_eq!(presets.next(1, 75), 3);
+    }
+
+    #[test]
+    fn cycling_continues_from_what_the_led_shows() {
+        let mut logic = logic(CONFIG);
+        let mut led = TestLed::default();
+
+        for level in [25, 50, 100, 0, 25] {
+            logic.on_input(&mut led, LedInput::CyclePreset, Source::Button, at(0));
+            assert_eq!(led.level, level);
+        }
+
+        // Another input moves the cycle to the nearest preset.
+        let brightness = LedInput::Brightness {
+            level: 90,
+            fade_ms: None,
+        };
+        logic.on_input(&mut led, brightness, Source::Http, at(0));
+        assert_eq!(logic.store().preset_index(MAIN_CHANNEL), 3);
+        logic.on_input(&mut led, LedInput::CyclePreset, Source::Button, at(0));
+        assert_eq!(led.level, 0);
+    }
+
+    #[test]
+    fn automations_are_ignored_after_a_manual_change() {
+        let mut logic = logic(LogicConfig {
+            manual_override_ms: 60_000,
+            ..CONFIG
+        });
+        let mut led = TestLed::default();
+        let on = LedInput::On {
+            fade_ms: None,
+            auto_off_secs: None,
+        };
+
+        // Automations are applied until someone changes the led.
+        assert!(matches!(
+            logic.admit(&on, Source::Schedule, false, None, at(0)),
+            Admission::Accept
+        ));
+        logic.on_input(&mut led, on, Source::Schedule, at(0));
+
+        logic.on_input(&mut led, LedInput::Toggle, Source::Button, at(1000));
+        let off = LedInput::Off { fade_ms: None };
+        for source in [Source::Schedule, Source::Motion] {
+            assert!(matches!(
+                logic.admit(&off, source, false, None, at(30_000)),
+                Admission::Ignore
+            ));
+        }
+        // Manual inputs are never held back.
+        assert!(matches!(
+            logic.admit(&off, Source::Http, false, None, at(30_000)),
+            Admission::Accept
+        ));
+        // The window ends a minute after the change.
+        assert!(matches!(
+            logic.admit(&on, Source::Schedule, false, None, at(61_000)),
+            Admission::Accept
+        ));
+    }
+
+    #[test]
+    fn relay_delays_or_rejects_early_switches() {
+        let delay = Some(Duration::from_millis(300));
+        let delaying = logic(LogicConfig {
+            min_switch_interval_ms: 1000,
+            ..CONFIG
+        });
+        assert!(matches!(
+            delaying.admit(&LedInput::Toggle, Source::Http, false, delay, at(100)),
+            Admission::Delay(until) if until == at(400)
+        ));
+        // Inputs which do not switch the led are applied right away.
+        assert!(matches!(
+            delaying.admit(
+                &LedInput::Off { fade_ms: None },
+                Source::Http,
+                false,
+                delay,
+                at(0)
+            ),
+            Admission::Accept
+        ));
+
+        let rejecting = logic(LogicConfig {
+            min_switch_interval_ms: 1000,
+            reject_early_switches: true,
+            ..CONFIG
+        });
+        assert!(matches!(
+            rejecting.admit(&LedInput::Toggle, Source::Http, false, delay, at(0)),
+            Admission::Ignore
+        ));
+    }
+
+    #[test]
+    fn relative_inputs_are_resolved_after_the_previous_input() {
+        let mut logic = logic(CONFIG);
+        let mut led = TestLed::default();
+
+        // Without a previous input, the current state is toggled.
+        assert!(matches!(
+            logic.resolve(LedInput::Toggle, None),
+            LedInput::On { .. }
+        ));
+        let on = LedInput::On {
+            fade_ms: None,
+            auto_off_secs: None,
+        };
+        assert!(matches!(
+            logic.resolve(LedInput::Button, Some(&on)),
+            LedInput::Off { .. }
+        ));
+
+        logic.on_input(&mut led, on, Source::Http, at(0));
+        assert!(matches!(
+            logic.resolve(LedInput::Toggle, None),
+            LedInput::Off { .. }
+        ));
+        assert!(matches!(
+            logic.resolve(LedInput::Toggle, Some(&LedInput::Off { fade_ms: None })),
+            LedInput::On { .. }
+        ));
+    }
+
+    #[test]
+    fn resolved_cycle_continues_from_the_previous_input() {
+        let logic = logic(CONFIG);
+
+        assert!(matches!(
+            logic.resolve(LedInput::CyclePreset, None),
+            LedInput::Brightness { level: 25, .. }
+        ));
+        let half = LedInput::Brightness {
+            level: 50,
+            fade_ms: None,
+        };
+        assert!(matches!(
+            logic.resolve(LedInput::CyclePreset, Some(&half)),
+            LedInput::Brightness { level: 100, .. }
+        ));
+    }
+
+    #[test]
+    fn resolved_blink_toggle_depends_on_the_previous_input() {
+        let logic = logic(CONFIG);
+
+        assert!(matches!(
+            logic.resolve(LedInput::ToggleBlink, None),
+            LedInput::Blink {
+                period_ms: DEFAULT_BLINK_PERIOD_MS
+            }
+        ));
+        assert!(matches!(
+            logic.resolve(
+                LedInput::ToggleBlink,
+                Some(&LedInput::Blink { period_ms: 200 })
+            ),
+            LedInput::Brightness {
+                level: 0,
+                fade_ms: Some(0)
+            }
+        ));
+    }
+
+    #[test]
+    fn absolute_inputs_are_not_resolved() {
+        let logic = logic(CONFIG);
+
+        assert!(matches!(
+            logic.resolve(LedInput::Off { fade_ms: Some(5) }, Some(&LedInput::Toggle)),
+            LedInput::Off { fade_ms: Some(5) }
+        ));
+    }
+}
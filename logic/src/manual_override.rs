@@ -0,0 +1,69 @@
+// Keeps the automations from undoing a change made by hand, such as the
+// schedule turning the led back on right after it was switched off with the
+// button, for a window following every manual change.
+//
+// A zero window disables the override.
+pub struct ManualOverride {
+    window_ms: u64,
+    // Timestamp of the last manual change, if any.
+    changed_at_ms: Option<u64>,
+}
+
+impl ManualOverride {
+    pub const fn new(window_ms: u64) -> Self {
+        Self {
+            window_ms,
+            changed_at_ms: None,
+        }
+    }
+
+    // Feeds a change of the led made by hand.
+    pub fn on_manual_change(&mut self, now_ms: u64) {
+        if self.window_ms > 0 {
+            self.changed_at_ms = Some(now_ms);
+        }
+    }
+
+    // Time left before the automations can change the led again, if they
+    // cannot yet.
+    pub fn remaining_ms(&self, now_ms: u64) -> Option<u64> {
+        let changed_at_ms = self.changed_at_ms?;
+        let elapsed_ms = now_ms.saturating_sub(changed_at_ms);
+        (elapsed_ms < self.window_ms).then(|| self.window_ms - elapsed_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_starts_at_the_manual_change() {
+        let mut manual_override = ManualOverride::new(1000);
+        assert_eq!(manual_override.remaining_ms(0), None);
+
+        manual_override.on_manual_change(500);
+        assert_eq!(manual_override.remaining_ms(500), Some(1000));
+        assert_eq!(manual_override.remaining_ms(1200), Some(300));
+    }
+
+    #[test]
+    fn window_expires_and_restarts_with_every_change() {
+        let mut manual_override = ManualOverride::new(1000);
+
+        manual_override.on_manual_change(0);
+        assert_eq!(manual_override.remaining_ms(999), Some(1));
+        assert_eq!(manual_override.remaining_ms(1000), None);
+
+        manual_override.on_manual_change(1500);
+        assert_eq!(manual_override.remaining_ms(2000), Some(500));
+    }
+
+    #[test]
+    fn zero_window_disables_the_override() {
+        let mut manual_override = ManualOverride::new(0);
+
+        manual_override.on_manual_change(0);
+        assert_eq!(manual_override.remaining_ms(0), None);
+    }
+}
@@ -0,0 +1,222 @@
+use core::fmt;
+
+// Longest message played by the `/morse` route.
+pub const MAX_MORSE_LEN: usize = 32;
+// Duration of a dot, the unit of every other Morse timing.
+const UNIT_MS: u64 = 150;
+// Durations, in units, of the symbols and of the gaps following them.
+const DOT_UNITS: u64 = 1;
+const DASH_UNITS: u64 = 3;
+const SYMBOL_GAP_UNITS: u64 = 1;
+const LETTER_GAP_UNITS: u64 = 3;
+const WORD_GAP_UNITS: u64 = 7;
+
+// Codes of the letters, from `A` to `Z`.
+const LETTERS: [&[u8]; 26] = [
+    b".-", b"-...", b"-.-.", b"-..", b".", b"..-.", b"--.", b"....", b"..", b".---", b"-.-",
+    b".-..", b"--", b"-.", b"---", b".--.", b"--.-", b".-.", b"...", b"-", b"..-", b"...-", b".--",
+    b"-..-", b"-.--", b"--..",
+];
+// Codes of the digits, from `0` to `9`.
+const DIGITS: [&[u8]; 10] = [
+    b"-----", b".----", b"..---", b"...--", b"....-", b".....", b"-....", b"--...", b"---..",
+    b"----.",
+];
+
+// Code of an uppercase letter or a digit.
+fn code(character: u8) -> Option<&'static [u8]> {
+    match character {
+        b'A'..=b'Z' => Some(LETTERS[usize::from(character - b'A')]),
+        b'0'..=b'9' => Some(DIGITS[usize::from(character - b'0')]),
+        _ => None,
+    }
+}
+
+// Errors arising while parsing a Morse message.
+pub enum MorseError {
+    // The message has no letters nor digits.
+    Empty,
+    TooLong,
+    // The message contains a character which has no Morse code.
+    InvalidCharacter(char),
+}
+
+impl fmt::Display for MorseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "Message must contain letters or digits"),
+            Self::TooLong => write!(f, "Message must be at most {MAX_MORSE_LEN} characters"),
+            Self::InvalidCharacter(character) => write!(
+                f,
+                "Invalid character {character:?}, expected letters, digits and spaces"
+            ),
+        }
+    }
+}
+
+// Message made of letters, digits and spaces, stored uppercase.
+#[derive(Clone, Copy)]
+pub struct MorseMessage {
+    bytes: [u8; MAX_MORSE_LEN],
+    len: usize,
+}
+
+impl MorseMessage {
+    pub fn parse(message: &str) -> Result<Self, MorseError> {
+        if let Some(character) = message
+            .chars()
+            .find(|character| *character != ' ' && !character.is_ascii_alphanumeric())
+        {
+            return Err(MorseError::InvalidCharacter(character));
+        }
+        if message.len() > MAX_MORSE_LEN {
+            return Err(MorseError::TooLong);
+        }
+        if message.trim().is_empty() {
+            return Err(MorseError::Empty);
+        }
+
+        let mut bytes = [0; MAX_MORSE_LEN];
+        bytes[..message.len()].copy_from_slice(message.as_bytes());
+        bytes.make_ascii_uppercase();
+        Ok(Self {
+            bytes,
+            len: message.len(),
+        })
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+// Led state held for the given time during a Morse playback.
+#[derive(Clone, Copy)]
+pub struct MorseStep {
+    pub on: bool,
+    pub duration_ms: u64,
+}
+
+impl MorseStep {
+    const fn on(units: u64) -> Self {
+        Self {
+            on: true,
+            duration_ms: units * UNIT_MS,
+        }
+    }
+
+    const fn off(units: u64) -> Self {
+        Self {
+            on: false,
+            duration_ms: units * UNIT_MS,
+        }
+    }
+}
+
+// Steps playing a message, alternating the symbols with the gaps between
+// them. Spaces separate words, and the playback ends with the last symbol.
+pub struct MorseSteps {
+    message: MorseMessage,
+    // Position of the character being played, and of its next symbol.
+    index: usize,
+    symbol: usize,
+    // Gap following the last played symbol, in units.
+    gap_units: u64,
+}
+
+impl MorseSteps {
+    pub const fn new(message: MorseMessage) -> Self {
+        Self {
+            message,
+            index: 0,
+            symbol: 0,
+            gap_units: 0,
+        }
+    }
+}
+
+impl Iterator for MorseSteps {
+    type Item = MorseStep;
+
+    fn next(&mut self) -> Option<MorseStep> {
+        if self.gap_units > 0 {
+            return Some(MorseStep::off(core::mem::take(&mut self.gap_units)));
+        }
+
+        let bytes = self.message.as_bytes();
+        // Spaces are played as the gap which precedes the next word.
+        while bytes.get(self.index) == Some(&b' ') {
+            self.index += 1;
+        }
+        let code = code(*bytes.get(self.index)?)?;
+
+        let symbol = code[self.symbol];
+        self.symbol += 1;
+        self.gap_units = if self.symbol < code.len() {
+            SYMBOL_GAP_UNITS
+        } else {
+            self.symbol = 0;
+            self.index += 1;
+            match bytes[self.index..].iter().position(|byte| *byte != b' ') {
+                // The message ends with this symbol.
+                None => 0,
+                Some(0) => LETTER_GAP_UNITS,
+                Some(_) => WORD_GAP_UNITS,
+            }
+        };
+
+        Some(MorseStep::on(if symbol == b'.' {
+            DOT_UNITS
+        } else {
+            DASH_UNITS
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timings(message: &str) -> Vec<(bool, u64)> {
+        let message = MorseMessage::parse(message).ok().unwrap();
+        MorseSteps::new(message)
+            .map(|step| (step.on, step.duration_ms / UNIT_MS))
+            .collect()
+    }
+
+    #[test]
+    fn invalid_messages_are_rejected() {
+        assert!(matches!(MorseMessage::parse("  "), Err(MorseError::Empty)));
+        assert!(matches!(
+            MorseMessage::parse("SOS!"),
+            Err(MorseError::InvalidCharacter('!'))
+        ));
+        let long = "A".repeat(MAX_MORSE_LEN + 1);
+        assert!(matches!(
+            MorseMessage::parse(&long),
+            Err(MorseError::TooLong)
+        ));
+    }
+
+    #[test]
+    fn letters_alternate_symbols_and_gaps() {
+        // `A` is a dot and a dash, lowercase letters are uppercased.
+        assert_eq!(timings("a"), [(true, 1), (false, 1), (true, 3)]);
+    }
+
+    #[test]
+    fn letters_and_words_are_separated_by_longer_gaps() {
+        assert_eq!(timings("E T"), [(true, 1), (false, 7), (true, 3)]);
+        assert_eq!(timings(" EE "), [(true, 1), (false, 3), (true, 1)]);
+    }
+
+    #[test]
+    fn digits_have_five_symbols() {
+        let steps = timings("0");
+        assert_eq!(steps.iter().filter(|(on, _)| *on).count(), 5);
+        assert!(steps
+            .iter()
+            .filter(|(on, _)| *on)
+            .all(|(_, units)| *units == 3));
+    }
+}
@@ -0,0 +1,218 @@
+// Led patterns showing the boot progress and the network state.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LedPattern {
+    // The firmware is starting, flash three times quickly so the led can be
+    // seen working.
+    SelfTest,
+    // Wi-Fi is not connected, pulse slowly.
+    Connecting,
+    // Wi-Fi is connected, but the IP address is missing, blink slowly.
+    WaitingForIp,
+    // The IP address has been acquired, flash three times quickly.
+    Connected,
+    // The Wi-Fi signal is weak, flash twice slowly.
+    WeakSignal,
+    // The device is about to restart because it stayed offline, flash five
+    // times quickly.
+    OfflineReboot,
+    // The firmware failed to start, flash the given number of times, at
+    // least once, pause and repeat until the device is restarted.
+    Fault(u32),
+}
+
+// Pause between two groups of flashes of the fault pattern.
+const FAULT_PAUSE_MS: u64 = 1500;
+// Time the led stays off between two pulses of the connecting pattern.
+const CONNECTING_PAUSE_MS: u64 = 850;
+
+impl LedPattern {
+    // Interval between two led switches, the time the led stays on for the
+    // patterns pausing between their flashes.
+    const fn period_ms(self) -> u64 {
+        match self {
+            Self::SelfTest => 100,
+            Self::Connecting => 150,
+            Self::WaitingForIp => 500,
+            Self::Connected => 80,
+            Self::WeakSignal => 300,
+            Self::OfflineReboot => 60,
+            Self::Fault(_) => 200,
+        }
+    }
+
+    // Number of led switches after which the pattern ends, if any.
+    const fn switches(self) -> Option<u32> {
+        match self {
+            Self::Connecting | Self::WaitingForIp | Self::Fault(_) => None,
+            // On and off three times.
+            Self::SelfTest | Self::Connected => Some(6),
+            // On and off twice.
+            Self::WeakSignal => Some(4),
+            // On and off five times.
+            Self::OfflineReboot => Some(10),
+        }
+    }
+
+    // Time the pattern takes to play, if it ends by itself.
+    pub const fn duration_ms(self) -> Option<u64> {
+        match self.switches() {
+            Some(switches) => Some(switches as u64 * self.period_ms()),
+            None => None,
+        }
+    }
+}
+
+// Number of led switches of a group of flashes of the fault pattern, on and
+// off once for every flash.
+const fn group_switches(flashes: u32) -> u32 {
+    if flashes == 0 {
+        2
+    } else {
+        flashes.saturating_mul(2)
+    }
+}
+
+// Endless patterns expire after this time, unless they are sent again, so a
+// missed network event never keeps the led blinking forever.
+const PATTERN_TIMEOUT_MS: u64 = 60_000;
+
+// Pattern temporarily overriding the led.
+pub struct PatternOverride {
+    pattern: LedPattern,
+    // Whether the pattern currently has the led on.
+    on: bool,
+    switches: u32,
+    expires_at_ms: u64,
+}
+
+impl PatternOverride {
+    // Starts a pattern with the led on, given the current timestamp.
+    pub const fn new(pattern: LedPattern, now_ms: u64) -> Self {
+        Self {
+            pattern,
+            on: true,
+            switches: 0,
+            expires_at_ms: now_ms + PATTERN_TIMEOUT_MS,
+        }
+    }
+
+    // Time the led stays in its current state.
+    pub const fn period_ms(&self) -> u64 {
+        match self.pattern {
+            LedPattern::Connecting if !self.on => CONNECTING_PAUSE_MS,
+            // The led stays off longer after the last flash of a group.
+            LedPattern::Fault(flashes)
+                if self.switches % group_switches(flashes) == group_switches(flashes) - 1 =>
+            {
+                FAULT_PAUSE_MS
+            }
+            pattern => pattern.period_ms(),
+        }
+    }
+
+    // Switches the led once the period expires, returning whether it is on,
+    // or `None` when the pattern has ended.
+    pub fn switch(&mut self, now_ms: u64) -> Option<bool> {
+        self.switches += 1;
+        let finished = self
+            .pattern
+            .switches()
+            .is_some_and(|switches| self.switches >= switches);
+        // A fault is shown until the device is restarted.
+        let expired = !matches!(self.pattern, LedPattern::Fault(_)) && now_ms >= self.expires_at_ms;
+        if finished || expired {
+            return None;
+        }
+
+        self.on = !self.on;
+        Some(self.on)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Led state and period of the pattern for the given number of switches,
+    // starting from the first flash.
+    fn play(pattern: LedPattern, switches: usize) -> Vec<(bool, u64)> {
+        let mut state = PatternOverride::new(pattern, 0);
+        let mut played = vec![(true, state.period_ms())];
+        for _ in 1..switches {
+            let on = state.switch(0).unwrap();
+            played.push((on, state.period_ms()));
+        }
+        played
+    }
+
+    #[test]
+    fn fault_pauses_after_every_group_of_flashes() {
+        let group = [
+            (true, 200),
+            (false, 200),
+            (true, 200),
+            (false, 200),
+            (true, 200),
+            (false, FAULT_PAUSE_MS),
+        ];
+        assert_eq!(play(LedPattern::Fault(3), 12), [group, group].concat());
+
+        let single = [(true, 200), (false, FAULT_PAUSE_MS)];
+        assert_eq!(play(LedPattern::Fault(1), 4), [single, single].concat());
+    }
+
+    #[test]
+    fn fault_flashes_at_least_once() {
+        assert_eq!(play(LedPattern::Fault(0), 4), play(LedPattern::Fault(1), 4));
+
+        // Counts too large to double never pause.
+        assert!(play(LedPattern::Fault(u32::MAX), 100)
+            .iter()
+            .all(|(_, period_ms)| *period_ms == 200));
+    }
+
+    #[test]
+    fn faults_never_expire() {
+        let mut fault = PatternOverride::new(LedPattern::Fault(2), 0);
+        for now_ms in [PATTERN_TIMEOUT_MS, 10 * PATTERN_TIMEOUT_MS, u64::MAX] {
+            assert!(fault.switch(now_ms).is_some());
+        }
+    }
+
+    #[test]
+    fn endless_patterns_expire() {
+        for pattern in [LedPattern::Connecting, LedPattern::WaitingForIp] {
+            let mut state = PatternOverride::new(pattern, 1000);
+            assert_eq!(state.switch(1000 + PATTERN_TIMEOUT_MS - 1), Some(false));
+            assert_eq!(state.switch(1000 + PATTERN_TIMEOUT_MS), None);
+        }
+    }
+
+    #[test]
+    fn finite_patterns_end_after_their_flashes() {
+        let mut state = PatternOverride::new(LedPattern::WeakSignal, 0);
+        assert_eq!(state.switch(0), Some(false));
+        assert_eq!(state.switch(0), Some(true));
+        assert_eq!(state.switch(0), Some(false));
+        assert_eq!(state.switch(0), None);
+        assert_eq!(LedPattern::WeakSignal.duration_ms(), Some(1200));
+
+        assert_eq!(LedPattern::SelfTest.duration_ms(), Some(600));
+        assert_eq!(LedPattern::Connected.duration_ms(), Some(480));
+        assert_eq!(LedPattern::OfflineReboot.duration_ms(), Some(600));
+        assert_eq!(LedPattern::Fault(3).duration_ms(), None);
+    }
+
+    #[test]
+    fn connecting_pauses_between_pulses() {
+        assert_eq!(
+            play(LedPattern::Connecting, 4),
+            [
+                (true, 150),
+                (false, CONNECTING_PAUSE_MS),
+                (true, 150),
+                (false, CONNECTING_PAUSE_MS)
+            ]
+        );
+    }
+}
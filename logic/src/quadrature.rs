@@ -0,0 +1,165 @@
+// Quadrature transitions between two detents of a common encoder.
+const TRANSITIONS_PER_DETENT: i8 = 4;
+// State of the contacts at the detents: both open, so both high with the
+// pull-ups.
+const REST_STATE: u8 = 0b11;
+
+// Direction of every transition, indexed by the previous and the current
+// state, two bits each. Invalid transitions, skipping a state because of a
+// bounce or a missed edge, and repeated states count as no movement.
+const TRANSITIONS: [i8; 16] = [0, 1, -1, 0, -1, 0, 0, 1, 1, 0, 0, -1, 0, -1, 1, 0];
+
+// Detents closer than these intervals, in milliseconds, are fast rotations,
+// changing the brightness by larger steps.
+const FAST_DETENT_MS: u64 = 40;
+const MEDIUM_DETENT_MS: u64 = 120;
+// Brightness steps, in percent, of slow, medium and fast rotations.
+const SLOW_STEP: u8 = 1;
+const MEDIUM_STEP: u8 = 5;
+const FAST_STEP: u8 = 10;
+
+// Quadrature decoder, turning the Gray-code states of the two encoder
+// contacts into detents.
+//
+// Detents are reported only when the contacts are back at rest, and the
+// transitions are counted from there, so a missed edge or a decoder started
+// between two detents never shifts the following ones. A bouncing contact
+// only moves back and forth between two adjacent states, whose transitions
+// cancel out, so it never produces spurious detents.
+pub struct QuadratureDecoder {
+    state: u8,
+    transitions: i8,
+}
+
+impl QuadratureDecoder {
+    // Creates a decoder starting from the given contact levels.
+    pub const fn new(a: bool, b: bool) -> Self {
+        Self {
+            state: Self::state(a, b),
+            transitions: 0,
+        }
+    }
+
+    const fn state(a: bool, b: bool) -> u8 {
+        (a as u8) << 1 | b as u8
+    }
+
+    // Feeds the contact levels, returning 1 or -1 once a detent is reached
+    // clockwise or counterclockwise.
+    pub fn update(&mut self, a: bool, b: bool) -> Option<i8> {
+        let state = Self::state(a, b);
+        self.transitions += TRANSITIONS[usize::from(self.state << 2 | state)];
+        self.state = state;
+        if state != REST_STATE {
+            return None;
+        }
+
+        // A rotation missing an edge counts half the transitions, while
+        // leaving the rest state and going back counts none.
+        let transitions = core::mem::take(&mut self.transitions);
+        (transitions.abs() >= TRANSITIONS_PER_DETENT / 2).then(|| transitions.signum())
+    }
+}
+
+// Brightness step of a detent, given the time since the previous one.
+pub const fn step(since_last_ms: u64) -> u8 {
+    if since_last_ms < FAST_DETENT_MS {
+        FAST_STEP
+    } else if since_last_ms < MEDIUM_DETENT_MS {
+        MEDIUM_STEP
+    } else {
+        SLOW_STEP
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Contact levels of a clockwise detent, from rest to rest.
+    const CLOCKWISE: [(bool, bool); 4] =
+        [(true, false), (false, false), (false, true), (true, true)];
+
+    fn feed(decoder: &mut QuadratureDecoder, levels: &[(bool, bool)]) -> Vec<Option<i8>> {
+        levels.iter().map(|&(a, b)| decoder.update(a, b)).collect()
+    }
+
+    #[test]
+    fn table_is_antisymmetric() {
+        for previous in 0..4 {
+            for current in 0..4 {
+                assert_eq!(
+                    TRANSITIONS[previous << 2 | current],
+                    -TRANSITIONS[current << 2 | previous]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn table_counts_one_step_per_gray_code_transition() {
+        // Clockwise Gray-code sequence of the contact states.
+        let sequence: [u8; 4] = [0b11, 0b10, 0b00, 0b01];
+        for (index, &previous) in sequence.iter().enumerate() {
+            let next = sequence[(index + 1) % 4];
+            let skipped = sequence[(index + 2) % 4];
+            assert_eq!(TRANSITIONS[usize::from(previous << 2 | next)], 1);
+            assert_eq!(TRANSITIONS[usize::from(previous << 2 | skipped)], 0);
+            assert_eq!(TRANSITIONS[usize::from(previous << 2 | previous)], 0);
+        }
+    }
+
+    #[test]
+    fn detents_are_reported_at_rest() {
+        let mut decoder = QuadratureDecoder::new(true, true);
+
+        assert_eq!(feed(&mut decoder, &CLOCKWISE), [None, None, None, Some(1)]);
+
+        let mut counterclockwise = CLOCKWISE;
+        counterclockwise[..3].reverse();
+        assert_eq!(
+            feed(&mut decoder, &counterclockwise),
+            [None, None, None, Some(-1)]
+        );
+    }
+
+    #[test]
+    fn bounces_report_no_detent() {
+        let mut decoder = QuadratureDecoder::new(true, true);
+
+        let bounces = [(true, false), (true, true), (true, false), (true, true)];
+        assert_eq!(feed(&mut decoder, &bounces), [None; 4]);
+        // Going halfway and back is no detent either.
+        let back = [(true, false), (false, false), (true, false), (true, true)];
+        assert_eq!(feed(&mut decoder, &back), [None; 4]);
+    }
+
+    #[test]
+    fn missed_edge_does_not_shift_the_next_detents() {
+        let mut decoder = QuadratureDecoder::new(true, true);
+
+        // The `(false, false)` state is missed.
+        let missed = [(true, false), (false, true), (true, true)];
+        assert_eq!(feed(&mut decoder, &missed), [None, None, Some(1)]);
+        assert_eq!(feed(&mut decoder, &CLOCKWISE), [None, None, None, Some(1)]);
+    }
+
+    #[test]
+    fn decoder_started_between_detents_aligns_at_rest() {
+        let mut decoder = QuadratureDecoder::new(false, false);
+
+        feed(&mut decoder, &[(false, true), (true, true)]);
+        assert_eq!(feed(&mut decoder, &CLOCKWISE), [None, None, None, Some(1)]);
+    }
+
+    #[test]
+    fn faster_rotations_take_larger_steps() {
+        assert_eq!(step(0), FAST_STEP);
+        assert_eq!(step(FAST_DETENT_MS - 1), FAST_STEP);
+        assert_eq!(step(FAST_DETENT_MS), MEDIUM_STEP);
+        assert_eq!(step(MEDIUM_DETENT_MS - 1), MEDIUM_STEP);
+        assert_eq!(step(MEDIUM_DETENT_MS), SLOW_STEP);
+        // The first detent, with no previous one, is slow.
+        assert_eq!(step(u64::MAX), SLOW_STEP);
+    }
+}
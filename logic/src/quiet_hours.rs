@@ -0,0 +1,59 @@
+use crate::led::MAX_BRIGHTNESS;
+use crate::schedule::TimeOfDay;
+
+// Size of the encoded quiet hours: whether they are enabled, their start and
+// end times and their brightness.
+pub const QUIET_HOURS_SIZE: usize = 6;
+
+// Daily window during which the led output is kept off, or dimmed, while the
+// led state keeps following the inputs. The status led stays off as well.
+//
+// Times are local, as given by the configured timezone offset. A window
+// ending before it starts crosses midnight, while an empty one never starts.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct QuietHours {
+    pub enabled: bool,
+    pub start: TimeOfDay,
+    pub end: TimeOfDay,
+    // Highest brightness percentage shown, 0 keeps the led off.
+    pub brightness: u8,
+}
+
+impl QuietHours {
+    pub const DISABLED: Self = Self {
+        enabled: false,
+        start: TimeOfDay::MIDNIGHT,
+        end: TimeOfDay::MIDNIGHT,
+        brightness: 0,
+    };
+
+    // Whether the window contains the given time of the day.
+    pub fn contains(&self, time: TimeOfDay) -> bool {
+        let (start, end, time) = (self.start.minutes(), self.end.minutes(), time.minutes());
+        if start <= end {
+            (start..end).contains(&time)
+        } else {
+            time >= start || time < end
+        }
+    }
+
+    pub fn encode(&self, bytes: &mut [u8; QUIET_HOURS_SIZE]) {
+        bytes[0] = u8::from(self.enabled);
+        bytes[1..3].copy_from_slice(&self.start.minutes().to_le_bytes());
+        bytes[3..5].copy_from_slice(&self.end.minutes().to_le_bytes());
+        bytes[5] = self.brightness;
+    }
+
+    pub fn decode(bytes: &[u8; QUIET_HOURS_SIZE]) -> Option<Self> {
+        let brightness = bytes[5];
+        if brightness > MAX_BRIGHTNESS {
+            return None;
+        }
+        Some(Self {
+            enabled: bytes[0] != 0,
+            start: TimeOfDay::from_minutes(u16::from_le_bytes([bytes[1], bytes[2]]))?,
+            end: TimeOfDay::from_minutes(u16::from_le_bytes([bytes[3], bytes[4]]))?,
+            brightness,
+        })
+    }
+}
@@ -0,0 +1,292 @@
+use core::fmt::Write;
+
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::led::LedInput;
+
+// Maximum number of schedule entries.
+const MAX_SCHEDULE_ENTRIES: usize = 8;
+// Size of an encoded entry: its minute of the day and its action.
+const ENTRY_SIZE: usize = 3;
+// Size of an encoded schedule: its number of entries, followed by them.
+pub const SCHEDULE_SIZE: usize = 1 + MAX_SCHEDULE_ENTRIES * ENTRY_SIZE;
+
+const SECONDS_PER_DAY: i64 = 86400;
+
+// Time of the day, as minutes since midnight, written as `HH:MM`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TimeOfDay(u16);
+
+impl TimeOfDay {
+    pub const MIDNIGHT: Self = Self(0);
+
+    pub const fn minutes(self) -> u16 {
+        self.0
+    }
+
+    pub const fn from_minutes(minutes: u16) -> Option<Self> {
+        if minutes < 24 * 60 {
+            Some(Self(minutes))
+        } else {
+            None
+        }
+    }
+
+    // Local time of the day at the given Unix time.
+    pub fn at(unix: u64, timezone_offset_minutes: i32) -> Self {
+        // Unix time fits an `i64` for billions of years.
+        let local = unix as i64 + i64::from(timezone_offset_minutes) * 60;
+        // Less than a day of minutes, which fits a `u16`.
+        Self((local.rem_euclid(SECONDS_PER_DAY) / 60) as u16)
+    }
+
+    fn parse(time: &str) -> Option<Self> {
+        let (hour, minute) = time.split_once(':')?;
+        if hour.len() != 2 || minute.len() != 2 {
+            return None;
+        }
+        let hour: u16 = hour.parse().ok()?;
+        let minute: u16 = minute.parse().ok()?;
+        (hour < 24 && minute < 60).then_some(Self(hour * 60 + minute))
+    }
+}
+
+impl Serialize for TimeOfDay {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut time = heapless::String::<5>::new();
+        // `HH:MM` always fits the string.
+        let _ = write!(time, "{:02}:{:02}", self.0 / 60, self.0 % 60);
+        serializer.serialize_str(&time)
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeOfDay {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let time = <&str>::deserialize(deserializer)?;
+        Self::parse(time).ok_or_else(|| D::Error::custom("invalid time, expected `HH:MM`"))
+    }
+}
+
+// Action performed by a schedule entry.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScheduleAction {
+    On,
+    Off,
+}
+
+impl ScheduleAction {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::On => "on",
+            Self::Off => "off",
+        }
+    }
+
+    pub const fn led_input(self) -> LedInput {
+        match self {
+            Self::On => LedInput::On {
+                fade_ms: None,
+                auto_off_secs: None,
+            },
+            Self::Off => LedInput::Off { fade_ms: None },
+        }
+    }
+}
+
+// Action performed every day at the given time.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+struct ScheduleEntry {
+    time: TimeOfDay,
+    action: ScheduleAction,
+}
+
+// Scheduled event, at a given Unix time.
+#[derive(PartialEq, Eq, Debug)]
+pub struct ScheduledEvent {
+    pub at_unix: u64,
+    pub action: ScheduleAction,
+}
+
+// Daily schedule, such as `[{"time":"18:00","action":"on"}]`.
+//
+// Times are local, as given by the configured timezone offset.
+#[derive(Clone, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Schedule {
+    entries: heapless::Vec<ScheduleEntry, MAX_SCHEDULE_ENTRIES>,
+}
+
+impl Schedule {
+    // Schedule without any entry.
+    pub const fn new() -> Self {
+        Self {
+            entries: heapless::Vec::new(),
+        }
+    }
+
+    pub fn encode(&self, bytes: &mut [u8; SCHEDULE_SIZE]) {
+        *bytes = [0; SCHEDULE_SIZE];
+        // The number of entries is bounded, so it fits a byte.
+        bytes[0] = self.entries.len() as u8;
+        for (entry, bytes) in self
+            .entries
+            .iter()
+            .zip(bytes[1..].chunks_exact_mut(ENTRY_SIZE))
+        {
+            bytes[..2].copy_from_slice(&entry.time.0.to_le_bytes());
+            bytes[2] = match entry.action {
+                ScheduleAction::Off => 0,
+                ScheduleAction::On => 1,
+            };
+        }
+    }
+
+    pub fn decode(bytes: &[u8; SCHEDULE_SIZE]) -> Option<Self> {
+        let len = usize::from(bytes[0]);
+        let mut entries = heapless::Vec::new();
+        for bytes in bytes[1..].chunks_exact(ENTRY_SIZE).take(len) {
+            let time = TimeOfDay::from_minutes(u16::from_le_bytes([bytes[0], bytes[1]]))?;
+            let action = match bytes[2] {
+                0 => ScheduleAction::Off,
+                1 => ScheduleAction::On,
+                _ => return None,
+            };
+            entries.push(ScheduleEntry { time, action }).ok()?;
+        }
+        (entries.len() == len).then_some(Self { entries })
+    }
+
+    // First event happening strictly after the given Unix time, if any.
+    pub fn next_event(
+        &self,
+        after_unix: u64,
+        timezone_offset_minutes: i32,
+    ) -> Option<ScheduledEvent> {
+        let offset_secs = i64::from(timezone_offset_minutes) * 60;
+        // Unix time fits an `i64` for billions of years.
+        let local = after_unix as i64 + offset_secs;
+        let second_of_day = local.rem_euclid(SECONDS_PER_DAY);
+
+        self.entries
+            .iter()
+            .map(|entry| {
+                // An entry happening right now is due again the next day.
+                let delay =
+                    (i64::from(entry.time.0) * 60 - second_of_day).rem_euclid(SECONDS_PER_DAY);
+                let delay = if delay == 0 { SECONDS_PER_DAY } else { delay };
+                ScheduledEvent {
+                    at_unix: after_unix + delay as u64,
+                    action: entry.action,
+                }
+            })
+            .min_by_key(|event| event.at_unix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-01-01T00:00:00Z, a midnight in UTC.
+    const MIDNIGHT_UTC: u64 = 1_704_067_200;
+    const HOUR: u64 = 3600;
+
+    fn schedule(json: &str) -> Schedule {
+        serde_json_core::from_str(json).unwrap().0
+    }
+
+    fn event(at_unix: u64, action: ScheduleAction) -> Option<ScheduledEvent> {
+        Some(ScheduledEvent { at_unix, action })
+    }
+
+    #[test]
+    fn earliest_entry_comes_next() {
+        let schedule =
+            schedule(r#"[{"time":"18:00","action":"on"},{"time":"07:30","action":"off"}]"#);
+        let noon = MIDNIGHT_UTC + 12 * HOUR;
+        assert_eq!(
+            schedule.next_event(noon, 0),
+            event(MIDNIGHT_UTC + 18 * HOUR, ScheduleAction::On)
+        );
+        // Past the last entry of the day, the first one of the next day.
+        let evening = MIDNIGHT_UTC + 20 * HOUR;
+        assert_eq!(
+            schedule.next_event(evening, 0),
+            event(
+                MIDNIGHT_UTC + 24 * HOUR + 7 * HOUR + 1800,
+                ScheduleAction::Off
+            )
+        );
+    }
+
+    #[test]
+    fn empty_schedule_has_no_event() {
+        assert_eq!(Schedule::new().next_event(MIDNIGHT_UTC, 0), None);
+        assert_eq!(schedule("[]").next_event(MIDNIGHT_UTC, -300), None);
+    }
+
+    #[test]
+    fn negative_offsets_shift_the_entries_later() {
+        // At UTC-5, 07:30 local time is 12:30 UTC.
+        let morning = schedule(r#"[{"time":"07:30","action":"off"}]"#);
+        let noon = MIDNIGHT_UTC + 12 * HOUR;
+        assert_eq!(
+            morning.next_event(noon, -300),
+            event(noon + 1800, ScheduleAction::Off)
+        );
+
+        // At 02:00 UTC it is still the previous day locally, 21:00, so the
+        // 18:00 entry happens at 23:00 UTC.
+        let evening = schedule(r#"[{"time":"18:00","action":"on"}]"#);
+        assert_eq!(
+            evening.next_event(MIDNIGHT_UTC + 2 * HOUR, -300),
+            event(MIDNIGHT_UTC + 23 * HOUR, ScheduleAction::On)
+        );
+    }
+
+    #[test]
+    fn entry_happening_now_is_due_the_next_day() {
+        let schedule = schedule(r#"[{"time":"18:00","action":"on"}]"#);
+        let now = MIDNIGHT_UTC + 18 * HOUR;
+        assert_eq!(
+            schedule.next_event(now, 0),
+            event(now + 24 * HOUR, ScheduleAction::On)
+        );
+        // The same at UTC+2, where 18:00 local time is 16:00 UTC.
+        let now = MIDNIGHT_UTC + 16 * HOUR;
+        assert_eq!(
+            schedule.next_event(now, 120),
+            event(now + 24 * HOUR, ScheduleAction::On)
+        );
+        // A second later, it is still due the next day.
+        assert_eq!(
+            schedule.next_event(now + 1, 120),
+            event(now + 24 * HOUR, ScheduleAction::On)
+        );
+    }
+
+    #[test]
+    fn entries_after_midnight_wrap_to_the_next_day() {
+        let schedule = schedule(r#"[{"time":"00:00","action":"off"}]"#);
+        // 23:59 local time at UTC+2 is 21:59 UTC.
+        let now = MIDNIGHT_UTC + 21 * HOUR + 59 * 60;
+        assert_eq!(
+            schedule.next_event(now, 120),
+            event(now + 60, ScheduleAction::Off)
+        );
+        // At UTC-1, the local midnight is 01:00 UTC.
+        assert_eq!(
+            schedule.next_event(MIDNIGHT_UTC + 30 * 60, -60),
+            event(MIDNIGHT_UTC + HOUR, ScheduleAction::Off)
+        );
+    }
+
+    #[test]
+    fn local_time_of_day_follows_the_offset() {
+        assert_eq!(TimeOfDay::at(MIDNIGHT_UTC, 0), TimeOfDay::MIDNIGHT);
+        assert_eq!(TimeOfDay::at(MIDNIGHT_UTC, -300).minutes(), 19 * 60);
+        assert_eq!(TimeOfDay::at(MIDNIGHT_UTC + 59, 90).minutes(), 90);
+    }
+}
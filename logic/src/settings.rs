@@ -0,0 +1,663 @@
+use core::fmt;
+use core::net::Ipv4Addr;
+
+use heapless::String;
+
+use serde::{Deserialize, Serialize};
+
+use log::{info, warn};
+
+use crate::button_actions::{ButtonAction, ButtonActions, BUTTON_ACTIONS_SIZE};
+use crate::crc::crc32;
+use crate::led::MAX_BRIGHTNESS;
+use crate::quiet_hours::{QuietHours, QUIET_HOURS_SIZE};
+use crate::schedule::{Schedule, TimeOfDay, SCHEDULE_SIZE};
+
+// Version of the layout of the settings in flash, bumped whenever a setting
+// is added.
+const SETTINGS_VERSION: u8 = 6;
+// Oldest layout still decoded, the settings it lacks are set to their
+// defaults.
+const OLDEST_SETTINGS_VERSION: u8 = 1;
+// Marks flash which contains settings, its last byte is the layout version.
+const SETTINGS_MAGIC: [u8; 4] = [b'B', b'L', b'D', SETTINGS_VERSION];
+// Maximum lengths of the string settings.
+pub const MAX_SSID_LEN: usize = 32;
+pub const MAX_PASSWORD_LEN: usize = 64;
+pub const MAX_HOSTNAME_LEN: usize = 32;
+pub const MAX_HOST_LEN: usize = 64;
+// Shortest WPA2 passphrase, an empty password means an open network.
+const MIN_PASSWORD_LEN: usize = 8;
+// Password shown by the `/config` route in place of the real one.
+const MASKED_PASSWORD: &str = "********";
+// Size of the encoded settings: the magic header, the string settings, each
+// one preceded by its length, the MQTT port, the schedule, whether it is
+// enabled, whether the buzzer is muted, the quiet hours, the button actions
+// and the CRC of all the previous bytes.
+pub const SETTINGS_SIZE: usize = settings_size(SETTINGS_VERSION);
+
+// Size of the settings encoded with the given layout version, which added
+// in turn the schedule, the hostname and whether the schedule is enabled,
+// whether the buzzer is muted, the quiet hours and the button actions.
+const fn settings_size(version: u8) -> usize {
+    let mut size =
+        SETTINGS_MAGIC.len() + 1 + MAX_SSID_LEN + 1 + MAX_PASSWORD_LEN + 1 + MAX_HOST_LEN + 2 + 4;
+    if version >= 2 {
+        size += SCHEDULE_SIZE;
+    }
+    if version >= 3 {
+        size += 1 + MAX_HOSTNAME_LEN + 1;
+    }
+    if version >= 4 {
+        size += 1;
+    }
+    if version >= 5 {
+        size += QUIET_HOURS_SIZE;
+    }
+    if version >= 6 {
+        size += BUTTON_ACTIONS_SIZE;
+    }
+    size
+}
+
+// Runtime settings.
+//
+// The password is never logged, so this type does not implement `Debug`.
+#[derive(PartialEq)]
+pub struct Settings {
+    pub ssid: String<MAX_SSID_LEN>,
+    pub password: String<MAX_PASSWORD_LEN>,
+    // Hostname answered over mDNS and sent to the syslog server.
+    pub hostname: String<MAX_HOSTNAME_LEN>,
+    // MQTT broker IPv4 address, MQTT is disabled when empty.
+    pub mqtt_host: String<MAX_HOST_LEN>,
+    pub mqtt_port: u16,
+    // Daily led schedule, changed through the `/schedule` route.
+    pub schedule: Schedule,
+    // Whether the schedule is followed.
+    pub schedule_enabled: bool,
+    // Whether the buzzer is silenced.
+    pub buzzer_muted: bool,
+    pub quiet_hours: QuietHours,
+    // Actions performed for the button gestures.
+    pub button_actions: ButtonActions,
+}
+
+// Settings changed through the `/setup` route, missing ones are kept.
+#[derive(Deserialize)]
+pub struct SettingsUpdate {
+    ssid: Option<String<MAX_SSID_LEN>>,
+    password: Option<String<MAX_PASSWORD_LEN>>,
+    mqtt_host: Option<String<MAX_HOST_LEN>>,
+    mqtt_port: Option<u16>,
+}
+
+// Settings returned by the `/config` route, with the password masked.
+#[derive(Serialize)]
+pub struct ConfigView {
+    ssid: String<MAX_SSID_LEN>,
+    // Masked when set, empty for an open network.
+    password: &'static str,
+    hostname: String<MAX_HOSTNAME_LEN>,
+    mqtt_host: String<MAX_HOST_LEN>,
+    mqtt_port: u16,
+    schedule_enabled: bool,
+    buzzer_muted: bool,
+    quiet_hours_enabled: bool,
+    quiet_hours_start: TimeOfDay,
+    quiet_hours_end: TimeOfDay,
+    // Brightness percentage shown during the quiet hours, 0 keeps the led off.
+    quiet_hours_brightness: u8,
+    // Actions of a click, a double click and a long press, such as `toggle:0`.
+    button_single: ButtonAction,
+    button_double: ButtonAction,
+    button_long: ButtonAction,
+}
+
+// Longest string accepted in a `/config` update, longer than any setting so
+// too long settings are reported as such.
+const MAX_CONFIG_FIELD_LEN: usize = 128;
+
+// Settings changed through the `/config` route, missing ones are kept.
+#[derive(Deserialize)]
+pub struct ConfigUpdate {
+    ssid: Option<String<MAX_CONFIG_FIELD_LEN>>,
+    password: Option<String<MAX_CONFIG_FIELD_LEN>>,
+    hostname: Option<String<MAX_CONFIG_FIELD_LEN>>,
+    mqtt_host: Option<String<MAX_CONFIG_FIELD_LEN>>,
+    // Wider than a port, so out of range ports are reported as such.
+    mqtt_port: Option<u32>,
+    schedule_enabled: Option<bool>,
+    buzzer_muted: Option<bool>,
+    quiet_hours_enabled: Option<bool>,
+    quiet_hours_start: Option<TimeOfDay>,
+    quiet_hours_end: Option<TimeOfDay>,
+    quiet_hours_brightness: Option<u8>,
+    button_single: Option<ButtonAction>,
+    button_double: Option<ButtonAction>,
+    button_long: Option<ButtonAction>,
+}
+
+// Field of a `/config` update which is invalid.
+pub struct InvalidField {
+    field: &'static str,
+    reason: &'static str,
+}
+
+impl fmt::Display for InvalidField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid {}: {}", self.field, self.reason)
+    }
+}
+
+// Converts a string field of a `/config` update, checking its length.
+fn bounded<const N: usize>(field: &'static str, value: &str) -> Result<String<N>, InvalidField> {
+    String::try_from(value).map_err(|()| InvalidField {
+        field,
+        reason: "too long",
+    })
+}
+
+// Replaces a setting with the updated value, if any, returning whether it
+// changed.
+fn replace<T: PartialEq>(setting: &mut T, value: Option<T>) -> bool {
+    let Some(value) = value else {
+        return false;
+    };
+    let changed = *setting != value;
+    *setting = value;
+    changed
+}
+
+// Whether a hostname is made of letters, digits and hyphens, without leading
+// or trailing hyphens.
+fn is_valid_hostname(hostname: &str) -> bool {
+    !hostname.is_empty()
+        && !hostname.starts_with('-')
+        && !hostname.ends_with('-')
+        && hostname
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-')
+}
+
+impl Settings {
+    // Applies an update, keeping the settings it does not contain.
+    pub fn update(&mut self, update: SettingsUpdate) {
+        if let Some(ssid) = update.ssid {
+            self.ssid = ssid;
+        }
+        if let Some(password) = update.password {
+            self.password = password;
+        }
+        if let Some(mqtt_host) = update.mqtt_host {
+            self.mqtt_host = mqtt_host;
+        }
+        if let Some(mqtt_port) = update.mqtt_port {
+            self.mqtt_port = mqtt_port;
+        }
+    }
+
+    // Settings shown by the `/config` route.
+    pub fn into_config(self) -> ConfigView {
+        ConfigView {
+            ssid: self.ssid,
+            password: if self.password.is_empty() {
+                ""
+            } else {
+                MASKED_PASSWORD
+            },
+            hostname: self.hostname,
+            mqtt_host: self.mqtt_host,
+            mqtt_port: self.mqtt_port,
+            schedule_enabled: self.schedule_enabled,
+            buzzer_muted: self.buzzer_muted,
+            quiet_hours_enabled: self.quiet_hours.enabled,
+            quiet_hours_start: self.quiet_hours.start,
+            quiet_hours_end: self.quiet_hours.end,
+            quiet_hours_brightness: self.quiet_hours.brightness,
+            button_single: self.button_actions.single,
+            button_double: self.button_actions.double,
+            button_long: self.button_actions.long,
+        }
+    }
+
+    // Applies a `/config` update, only once all its fields are valid, the
+    // button actions changing one of the given number of led channels.
+    //
+    // Returns whether any setting changed.
+    pub fn apply(
+        &mut self,
+        update: ConfigUpdate,
+        led_channels: usize,
+    ) -> Result<bool, InvalidField> {
+        let invalid = |field, reason| InvalidField { field, reason };
+
+        let ssid = update
+            .ssid
+            .as_deref()
+            .map(|ssid| match bounded::<MAX_SSID_LEN>("ssid", ssid)? {
+                ssid if ssid.is_empty() => Err(invalid("ssid", "must not be empty")),
+                ssid => Ok(ssid),
+            })
+            .transpose()?;
+        let password = update
+            .password
+            .as_deref()
+            .map(
+                |password| match bounded::<MAX_PASSWORD_LEN>("password", password)? {
+                    password if (1..MIN_PASSWORD_LEN).contains(&password.len()) => Err(invalid(
+                        "password",
+                        "must be empty or at least 8 characters",
+                    )),
+                    password => Ok(password),
+                },
+            )
+            .transpose()?;
+        let hostname = update
+            .hostname
+            .as_deref()
+            .map(
+                |hostname| match bounded::<MAX_HOSTNAME_LEN>("hostname", hostname)? {
+                    hostname if !is_valid_hostname(&hostname) => Err(invalid(
+                        "hostname",
+                        "expected letters, digits and inner hyphens",
+                    )),
+                    hostname => Ok(hostname),
+                },
+            )
+            .transpose()?;
+        let mqtt_host = update
+            .mqtt_host
+            .as_deref()
+            .map(
+                |mqtt_host| match bounded::<MAX_HOST_LEN>("mqtt_host", mqtt_host)? {
+                    mqtt_host
+                        if !mqtt_host.is_empty() && mqtt_host.parse::<Ipv4Addr>().is_err() =>
+                    {
+                        Err(invalid("mqtt_host", "expected an IPv4 address"))
+                    }
+                    mqtt_host => Ok(mqtt_host),
+                },
+            )
+            .transpose()?;
+        let mqtt_port = update
+            .mqtt_port
+            .map(|mqtt_port| match u16::try_from(mqtt_port) {
+                Ok(mqtt_port) if mqtt_port > 0 => Ok(mqtt_port),
+                _ => Err(invalid("mqtt_port", "expected a port between 1 and 65535")),
+            })
+            .transpose()?;
+        let quiet_hours_brightness = update
+            .quiet_hours_brightness
+            .map(|brightness| {
+                if brightness <= MAX_BRIGHTNESS {
+                    Ok(brightness)
+                } else {
+                    Err(invalid(
+                        "quiet_hours_brightness",
+                        "expected a percentage between 0 and 100",
+                    ))
+                }
+            })
+            .transpose()?;
+        let button_action = |field, action: Option<ButtonAction>| {
+            action
+                .map(|action| {
+                    if action.fits(led_channels) {
+                        Ok(action)
+                    } else {
+                        Err(invalid(field, "no such led channel"))
+                    }
+                })
+                .transpose()
+        };
+        let button_single = button_action("button_single", update.button_single)?;
+        let button_double = button_action("button_double", update.button_double)?;
+        let button_long = button_action("button_long", update.button_long)?;
+
+        // Every setting is replaced, so `|` is used rather than `||`.
+        Ok(replace(&mut self.ssid, ssid)
+            | replace(&mut self.password, password)
+            | replace(&mut self.hostname, hostname)
+            | replace(&mut self.mqtt_host, mqtt_host)
+            | replace(&mut self.mqtt_port, mqtt_port)
+            | replace(&mut self.schedule_enabled, update.schedule_enabled)
+            | replace(&mut self.buzzer_muted, update.buzzer_muted)
+            | replace(&mut self.quiet_hours.enabled, update.quiet_hours_enabled)
+            | replace(&mut self.quiet_hours.start, update.quiet_hours_start)
+            | replace(&mut self.quiet_hours.end, update.quiet_hours_end)
+            | replace(&mut self.quiet_hours.brightness, quiet_hours_brightness)
+            | replace(&mut self.button_actions.single, button_single)
+            | replace(&mut self.button_actions.double, button_double)
+            | replace(&mut self.button_actions.long, button_long))
+    }
+
+    pub fn encode(&self) -> [u8; SETTINGS_SIZE] {
+        let mut bytes = [0; SETTINGS_SIZE];
+        let (magic, rest) = bytes.split_at_mut(SETTINGS_MAGIC.len());
+        magic.copy_from_slice(&SETTINGS_MAGIC);
+        let rest = encode_field(rest, &self.ssid, MAX_SSID_LEN);
+        let rest = encode_field(rest, &self.password, MAX_PASSWORD_LEN);
+        let rest = encode_field(rest, &self.hostname, MAX_HOSTNAME_LEN);
+        let rest = encode_field(rest, &self.mqtt_host, MAX_HOST_LEN);
+        let (mqtt_port, rest) = rest.split_at_mut(2);
+        mqtt_port.copy_from_slice(&self.mqtt_port.to_le_bytes());
+        let (schedule, rest) = rest.split_at_mut(SCHEDULE_SIZE);
+        self.schedule.encode(schedule.try_into().unwrap());
+        let (flags, rest) = rest.split_at_mut(2);
+        flags[0] = u8::from(self.schedule_enabled);
+        flags[1] = u8::from(self.buzzer_muted);
+        let (quiet_hours, rest) = rest.split_at_mut(QUIET_HOURS_SIZE);
+        self.quiet_hours.encode(quiet_hours.try_into().unwrap());
+        // The button actions are followed by the CRC.
+        let (button_actions, _) = rest.split_at_mut(BUTTON_ACTIONS_SIZE);
+        self.button_actions
+            .encode(button_actions.try_into().unwrap());
+
+        let (data, crc) = bytes.split_at_mut(SETTINGS_SIZE - 4);
+        crc.copy_from_slice(&crc32(data).to_le_bytes());
+        bytes
+    }
+
+    // Decodes settings encoded with the current layout or an older one, the
+    // button actions changing one of the given number of led channels.
+    //
+    // The settings missing from older layouts, or invalid button actions, are
+    // taken from the given defaults.
+    pub fn decode(
+        bytes: &[u8; SETTINGS_SIZE],
+        defaults: &Self,
+        led_channels: usize,
+    ) -> Option<Self> {
+        let version = match bytes {
+            [b'B', b'L', b'D', version, ..]
+                if (OLDEST_SETTINGS_VERSION..=SETTINGS_VERSION).contains(version) =>
+            {
+                *version
+            }
+            _ => return None,
+        };
+        let (data, rest) = bytes.split_at(settings_size(version) - 4);
+        if crc32(data).to_le_bytes() != rest[..4] {
+            return None;
+        }
+        if version < SETTINGS_VERSION {
+            info!("Settings in flash have layout version {version}, migrating them");
+        }
+
+        let rest = &data[SETTINGS_MAGIC.len()..];
+        let (ssid, rest) = decode_field(rest, MAX_SSID_LEN)?;
+        let (password, rest) = decode_field(rest, MAX_PASSWORD_LEN)?;
+        let (hostname, rest) = if version >= 3 {
+            let (hostname, rest) = decode_field(rest, MAX_HOSTNAME_LEN)?;
+            (String::try_from(hostname).ok()?, rest)
+        } else {
+            (defaults.hostname.clone(), rest)
+        };
+        let (mqtt_host, rest) = decode_field(rest, MAX_HOST_LEN)?;
+        let (mqtt_port, rest) = rest.split_at_checked(2)?;
+        let mqtt_port = u16::from_le_bytes(mqtt_port.try_into().ok()?);
+        let (schedule, rest) = if version >= 2 {
+            let (schedule, rest) = rest.split_at_checked(SCHEDULE_SIZE)?;
+            (Schedule::decode(schedule.try_into().ok()?)?, rest)
+        } else {
+            (defaults.schedule.clone(), rest)
+        };
+        let (schedule_enabled, rest) = if version >= 3 {
+            let (flag, rest) = rest.split_first()?;
+            (*flag != 0, rest)
+        } else {
+            (defaults.schedule_enabled, rest)
+        };
+        let (buzzer_muted, rest) = if version >= 4 {
+            let (flag, rest) = rest.split_first()?;
+            (*flag != 0, rest)
+        } else {
+            (defaults.buzzer_muted, rest)
+        };
+        let (quiet_hours, rest) = if version >= 5 {
+            let (quiet_hours, rest) = rest.split_at_checked(QUIET_HOURS_SIZE)?;
+            (QuietHours::decode(quiet_hours.try_into().ok()?)?, rest)
+        } else {
+            (defaults.quiet_hours, rest)
+        };
+        let button_actions = if version >= 6 {
+            // Invalid button actions do not discard the other settings.
+            ButtonActions::decode(rest.try_into().ok()?)
+                .filter(|actions| actions.fits(led_channels))
+                .unwrap_or_else(|| {
+                    warn!("Button actions in flash are invalid, using the default ones");
+                    defaults.button_actions
+                })
+        } else {
+            defaults.button_actions
+        };
+
+        Some(Self {
+            ssid: String::try_from(ssid).ok()?,
+            password: String::try_from(password).ok()?,
+            hostname,
+            mqtt_host: String::try_from(mqtt_host).ok()?,
+            mqtt_port,
+            schedule,
+            schedule_enabled,
+            buzzer_muted,
+            quiet_hours,
+            button_actions,
+        })
+    }
+}
+
+// Writes a length-prefixed field of the given capacity, returning the bytes
+// following it.
+fn encode_field<'a>(bytes: &'a mut [u8], value: &str, capacity: usize) -> &'a mut [u8] {
+    let (field, rest) = bytes.split_at_mut(1 + capacity);
+    // Values are bounded by their capacity, which fits a byte.
+    field[0] = value.len() as u8;
+    field[1..=value.len()].copy_from_slice(value.as_bytes());
+    rest
+}
+
+// Reads a length-prefixed field of the given capacity, returning it together
+// with the bytes following it.
+fn decode_field(bytes: &[u8], capacity: usize) -> Option<(&str, &[u8])> {
+    let (field, rest) = bytes.split_at_checked(1 + capacity)?;
+    let len = usize::from(field[0]);
+    let value = core::str::from_utf8(field.get(1..=len)?).ok()?;
+    Some((value, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Settings of a device without settings in flash.
+    fn defaults() -> Settings {
+        Settings {
+            ssid: String::try_from("configured").unwrap(),
+            password: String::new(),
+            hostname: String::try_from("button-led-a1b2c3").unwrap(),
+            mqtt_host: String::new(),
+            mqtt_port: 1883,
+            schedule: Schedule::default(),
+            schedule_enabled: true,
+            buzzer_muted: false,
+            quiet_hours: QuietHours::DISABLED,
+            button_actions: ButtonActions::defaults(1, false),
+        }
+    }
+
+    // Settings differing from the defaults in every field.
+    fn stored() -> Settings {
+        Settings {
+            ssid: String::try_from("home").unwrap(),
+            password: String::try_from("correct horse").unwrap(),
+            hostname: String::try_from("porch").unwrap(),
+            mqtt_host: String::try_from("192.168.1.10").unwrap(),
+            mqtt_port: 8883,
+            schedule: serde_json_core::from_str(
+                r#"[{"time":"07:30","action":"on"},{"time":"23:00","action":"off"}]"#,
+            )
+            .unwrap()
+            .0,
+            schedule_enabled: false,
+            buzzer_muted: true,
+            quiet_hours: QuietHours {
+                enabled: true,
+                start: TimeOfDay::from_minutes(22 * 60).unwrap(),
+                end: TimeOfDay::from_minutes(6 * 60).unwrap(),
+                brightness: 10,
+            },
+            button_actions: ButtonActions {
+                single: ButtonAction::Cycle { channel: 2 },
+                double: ButtonAction::Mqtt,
+                long: ButtonAction::Brightness {
+                    channel: 0,
+                    level: 40,
+                },
+            },
+        }
+    }
+
+    fn decode(bytes: &[u8; SETTINGS_SIZE]) -> Option<Settings> {
+        Settings::decode(bytes, &defaults(), 3)
+    }
+
+    // Writes the CRC of a layout version after its data.
+    fn seal(bytes: &mut [u8; SETTINGS_SIZE], version: u8) {
+        let (data, rest) = bytes.split_at_mut(settings_size(version) - 4);
+        rest[..4].copy_from_slice(&crc32(data).to_le_bytes());
+    }
+
+    // The stored settings written with an older layout version, as found in
+    // the flash of a device running an older firmware.
+    fn fixture(version: u8) -> [u8; SETTINGS_SIZE] {
+        let settings = stored();
+        // Flash past the settings is erased.
+        let mut bytes = [0xFF; SETTINGS_SIZE];
+        let (magic, rest) = bytes.split_at_mut(SETTINGS_MAGIC.len());
+        magic.copy_from_slice(&[b'B', b'L', b'D', version]);
+        let rest = encode_field(rest, &settings.ssid, MAX_SSID_LEN);
+        let mut rest = encode_field(rest, &settings.password, MAX_PASSWORD_LEN);
+        if version >= 3 {
+            rest = encode_field(rest, &settings.hostname, MAX_HOSTNAME_LEN);
+        }
+        let rest = encode_field(rest, &settings.mqtt_host, MAX_HOST_LEN);
+        let (mqtt_port, mut rest) = rest.split_at_mut(2);
+        mqtt_port.copy_from_slice(&settings.mqtt_port.to_le_bytes());
+        if version >= 2 {
+            let (schedule, tail) = rest.split_at_mut(SCHEDULE_SIZE);
+            settings.schedule.encode(schedule.try_into().unwrap());
+            rest = tail;
+        }
+        if version >= 3 {
+            let (flag, tail) = rest.split_first_mut().unwrap();
+            *flag = u8::from(settings.schedule_enabled);
+            rest = tail;
+        }
+        if version >= 4 {
+            let (flag, tail) = rest.split_first_mut().unwrap();
+            *flag = u8::from(settings.buzzer_muted);
+            rest = tail;
+        }
+        if version >= 5 {
+            let (quiet_hours, _) = rest.split_at_mut(QUIET_HOURS_SIZE);
+            settings.quiet_hours.encode(quiet_hours.try_into().unwrap());
+        }
+        seal(&mut bytes, version);
+        bytes
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        assert!(decode(&stored().encode()) == Some(stored()));
+        assert!(decode(&defaults().encode()) == Some(defaults()));
+    }
+
+    #[test]
+    fn older_layouts_are_migrated() {
+        let stored = stored();
+        let defaults = defaults();
+        for version in OLDEST_SETTINGS_VERSION..SETTINGS_VERSION {
+            let settings = decode(&fixture(version)).unwrap();
+            assert!(settings.ssid == stored.ssid);
+            assert!(settings.password == stored.password);
+            assert!(settings.mqtt_host == stored.mqtt_host);
+            assert_eq!(settings.mqtt_port, stored.mqtt_port);
+
+            // The settings added later take their defaults.
+            let from = |added| if version >= added { &stored } else { &defaults };
+            assert!(settings.hostname == from(3).hostname);
+            assert_eq!(settings.schedule, from(2).schedule);
+            assert_eq!(settings.schedule_enabled, from(3).schedule_enabled);
+            assert_eq!(settings.buzzer_muted, from(4).buzzer_muted);
+            assert_eq!(settings.quiet_hours, from(5).quiet_hours);
+            assert_eq!(settings.button_actions, defaults.button_actions);
+        }
+    }
+
+    #[test]
+    fn unknown_layouts_are_rejected() {
+        for version in [0, SETTINGS_VERSION + 1] {
+            let mut bytes = stored().encode();
+            bytes[3] = version;
+            seal(&mut bytes, SETTINGS_VERSION);
+            assert!(decode(&bytes).is_none());
+        }
+        // Erased flash.
+        assert!(decode(&[0xFF; SETTINGS_SIZE]).is_none());
+    }
+
+    #[test]
+    fn crc_mismatch_is_rejected() {
+        let mut bytes = stored().encode();
+        bytes[10] ^= 1;
+        assert!(decode(&bytes).is_none());
+
+        let mut bytes = stored().encode();
+        bytes[SETTINGS_SIZE - 1] ^= 1;
+        assert!(decode(&bytes).is_none());
+
+        // An older layout is checked up to its own CRC.
+        let mut bytes = fixture(1);
+        bytes[settings_size(1) - 1] ^= 1;
+        assert!(decode(&bytes).is_none());
+    }
+
+    #[test]
+    fn truncated_length_byte_is_rejected() {
+        // A length past the capacity of its field, with a valid CRC.
+        let mut bytes = stored().encode();
+        bytes[SETTINGS_MAGIC.len()] = MAX_SSID_LEN as u8 + 1;
+        seal(&mut bytes, SETTINGS_VERSION);
+        assert!(decode(&bytes).is_none());
+
+        // A length ending in the middle of a character.
+        let mut settings = stored();
+        settings.ssid = String::try_from("café").unwrap();
+        let mut bytes = settings.encode();
+        bytes[SETTINGS_MAGIC.len()] -= 1;
+        seal(&mut bytes, SETTINGS_VERSION);
+        assert!(decode(&bytes).is_none());
+
+        // The full capacity is used.
+        settings.ssid = String::try_from("s".repeat(MAX_SSID_LEN).as_str()).unwrap();
+        assert!(decode(&settings.encode()) == Some(settings));
+    }
+
+    #[test]
+    fn invalid_button_actions_keep_the_other_settings() {
+        // The led channel 2 is missing from two led channels.
+        let settings = Settings::decode(&stored().encode(), &defaults(), 2).unwrap();
+        assert_eq!(settings.button_actions, defaults().button_actions);
+        assert!(settings.ssid == stored().ssid);
+
+        // An unknown kind of action.
+        let mut bytes = stored().encode();
+        bytes[SETTINGS_SIZE - 4 - BUTTON_ACTIONS_SIZE] = 7;
+        seal(&mut bytes, SETTINGS_VERSION);
+        let settings = decode(&bytes).unwrap();
+        assert_eq!(settings.button_actions, defaults().button_actions);
+        assert_eq!(settings.quiet_hours, stored().quiet_hours);
+    }
+}
@@ -0,0 +1,336 @@
+use core::net::Ipv6Addr;
+
+use heapless::Vec;
+
+// Length of the prefixes and of the interface identifiers.
+pub const PREFIX_LEN: u8 = 64;
+// Size of the fixed IPv6 header.
+const IPV6_HEADER_SIZE: usize = 40;
+// Size of a Router Solicitation, including the IPv6 header.
+pub const ROUTER_SOLICITATION_SIZE: usize = IPV6_HEADER_SIZE + 16;
+// Hop limit of the Neighbor Discovery messages, which are dropped otherwise.
+const NDP_HOP_LIMIT: u8 = 255;
+// Maximum number of DNS servers kept from an advertisement.
+pub const MAX_DNS_SERVERS: usize = 3;
+
+// Next header value of ICMPv6.
+const ICMPV6: u8 = 58;
+// ICMPv6 message types.
+const ROUTER_SOLICITATION: u8 = 133;
+const ROUTER_ADVERTISEMENT: u8 = 134;
+// Neighbor Discovery options.
+const OPTION_SOURCE_LINK_LAYER_ADDRESS: u8 = 1;
+const OPTION_PREFIX_INFORMATION: u8 = 3;
+const OPTION_RDNSS: u8 = 25;
+// Flag of the prefix information option allowing autonomous addresses.
+const PREFIX_AUTONOMOUS: u8 = 0x40;
+
+// Multicast address of all the routers on the link.
+const ALL_ROUTERS: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 2);
+
+// Interface identifier derived from the MAC address, in the modified EUI-64
+// format.
+pub fn interface_id(mac: [u8; 6]) -> [u8; 8] {
+    [
+        mac[0] ^ 0x02,
+        mac[1],
+        mac[2],
+        0xff,
+        0xfe,
+        mac[3],
+        mac[4],
+        mac[5],
+    ]
+}
+
+// Address made of a 64 bits prefix followed by the interface identifier.
+pub fn address(prefix: [u8; 8], mac: [u8; 6]) -> Ipv6Addr {
+    let mut octets = [0; 16];
+    octets[..8].copy_from_slice(&prefix);
+    octets[8..].copy_from_slice(&interface_id(mac));
+    Ipv6Addr::from(octets)
+}
+
+// Link-local address of the device.
+pub fn link_local_address(mac: [u8; 6]) -> Ipv6Addr {
+    address([0xfe, 0x80, 0, 0, 0, 0, 0, 0], mac)
+}
+
+// ICMPv6 checksum over the pseudo-header and the message.
+pub fn checksum(source: Ipv6Addr, destination: Ipv6Addr, message: &[u8]) -> u16 {
+    let mut sum = 0u32;
+    let mut add = |bytes: &[u8]| {
+        for chunk in bytes.chunks(2) {
+            let high = u32::from(chunk[0]) << 8;
+            sum += high | chunk.get(1).copied().map_or(0, u32::from);
+        }
+    };
+    add(&source.octets());
+    add(&destination.octets());
+    // The messages are smaller than the raw socket buffers.
+    add(&(message.len() as u32).to_be_bytes());
+    add(&[0, 0, 0, ICMPV6]);
+    add(message);
+
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    // The carries are folded, so the sum fits.
+    !(sum as u16)
+}
+
+// Router Solicitation sent to all the routers from the link-local address,
+// including the IPv6 header.
+pub fn router_solicitation(mac: [u8; 6]) -> [u8; ROUTER_SOLICITATION_SIZE] {
+    let source = link_local_address(mac);
+    let mut packet = [0; ROUTER_SOLICITATION_SIZE];
+
+    let (header, message) = packet.split_at_mut(IPV6_HEADER_SIZE);
+    header[0] = 0x60;
+    header[4..6].copy_from_slice(&16u16.to_be_bytes());
+    header[6] = ICMPV6;
+    header[7] = NDP_HOP_LIMIT;
+    header[8..24].copy_from_slice(&source.octets());
+    header[24..40].copy_from_slice(&ALL_ROUTERS.octets());
+
+    message[0] = ROUTER_SOLICITATION;
+    message[8] = OPTION_SOURCE_LINK_LAYER_ADDRESS;
+    message[9] = 1;
+    message[10..16].copy_from_slice(&mac);
+    let checksum = checksum(source, ALL_ROUTERS, message);
+    message[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+    packet
+}
+
+// Fields of a Router Advertisement used to configure the device.
+#[derive(PartialEq, Eq, Debug)]
+pub struct RouterAdvertisement {
+    pub router: Ipv6Addr,
+    // Whether the router is a default router.
+    pub default_router: bool,
+    // First prefix allowing autonomous addresses, if any.
+    pub prefix: Option<[u8; 8]>,
+    pub dns_servers: Vec<Ipv6Addr, MAX_DNS_SERVERS>,
+}
+
+impl RouterAdvertisement {
+    // Parses an IPv6 packet, returning `None` when it is not a valid Router
+    // Advertisement.
+    pub fn parse(packet: &[u8]) -> Option<Self> {
+        let header = packet.get(..IPV6_HEADER_SIZE)?;
+        let message = packet.get(IPV6_HEADER_SIZE..)?;
+        if header[6] != ICMPV6
+            || header[7] != NDP_HOP_LIMIT
+            || message.len() < 16
+            || message[0] != ROUTER_ADVERTISEMENT
+            || message[1] != 0
+        {
+            return None;
+        }
+        let router = Ipv6Addr::from(<[u8; 16]>::try_from(&header[8..24]).ok()?);
+        // Routers advertise from their link-local address.
+        if !router.is_unicast_link_local() {
+            return None;
+        }
+
+        let mut advertisement = Self {
+            router,
+            default_router: u16::from_be_bytes([message[6], message[7]]) > 0,
+            prefix: None,
+            dns_servers: Vec::new(),
+        };
+        let mut options = &message[16..];
+        while options.len() >= 8 {
+            let len = usize::from(options[1]) * 8;
+            if len == 0 || len > options.len() {
+                return None;
+            }
+            let (option, rest) = options.split_at(len);
+            match option[0] {
+                OPTION_PREFIX_INFORMATION if len == 32 => {
+                    let valid_lifetime = u32::from_be_bytes(option[4..8].try_into().ok()?);
+                    if advertisement.prefix.is_none()
+                        && option[2] == PREFIX_LEN
+                        && option[3] & PREFIX_AUTONOMOUS != 0
+                        && valid_lifetime > 0
+                    {
+                        advertisement.prefix = option[16..24].try_into().ok();
+                    }
+                }
+                OPTION_RDNSS => {
+                    for server in option[8..].as_chunks::<16>().0 {
+                        let _ = advertisement.dns_servers.push(Ipv6Addr::from(*server));
+                    }
+                }
+                _ => {}
+            }
+            options = rest;
+        }
+
+        Some(advertisement)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+    const ROUTER: Ipv6Addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 0x5054, 0xff, 0xfe12, 0x3456);
+
+    // Offsets of the options in `ADVERTISEMENT`.
+    const MTU_OPTION: usize = 64;
+    const PREFIX_OPTION: usize = 72;
+    const RDNSS_OPTION: usize = 104;
+
+    // Router Advertisement of a radvd router advertising 2001:db8:0:1::/64
+    // and two DNS servers, with a router lifetime of 1800 s and its link
+    // layer address and MTU options.
+    const ADVERTISEMENT: &str = "\
+        6000000000683aff fe80000000000000 505400fffe123456 ff02000000000000 \
+        0000000000000001 8600a71240000708 0000000000000000 0101525400123456 \
+        05010000000005dc 030440c000015180 0000384000000000 20010db800000001 \
+        0000000000000000 1905000000000708 20010db800000000 0000000000000053 \
+        20010db800000000 0000000000000054";
+
+    fn bytes(hex: &str) -> std::vec::Vec<u8> {
+        let hex: String = hex.split_whitespace().collect();
+        (0..hex.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn advertisement() -> std::vec::Vec<u8> {
+        bytes(ADVERTISEMENT)
+    }
+
+    #[test]
+    fn interface_id_is_modified_eui64() {
+        assert_eq!(
+            interface_id(MAC),
+            [0x02, 0x11, 0x22, 0xff, 0xfe, 0x33, 0x44, 0x55]
+        );
+        assert_eq!(
+            link_local_address(MAC),
+            Ipv6Addr::new(0xfe80, 0, 0, 0, 0x0211, 0x22ff, 0xfe33, 0x4455)
+        );
+        // The universal/local bit is flipped, not set.
+        assert_eq!(interface_id([0x02, 0, 0, 0, 0, 0])[0], 0x00);
+    }
+
+    #[test]
+    fn checksum_matches_the_captured_one() {
+        let packet = advertisement();
+        let mut message = packet[IPV6_HEADER_SIZE..].to_vec();
+        let captured = u16::from_be_bytes([message[2], message[3]]);
+        message[2..4].fill(0);
+        let destination = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+        assert_eq!(checksum(ROUTER, destination, &message), captured);
+    }
+
+    #[test]
+    fn router_solicitation_is_valid() {
+        let packet = router_solicitation(MAC);
+        let source = link_local_address(MAC);
+        assert_eq!(&packet[8..24], &source.octets());
+        assert_eq!(&packet[24..40], &ALL_ROUTERS.octets());
+        // The checksum of a message including its own checksum is zero.
+        assert_eq!(
+            checksum(source, ALL_ROUTERS, &packet[IPV6_HEADER_SIZE..]),
+            0
+        );
+        assert_eq!(&packet[IPV6_HEADER_SIZE + 10..], &MAC);
+    }
+
+    #[test]
+    fn captured_advertisement_is_parsed() {
+        let advertisement = RouterAdvertisement::parse(&advertisement()).unwrap();
+        assert_eq!(advertisement.router, ROUTER);
+        assert!(advertisement.default_router);
+        assert_eq!(
+            advertisement.prefix,
+            Some([0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 1])
+        );
+        assert_eq!(
+            advertisement.dns_servers,
+            [
+                Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x53),
+                Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x54),
+            ]
+        );
+        assert_eq!(
+            address(advertisement.prefix.unwrap(), MAC).segments()[..4],
+            [0x2001, 0xdb8, 0, 1]
+        );
+    }
+
+    #[test]
+    fn prefixes_without_autonomous_flag_are_ignored() {
+        // Only the on-link flag is set, so the hosts use DHCPv6 instead.
+        let mut packet = advertisement();
+        packet[PREFIX_OPTION + 3] = 0x80;
+        let advertisement = RouterAdvertisement::parse(&packet).unwrap();
+        assert_eq!(advertisement.prefix, None);
+        assert_eq!(advertisement.dns_servers.len(), 2);
+    }
+
+    #[test]
+    fn zero_lifetimes_are_ignored() {
+        // A zero valid lifetime withdraws the prefix.
+        let mut packet = advertisement();
+        packet[PREFIX_OPTION + 4..PREFIX_OPTION + 8].fill(0);
+        assert_eq!(RouterAdvertisement::parse(&packet).unwrap().prefix, None);
+
+        // A zero router lifetime means the router is not a default router.
+        let mut packet = advertisement();
+        packet[IPV6_HEADER_SIZE + 6..IPV6_HEADER_SIZE + 8].fill(0);
+        let advertisement = RouterAdvertisement::parse(&packet).unwrap();
+        assert!(!advertisement.default_router);
+        assert!(advertisement.prefix.is_some());
+    }
+
+    #[test]
+    fn truncated_options_are_rejected() {
+        // The RDNSS option claims more bytes than the packet holds.
+        let packet = advertisement();
+        assert_eq!(
+            RouterAdvertisement::parse(&packet[..packet.len() - 8]),
+            None
+        );
+
+        // Trailing bytes too short for an option are ignored.
+        let mut packet = advertisement();
+        packet.truncate(RDNSS_OPTION);
+        packet.extend_from_slice(&[OPTION_RDNSS, 5, 0, 0]);
+        let advertisement = RouterAdvertisement::parse(&packet).unwrap();
+        assert!(advertisement.prefix.is_some());
+        assert!(advertisement.dns_servers.is_empty());
+    }
+
+    #[test]
+    fn zero_length_options_are_rejected() {
+        // A zero length would loop forever, so the whole packet is invalid.
+        let mut packet = advertisement();
+        packet[MTU_OPTION + 1] = 0;
+        assert_eq!(RouterAdvertisement::parse(&packet), None);
+    }
+
+    #[test]
+    fn other_packets_are_rejected() {
+        // Forwarded by a router, so not from the link.
+        let mut packet = advertisement();
+        packet[7] = 64;
+        assert_eq!(RouterAdvertisement::parse(&packet), None);
+
+        // From a global address.
+        let mut packet = advertisement();
+        packet[8..10].copy_from_slice(&[0x20, 0x01]);
+        assert_eq!(RouterAdvertisement::parse(&packet), None);
+
+        // A Router Solicitation.
+        assert_eq!(RouterAdvertisement::parse(&router_solicitation(MAC)), None);
+        assert_eq!(RouterAdvertisement::parse(&[]), None);
+    }
+}
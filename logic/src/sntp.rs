@@ -0,0 +1,124 @@
+// Size of an NTP message without extensions.
+pub const NTP_PACKET_SIZE: usize = 48;
+// Mode of a server response.
+const NTP_SERVER_MODE: u8 = 4;
+// Seconds between the NTP epoch (1900) and the Unix epoch (1970).
+const NTP_UNIX_OFFSET_SECS: u64 = 2_208_988_800;
+
+// Parses an NTP response to the request with the given transmit timestamp,
+// returning the server time in microseconds since the Unix epoch.
+pub fn parse_response(response: &[u8], request_timestamp: [u8; 8]) -> Option<u64> {
+    if response.len() < NTP_PACKET_SIZE || response[0] & 0x07 != NTP_SERVER_MODE {
+        return None;
+    }
+    // Stratum 0 marks a kiss-o'-death message.
+    if response[1] == 0 {
+        return None;
+    }
+    // The server copies the request transmit timestamp as origin timestamp.
+    if response[24..32] != request_timestamp {
+        return None;
+    }
+
+    let secs = u64::from(u32::from_be_bytes([
+        response[40],
+        response[41],
+        response[42],
+        response[43],
+    ]));
+    let fraction = u64::from(u32::from_be_bytes([
+        response[44],
+        response[45],
+        response[46],
+        response[47],
+    ]));
+    let unix_secs = secs.checked_sub(NTP_UNIX_OFFSET_SECS)?;
+
+    Some(unix_secs * 1_000_000 + ((fraction * 1_000_000) >> 32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REQUEST_TIMESTAMP: [u8; 8] = 123_456_789u64.to_be_bytes();
+    // 2024-01-01T00:00:00Z.
+    const UNIX_SECS: u64 = 1_704_067_200;
+
+    // Response of a stratum 2 server, version 3, answering the request with
+    // the given transmit timestamp at the given NTP time.
+    fn response(request_timestamp: [u8; 8], ntp_secs: u32, fraction: u32) -> [u8; NTP_PACKET_SIZE] {
+        let mut response = [0; NTP_PACKET_SIZE];
+        response[0] = 0x1c;
+        response[1] = 2;
+        response[24..32].copy_from_slice(&request_timestamp);
+        response[40..44].copy_from_slice(&ntp_secs.to_be_bytes());
+        response[44..48].copy_from_slice(&fraction.to_be_bytes());
+        response
+    }
+
+    fn ntp_secs(unix_secs: u64) -> u32 {
+        u32::try_from(unix_secs + NTP_UNIX_OFFSET_SECS).unwrap()
+    }
+
+    #[test]
+    fn server_time_is_converted_to_unix() {
+        let whole = response(REQUEST_TIMESTAMP, ntp_secs(UNIX_SECS), 0);
+        assert_eq!(
+            parse_response(&whole, REQUEST_TIMESTAMP),
+            Some(UNIX_SECS * 1_000_000)
+        );
+
+        // Half a second.
+        let half = response(REQUEST_TIMESTAMP, ntp_secs(UNIX_SECS), 1 << 31);
+        assert_eq!(
+            parse_response(&half, REQUEST_TIMESTAMP),
+            Some(UNIX_SECS * 1_000_000 + 500_000)
+        );
+
+        // Extensions after the message are ignored.
+        let mut extended = whole.to_vec();
+        extended.extend_from_slice(&[0; 20]);
+        assert!(parse_response(&extended, REQUEST_TIMESTAMP).is_some());
+    }
+
+    #[test]
+    fn responses_to_other_requests_are_rejected() {
+        let response = response(REQUEST_TIMESTAMP, ntp_secs(UNIX_SECS), 0);
+        let other_request = 123_456_790u64.to_be_bytes();
+        assert_eq!(parse_response(&response, other_request), None);
+    }
+
+    #[test]
+    fn times_before_1970_are_rejected() {
+        let before = response(REQUEST_TIMESTAMP, 3_600, 0);
+        assert_eq!(parse_response(&before, REQUEST_TIMESTAMP), None);
+
+        // The Unix epoch itself is valid.
+        let epoch = response(REQUEST_TIMESTAMP, ntp_secs(0), 0);
+        assert_eq!(parse_response(&epoch, REQUEST_TIMESTAMP), Some(0));
+    }
+
+    #[test]
+    fn short_packets_are_rejected() {
+        let response = response(REQUEST_TIMESTAMP, ntp_secs(UNIX_SECS), 0);
+        assert_eq!(
+            parse_response(&response[..NTP_PACKET_SIZE - 1], REQUEST_TIMESTAMP),
+            None
+        );
+        assert_eq!(parse_response(&[], REQUEST_TIMESTAMP), None);
+    }
+
+    #[test]
+    fn other_modes_and_kiss_of_death_are_rejected() {
+        // A client request sent back.
+        let mut request = response(REQUEST_TIMESTAMP, ntp_secs(UNIX_SECS), 0);
+        request[0] = 0x1b;
+        assert_eq!(parse_response(&request, REQUEST_TIMESTAMP), None);
+
+        // Stratum 0, asking the client to back off.
+        let mut kiss_of_death = response(REQUEST_TIMESTAMP, ntp_secs(UNIX_SECS), 0);
+        kiss_of_death[1] = 0;
+        assert_eq!(parse_response(&kiss_of_death, REQUEST_TIMESTAMP), None);
+    }
+}
@@ -0,0 +1,151 @@
+// Weight of a new idle sample in the baseline, as a power of two: every
+// sample moves the baseline by 1/64 of its distance from it, so the baseline
+// follows the slow drifts caused by temperature and humidity but not the
+// touches.
+const DRIFT_SHIFT: u32 = 6;
+
+// Capacitive touch pad detector.
+//
+// It is fed with the raw readings of the pad, which grow while it is touched,
+// and reports a touch once a reading exceeds the baseline by the threshold.
+// The touch ends only once the readings fall below a lower threshold, so the
+// noise around the threshold does not produce spurious touches.
+pub struct TouchDetector {
+    // Idle reading, scaled by `2^DRIFT_SHIFT` so the slow drift is not
+    // rounded away.
+    scaled_baseline: u64,
+    // Increase over the baseline starting and ending a touch, in percent.
+    touch_percent: u32,
+    release_percent: u32,
+    // Samples after which a touch is considered a drift of the baseline, so
+    // a pad which stays above the threshold does not stick.
+    max_touch_samples: u32,
+    touch_samples: u32,
+}
+
+impl TouchDetector {
+    // Creates a detector for an idle pad reading `baseline`, touched once its
+    // readings exceed the baseline by `threshold_percent`, released once they
+    // fall below half of that.
+    pub const fn new(baseline: u32, threshold_percent: u8, max_touch_samples: u32) -> Self {
+        let touch_percent = threshold_percent as u32;
+        Self {
+            scaled_baseline: (baseline as u64) << DRIFT_SHIFT,
+            touch_percent,
+            release_percent: touch_percent / 2,
+            max_touch_samples,
+            touch_samples: 0,
+        }
+    }
+
+    // Current idle reading of the pad.
+    pub const fn baseline(&self) -> u32 {
+        // The baseline is scaled from a `u32`, so it fits once scaled back.
+        (self.scaled_baseline >> DRIFT_SHIFT) as u32
+    }
+
+    // Whether the pad is touched.
+    pub const fn is_touched(&self) -> bool {
+        self.touch_samples > 0
+    }
+
+    // Feeds a raw reading, returning whether the pad is touched.
+    pub fn update(&mut self, raw: u32) -> bool {
+        let percent = if self.is_touched() {
+            self.release_percent
+        } else {
+            self.touch_percent
+        };
+        let baseline = u64::from(self.baseline());
+        let touched = u64::from(raw) * 100 > baseline * (100 + u64::from(percent));
+
+        if !touched {
+            self.touch_samples = 0;
+            self.track(raw);
+        } else if self.touch_samples >= self.max_touch_samples {
+            // Nobody holds a pad that long, the baseline moved instead.
+            self.touch_samples = 0;
+            self.scaled_baseline = u64::from(raw) << DRIFT_SHIFT;
+        } else {
+            self.touch_samples += 1;
+        }
+        self.is_touched()
+    }
+
+    // Moves the baseline towards an idle reading.
+    fn track(&mut self, raw: u32) {
+        let scaled_raw = u64::from(raw) << DRIFT_SHIFT;
+        if scaled_raw > self.scaled_baseline {
+            self.scaled_baseline += (scaled_raw - self.scaled_baseline) >> DRIFT_SHIFT;
+        } else {
+            self.scaled_baseline -= (self.scaled_baseline - scaled_raw) >> DRIFT_SHIFT;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASELINE: u32 = 10_000;
+    const MAX_TOUCH_SAMPLES: u32 = 100;
+
+    fn detector() -> TouchDetector {
+        TouchDetector::new(BASELINE, 10, MAX_TOUCH_SAMPLES)
+    }
+
+    #[test]
+    fn readings_above_the_threshold_are_touches() {
+        let mut detector = detector();
+
+        assert!(!detector.update(10_500));
+        assert!(detector.update(11_500));
+        assert!(!detector.update(BASELINE));
+    }
+
+    #[test]
+    fn touch_ends_below_the_lower_threshold() {
+        let mut detector = detector();
+
+        assert!(detector.update(11_500));
+        // Still above the release threshold, at 5% over the baseline.
+        assert!(detector.update(10_900));
+        assert!(detector.update(10_600));
+        assert!(!detector.update(10_400));
+    }
+
+    #[test]
+    fn baseline_follows_the_idle_drift() {
+        let mut detector = detector();
+
+        // The idle reading slowly rises by 20%, which would be a touch
+        // for the initial baseline.
+        for raw in (BASELINE..=12_000).step_by(10) {
+            assert!(!detector.update(raw), "{raw}");
+        }
+        assert!(detector.baseline() > 11_000);
+        assert!(detector.update(13_500));
+    }
+
+    #[test]
+    fn touches_do_not_move_the_baseline() {
+        let mut detector = detector();
+
+        for _ in 0..MAX_TOUCH_SAMPLES {
+            assert!(detector.update(12_000));
+        }
+        assert_eq!(detector.baseline(), BASELINE);
+    }
+
+    #[test]
+    fn stuck_touch_becomes_the_baseline() {
+        let mut detector = detector();
+
+        for _ in 0..MAX_TOUCH_SAMPLES {
+            detector.update(12_000);
+        }
+        assert!(!detector.update(12_000));
+        assert_eq!(detector.baseline(), 12_000);
+        assert!(!detector.update(12_100));
+    }
+}
@@ -0,0 +1,159 @@
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+
+use esp_hal::analog::adc::{
+    Adc, AdcCalBasic, AdcCalCurve, AdcCalScheme, AdcConfig, AdcPin, Attenuation,
+};
+use esp_hal::peripherals::ADC1;
+use esp_hal::Blocking;
+
+use serde::Serialize;
+
+// Samples averaged by every reading, smoothing the noise of the ADC.
+const BURST_SAMPLES: u32 = 16;
+// Attenuation of the input, measuring up to about 2.5 V.
+const ATTENUATION: Attenuation = Attenuation::_11dB;
+
+// ADC pin returning the raw readings, offset by the calibrated init code
+// only, so they can be converted to millivolts afterwards.
+type RawPin<PIN> = AdcPin<PIN, ADC1<'static>, AdcCalBasic<ADC1<'static>>>;
+
+// Declares the GPIOs connected to ADC1, which differ between chips. ADC2 is
+// unusable, since Wi-Fi takes it.
+macro_rules! analog_pins {
+    ($range:literal, $($number:literal => $variant:ident($gpio:ident)),+ $(,)?) => {
+        use esp_hal::peripherals::{$($gpio),+};
+
+        // GPIOs of the analog sensor, for the error messages.
+        pub(crate) const GPIO_RANGE: &str = $range;
+
+        enum AnalogPin {
+            $($variant(RawPin<$gpio<'static>>)),+
+        }
+
+        impl AnalogPin {
+            // Configures the ADC to read the given GPIO, if connected to ADC1.
+            //
+            // SAFETY: the caller must own the GPIO, and nothing else may use
+            // it.
+            unsafe fn steal(config: &mut AdcConfig<ADC1<'static>>, gpio: u8) -> Option<Self> {
+                // SAFETY: guaranteed by the caller.
+                unsafe {
+                    match gpio {
+                        $($number => Some(Self::$variant(
+                            config.enable_pin_with_cal($gpio::steal(), ATTENUATION),
+                        )),)+
+                        _ => None,
+                    }
+                }
+            }
+
+            fn read(&mut self, adc: &mut Adc<'static, ADC1<'static>, Blocking>) -> u16 {
+                let sample = match self {
+                    $(Self::$variant(pin) => nb::block!(adc.read_oneshot(pin)),)+
+                };
+                // Reading a configured pin never fails.
+                sample.unwrap_or_default()
+            }
+        }
+    };
+}
+
+#[cfg(feature = "esp32c3")]
+analog_pins!(
+    "GPIO0 to GPIO4",
+    0 => Gpio0(GPIO0),
+    1 => Gpio1(GPIO1),
+    2 => Gpio2(GPIO2),
+    3 => Gpio3(GPIO3),
+    4 => Gpio4(GPIO4),
+);
+
+#[cfg(feature = "esp32c6")]
+analog_pins!(
+    "GPIO0 to GPIO6",
+    0 => Gpio0(GPIO0),
+    1 => Gpio1(GPIO1),
+    2 => Gpio2(GPIO2),
+    3 => Gpio3(GPIO3),
+    4 => Gpio4(GPIO4),
+    5 => Gpio5(GPIO5),
+    6 => Gpio6(GPIO6),
+);
+
+#[cfg(feature = "esp32s3")]
+analog_pins!(
+    "GPIO1 to GPIO10",
+    1 => Gpio1(GPIO1),
+    2 => Gpio2(GPIO2),
+    3 => Gpio3(GPIO3),
+    4 => Gpio4(GPIO4),
+    5 => Gpio5(GPIO5),
+    6 => Gpio6(GPIO6),
+    7 => Gpio7(GPIO7),
+    8 => Gpio8(GPIO8),
+    9 => Gpio9(GPIO9),
+    10 => Gpio10(GPIO10),
+);
+
+// ADC with the pin it reads.
+struct AnalogSensor {
+    adc: Adc<'static, ADC1<'static>, Blocking>,
+    pin: AnalogPin,
+    // Conversion from raw readings to millivolts, from the eFuse calibration.
+    curve: AdcCalCurve<ADC1<'static>>,
+}
+
+impl AnalogSensor {
+    // Average of a burst of raw samples. Every conversion takes a few
+    // microseconds, so waiting for them is cheaper than an interrupt.
+    fn read_raw(&mut self) -> u16 {
+        let mut sum = 0;
+        for _ in 0..BURST_SAMPLES {
+            sum += u32::from(self.pin.read(&mut self.adc));
+        }
+        // The average of 12-bit samples always fits.
+        (sum / BURST_SAMPLES) as u16
+    }
+}
+
+// Analog sensor, if configured. Only one reading at a time can use the ADC,
+// so concurrent requests wait for each other.
+static SENSOR: Mutex<CriticalSectionRawMutex, Option<AnalogSensor>> = Mutex::new(None);
+
+// Reading of the analog sensor, returned by the `/adc` route.
+#[derive(Clone, Copy, Serialize)]
+pub(crate) struct AnalogReading {
+    pub(crate) raw: u16,
+    pub(crate) millivolts: u16,
+}
+
+// Configures the ADC to read the given GPIO, returning `false` when it is
+// not connected to ADC1.
+//
+// The GPIO must be owned by the caller, which gives it up.
+pub(crate) async fn init(adc: ADC1<'static>, gpio: u8) -> bool {
+    let mut config = AdcConfig::new();
+    // SAFETY: the caller owns the GPIO, and nothing else uses it.
+    let Some(pin) = (unsafe { AnalogPin::steal(&mut config, gpio) }) else {
+        return false;
+    };
+
+    *SENSOR.lock().await = Some(AnalogSensor {
+        adc: Adc::new(adc, config),
+        pin,
+        curve: AdcCalCurve::new_cal(ATTENUATION),
+    });
+    true
+}
+
+// Samples the analog sensor, if configured.
+pub(crate) async fn read() -> Option<AnalogReading> {
+    let mut sensor = SENSOR.lock().await;
+    let sensor = sensor.as_mut()?;
+    let raw = sensor.read_raw();
+    Some(AnalogReading {
+        raw,
+        millivolts: sensor.curve.adc_val(raw),
+    })
+}
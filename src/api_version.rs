@@ -0,0 +1,85 @@
+use picoserve::io::Read;
+use picoserve::request::RequestParts;
+use picoserve::response::{
+    Body, Connection, HeadersIter, IntoResponse, Json, Response, ResponseWriter, StatusCode,
+};
+use picoserve::routing::{Layer, Next};
+use picoserve::ResponseSent;
+
+use serde::Serialize;
+
+use button_led_logic::api_version::requested_version;
+pub(crate) use button_led_logic::api_version::{unversioned, API_PREFIX, API_VERSION};
+
+// Body of the `404 Not Found` answering a request to an unsupported version,
+// so clients can fall back to a supported one.
+#[derive(Serialize)]
+struct UnsupportedVersion {
+    error: &'static str,
+    highest_version: u32,
+}
+
+// Response writer adding the API version to a response.
+struct AddVersion<W> {
+    writer: W,
+}
+
+impl<W: ResponseWriter> ResponseWriter for AddVersion<W> {
+    type Error = W::Error;
+
+    async fn write_response<R: Read<Error = Self::Error>, H: HeadersIter, B: Body>(
+        self,
+        connection: Connection<'_, R>,
+        response: Response<H, B>,
+    ) -> Result<ResponseSent, Self::Error> {
+        self.writer
+            .write_response(
+                connection,
+                response.with_header("X-Api-Version", API_VERSION),
+            )
+            .await
+    }
+}
+
+// Layer adding the API version to every response, and answering the requests
+// to an unsupported version of the API with `404 Not Found`.
+pub(crate) struct ApiVersion;
+
+impl<State, PathParameters> Layer<State, PathParameters> for ApiVersion {
+    type NextState = State;
+    type NextPathParameters = PathParameters;
+
+    async fn call_layer<
+        'a,
+        R: Read + 'a,
+        NextLayer: Next<'a, R, State, PathParameters>,
+        W: ResponseWriter<Error = R::Error>,
+    >(
+        &self,
+        next: NextLayer,
+        state: &State,
+        path_parameters: PathParameters,
+        request_parts: RequestParts<'_>,
+        response_writer: W,
+    ) -> Result<ResponseSent, W::Error> {
+        let response_writer = AddVersion {
+            writer: response_writer,
+        };
+
+        let version = requested_version(request_parts.path().encoded());
+        if version.is_some_and(|version| version != API_VERSION) {
+            let connection = next.into_connection().await?;
+            return (
+                StatusCode::NOT_FOUND,
+                Json(UnsupportedVersion {
+                    error: "Unsupported API version",
+                    highest_version: API_VERSION,
+                }),
+            )
+                .write_to(connection, response_writer)
+                .await;
+        }
+
+        next.run(state, path_parameters, response_writer).await
+    }
+}
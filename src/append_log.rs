@@ -0,0 +1,81 @@
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+
+use esp_storage::{FlashStorage, FlashStorageError};
+
+// Size of the flash sector holding a log.
+const SECTOR_SIZE: u32 = 4096;
+// Bytes read from flash at once while looking for the last record.
+const READ_SIZE: usize = 256;
+// Offset of a full sector, so the next record erases it first.
+pub(crate) const FULL: u32 = SECTOR_SIZE;
+
+// Records of `N` bytes appended to a flash sector, which is erased only once
+// full, so every write does not wear the flash.
+//
+// Every record must tell a complete write apart from an interrupted one, and
+// must not be erased flash, filled with ones.
+pub(crate) struct AppendLog<const N: usize> {
+    // Offset of the sector in flash.
+    offset: u32,
+}
+
+impl<const N: usize> AppendLog<N> {
+    const RECORD_SIZE: u32 = N as u32;
+
+    pub(crate) const fn new(offset: u32) -> Self {
+        Self { offset }
+    }
+
+    // Finds the last complete record, decoded by the given function, and the
+    // offset of the first free record.
+    //
+    // A sector without any complete record is reported as full, so it is
+    // erased rather than appended to.
+    pub(crate) fn find_last<T>(
+        &self,
+        flash: &mut FlashStorage,
+        decode: impl Fn(&[u8; N]) -> Option<T>,
+    ) -> Result<(Option<T>, u32), FlashStorageError> {
+        let mut last = None;
+        let mut buffer = [0; READ_SIZE];
+        let mut offset = 0;
+        while offset + Self::RECORD_SIZE <= SECTOR_SIZE {
+            // Only whole records are read, up to the end of the sector.
+            let records_left = ((SECTOR_SIZE - offset) / Self::RECORD_SIZE) as usize;
+            let bytes = &mut buffer[..(READ_SIZE / N).min(records_left) * N];
+            flash.read(self.offset + offset, bytes)?;
+            for record in bytes.as_chunks::<N>().0 {
+                // Erased flash is filled with ones.
+                if record.iter().all(|byte| *byte == 0xFF) {
+                    let offset = if last.is_none() && offset > 0 {
+                        SECTOR_SIZE
+                    } else {
+                        offset
+                    };
+                    return Ok((last, offset));
+                }
+                last = decode(record).or(last);
+                offset += Self::RECORD_SIZE;
+            }
+        }
+        Ok((last, SECTOR_SIZE))
+    }
+
+    // Appends a record at the given offset, erasing the sector first when
+    // full, and returns the offset of the next free record.
+    pub(crate) fn store(
+        &self,
+        flash: &mut FlashStorage,
+        record: &[u8; N],
+        offset: u32,
+    ) -> Result<u32, FlashStorageError> {
+        let offset = if offset + Self::RECORD_SIZE > SECTOR_SIZE {
+            flash.erase(self.offset, self.offset + SECTOR_SIZE)?;
+            0
+        } else {
+            offset
+        };
+        NorFlash::write(flash, self.offset + offset, record)?;
+        Ok(offset + Self::RECORD_SIZE)
+    }
+}
@@ -0,0 +1,123 @@
+use core::cell::Cell;
+use core::net::Ipv4Addr;
+use core::task::Context;
+
+use embassy_net_driver::{Capabilities, Driver, HardwareAddress, LinkState, TxToken};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::waitqueue::AtomicWaker;
+
+use log::info;
+
+// Size of an ARP packet in an Ethernet frame: the Ethernet header, followed
+// by the ARP packet for IPv4 over Ethernet.
+const ARP_FRAME_SIZE: usize = 14 + 28;
+const ETHERTYPE_ARP: [u8; 2] = [0x08, 0x06];
+const ETHERTYPE_IPV4: [u8; 2] = [0x08, 0x00];
+const HARDWARE_ETHERNET: [u8; 2] = [0x00, 0x01];
+const OPERATION_REQUEST: [u8; 2] = [0x00, 0x01];
+const BROADCAST: [u8; 6] = [0xFF; 6];
+
+// Address waiting to be announced, if any.
+static PENDING: Mutex<CriticalSectionRawMutex, Cell<Option<Ipv4Addr>>> =
+    Mutex::new(Cell::new(None));
+// Wakes the network stack up, so it polls the device and sends the
+// announcement.
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+// Announces the given address with a gratuitous ARP request, so the switches
+// and the router update their ARP entry of the device right away.
+pub(crate) fn announce(ip: Ipv4Addr) {
+    PENDING.lock(|pending| pending.set(Some(ip)));
+    WAKER.wake();
+}
+
+// Writes an ARP announcement (RFC 5227), a broadcast request whose sender and
+// target addresses are both the announced one.
+fn write_announcement(frame: &mut [u8], mac: [u8; 6], ip: Ipv4Addr) {
+    let ip = ip.octets();
+    let fields: [&[u8]; 11] = [
+        // Ethernet header.
+        &BROADCAST,
+        &mac,
+        &ETHERTYPE_ARP,
+        // ARP packet.
+        &HARDWARE_ETHERNET,
+        &ETHERTYPE_IPV4,
+        &[6, 4],
+        &OPERATION_REQUEST,
+        &mac,
+        &ip,
+        &[0; 6],
+        &ip,
+    ];
+    let mut offset = 0;
+    for field in fields {
+        frame[offset..offset + field.len()].copy_from_slice(field);
+        offset += field.len();
+    }
+}
+
+// Network device sending the pending ARP announcements before the frames of
+// the stack, since embassy-net does not expose the ARP layer of smoltcp.
+pub(crate) struct AnnouncingDevice<D> {
+    inner: D,
+}
+
+impl<D: Driver> AnnouncingDevice<D> {
+    pub(crate) const fn new(inner: D) -> Self {
+        Self { inner }
+    }
+
+    // Sends the pending announcement, once the device can transmit.
+    fn send_announcement(&mut self, cx: &mut Context) {
+        WAKER.register(cx.waker());
+        let Some(ip) = PENDING.lock(Cell::get) else {
+            return;
+        };
+        let HardwareAddress::Ethernet(mac) = self.inner.hardware_address() else {
+            PENDING.lock(|pending| pending.set(None));
+            return;
+        };
+        // The stack is woken up again once the device can transmit.
+        let Some(token) = self.inner.transmit(cx) else {
+            return;
+        };
+        token.consume(ARP_FRAME_SIZE, |frame| write_announcement(frame, mac, ip));
+        PENDING.lock(|pending| pending.set(None));
+        info!("Sent gratuitous ARP for {ip}");
+    }
+}
+
+impl<D: Driver> Driver for AnnouncingDevice<D> {
+    type RxToken<'a>
+        = D::RxToken<'a>
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = D::TxToken<'a>
+    where
+        Self: 'a;
+
+    fn receive(&mut self, cx: &mut Context) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        self.send_announcement(cx);
+        self.inner.receive(cx)
+    }
+
+    fn transmit(&mut self, cx: &mut Context) -> Option<Self::TxToken<'_>> {
+        self.send_announcement(cx);
+        self.inner.transmit(cx)
+    }
+
+    fn link_state(&mut self, cx: &mut Context) -> LinkState {
+        self.inner.link_state(cx)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn hardware_address(&self) -> HardwareAddress {
+        self.inner.hardware_address()
+    }
+}
@@ -0,0 +1,162 @@
+use picoserve::extract::{FromRequestParts, Query};
+use picoserve::io::Read;
+use picoserve::request::RequestParts;
+use picoserve::response::{IntoResponse, ResponseWriter, StatusCode};
+use picoserve::routing::{Layer, Next};
+use picoserve::ResponseSent;
+
+use serde::Deserialize;
+
+pub(crate) use button_led_logic::basic_auth::keys_match;
+use button_led_logic::basic_auth::{credentials_match, MAX_PASS_LEN, MAX_USER_LEN};
+
+use crate::DEVICE_CONFIG;
+
+// Header carrying the API key.
+const API_KEY_HEADER: &str = "X-Api-Key";
+// Maximum length of an API key passed as query parameter.
+const MAX_API_KEY_LEN: usize = 64;
+
+// Query parameter carrying the API key, ignored by the routes.
+#[derive(Deserialize)]
+struct KeyQuery {
+    key: Option<heapless::String<MAX_API_KEY_LEN>>,
+}
+
+// Whether a request carries the configured API key, either in the
+// `X-Api-Key` header or in the `key` query parameter.
+//
+// Every request is authorized when no API key is configured.
+pub(crate) struct ApiKey {
+    pub(crate) authorized: bool,
+}
+
+impl<'r, State> FromRequestParts<'r, State> for ApiKey {
+    type Rejection = core::convert::Infallible;
+
+    async fn from_request_parts(
+        state: &'r State,
+        request_parts: &RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        let expected = DEVICE_CONFIG.api_key.as_bytes();
+        if expected.is_empty() {
+            return Ok(Self { authorized: true });
+        }
+
+        if let Some(key) = request_parts.headers().get(API_KEY_HEADER) {
+            return Ok(Self {
+                authorized: keys_match(key.as_raw(), expected),
+            });
+        }
+
+        let key = <Query<KeyQuery>>::from_request_parts(state, request_parts)
+            .await
+            .ok()
+            .and_then(|Query(query)| query.key);
+        Ok(Self {
+            authorized: key.is_some_and(|key| keys_match(key.as_bytes(), expected)),
+        })
+    }
+}
+
+// Extractor rejecting the requests of mutating routes which lack the
+// configured API key, with a `401 Unauthorized` response.
+pub(crate) struct Authorized;
+
+impl<'r, State> FromRequestParts<'r, State> for Authorized {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(
+        state: &'r State,
+        request_parts: &RequestParts<'r>,
+    ) -> Result<Self, Self::Rejection> {
+        let ApiKey { authorized } = ApiKey::from_request_parts(state, request_parts)
+            .await
+            .unwrap_or_else(|never| match never {});
+        if authorized {
+            Ok(Self)
+        } else {
+            log::warn!(
+                "Request to {} rejected, invalid API key",
+                request_parts.path()
+            );
+            Err((StatusCode::UNAUTHORIZED, "Missing or invalid API key\n"))
+        }
+    }
+}
+
+// Realm of the Basic authentication challenge.
+const BASIC_AUTH_CHALLENGE: &str = "Basic realm=\"button-led\", charset=\"UTF-8\"";
+
+// The configured credentials must fit the decoded ones.
+const _: () = assert!(
+    DEVICE_CONFIG.http_user.len() <= MAX_USER_LEN,
+    "http_user is too long"
+);
+const _: () = assert!(
+    DEVICE_CONFIG.http_pass.len() <= MAX_PASS_LEN,
+    "http_pass is too long"
+);
+
+// Layer requiring HTTP Basic authentication for every route, when a user is
+// configured.
+//
+// It is disabled in provisioning mode, so a device can always be provisioned.
+pub(crate) struct BasicAuth {
+    enabled: bool,
+}
+
+impl BasicAuth {
+    pub(crate) const fn new(provisioning: bool) -> Self {
+        Self {
+            enabled: !provisioning && !DEVICE_CONFIG.http_user.is_empty(),
+        }
+    }
+}
+
+impl<State, PathParameters> Layer<State, PathParameters> for BasicAuth {
+    type NextState = State;
+    type NextPathParameters = PathParameters;
+
+    async fn call_layer<
+        'a,
+        R: Read + 'a,
+        NextLayer: Next<'a, R, State, PathParameters>,
+        W: ResponseWriter<Error = R::Error>,
+    >(
+        &self,
+        next: NextLayer,
+        state: &State,
+        path_parameters: PathParameters,
+        request_parts: RequestParts<'_>,
+        response_writer: W,
+    ) -> Result<ResponseSent, W::Error> {
+        let authorized = !self.enabled
+            || request_parts
+                .headers()
+                .get("Authorization")
+                .is_some_and(|authorization| {
+                    credentials_match(
+                        authorization.as_raw(),
+                        DEVICE_CONFIG.http_user.as_bytes(),
+                        DEVICE_CONFIG.http_pass.as_bytes(),
+                    )
+                });
+        if authorized {
+            return next.run(state, path_parameters, response_writer).await;
+        }
+
+        log::warn!(
+            "Request to {} rejected, invalid credentials",
+            request_parts.path()
+        );
+        let connection = next.into_connection().await?;
+        (
+            StatusCode::UNAUTHORIZED,
+            ("WWW-Authenticate", BASIC_AUTH_CHALLENGE),
+            "Authentication required\n",
+        )
+            .write_to(connection, response_writer)
+            .await
+    }
+}
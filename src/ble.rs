@@ -0,0 +1,249 @@
+use embassy_futures::join::join;
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+
+use esp_wifi::ble::controller::{BleConnector, BleConnectorError};
+
+use log::{error, info, warn};
+
+use trouble_host::prelude::*;
+
+use crate::led::{LedCommand, LedInput, Source, MAIN_CHANNEL};
+use crate::state::{self, LedState, NOTIFY_LED};
+use crate::udp_control::{COMMAND_OFF, COMMAND_ON, COMMAND_TOGGLE};
+
+// Commands queued by the controller before the host handles them.
+const CONTROLLER_SLOTS: usize = 20;
+// Only one central at a time, which keeps the host resources small.
+const CONNECTIONS_MAX: usize = 1;
+// L2CAP channels, the signaling and ATT ones.
+const L2CAP_CHANNELS_MAX: usize = 2;
+// Maximum size of the advertising and scan response data.
+const ADVERTISING_DATA_SIZE: usize = 31;
+// Maximum length of the local name in the scan response data.
+const MAX_NAME_LEN: usize = ADVERTISING_DATA_SIZE - 2;
+
+// UUID of the led service, in the little-endian order of the advertising
+// data.
+const SERVICE_UUID: [u8; 16] = [
+    0x20, 0x1c, 0x0b, 0x8f, 0x3e, 0x6d, 0x1a, 0x9c, 0x8e, 0x4b, 0x5d, 0x2f, 0xe0, 0xb0, 0xc4, 0xa7,
+];
+
+type Controller = ExternalController<BleConnector<'static>, CONTROLLER_SLOTS>;
+
+// Led state changes, notified to the connected central.
+static LED_CHANGED: Signal<CriticalSectionRawMutex, LedState> = Signal::new();
+
+// Notifies a led state change to the connected central, if any.
+pub(crate) fn publish(led_state: LedState) {
+    LED_CHANGED.signal(led_state);
+}
+
+#[gatt_server]
+struct Server {
+    led: LedService,
+}
+
+// Led service: the same commands as the UDP control, the led state and the
+// IP address, so the device can be found and controlled without Wi-Fi.
+#[gatt_service(uuid = "a7c4b0e0-2f5d-4b8e-9c1a-6d3e8f0b1c20")]
+struct LedService {
+    // 0x00 turns the led off, 0x01 on and 0x02 toggles it.
+    #[characteristic(uuid = "a7c4b0e0-2f5d-4b8e-9c1a-6d3e8f0b1c21", write)]
+    command: u8,
+    // 0x00 when the led is off, 0x01 when it is on.
+    #[characteristic(uuid = "a7c4b0e0-2f5d-4b8e-9c1a-6d3e8f0b1c22", read, notify)]
+    state: u8,
+    // IPv4 address of the device, 0.0.0.0 while it has none.
+    #[characteristic(uuid = "a7c4b0e0-2f5d-4b8e-9c1a-6d3e8f0b1c23", read)]
+    ip_address: [u8; 4],
+}
+
+impl Server<'_> {
+    // Refreshes the values read by the central.
+    fn refresh(&self) {
+        let _ = self.set(
+            &self.led.state,
+            &led_state_byte(state::led_state(MAIN_CHANNEL)),
+        );
+        let _ = self.set(
+            &self.led.ip_address,
+            &state::ip_address().map_or([0; 4], |ip| ip.octets()),
+        );
+    }
+}
+
+const fn led_state_byte(led_state: LedState) -> u8 {
+    match led_state {
+        LedState::On => COMMAND_ON,
+        LedState::Off => COMMAND_OFF,
+    }
+}
+
+// Static random address derived from the MAC address, in the little-endian
+// order of the controller, with the two most significant bits set as
+// required for static addresses.
+fn random_address(mac: [u8; 6]) -> Address {
+    let mut address = mac;
+    address.reverse();
+    address[5] |= 0xc0;
+    Address::random(address)
+}
+
+// Advertises the led service with the hostname, until a central connects.
+async fn advertise<'values, 'server>(
+    hostname: &str,
+    peripheral: &mut Peripheral<'values, Controller, DefaultPacketPool>,
+    server: &'server Server<'values>,
+) -> Result<GattConnection<'values, 'server, DefaultPacketPool>, BleHostError<BleConnectorError>> {
+    let mut adv_data = [0; ADVERTISING_DATA_SIZE];
+    let adv_len = AdStructure::encode_slice(
+        &[
+            AdStructure::Flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED),
+            AdStructure::ServiceUuids128(&[SERVICE_UUID]),
+        ],
+        &mut adv_data,
+    )?;
+
+    // The name goes in the scan response, since the service UUID takes most
+    // of the advertising data.
+    let name = hostname.as_bytes();
+    let name = if name.len() > MAX_NAME_LEN {
+        AdStructure::ShortenedLocalName(&name[..MAX_NAME_LEN])
+    } else {
+        AdStructure::CompleteLocalName(name)
+    };
+    let mut scan_data = [0; ADVERTISING_DATA_SIZE];
+    let scan_len = AdStructure::encode_slice(&[name], &mut scan_data)?;
+
+    let advertiser = peripheral
+        .advertise(
+            &AdvertisementParameters::default(),
+            Advertisement::ConnectableScannableUndirected {
+                adv_data: &adv_data[..adv_len],
+                scan_data: &scan_data[..scan_len],
+            },
+        )
+        .await?;
+    let connection = advertiser.accept().await?.with_attribute_server(server)?;
+    Ok(connection)
+}
+
+// Handles the requests of a connected central, and notifies it of the led
+// state changes, until it disconnects.
+async fn serve(server: &Server<'_>, connection: &GattConnection<'_, '_, DefaultPacketPool>) {
+    let led = &server.led;
+    loop {
+        let event = match select(connection.next(), LED_CHANGED.wait()).await {
+            Either::First(GattConnectionEvent::Disconnected { reason }) => {
+                info!("BLE central disconnected: {reason:?}");
+                return;
+            }
+            Either::First(GattConnectionEvent::Gatt { event }) => event,
+            Either::First(_) => continue,
+            Either::Second(led_state) => {
+                if let Err(e) = led
+                    .state
+                    .notify(connection, &led_state_byte(led_state))
+                    .await
+                {
+                    warn!("Failed to notify the led state over BLE: {e:?}");
+                }
+                continue;
+            }
+        };
+
+        let reply = match &event {
+            GattEvent::Read(_) => {
+                server.refresh();
+                event.accept()
+            }
+            GattEvent::Write(write) if write.handle() == led.command.handle => {
+                let led_input = match write.data() {
+                    [COMMAND_OFF] => Some(LedInput::Off { fade_ms: None }),
+                    [COMMAND_ON] => Some(LedInput::On {
+                        fade_ms: None,
+                        auto_off_secs: None,
+                    }),
+                    [COMMAND_TOGGLE] => Some(LedInput::Toggle),
+                    _ => None,
+                };
+                match led_input {
+                    Some(led_input) => {
+                        if NOTIFY_LED
+                            .try_send(LedCommand::new(Source::Ble, led_input))
+                            .is_err()
+                        {
+                            warn!("Led channel is full, BLE command dropped!");
+                        }
+                        event.accept()
+                    }
+                    None => event.reject(AttErrorCode::VALUE_NOT_ALLOWED),
+                }
+            }
+            _ => event.accept(),
+        };
+        match reply {
+            Ok(reply) => reply.send().await,
+            Err(e) => warn!("Failed to answer a BLE request: {e:?}"),
+        }
+    }
+}
+
+// Exposes the led through a GATT service, so it can be controlled from a
+// phone when Wi-Fi is unavailable.
+//
+// The Bluetooth controller shares the radio with Wi-Fi through coexistence,
+// and a single central can connect at a time.
+#[embassy_executor::task]
+pub(crate) async fn ble_task(
+    connector: BleConnector<'static>,
+    hostname: &'static str,
+    mac: [u8; 6],
+) {
+    let controller: Controller = ExternalController::new(connector);
+    let mut resources: HostResources<DefaultPacketPool, CONNECTIONS_MAX, L2CAP_CHANNELS_MAX> =
+        HostResources::new();
+    let stack =
+        trouble_host::new(controller, &mut resources).set_random_address(random_address(mac));
+    let Host {
+        mut peripheral,
+        mut runner,
+        ..
+    } = stack.build();
+
+    let server = match Server::new_with_config(GapConfig::Peripheral(PeripheralConfig {
+        name: hostname,
+        appearance: &appearance::light_source::GENERIC_LIGHT_SOURCE,
+    })) {
+        Ok(server) => server,
+        Err(e) => {
+            error!("Failed to create the BLE GATT server: {e}");
+            return;
+        }
+    };
+
+    let host = async {
+        if let Err(e) = runner.run().await {
+            error!("BLE host stopped: {e:?}");
+        }
+    };
+    let peripheral = async {
+        info!("BLE advertising as {hostname}");
+        loop {
+            server.refresh();
+            match advertise(hostname, &mut peripheral, &server).await {
+                Ok(connection) => {
+                    info!("BLE central connected");
+                    serve(&server, &connection).await;
+                }
+                Err(e) => {
+                    error!("BLE advertising failed: {e:?}");
+                    return;
+                }
+            }
+        }
+    };
+    join(host, peripheral).await;
+}
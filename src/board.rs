@@ -0,0 +1,291 @@
+use esp_hal::gpio::{AnyPin, Pin};
+#[cfg(not(feature = "esp32s3"))]
+use esp_hal::peripherals::TSENS;
+use esp_hal::peripherals::{Peripherals, ADC1, BT, I2C0, LEDC, LPWR, RMT, TIMG0, TIMG1, WIFI};
+use esp_hal::rng::Rng;
+use esp_hal::rtc_cntl::SocResetReason;
+use esp_hal::timer::systimer::{Alarm, SystemTimer};
+
+use crate::error::FirmwareError;
+use crate::DEVICE_CONFIG;
+
+#[cfg(not(any(feature = "esp32c3", feature = "esp32c6", feature = "esp32s3")))]
+compile_error!("A chip feature is required: `esp32c3`, `esp32c6` or `esp32s3`");
+
+#[cfg(any(
+    all(feature = "esp32c3", feature = "esp32c6"),
+    all(feature = "esp32c3", feature = "esp32s3"),
+    all(feature = "esp32c6", feature = "esp32s3"),
+))]
+compile_error!(
+    "Only one chip feature can be selected, build with `--no-default-features` to change it"
+);
+
+#[cfg(all(feature = "touch", not(feature = "esp32s3")))]
+compile_error!("The `touch` feature needs the `esp32s3` chip feature");
+
+// Default GPIOs of the led and the button, the ones of the on-board led and
+// BOOT button of the Espressif development kits.
+#[cfg(any(feature = "esp32c3", feature = "esp32c6"))]
+pub(crate) const DEFAULT_LED_GPIO: u8 = 8;
+#[cfg(any(feature = "esp32c3", feature = "esp32c6"))]
+pub(crate) const DEFAULT_BUTTON_GPIO: u8 = 9;
+#[cfg(feature = "esp32s3")]
+pub(crate) const DEFAULT_LED_GPIO: u8 = 48;
+#[cfg(feature = "esp32s3")]
+pub(crate) const DEFAULT_BUTTON_GPIO: u8 = 0;
+
+// Default type of the led, the ESP32-S3 development kit having only an
+// addressable one.
+#[cfg(any(feature = "esp32c3", feature = "esp32c6"))]
+pub(crate) const DEFAULT_LED_TYPE: &str = "pwm";
+#[cfg(feature = "esp32s3")]
+pub(crate) const DEFAULT_LED_TYPE: &str = "ws2812";
+
+// Chip name, advertised as the device model.
+#[cfg(feature = "esp32c3")]
+pub(crate) const MODEL: &str = "ESP32-C3";
+#[cfg(feature = "esp32c6")]
+pub(crate) const MODEL: &str = "ESP32-C6";
+#[cfg(feature = "esp32s3")]
+pub(crate) const MODEL: &str = "ESP32-S3";
+
+// Highest GPIO able to wake the chip from deep sleep, the last RTC GPIO.
+#[cfg(feature = "esp32c3")]
+pub(crate) const MAX_WAKE_GPIO: u8 = 5;
+#[cfg(feature = "esp32c6")]
+pub(crate) const MAX_WAKE_GPIO: u8 = 7;
+#[cfg(feature = "esp32s3")]
+pub(crate) const MAX_WAKE_GPIO: u8 = 21;
+
+// Resets caused by a software restart, a watchdog and a drop of the supply
+// voltage, which each chip names differently.
+#[cfg(any(feature = "esp32c3", feature = "esp32c6"))]
+pub(crate) const SOFTWARE_RESETS: &[SocResetReason] =
+    &[SocResetReason::CoreSw, SocResetReason::Cpu0Sw];
+#[cfg(feature = "esp32s3")]
+pub(crate) const SOFTWARE_RESETS: &[SocResetReason] =
+    &[SocResetReason::CoreSw, SocResetReason::CpuSw];
+#[cfg(any(feature = "esp32c3", feature = "esp32c6"))]
+pub(crate) const WATCHDOG_RESETS: &[SocResetReason] = &[
+    SocResetReason::CoreMwdt0,
+    SocResetReason::CoreMwdt1,
+    SocResetReason::CoreRtcWdt,
+    SocResetReason::Cpu0Mwdt0,
+    SocResetReason::Cpu0Mwdt1,
+    SocResetReason::Cpu0RtcWdt,
+    SocResetReason::SysRtcWdt,
+    SocResetReason::SysSuperWdt,
+];
+#[cfg(feature = "esp32s3")]
+pub(crate) const WATCHDOG_RESETS: &[SocResetReason] = &[
+    SocResetReason::CoreMwdt0,
+    SocResetReason::CoreMwdt1,
+    SocResetReason::CoreRtcWdt,
+    SocResetReason::CpuMwdt0,
+    SocResetReason::CpuMwdt1,
+    SocResetReason::CpuRtcWdt,
+    SocResetReason::SysRtcWdt,
+    SocResetReason::SysSuperWdt,
+];
+// The ESP32-C6 does not detect power glitches.
+#[cfg(any(feature = "esp32c3", feature = "esp32s3"))]
+pub(crate) const BROWNOUT_RESETS: &[SocResetReason] =
+    &[SocResetReason::SysBrownOut, SocResetReason::CorePwrGlitch];
+#[cfg(feature = "esp32c6")]
+pub(crate) const BROWNOUT_RESETS: &[SocResetReason] = &[SocResetReason::SysBrownOut];
+
+#[cfg(feature = "esp32c3")]
+const GPIO_COUNT: usize = 22;
+#[cfg(feature = "esp32c6")]
+const GPIO_COUNT: usize = 24;
+#[cfg(feature = "esp32s3")]
+const GPIO_COUNT: usize = 49;
+
+// GPIO pins which can be assigned to the devices, indexed by their number.
+pub(crate) struct Gpios([Option<AnyPin<'static>>; GPIO_COUNT]);
+
+impl Gpios {
+    // Takes the GPIO pin with the given number out of the available ones.
+    //
+    // Fails when the pin does not exist, cannot be used or has already been
+    // taken.
+    pub(crate) fn take(
+        &mut self,
+        number: u8,
+        name: &'static str,
+    ) -> Result<AnyPin<'static>, FirmwareError> {
+        self.0
+            .get_mut(usize::from(number))
+            .and_then(Option::take)
+            .ok_or(FirmwareError::GpioUnavailable { number, name })
+    }
+}
+
+// Peripherals of the board used by the firmware, whatever the chip.
+pub(crate) struct Board {
+    // Pins of the led and of the button, at their configured GPIOs. A pin
+    // which is unavailable fails the start of the firmware once it is used,
+    // so the led can still show a failure of the button.
+    pub(crate) led: Result<AnyPin<'static>, FirmwareError>,
+    pub(crate) button: Result<AnyPin<'static>, FirmwareError>,
+    // GPIO pins left for the other devices.
+    pub(crate) gpios: Gpios,
+    pub(crate) rng: Rng,
+    // Alarm driving the embassy timers.
+    pub(crate) embassy_alarm: Alarm<'static>,
+    // Timer group whose first timer drives the Wi-Fi scheduler.
+    pub(crate) wifi_timer: TIMG0<'static>,
+    // Timer group whose watchdog resets the stuck device.
+    pub(crate) watchdog_timer: TIMG1<'static>,
+    pub(crate) wifi: WIFI<'static>,
+    // Only used by the `ble` feature.
+    #[cfg_attr(not(feature = "ble"), allow(dead_code))]
+    pub(crate) bt: BT<'static>,
+    pub(crate) ledc: LEDC<'static>,
+    pub(crate) rmt: RMT<'static>,
+    pub(crate) lpwr: LPWR<'static>,
+    pub(crate) adc1: ADC1<'static>,
+    // Only used by the `display` feature.
+    #[cfg_attr(not(feature = "display"), allow(dead_code))]
+    pub(crate) i2c0: I2C0<'static>,
+    // Internal temperature sensor, which the ESP32-S3 driver lacks.
+    #[cfg(not(feature = "esp32s3"))]
+    pub(crate) tsens: TSENS<'static>,
+}
+
+impl Board {
+    pub(crate) fn new(peripherals: Peripherals) -> Self {
+        let timer = SystemTimer::new(peripherals.SYSTIMER);
+
+        #[cfg(feature = "esp32c3")]
+        let gpios = [
+            Some(peripherals.GPIO0.degrade()),
+            Some(peripherals.GPIO1.degrade()),
+            Some(peripherals.GPIO2.degrade()),
+            Some(peripherals.GPIO3.degrade()),
+            Some(peripherals.GPIO4.degrade()),
+            Some(peripherals.GPIO5.degrade()),
+            Some(peripherals.GPIO6.degrade()),
+            Some(peripherals.GPIO7.degrade()),
+            Some(peripherals.GPIO8.degrade()),
+            Some(peripherals.GPIO9.degrade()),
+            Some(peripherals.GPIO10.degrade()),
+            // GPIO11 to GPIO17 are connected to the SPI flash.
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            Some(peripherals.GPIO18.degrade()),
+            Some(peripherals.GPIO19.degrade()),
+            Some(peripherals.GPIO20.degrade()),
+            Some(peripherals.GPIO21.degrade()),
+        ];
+        // GPIO24 to GPIO30 are connected to the SPI flash.
+        #[cfg(feature = "esp32c6")]
+        let gpios = [
+            Some(peripherals.GPIO0.degrade()),
+            Some(peripherals.GPIO1.degrade()),
+            Some(peripherals.GPIO2.degrade()),
+            Some(peripherals.GPIO3.degrade()),
+            Some(peripherals.GPIO4.degrade()),
+            Some(peripherals.GPIO5.degrade()),
+            Some(peripherals.GPIO6.degrade()),
+            Some(peripherals.GPIO7.degrade()),
+            Some(peripherals.GPIO8.degrade()),
+            Some(peripherals.GPIO9.degrade()),
+            Some(peripherals.GPIO10.degrade()),
+            Some(peripherals.GPIO11.degrade()),
+            Some(peripherals.GPIO12.degrade()),
+            Some(peripherals.GPIO13.degrade()),
+            Some(peripherals.GPIO14.degrade()),
+            Some(peripherals.GPIO15.degrade()),
+            Some(peripherals.GPIO16.degrade()),
+            Some(peripherals.GPIO17.degrade()),
+            Some(peripherals.GPIO18.degrade()),
+            Some(peripherals.GPIO19.degrade()),
+            Some(peripherals.GPIO20.degrade()),
+            Some(peripherals.GPIO21.degrade()),
+            Some(peripherals.GPIO22.degrade()),
+            Some(peripherals.GPIO23.degrade()),
+        ];
+        #[cfg(feature = "esp32s3")]
+        let gpios = [
+            Some(peripherals.GPIO0.degrade()),
+            Some(peripherals.GPIO1.degrade()),
+            Some(peripherals.GPIO2.degrade()),
+            Some(peripherals.GPIO3.degrade()),
+            Some(peripherals.GPIO4.degrade()),
+            Some(peripherals.GPIO5.degrade()),
+            Some(peripherals.GPIO6.degrade()),
+            Some(peripherals.GPIO7.degrade()),
+            Some(peripherals.GPIO8.degrade()),
+            Some(peripherals.GPIO9.degrade()),
+            Some(peripherals.GPIO10.degrade()),
+            Some(peripherals.GPIO11.degrade()),
+            Some(peripherals.GPIO12.degrade()),
+            Some(peripherals.GPIO13.degrade()),
+            Some(peripherals.GPIO14.degrade()),
+            Some(peripherals.GPIO15.degrade()),
+            Some(peripherals.GPIO16.degrade()),
+            Some(peripherals.GPIO17.degrade()),
+            Some(peripherals.GPIO18.degrade()),
+            Some(peripherals.GPIO19.degrade()),
+            Some(peripherals.GPIO20.degrade()),
+            Some(peripherals.GPIO21.degrade()),
+            // GPIO22 to GPIO25 do not exist, GPIO26 to GPIO32 are connected
+            // to the SPI flash, and GPIO33 to GPIO37 to the octal PSRAM of
+            // the modules embedding one.
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            Some(peripherals.GPIO38.degrade()),
+            Some(peripherals.GPIO39.degrade()),
+            Some(peripherals.GPIO40.degrade()),
+            Some(peripherals.GPIO41.degrade()),
+            Some(peripherals.GPIO42.degrade()),
+            Some(peripherals.GPIO43.degrade()),
+            Some(peripherals.GPIO44.degrade()),
+            Some(peripherals.GPIO45.degrade()),
+            Some(peripherals.GPIO46.degrade()),
+            Some(peripherals.GPIO47.degrade()),
+            Some(peripherals.GPIO48.degrade()),
+        ];
+
+        let mut gpios = Gpios(gpios);
+        Self {
+            led: gpios.take(DEVICE_CONFIG.led_gpio, "led"),
+            button: gpios.take(DEVICE_CONFIG.button_gpio, "button"),
+            gpios,
+            rng: Rng::new(peripherals.RNG),
+            embassy_alarm: timer.alarm0,
+            wifi_timer: peripherals.TIMG0,
+            watchdog_timer: peripherals.TIMG1,
+            wifi: peripherals.WIFI,
+            bt: peripherals.BT,
+            ledc: peripherals.LEDC,
+            rmt: peripherals.RMT,
+            lpwr: peripherals.LPWR,
+            adc1: peripherals.ADC1,
+            i2c0: peripherals.I2C0,
+            #[cfg(not(feature = "esp32s3"))]
+            tsens: peripherals.TSENS,
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use esp_hal::rtc_cntl::SocResetReason;
+
+use embassy_time::Timer;
+
+use log::{info, warn};
+
+use crate::board;
+use crate::ota::RunningImage;
+use crate::state;
+
+// Consecutive abnormal resets after which the device boots in safe mode.
+const MAX_ABNORMAL_RESETS: u32 = 3;
+// Uptime after which the device is considered stable, clearing the count of
+// abnormal resets.
+const STABLE_UPTIME_SECS: u64 = 5 * 60;
+
+// Values marking the RTC RAM as written by this firmware, and the last reset
+// as caused by a panic.
+const RECORD_MAGIC: u32 = 0x4254_4e4c;
+const PANIC_MAGIC: u32 = 0x5041_4e43;
+
+// Boot record, kept in RTC RAM across every reset but power loss.
+//
+// The RAM holds random bytes until it is first written, which the magic value
+// detects.
+#[esp_hal::ram(rtc_fast, persistent)]
+static mut RECORD: u32 = 0;
+#[esp_hal::ram(rtc_fast, persistent)]
+static mut ABNORMAL_RESETS: u32 = 0;
+#[esp_hal::ram(rtc_fast, persistent)]
+static mut PANICKED: u32 = 0;
+#[esp_hal::ram(rtc_fast, persistent)]
+static mut OFFLINE_REBOOTS: u32 = 0;
+
+// Reason of the last reset.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BootReason {
+    PowerOn,
+    // Requested by the firmware, for example after an update.
+    Software,
+    // A panic or a CPU exception.
+    Panic,
+    Brownout,
+    Watchdog,
+    // Restarted to run a new firmware image, which has not been verified
+    // yet.
+    Update,
+    // Woken from deep sleep.
+    DeepSleep,
+    Other,
+}
+
+impl BootReason {
+    fn from_reset(reason: Option<SocResetReason>, panicked: bool, updated: bool) -> Self {
+        // Panics and updates end with a software reset.
+        if panicked {
+            return Self::Panic;
+        }
+        match reason {
+            Some(SocResetReason::ChipPowerOn) => Self::PowerOn,
+            Some(SocResetReason::CoreDeepSleep) => Self::DeepSleep,
+            Some(reason) if board::SOFTWARE_RESETS.contains(&reason) => {
+                if updated {
+                    Self::Update
+                } else {
+                    Self::Software
+                }
+            }
+            Some(reason) if board::BROWNOUT_RESETS.contains(&reason) => Self::Brownout,
+            Some(reason) if board::WATCHDOG_RESETS.contains(&reason) => Self::Watchdog,
+            _ => Self::Other,
+        }
+    }
+
+    // Whether the reset was not wanted, so it could repeat at every boot.
+    const fn is_abnormal(self) -> bool {
+        matches!(self, Self::Panic | Self::Brownout | Self::Watchdog)
+    }
+
+    // Boot reason as a lowercase string.
+    pub(crate) const fn as_str(self) -> &'static str {
+        match self {
+            Self::PowerOn => "power_on",
+            Self::Software => "software",
+            Self::Panic => "panic",
+            Self::Brownout => "brownout",
+            Self::Watchdog => "watchdog",
+            Self::Update => "update",
+            Self::DeepSleep => "deep_sleep",
+            Self::Other => "other",
+        }
+    }
+}
+
+// Reads and logs the reason of the last reset, and counts the consecutive
+// abnormal resets.
+//
+// A new image is told apart from its OTA data state rather than from RTC RAM,
+// whose layout may change with the image.
+//
+// Returns whether the device must boot in safe mode, skipping the optional
+// subsystems, because it keeps resetting.
+pub(crate) fn check_reset_reason(running_image: Option<RunningImage>) -> bool {
+    let reset_reason = esp_hal::system::reset_reason();
+    let updated = running_image.is_some_and(|image| image.is_unverified());
+
+    // SAFETY: the boot record is only accessed at boot, before any other task
+    // runs, and by `clear_abnormal_resets`, `count_offline_reboot` and
+    // `reset_after_panic` afterwards, which never run at the same time on this
+    // single core.
+    let (reason, abnormal_resets, offline_reboots) = unsafe {
+        if RECORD != RECORD_MAGIC {
+            RECORD = RECORD_MAGIC;
+            ABNORMAL_RESETS = 0;
+            PANICKED = 0;
+            OFFLINE_REBOOTS = 0;
+        }
+        let reason = BootReason::from_reset(reset_reason, PANICKED == PANIC_MAGIC, updated);
+        PANICKED = 0;
+        ABNORMAL_RESETS = if reason.is_abnormal() {
+            ABNORMAL_RESETS.saturating_add(1)
+        } else {
+            0
+        };
+        (reason, ABNORMAL_RESETS, OFFLINE_REBOOTS)
+    };
+
+    if reason.is_abnormal() {
+        warn!(
+            "Reset reason: {} ({reset_reason:?}), {abnormal_resets} abnormal resets in a row",
+            reason.as_str()
+        );
+    } else {
+        info!("Reset reason: {} ({reset_reason:?})", reason.as_str());
+    }
+    state::set_boot_reason(reason);
+    if offline_reboots > 0 {
+        info!("Restarted {offline_reboots} times for being offline since power on");
+    }
+    state::set_offline_reboots(offline_reboots);
+
+    let safe_mode = abnormal_resets > MAX_ABNORMAL_RESETS;
+    if safe_mode {
+        warn!("Too many abnormal resets, booting in safe mode");
+    }
+    state::set_safe_mode(safe_mode);
+    safe_mode
+}
+
+// Clears the count of abnormal resets once the device stays up long enough.
+#[embassy_executor::task]
+pub(crate) async fn clear_abnormal_resets() {
+    Timer::after_secs(STABLE_UPTIME_SECS).await;
+
+    // SAFETY: see `check_reset_reason`.
+    unsafe {
+        ABNORMAL_RESETS = 0;
+    }
+    info!("Device stable, abnormal resets cleared");
+}
+
+// Counts a restart caused by the device staying offline.
+pub(crate) fn count_offline_reboot() {
+    // SAFETY: see `check_reset_reason`.
+    unsafe {
+        OFFLINE_REBOOTS = OFFLINE_REBOOTS.saturating_add(1);
+    }
+}
+
+// Resets the device, reporting a panic as the reason at the next boot.
+pub(crate) fn reset_after_panic() -> ! {
+    // SAFETY: see `check_reset_reason`.
+    unsafe {
+        PANICKED = PANIC_MAGIC;
+    }
+    esp_hal::system::software_reset()
+}
+
+// Called by `esp-backtrace` once an exception is printed, instead of halting
+// forever.
+#[unsafe(no_mangle)]
+fn custom_halt() -> ! {
+    reset_after_panic()
+}
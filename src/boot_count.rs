@@ -0,0 +1,113 @@
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+use esp_storage::FlashStorage;
+
+use log::{error, info};
+
+use serde::Serialize;
+
+use crate::append_log::AppendLog;
+use crate::boot::BootReason;
+use crate::crc::crc32;
+
+// Size of a record: the four counters and the CRC of their bytes, which tells
+// a complete record apart from an interrupted write.
+const RECORD_SIZE: usize = 20;
+// Boot counters in flash, in the third sector of the `nvs` partition, after
+// the toggle counter.
+const BOOTS: AppendLog<RECORD_SIZE> = AppendLog::new(0xb000);
+
+// Boots counted since the counters were first stored, which survive power
+// loss unlike the boot record in RTC RAM.
+#[derive(Clone, Copy, Default, Serialize)]
+pub(crate) struct BootCounts {
+    pub(crate) total: u32,
+    pub(crate) watchdog: u32,
+    pub(crate) panic: u32,
+    // Restarts to run a new firmware image.
+    pub(crate) update: u32,
+}
+
+// Counters loaded and updated at boot, missing when flash is unreadable.
+static BOOT_COUNTS: Mutex<CriticalSectionRawMutex, Cell<Option<BootCounts>>> =
+    Mutex::new(Cell::new(None));
+
+impl BootCounts {
+    // Counts a boot for the given reason.
+    fn count(&mut self, reason: Option<BootReason>) {
+        self.total = self.total.wrapping_add(1);
+        let counter = match reason {
+            Some(BootReason::Watchdog) => &mut self.watchdog,
+            Some(BootReason::Panic) => &mut self.panic,
+            Some(BootReason::Update) => &mut self.update,
+            _ => return,
+        };
+        *counter = counter.wrapping_add(1);
+    }
+
+    fn encode(self) -> [u8; RECORD_SIZE] {
+        let mut record = [0; RECORD_SIZE];
+        let counters = [self.total, self.watchdog, self.panic, self.update];
+        for (bytes, counter) in record.chunks_exact_mut(4).zip(counters) {
+            bytes.copy_from_slice(&counter.to_le_bytes());
+        }
+        let (data, crc) = record.split_at_mut(RECORD_SIZE - 4);
+        crc.copy_from_slice(&crc32(data).to_le_bytes());
+        record
+    }
+
+    // Counters stored in a record, if the record is complete.
+    fn decode(record: &[u8; RECORD_SIZE]) -> Option<Self> {
+        let (data, crc) = record.split_at(RECORD_SIZE - 4);
+        if crc32(data).to_le_bytes() != crc {
+            return None;
+        }
+        let counter = |index: usize| {
+            u32::from_le_bytes([
+                data[4 * index],
+                data[4 * index + 1],
+                data[4 * index + 2],
+                data[4 * index + 3],
+            ])
+        };
+        Some(Self {
+            total: counter(0),
+            watchdog: counter(1),
+            panic: counter(2),
+            update: counter(3),
+        })
+    }
+}
+
+// Counts this boot in flash, according to the reason of the last reset.
+//
+// Blank or corrupted counters start over from zero.
+pub(crate) fn record(reason: Option<BootReason>) {
+    let mut flash = FlashStorage::new();
+    let (last, offset) = match BOOTS.find_last(&mut flash, BootCounts::decode) {
+        Ok(found) => found,
+        Err(e) => {
+            error!("Failed to read the boot counters from flash: {e:?}");
+            return;
+        }
+    };
+
+    let mut counts = last.unwrap_or_default();
+    counts.count(reason);
+    if let Err(e) = BOOTS.store(&mut flash, &counts.encode(), offset) {
+        error!("Failed to store the boot counters: {e:?}");
+    }
+    info!(
+        "Boot {} ({} watchdog resets, {} panics, {} updates)",
+        counts.total, counts.watchdog, counts.panic, counts.update
+    );
+    BOOT_COUNTS.lock(|boot_counts| boot_counts.set(Some(counts)));
+}
+
+// Boot counters, if they could be read from flash.
+pub(crate) fn boot_counts() -> Option<BootCounts> {
+    BOOT_COUNTS.lock(Cell::get)
+}
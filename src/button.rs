@@ -0,0 +1,186 @@
+use embassy_futures::select::{select, Either};
+use embassy_time::{Instant, Timer};
+
+use esp_hal::gpio::Input;
+
+use log::{error, info, warn};
+
+use crate::button_actions;
+use crate::buzzer::{self, BeepPattern};
+use crate::click::Click;
+use crate::debounce::Debouncer;
+use crate::factory_reset::HoldProgress;
+use crate::gesture::ButtonGestures;
+use crate::led::{LedCommand, LedInput, Source, MAIN_CHANNEL};
+use crate::metrics;
+use crate::settings;
+use crate::state::{self, NOTIFY_LED, REBOOT};
+use crate::DEVICE_CONFIG;
+
+// Time the led stays on to confirm a factory reset.
+const FACTORY_RESET_CONFIRMATION_MS: u64 = 1000;
+// Interval between two button samples while debouncing.
+const DEBOUNCE_SAMPLE_MS: u64 = 5;
+
+// Raw levels of a button, bouncing included, so the debouncing and the
+// gestures do not depend on the pin driving it.
+pub(crate) trait ButtonEvents {
+    // Whether the button is pressed right now.
+    fn is_pressed(&mut self) -> bool;
+
+    // Waits until the button is pressed, or released.
+    async fn wait_for_state(&mut self, pressed: bool);
+}
+
+// Led channel controlled by the button, the main led when the configured one
+// does not exist.
+pub(crate) fn led_channel() -> usize {
+    let channel = DEVICE_CONFIG.button_led_channel;
+    if channel < state::led_channels() {
+        channel
+    } else {
+        error!("Led channel {channel} of the button does not exist, using the main led");
+        MAIN_CHANNEL
+    }
+}
+
+// Button pulled up, so it is pressed while its level is low.
+impl ButtonEvents for Input<'static> {
+    fn is_pressed(&mut self) -> bool {
+        self.is_low()
+    }
+
+    async fn wait_for_state(&mut self, pressed: bool) {
+        if pressed {
+            self.wait_for_low().await;
+        } else {
+            self.wait_for_high().await;
+        }
+    }
+}
+
+// Waits until the debounced button state becomes the given one.
+async fn wait_for_button(button: &mut impl ButtonEvents, debouncer: &mut Debouncer, pressed: bool) {
+    while debouncer.is_pressed() != pressed {
+        // Wait for a level change, unless the level is already settling.
+        if !debouncer.is_settling() {
+            button.wait_for_state(pressed).await;
+        }
+
+        debouncer.update(button.is_pressed(), Instant::now().as_millis());
+
+        // Sample the level again until it is stable for the whole window.
+        if debouncer.is_settling() {
+            Timer::after_millis(DEBOUNCE_SAMPLE_MS).await;
+        }
+    }
+}
+
+// Handles the presses of a button wired to a GPIO.
+//
+// One instance serves the button, the other the switch of the rotary encoder,
+// each owning its GPIO.
+#[embassy_executor::task(pool_size = 2)]
+pub(crate) async fn press_button(mut button: Input<'static>, source: Source, woke_by_button: bool) {
+    // The press which woke the device from deep sleep already toggled the
+    // led, so it is not counted again once released.
+    if woke_by_button {
+        button.wait_for_state(false).await;
+        Timer::after_millis(DEVICE_CONFIG.debounce_ms).await;
+    }
+
+    handle_presses(&mut button, source).await;
+}
+
+// Debounces and classifies the presses of a button, emitting the gestures to
+// the actions dispatcher.
+pub(crate) async fn handle_presses(button: &mut impl ButtonEvents, source: Source) {
+    let mut debouncer = Debouncer::new(DEVICE_CONFIG.debounce_ms);
+    let mut gestures = ButtonGestures::new(
+        DEVICE_CONFIG.long_press_ms,
+        DEVICE_CONFIG.double_click_ms,
+        !button.is_pressed(),
+    );
+
+    loop {
+        // Wait for the next button press or release, or for the gesture or
+        // factory reset deadline to expire.
+        let pressed = !debouncer.is_pressed();
+        let button_change = wait_for_button(button, &mut debouncer, pressed);
+        let expired = match gestures.deadline_ms() {
+            Some(deadline_ms) => matches!(
+                select(button_change, Timer::at(Instant::from_millis(deadline_ms))).await,
+                Either::Second(())
+            ),
+            None => {
+                button_change.await;
+                false
+            }
+        };
+
+        let now_ms = Instant::now().as_millis();
+        let gesture = if expired {
+            gestures.on_timeout(now_ms)
+        } else if debouncer.is_pressed() == pressed {
+            // Feed the button change when the deadline has not expired.
+            if pressed {
+                metrics::count_button_press(Instant::from_millis(now_ms));
+                buzzer::beep(BeepPattern::Press);
+            }
+            gestures.on_change(pressed, now_ms)
+        } else {
+            continue;
+        };
+
+        if gesture.feedback_ended {
+            // Stop the factory reset feedback.
+            let _ = NOTIFY_LED.try_send(LedCommand::new(
+                Source::System,
+                LedInput::Off { fade_ms: Some(0) },
+            ));
+        }
+
+        if let Some(click) = gesture.click {
+            match click {
+                Click::Single => info!("Button Pressed!"),
+                Click::Double => info!("Button Double Clicked!"),
+                Click::Long => info!("Button Long Pressed!"),
+            }
+            // The dispatcher performs the action mapped to the gesture.
+            button_actions::emit(source, click);
+        }
+
+        match gesture.progress {
+            Some(HoldProgress::Feedback { period_ms }) => {
+                let _ = NOTIFY_LED.try_send(LedCommand::new(
+                    Source::System,
+                    LedInput::Blink { period_ms },
+                ));
+            }
+            Some(HoldProgress::Reset) => factory_reset().await,
+            None => {}
+        }
+    }
+}
+
+// Erases the settings stored in flash and reboots, so the device restarts
+// with the configured settings.
+async fn factory_reset() {
+    warn!("Button held, resetting to factory settings!");
+
+    // Keep the led on for a while to confirm the reset.
+    let _ = NOTIFY_LED.try_send(LedCommand::new(
+        Source::System,
+        LedInput::On {
+            fade_ms: Some(0),
+            auto_off_secs: None,
+        },
+    ));
+    Timer::after_millis(FACTORY_RESET_CONFIRMATION_MS).await;
+
+    if let Err(e) = settings::erase_settings() {
+        error!("Failed to erase settings: {e:?}");
+        return;
+    }
+    REBOOT.signal("factory reset");
+}
@@ -0,0 +1,83 @@
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::channel::Channel;
+
+use log::{info, warn};
+
+pub(crate) use button_led_logic::button_actions::{ButtonAction, ButtonActions};
+
+use crate::click::Click;
+#[cfg(feature = "espnow-remote")]
+use crate::espnow;
+use crate::events::{self, Event};
+use crate::history::{self, Action};
+use crate::led::{LedCommand, Source, MAIN_CHANNEL};
+use crate::metrics;
+use crate::mqtt::{self, MqttEvent};
+use crate::state::NOTIFY_LED;
+#[cfg(feature = "espnow-remote")]
+use crate::udp_control;
+use crate::webhook::{self, WebhookEvent};
+use crate::DEVICE_CONFIG;
+
+// Gestures waiting to be dispatched.
+const BUTTON_EVENT_QUEUE_SIZE: usize = 4;
+
+static BUTTON_ACTIONS: Mutex<CriticalSectionRawMutex, Cell<ButtonActions>> = Mutex::new(Cell::new(
+    ButtonActions::defaults(MAIN_CHANNEL, DEVICE_CONFIG.button_cycles_presets),
+));
+
+// Gestures emitted by the buttons, with the source of each one.
+static BUTTON_EVENTS: Channel<CriticalSectionRawMutex, (Source, Click), BUTTON_EVENT_QUEUE_SIZE> =
+    Channel::new();
+
+// Replaces the actions performed for the button gestures.
+pub(crate) fn set_button_actions(actions: ButtonActions) {
+    BUTTON_ACTIONS.lock(|current| current.set(actions));
+}
+
+// Queues a gesture without waiting, dropping it when too many are queued.
+pub(crate) fn emit(source: Source, click: Click) {
+    if BUTTON_EVENTS.try_send((source, click)).is_err() {
+        warn!("Button event channel is full, button press dropped!");
+    }
+}
+
+// Performs the action mapped to every gesture emitted by the buttons, and
+// reports the gestures which are not ignored.
+#[embassy_executor::task]
+pub(crate) async fn dispatch_actions() {
+    loop {
+        let (source, click) = BUTTON_EVENTS.receive().await;
+        let action = BUTTON_ACTIONS.lock(Cell::get).get(click);
+        if action == ButtonAction::None {
+            info!("Button gesture ignored");
+            continue;
+        }
+
+        history::record(source, Action::Click(click));
+        if let (Some(channel), Some(led_input)) = (action.channel(), action.led_input()) {
+            // A remote toggles the led of its peers too.
+            #[cfg(feature = "espnow-remote")]
+            if matches!(action, ButtonAction::Toggle { .. }) {
+                espnow::send(udp_control::COMMAND_TOGGLE);
+            }
+
+            if NOTIFY_LED
+                .try_send(LedCommand::on_channel(channel, source, led_input))
+                .is_err()
+            {
+                warn!("Led channel is full, button press dropped!");
+            }
+        }
+
+        mqtt::publish(MqttEvent::Button(click));
+        events::publish(Event::Button(click));
+        webhook::notify(WebhookEvent::Button {
+            click,
+            count: metrics::metrics().button_presses,
+        });
+    }
+}
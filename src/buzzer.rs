@@ -0,0 +1,123 @@
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::channel::Channel;
+use embassy_time::Timer;
+
+use esp_hal::gpio::AnyPin;
+use esp_hal::ledc::channel::{self, ChannelIFace};
+use esp_hal::ledc::timer::{self, TimerIFace};
+use esp_hal::ledc::LowSpeed;
+use esp_hal::time::Rate;
+
+use log::{error, info};
+
+// Patterns waiting to be played, the newest ones are dropped beyond this.
+const BEEP_QUEUE_SIZE: usize = 4;
+// Duty cycle of a tone, the loudest for a piezo buzzer.
+const TONE_DUTY_PCT: u8 = 50;
+
+// Tone of a pattern, silent when its frequency is zero.
+struct Tone {
+    frequency_hz: u32,
+    duration_ms: u64,
+}
+
+const fn tone(frequency_hz: u32, duration_ms: u64) -> Tone {
+    Tone {
+        frequency_hz,
+        duration_ms,
+    }
+}
+
+// Feedback played by the buzzer.
+#[derive(Clone, Copy)]
+pub(crate) enum BeepPattern {
+    // Short beep, when the button is pressed.
+    Press,
+    // Double beep, when Wi-Fi connects.
+    Connected,
+    // Descending tones, when an optional subsystem fails to start.
+    Error,
+}
+
+impl BeepPattern {
+    const PRESS: &[Tone] = &[tone(4000, 30)];
+    const CONNECTED: &[Tone] = &[tone(2500, 80), tone(0, 80), tone(2500, 80)];
+    const ERROR: &[Tone] = &[tone(1000, 200), tone(500, 400)];
+
+    const fn tones(self) -> &'static [Tone] {
+        match self {
+            Self::Press => Self::PRESS,
+            Self::Connected => Self::CONNECTED,
+            Self::Error => Self::ERROR,
+        }
+    }
+}
+
+// Patterns waiting to be played.
+static BEEPS: Channel<CriticalSectionRawMutex, BeepPattern, BEEP_QUEUE_SIZE> = Channel::new();
+// Whether the buzzer is silenced, from the settings.
+static MUTED: Mutex<CriticalSectionRawMutex, Cell<bool>> = Mutex::new(Cell::new(false));
+
+// Queues a pattern without waiting, dropping it when too many are queued or
+// no buzzer is configured.
+pub(crate) fn beep(pattern: BeepPattern) {
+    let _ = BEEPS.try_send(pattern);
+}
+
+// Silences the buzzer, or lets it play again.
+pub(crate) fn set_muted(muted: bool) {
+    MUTED.lock(|cell| cell.set(muted));
+}
+
+// Plays the queued patterns on a piezo buzzer, through its own LEDC timer and
+// channel, whose frequency changes with every tone.
+#[embassy_executor::task]
+pub(crate) async fn buzzer_task(
+    mut timer: timer::Timer<'static, LowSpeed>,
+    channel_number: channel::Number,
+    mut pin: AnyPin<'static>,
+) {
+    info!("Buzzer initialized");
+
+    loop {
+        let pattern = BEEPS.receive().await;
+        // Patterns queued while muted are dropped.
+        if MUTED.lock(Cell::get) {
+            continue;
+        }
+
+        for tone in pattern.tones() {
+            if tone.frequency_hz > 0 {
+                let configured = timer.configure(timer::config::Config {
+                    duty: timer::config::Duty::Duty10Bit,
+                    clock_source: timer::LSClockSource::APBClk,
+                    frequency: Rate::from_hz(tone.frequency_hz),
+                });
+                if let Err(e) = configured {
+                    error!("Failed to configure the buzzer timer: {e:?}");
+                    break;
+                }
+
+                // The channel is configured again for every tone, since it
+                // borrows the timer being reconfigured.
+                let mut channel = channel::Channel::new(channel_number, pin.reborrow());
+                let configured = channel.configure(channel::config::Config {
+                    timer: &timer,
+                    duty_pct: TONE_DUTY_PCT,
+                    pin_config: channel::config::PinConfig::PushPull,
+                });
+                if let Err(e) = configured {
+                    error!("Failed to configure the buzzer channel: {e:?}");
+                    break;
+                }
+                Timer::after_millis(tone.duration_ms).await;
+                let _ = channel.set_duty(0);
+            } else {
+                Timer::after_millis(tone.duration_ms).await;
+            }
+        }
+    }
+}
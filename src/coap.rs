@@ -0,0 +1,377 @@
+use core::cell::Cell;
+
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::Stack;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+use log::{error, info, warn};
+
+use serde::Serialize;
+
+use crate::auth::keys_match;
+use crate::led::{LedCommand, LedInput, Source, MAIN_CHANNEL};
+use crate::state::{self, LedState, NOTIFY_LED};
+use crate::DEVICE_CONFIG;
+
+// Port of the CoAP server.
+const COAP_PORT: u16 = 5683;
+// Size of the CoAP socket buffers, enough for the small messages of the
+// supported resources.
+const COAP_BUFFER_SIZE: usize = 256;
+// Longest `/status` payload.
+const STATUS_PAYLOAD_SIZE: usize = 64;
+
+// Supported protocol version.
+const VERSION: u8 = 1;
+// Size of the fixed header.
+const HEADER_SIZE: usize = 4;
+// Longest token.
+const MAX_TOKEN_LEN: usize = 8;
+// Marker between the options and the payload.
+const PAYLOAD_MARKER: u8 = 0xff;
+
+// Option numbers.
+const OPTION_URI_PATH: u16 = 11;
+const OPTION_CONTENT_FORMAT: u16 = 12;
+const OPTION_URI_QUERY: u16 = 15;
+// Content formats.
+const FORMAT_TEXT: u8 = 0;
+const FORMAT_JSON: u8 = 50;
+
+// Message types.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MessageType {
+    Confirmable,
+    NonConfirmable,
+    Acknowledgement,
+    Reset,
+}
+
+impl MessageType {
+    const fn from_bits(bits: u8) -> Self {
+        match bits & 0b11 {
+            0 => Self::Confirmable,
+            1 => Self::NonConfirmable,
+            2 => Self::Acknowledgement,
+            _ => Self::Reset,
+        }
+    }
+
+    const fn bits(self) -> u8 {
+        match self {
+            Self::Confirmable => 0,
+            Self::NonConfirmable => 1,
+            Self::Acknowledgement => 2,
+            Self::Reset => 3,
+        }
+    }
+}
+
+// Message codes, as `class << 5 | detail`.
+const EMPTY: u8 = 0x00;
+const GET: u8 = 0x01;
+const PUT: u8 = 0x03;
+const CHANGED: u8 = 0x44;
+const CONTENT: u8 = 0x45;
+const BAD_REQUEST: u8 = 0x80;
+const UNAUTHORIZED: u8 = 0x81;
+const BAD_OPTION: u8 = 0x82;
+const NOT_FOUND: u8 = 0x84;
+const METHOD_NOT_ALLOWED: u8 = 0x85;
+const SERVICE_UNAVAILABLE: u8 = 0xa3;
+
+// Identifier of the next non-confirmable response.
+static NEXT_MESSAGE_ID: Mutex<CriticalSectionRawMutex, Cell<u16>> = Mutex::new(Cell::new(0));
+
+// Returns a new message identifier.
+fn next_message_id() -> u16 {
+    NEXT_MESSAGE_ID.lock(|next| {
+        let message_id = next.get();
+        next.set(message_id.wrapping_add(1));
+        message_id
+    })
+}
+
+// Resources served by the CoAP server.
+#[derive(Clone, Copy)]
+enum Resource {
+    Led,
+    Status,
+}
+
+// Device status returned by the `/status` resource.
+#[derive(Serialize)]
+struct CoapStatus {
+    led: LedState,
+    brightness: u8,
+}
+
+// Fields of a request used by the server.
+struct Request<'a> {
+    message_type: MessageType,
+    code: u8,
+    message_id: u16,
+    token: &'a [u8],
+    // Requested resource, `None` when it does not exist.
+    resource: Option<Resource>,
+    // Whether the request carries the configured API key.
+    authorized: bool,
+    // Whether a critical option is not understood.
+    bad_option: bool,
+    payload: &'a [u8],
+}
+
+// Reads an option delta or length, extended by the following bytes when
+// larger than 12.
+fn option_value(nibble: u8, bytes: &mut &[u8]) -> Option<u16> {
+    match nibble {
+        13 => {
+            let (&extended, rest) = bytes.split_first()?;
+            *bytes = rest;
+            Some(u16::from(extended) + 13)
+        }
+        14 => {
+            let (extended, rest) = bytes.split_first_chunk::<2>()?;
+            *bytes = rest;
+            u16::from_be_bytes(*extended).checked_add(269)
+        }
+        // Reserved for the payload marker.
+        15 => None,
+        nibble => Some(u16::from(nibble)),
+    }
+}
+
+impl<'a> Request<'a> {
+    // Parses a message, returning `None` when it is malformed.
+    fn parse(message: &'a [u8]) -> Option<Self> {
+        let (header, rest) = message.split_first_chunk::<HEADER_SIZE>()?;
+        if header[0] >> 6 != VERSION {
+            return None;
+        }
+        let token_len = usize::from(header[0] & 0x0f);
+        if token_len > MAX_TOKEN_LEN || rest.len() < token_len {
+            return None;
+        }
+        let (token, mut options) = rest.split_at(token_len);
+
+        let mut request = Self {
+            message_type: MessageType::from_bits(header[0] >> 4),
+            code: header[1],
+            message_id: u16::from_be_bytes([header[2], header[3]]),
+            token,
+            resource: None,
+            authorized: DEVICE_CONFIG.api_key.is_empty(),
+            bad_option: false,
+            payload: &[],
+        };
+
+        // Path segments of the requested resource, up to two so deeper paths
+        // are not found.
+        let mut path: [&[u8]; 2] = [&[], &[]];
+        let mut segments = 0;
+        let mut number = 0u16;
+        while let Some((&first, rest)) = options.split_first() {
+            options = rest;
+            if first == PAYLOAD_MARKER {
+                if options.is_empty() {
+                    return None;
+                }
+                request.payload = options;
+                break;
+            }
+            number = number.checked_add(option_value(first >> 4, &mut options)?)?;
+            let len = usize::from(option_value(first & 0x0f, &mut options)?);
+            if options.len() < len {
+                return None;
+            }
+            let (value, rest) = options.split_at(len);
+            options = rest;
+
+            match number {
+                OPTION_URI_PATH => {
+                    if let Some(segment) = path.get_mut(segments) {
+                        *segment = value;
+                    }
+                    segments += 1;
+                }
+                OPTION_URI_QUERY => {
+                    if let Some(key) = value.strip_prefix(b"key=") {
+                        let expected = DEVICE_CONFIG.api_key.as_bytes();
+                        request.authorized |= keys_match(key, expected);
+                    }
+                }
+                OPTION_CONTENT_FORMAT => {}
+                // Odd option numbers are critical, and must be understood.
+                number if number % 2 == 1 => request.bad_option = true,
+                _ => {}
+            }
+        }
+
+        request.resource = match (segments, path[0]) {
+            (1, b"led") => Some(Resource::Led),
+            (1, b"status") => Some(Resource::Status),
+            _ => None,
+        };
+        Some(request)
+    }
+}
+
+// Writes a response to a request into the buffer, returning its length.
+fn write_response(
+    request: &Request<'_>,
+    code: u8,
+    content_format: Option<u8>,
+    payload: &[u8],
+    buffer: &mut [u8],
+) -> Option<usize> {
+    // Confirmable requests are answered with a piggybacked acknowledgement,
+    // the others with a new non-confirmable message, matched by its token.
+    let (message_type, message_id) = if request.message_type == MessageType::Confirmable {
+        (MessageType::Acknowledgement, request.message_id)
+    } else {
+        (MessageType::NonConfirmable, next_message_id())
+    };
+    // The token is at most 8 bytes long, so its length fits the header.
+    let token_len = request.token.len() as u8;
+    let content_len = usize::from(content_format.is_some()) * 2;
+    let marker_len = usize::from(!payload.is_empty());
+    let len = HEADER_SIZE + request.token.len() + content_len + marker_len + payload.len();
+    let response = buffer.get_mut(..len)?;
+
+    response[0] = VERSION << 6 | message_type.bits() << 4 | token_len;
+    response[1] = code;
+    response[2..4].copy_from_slice(&message_id.to_be_bytes());
+    let (_, rest) = response.split_at_mut(HEADER_SIZE);
+    let (token, rest) = rest.split_at_mut(request.token.len());
+    token.copy_from_slice(request.token);
+    let rest = if let Some(content_format) = content_format {
+        // The option delta is 12, from no previous option, and the value is
+        // one byte long.
+        rest[0] = 0xc1;
+        rest[1] = content_format;
+        &mut rest[2..]
+    } else {
+        rest
+    };
+    if !payload.is_empty() {
+        rest[0] = PAYLOAD_MARKER;
+        rest[1..].copy_from_slice(payload);
+    }
+    Some(len)
+}
+
+// Handles a request, writing the response into the buffer and returning its
+// length, or `None` when nothing is answered.
+fn handle(request: &Request<'_>, buffer: &mut [u8]) -> Option<usize> {
+    match request.message_type {
+        // Unexpected acknowledgements and resets carry no request.
+        MessageType::Acknowledgement | MessageType::Reset => return None,
+        // Empty confirmable messages are pings, answered with a reset.
+        MessageType::Confirmable if request.code == EMPTY => {
+            let response = buffer.get_mut(..HEADER_SIZE)?;
+            response[0] = VERSION << 6 | MessageType::Reset.bits() << 4;
+            response[1] = EMPTY;
+            response[2..4].copy_from_slice(&request.message_id.to_be_bytes());
+            return Some(HEADER_SIZE);
+        }
+        MessageType::Confirmable | MessageType::NonConfirmable => {}
+    }
+
+    if request.bad_option {
+        return write_response(request, BAD_OPTION, None, &[], buffer);
+    }
+    let Some(resource) = request.resource else {
+        return write_response(request, NOT_FOUND, None, &[], buffer);
+    };
+
+    match (resource, request.code) {
+        (Resource::Led, GET) => {
+            let payload: &[u8] = match state::led_state(MAIN_CHANNEL) {
+                LedState::On => b"1",
+                LedState::Off => b"0",
+            };
+            write_response(request, CONTENT, Some(FORMAT_TEXT), payload, buffer)
+        }
+        (Resource::Led, PUT) => {
+            if !request.authorized {
+                return write_response(request, UNAUTHORIZED, None, &[], buffer);
+            }
+            let led_input = match request.payload.trim_ascii() {
+                b"1" => LedInput::On {
+                    fade_ms: None,
+                    auto_off_secs: None,
+                },
+                b"0" => LedInput::Off { fade_ms: None },
+                _ => return write_response(request, BAD_REQUEST, None, &[], buffer),
+            };
+            if NOTIFY_LED
+                .try_send(LedCommand::new(Source::Coap, led_input))
+                .is_err()
+            {
+                warn!("Led channel is full, CoAP request rejected!");
+                return write_response(request, SERVICE_UNAVAILABLE, None, &[], buffer);
+            }
+            info!("Led changed through CoAP!");
+            write_response(request, CHANGED, None, &[], buffer)
+        }
+        (Resource::Status, GET) => {
+            let status = CoapStatus {
+                led: state::led_state(MAIN_CHANNEL),
+                brightness: state::led_brightness(MAIN_CHANNEL),
+            };
+            let payload = serde_json_core::to_vec::<_, STATUS_PAYLOAD_SIZE>(&status).ok()?;
+            write_response(request, CONTENT, Some(FORMAT_JSON), &payload, buffer)
+        }
+        _ => write_response(request, METHOD_NOT_ALLOWED, None, &[], buffer),
+    }
+}
+
+// Minimal CoAP server (RFC 7252), exposing the led as the `/led` resource,
+// which is read with GET and changed with PUT and a `0` or `1` payload, and
+// the device status as the `/status` resource.
+//
+// PUT requests carry the API key, when configured, as a `key=` URI query.
+#[embassy_executor::task]
+pub(crate) async fn coap_server(stack: Stack<'static>) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; COAP_BUFFER_SIZE];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; COAP_BUFFER_SIZE];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+
+    if let Err(e) = socket.bind(COAP_PORT) {
+        error!("Failed to bind CoAP socket: {e:?}");
+        return;
+    }
+    info!("CoAP server listening on port {COAP_PORT}");
+
+    let mut message = [0; COAP_BUFFER_SIZE];
+    let mut response = [0; COAP_BUFFER_SIZE];
+    loop {
+        let (len, metadata) = match socket.recv_from(&mut message).await {
+            Ok(received) => received,
+            Err(e) => {
+                warn!("Failed to receive a CoAP message: {e:?}");
+                continue;
+            }
+        };
+
+        // Malformed messages are silently ignored.
+        let Some(request) = Request::parse(&message[..len]) else {
+            continue;
+        };
+        let Some(len) = handle(&request, &mut response) else {
+            continue;
+        };
+        if let Err(e) = socket.send_to(&response[..len], metadata.endpoint).await {
+            warn!("Failed to send a CoAP response: {e:?}");
+        }
+    }
+}
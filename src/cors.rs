@@ -0,0 +1,104 @@
+use picoserve::io::Read;
+use picoserve::request::RequestParts;
+use picoserve::response::{
+    Body, Connection, HeadersIter, IntoResponse, Response, ResponseWriter, StatusCode,
+};
+use picoserve::routing::{Layer, Next};
+use picoserve::ResponseSent;
+
+use crate::DEVICE_CONFIG;
+
+// Methods and request headers allowed to cross-origin requests.
+const ALLOWED_METHODS: &str = "GET, POST, DELETE, OPTIONS";
+const ALLOWED_HEADERS: &str = "Authorization, Content-Type, X-Api-Key";
+// Time browsers can cache a preflight response.
+const PREFLIGHT_MAX_AGE_SECS: &str = "600";
+
+// Response writer adding the allowed origin to a response.
+struct AllowOrigin<W> {
+    writer: W,
+    origin: &'static str,
+}
+
+impl<W: ResponseWriter> ResponseWriter for AllowOrigin<W> {
+    type Error = W::Error;
+
+    async fn write_response<R: Read<Error = Self::Error>, H: HeadersIter, B: Body>(
+        self,
+        connection: Connection<'_, R>,
+        response: Response<H, B>,
+    ) -> Result<ResponseSent, Self::Error> {
+        self.writer
+            .write_response(
+                connection,
+                response.with_header("Access-Control-Allow-Origin", self.origin),
+            )
+            .await
+    }
+}
+
+// Layer letting a browser page on the configured origin call the routes,
+// answering the preflight `OPTIONS` requests and adding the allowed origin to
+// the other responses.
+//
+// CORS is disabled when no origin is configured.
+pub(crate) struct Cors {
+    origin: &'static str,
+}
+
+impl Cors {
+    pub(crate) const fn new() -> Self {
+        Self {
+            origin: DEVICE_CONFIG.cors_origin,
+        }
+    }
+}
+
+impl<State, PathParameters> Layer<State, PathParameters> for Cors {
+    type NextState = State;
+    type NextPathParameters = PathParameters;
+
+    async fn call_layer<
+        'a,
+        R: Read + 'a,
+        NextLayer: Next<'a, R, State, PathParameters>,
+        W: ResponseWriter<Error = R::Error>,
+    >(
+        &self,
+        next: NextLayer,
+        state: &State,
+        path_parameters: PathParameters,
+        request_parts: RequestParts<'_>,
+        response_writer: W,
+    ) -> Result<ResponseSent, W::Error> {
+        if self.origin.is_empty() {
+            return next.run(state, path_parameters, response_writer).await;
+        }
+
+        if request_parts.method() == "OPTIONS" {
+            let connection = next.into_connection().await?;
+            return (
+                StatusCode::NO_CONTENT,
+                [
+                    ("Access-Control-Allow-Origin", self.origin),
+                    ("Access-Control-Allow-Methods", ALLOWED_METHODS),
+                    ("Access-Control-Allow-Headers", ALLOWED_HEADERS),
+                    ("Access-Control-Max-Age", PREFLIGHT_MAX_AGE_SECS),
+                ],
+                "",
+            )
+                .write_to(connection, response_writer)
+                .await;
+        }
+
+        next.run(
+            state,
+            path_parameters,
+            AllowOrigin {
+                writer: response_writer,
+                origin: self.origin,
+            },
+        )
+        .await
+    }
+}
@@ -0,0 +1,217 @@
+use core::net::Ipv4Addr;
+
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::Stack;
+
+use log::{error, info};
+
+// Ports of the DHCP server and clients.
+const DHCP_SERVER_PORT: u16 = 67;
+const DHCP_CLIENT_PORT: u16 = 68;
+// Size of the DHCP socket buffers, enough for a single message.
+const DHCP_BUFFER_SIZE: usize = 576;
+// Number of addresses leased to the clients, starting from the one following
+// the server address.
+const DHCP_LEASES: usize = 4;
+// Lease time announced to the clients.
+const DHCP_LEASE_SECS: u32 = 60 * 60;
+
+// Size of the fixed part of a DHCP message, up to the magic cookie included.
+const DHCP_HEADER_SIZE: usize = 240;
+const DHCP_MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+// Offsets of the fields used by the server.
+const XID_OFFSET: usize = 4;
+const FLAGS_OFFSET: usize = 10;
+const YIADDR_OFFSET: usize = 16;
+const SIADDR_OFFSET: usize = 20;
+const CHADDR_OFFSET: usize = 28;
+const MAGIC_COOKIE_OFFSET: usize = 236;
+
+// Options understood by the server.
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_END: u8 = 255;
+
+// Message types.
+const DHCP_DISCOVER: u8 = 1;
+const DHCP_OFFER: u8 = 2;
+const DHCP_REQUEST: u8 = 3;
+const DHCP_ACK: u8 = 5;
+
+// Addresses leased to the clients, identified by their MAC address.
+struct Leases {
+    server: Ipv4Addr,
+    clients: [Option<[u8; 6]>; DHCP_LEASES],
+    // Lease replaced when all of them are taken.
+    next_replaced: usize,
+}
+
+impl Leases {
+    const fn new(server: Ipv4Addr) -> Self {
+        Self {
+            server,
+            clients: [None; DHCP_LEASES],
+            next_replaced: 0,
+        }
+    }
+
+    // Address leased to the given client, leasing a new one when needed.
+    //
+    // When all the addresses are taken, the oldest lease is replaced.
+    fn lease(&mut self, client: [u8; 6]) -> Ipv4Addr {
+        let index = self
+            .clients
+            .iter()
+            .position(|lease| *lease == Some(client))
+            .or_else(|| self.clients.iter().position(Option::is_none))
+            .unwrap_or_else(|| {
+                let index = self.next_replaced;
+                self.next_replaced = (index + 1) % DHCP_LEASES;
+                index
+            });
+        self.clients[index] = Some(client);
+
+        // The lease index is bounded by the number of leases.
+        Ipv4Addr::from(u32::from(self.server) + 1 + index as u32)
+    }
+}
+
+// Retrieves the DHCP message type of a request.
+fn message_type(request: &[u8]) -> Option<u8> {
+    let mut options = request.get(DHCP_HEADER_SIZE..)?;
+    loop {
+        match *options {
+            [OPTION_PAD, ref rest @ ..] => options = rest,
+            [OPTION_END, ..] | [] => return None,
+            [OPTION_MESSAGE_TYPE, 1, message_type, ..] => return Some(message_type),
+            [_, len, ref rest @ ..] => options = rest.get(usize::from(len)..)?,
+            [_] => return None,
+        }
+    }
+}
+
+// Writes the reply to a DHCP request, returning its size.
+//
+// Discover messages are answered with an offer and request messages with an
+// acknowledgement of the address leased to the client. Other messages are
+// ignored.
+fn reply(request: &[u8], leases: &mut Leases, reply: &mut [u8]) -> Option<usize> {
+    if request.get(MAGIC_COOKIE_OFFSET..DHCP_HEADER_SIZE)? != DHCP_MAGIC_COOKIE {
+        return None;
+    }
+
+    let reply_type = match message_type(request)? {
+        DHCP_DISCOVER => DHCP_OFFER,
+        DHCP_REQUEST => DHCP_ACK,
+        _ => return None,
+    };
+
+    let client: [u8; 6] = request
+        .get(CHADDR_OFFSET..CHADDR_OFFSET + 6)?
+        .try_into()
+        .ok()?;
+    let address = leases.lease(client);
+    let server = leases.server.octets();
+
+    let header = reply.get_mut(..DHCP_HEADER_SIZE)?;
+    header.fill(0);
+    // Boot reply over Ethernet.
+    header[..3].copy_from_slice(&[2, 1, 6]);
+    header[XID_OFFSET..XID_OFFSET + 4].copy_from_slice(&request[XID_OFFSET..XID_OFFSET + 4]);
+    header[FLAGS_OFFSET..FLAGS_OFFSET + 2]
+        .copy_from_slice(&request[FLAGS_OFFSET..FLAGS_OFFSET + 2]);
+    header[YIADDR_OFFSET..YIADDR_OFFSET + 4].copy_from_slice(&address.octets());
+    header[SIADDR_OFFSET..SIADDR_OFFSET + 4].copy_from_slice(&server);
+    header[CHADDR_OFFSET..CHADDR_OFFSET + 6].copy_from_slice(&client);
+    header[MAGIC_COOKIE_OFFSET..].copy_from_slice(&DHCP_MAGIC_COOKIE);
+
+    let lease_secs = DHCP_LEASE_SECS.to_be_bytes();
+    let options = [
+        OPTION_MESSAGE_TYPE,
+        1,
+        reply_type,
+        OPTION_SERVER_ID,
+        4,
+        server[0],
+        server[1],
+        server[2],
+        server[3],
+        OPTION_LEASE_TIME,
+        4,
+        lease_secs[0],
+        lease_secs[1],
+        lease_secs[2],
+        lease_secs[3],
+        OPTION_SUBNET_MASK,
+        4,
+        255,
+        255,
+        255,
+        0,
+        OPTION_ROUTER,
+        4,
+        server[0],
+        server[1],
+        server[2],
+        server[3],
+        OPTION_END,
+    ];
+    let len = DHCP_HEADER_SIZE + options.len();
+    reply
+        .get_mut(DHCP_HEADER_SIZE..len)?
+        .copy_from_slice(&options);
+
+    Some(len)
+}
+
+// Minimal DHCP server, leasing addresses of the /24 network of the server to
+// the clients connected to the access point.
+#[embassy_executor::task]
+pub(crate) async fn dhcp_server(stack: Stack<'static>, server: Ipv4Addr) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0; DHCP_BUFFER_SIZE];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; DHCP_BUFFER_SIZE];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+
+    if let Err(e) = socket.bind(DHCP_SERVER_PORT) {
+        error!("Failed to bind DHCP server socket: {e:?}");
+        return;
+    }
+    info!("DHCP server started");
+
+    let mut leases = Leases::new(server);
+    let mut request = [0; DHCP_BUFFER_SIZE];
+    let mut response = [0; DHCP_BUFFER_SIZE];
+    loop {
+        let len = match socket.recv_from(&mut request).await {
+            Ok((len, _)) => len,
+            Err(e) => {
+                error!("Failed to receive DHCP request: {e:?}");
+                continue;
+            }
+        };
+
+        let Some(len) = reply(&request[..len], &mut leases, &mut response) else {
+            continue;
+        };
+
+        // Clients have no address yet, so replies are broadcast.
+        if let Err(e) = socket
+            .send_to(&response[..len], (Ipv4Addr::BROADCAST, DHCP_CLIENT_PORT))
+            .await
+        {
+            error!("Failed to send DHCP reply: {e:?}");
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use core::fmt::Write;
+
+use embassy_futures::select::select;
+use embassy_time::{Duration, Instant, Timer};
+
+use embedded_graphics::mono_font::ascii::FONT_6X10;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+use embedded_graphics::text::{Baseline, Text};
+
+use esp_hal::gpio::AnyPin;
+use esp_hal::i2c::master::{Config, I2c};
+use esp_hal::peripherals::I2C0;
+use esp_hal::time::Rate;
+use esp_hal::Async;
+
+use log::{error, info};
+
+use picoserve::make_static;
+
+use ssd1306::mode::BufferedGraphicsModeAsync;
+use ssd1306::prelude::*;
+use ssd1306::{I2CDisplayInterface, Ssd1306Async};
+
+use crate::events::EventSubscriber;
+use crate::led::MAIN_CHANNEL;
+use crate::metrics;
+use crate::state;
+
+// Frequency of the I2C bus, the fastest the SSD1306 supports.
+const I2C_FREQUENCY_KHZ: u32 = 400;
+// Interval between two checks of the shown values, catching the changes which
+// are not published as events, such as a new IP address.
+const CHECK_INTERVAL_SECS: u64 = 1;
+// Interval between two redraws, even when nothing changed.
+const HEARTBEAT_SECS: u64 = 30;
+// Height of a text line, in pixels.
+const LINE_HEIGHT: i32 = 12;
+// Longest text line, the display being 21 characters wide.
+const LINE_LEN: usize = 21;
+
+type Display = Ssd1306Async<
+    I2CInterface<I2c<'static, Async>>,
+    DisplaySize128x64,
+    BufferedGraphicsModeAsync<DisplaySize128x64>,
+>;
+
+// Values shown on the display.
+#[derive(PartialEq, Eq)]
+struct Screen {
+    hostname: heapless::String<LINE_LEN>,
+    ip: heapless::String<LINE_LEN>,
+    wifi: heapless::String<LINE_LEN>,
+    led: heapless::String<LINE_LEN>,
+    presses: heapless::String<LINE_LEN>,
+}
+
+impl Screen {
+    // Current values, cut to the width of the display.
+    fn current() -> Self {
+        let mut screen = Self {
+            hostname: heapless::String::new(),
+            ip: heapless::String::new(),
+            wifi: heapless::String::new(),
+            led: heapless::String::new(),
+            presses: heapless::String::new(),
+        };
+        // Lines longer than the display are cut by the failed writes.
+        let _ = write!(screen.hostname, "{}", state::hostname());
+        let _ = match state::ip_address() {
+            Some(ip) => write!(screen.ip, "IP {ip}"),
+            None => write!(screen.ip, "connecting..."),
+        };
+        let _ = match state::wifi_rssi() {
+            Some(rssi) => write!(screen.wifi, "Wi-Fi {rssi} dBm"),
+            None => write!(screen.wifi, "Wi-Fi down"),
+        };
+        let _ = write!(
+            screen.led,
+            "Led {} {}%",
+            state::led_state(MAIN_CHANNEL).as_str(),
+            state::led_brightness(MAIN_CHANNEL)
+        );
+        let _ = write!(
+            screen.presses,
+            "Presses {}",
+            metrics::metrics().button_presses
+        );
+        screen
+    }
+
+    // Draws the values into the framebuffer of the display.
+    fn draw(&self, display: &mut Display) {
+        let style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+        display.clear_buffer();
+        let lines = [
+            &self.hostname,
+            &self.ip,
+            &self.wifi,
+            &self.led,
+            &self.presses,
+        ];
+        for (y, line) in (0..).step_by(LINE_HEIGHT as usize).zip(lines) {
+            // Drawing into the framebuffer cannot fail.
+            let _ = Text::with_baseline(line, Point::new(0, y), style, Baseline::Top).draw(display);
+        }
+    }
+}
+
+// Initializes an SSD1306 display on the given I2C pins, returning `None` when
+// it does not answer, so the device keeps running without it.
+pub(crate) async fn init(
+    i2c: I2C0<'static>,
+    sda: AnyPin<'static>,
+    scl: AnyPin<'static>,
+) -> Option<&'static mut Display> {
+    let config = Config::default().with_frequency(Rate::from_khz(I2C_FREQUENCY_KHZ));
+    let i2c = match I2c::new(i2c, config) {
+        Ok(i2c) => i2c.with_sda(sda).with_scl(scl).into_async(),
+        Err(e) => {
+            error!("Failed to configure the display I2C bus: {e:?}");
+            return None;
+        }
+    };
+
+    // The framebuffer is kept in a static, away from the heap and the stack.
+    let display = make_static!(
+        Display,
+        Ssd1306Async::new(
+            I2CDisplayInterface::new(i2c),
+            DisplaySize128x64,
+            DisplayRotation::Rotate0,
+        )
+        .into_buffered_graphics_mode()
+    );
+    if let Err(e) = display.init().await {
+        error!("Failed to initialize the display: {e:?}");
+        return None;
+    }
+    Some(display)
+}
+
+// Shows the hostname, the IP address, the Wi-Fi signal, the led state and the
+// button presses, redrawing them when they change.
+#[embassy_executor::task]
+pub(crate) async fn display_task(display: &'static mut Display, mut events: EventSubscriber) {
+    info!("Display initialized");
+    let mut shown: Option<Screen> = None;
+    let mut drawn_at = Instant::now();
+
+    loop {
+        let screen = Screen::current();
+        let heartbeat = drawn_at.elapsed() >= Duration::from_secs(HEARTBEAT_SECS);
+        if heartbeat || shown.as_ref() != Some(&screen) {
+            screen.draw(display);
+            if let Err(e) = display.flush().await {
+                error!("Failed to refresh the display: {e:?}");
+            }
+            shown = Some(screen);
+            drawn_at = Instant::now();
+        }
+
+        // Led and button events are shown at once.
+        select(
+            events.next_message(),
+            Timer::after_secs(CHECK_INTERVAL_SECS),
+        )
+        .await;
+    }
+}
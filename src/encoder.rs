@@ -0,0 +1,64 @@
+use embassy_futures::select::select;
+use embassy_time::{Duration, Instant, Timer};
+
+use esp_hal::gpio::Input;
+
+use log::{info, warn};
+
+use crate::led::{LedCommand, LedInput, Source, MAIN_CHANNEL, MAX_BRIGHTNESS};
+use crate::quadrature::{step, QuadratureDecoder};
+use crate::state::{self, NOTIFY_LED};
+
+// Time after the last detent from which the brightness is read again, so
+// changes from other inputs are not overwritten.
+const RESYNC_MS: u64 = 1000;
+// Time for the levels to settle after an edge, filtering the bounces between
+// two valid states.
+const SETTLE_MS: u64 = 1;
+
+// Changes the led brightness with a rotary encoder, whose contacts are pulled
+// up.
+#[embassy_executor::task]
+pub(crate) async fn encoder_task(mut a: Input<'static>, mut b: Input<'static>) {
+    info!("Rotary encoder initialized");
+    let mut decoder = QuadratureDecoder::new(a.is_high(), b.is_high());
+    let mut last_detent: Option<Instant> = None;
+    let mut level = state::led_brightness(MAIN_CHANNEL);
+
+    loop {
+        select(a.wait_for_any_edge(), b.wait_for_any_edge()).await;
+        Timer::after_millis(SETTLE_MS).await;
+
+        let Some(direction) = decoder.update(a.is_high(), b.is_high()) else {
+            continue;
+        };
+        let now = Instant::now();
+        let since_last = last_detent.map(|last| now - last);
+        last_detent = Some(now);
+
+        // The brightness sent for the previous detent may not be applied
+        // yet, so it is only read again after a pause.
+        if since_last.is_none_or(|since_last| since_last >= Duration::from_millis(RESYNC_MS)) {
+            level = state::led_brightness(MAIN_CHANNEL);
+        }
+        let step = step(since_last.map_or(u64::MAX, |since_last| since_last.as_millis()));
+        level = if direction > 0 {
+            level.saturating_add(step).min(MAX_BRIGHTNESS)
+        } else {
+            level.saturating_sub(step)
+        };
+
+        // The encoder applies the brightness right away, following the
+        // rotation.
+        let input = LedInput::Brightness {
+            level,
+            fade_ms: Some(0),
+        };
+        if NOTIFY_LED
+            .try_send(LedCommand::new(Source::Encoder, input))
+            .is_err()
+        {
+            warn!("Led channel is full, encoder rotation dropped!");
+        }
+    }
+}
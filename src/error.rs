@@ -0,0 +1,107 @@
+use core::fmt;
+
+use embassy_executor::SpawnError;
+
+use esp_hal::ledc;
+use esp_hal::rmt;
+
+use esp_wifi::wifi::WifiError;
+use esp_wifi::InitializationError;
+
+// Fault codes of the boot steps which can fail, given as the number of flashes
+// of the led fault pattern, so the failed step is told apart without a serial
+// console. The led flashes that many times, pauses and repeats until the
+// device is restarted:
+//
+// 1. the Wi-Fi or BLE controller failed to initialize;
+// 2. a configured GPIO is wrong;
+// 3. the network stack failed to start;
+// 4. the led could not be configured;
+// 5. any other task failed to start.
+pub(crate) const FAULT_CODES: [(u32, &str); 5] = [
+    (1, "Wi-Fi init"),
+    (2, "configuration"),
+    (3, "network stack"),
+    (4, "led setup"),
+    (5, "task startup"),
+];
+
+// Errors which stop the firmware from starting.
+pub(crate) enum FirmwareError {
+    // A configured GPIO pin does not exist, cannot be used or has already
+    // been taken.
+    GpioUnavailable {
+        number: u8,
+        name: &'static str,
+    },
+    LedTimer(ledc::timer::Error),
+    LedChannel(ledc::channel::Error),
+    LedRmt(rmt::Error),
+    WifiInit(InitializationError),
+    Wifi(WifiError),
+    // The task running the network stack could not be spawned.
+    Stack(SpawnError),
+    // A task could not be spawned, because its pool is exhausted.
+    Spawn {
+        task: &'static str,
+        error: SpawnError,
+    },
+}
+
+impl FirmwareError {
+    // Builds the error of a task which could not be spawned, for `map_err`.
+    pub(crate) const fn spawn(task: &'static str) -> impl FnOnce(SpawnError) -> Self {
+        move |error| Self::Spawn { task, error }
+    }
+
+    // Index of the failed boot step in `FAULT_CODES`.
+    const fn fault(&self) -> usize {
+        match self {
+            Self::WifiInit(_) | Self::Wifi(_) => 0,
+            // The configured GPIO pins are wrong.
+            Self::GpioUnavailable { .. } => 1,
+            Self::Stack(_) => 2,
+            Self::LedTimer(_) | Self::LedChannel(_) | Self::LedRmt(_) => 3,
+            Self::Spawn { .. } => 4,
+        }
+    }
+
+    // Number of flashes of the led fault pattern.
+    pub(crate) const fn flashes(&self) -> u32 {
+        FAULT_CODES[self.fault()].0
+    }
+
+    // Boot step which failed.
+    pub(crate) const fn step(&self) -> &'static str {
+        FAULT_CODES[self.fault()].1
+    }
+}
+
+impl fmt::Display for FirmwareError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::GpioUnavailable { number, name } => {
+                write!(f, "GPIO{number} is not available for the {name}")
+            }
+            Self::LedTimer(e) => write!(f, "Failed to configure led PWM timer: {e:?}"),
+            Self::LedChannel(e) => write!(f, "Failed to configure led PWM channel: {e:?}"),
+            Self::LedRmt(e) => write!(f, "Failed to configure led RMT channel: {e:?}"),
+            Self::WifiInit(e) => write!(f, "Failed to initialize Wi-Fi/BLE controller: {e:?}"),
+            Self::Wifi(e) => write!(f, "Wi-Fi controller error: {e:?}"),
+            Self::Stack(e) => write!(f, "Failed to start the network stack: {e:?}"),
+            Self::Spawn { task, error } => write!(f, "Failed to spawn the {task} task: {error:?}"),
+        }
+    }
+}
+
+impl From<InitializationError> for FirmwareError {
+    fn from(e: InitializationError) -> Self {
+        Self::WifiInit(e)
+    }
+}
+
+impl From<WifiError> for FirmwareError {
+    fn from(e: WifiError) -> Self {
+        Self::Wifi(e)
+    }
+}
@@ -0,0 +1,250 @@
+use esp_wifi::esp_now::EspNowReceiver;
+
+use heapless::Vec;
+
+use log::{error, info, warn};
+
+use button_led_logic::espnow::parse;
+
+use crate::led::{LedCommand, LedInput, Source};
+use crate::state::NOTIFY_LED;
+use crate::udp_control::{COMMAND_OFF, COMMAND_ON, COMMAND_TOGGLE};
+
+// Maximum number of allowed peers.
+pub(crate) const MAX_PEERS: usize = 4;
+
+// Parses a `aa:bb:cc:dd:ee:ff` MAC address.
+fn parse_mac(mac: &str) -> Option<[u8; 6]> {
+    let mut address = [0; 6];
+    let mut bytes = mac.split(':');
+    for byte in &mut address {
+        *byte = u8::from_str_radix(bytes.next()?, 16).ok()?;
+    }
+    bytes.next().is_none().then_some(address)
+}
+
+// Parses a comma-separated list of MAC addresses, logging the invalid ones.
+pub(crate) fn parse_peers(peers: &str) -> Vec<[u8; 6], MAX_PEERS> {
+    let mut addresses = Vec::new();
+    for peer in peers
+        .split(',')
+        .map(str::trim)
+        .filter(|peer| !peer.is_empty())
+    {
+        match parse_mac(peer) {
+            Some(address) => {
+                if addresses.push(address).is_err() {
+                    error!("Too many ESP-NOW peers, {peer} is ignored");
+                }
+            }
+            None => error!("Invalid ESP-NOW peer {peer}"),
+        }
+    }
+    addresses
+}
+
+// Allowed peer, with the last counter received from it.
+struct Peer {
+    address: [u8; 6],
+    counter: Option<u32>,
+}
+
+// Listens for the commands of the allowed peers, such as battery-powered
+// remotes, which keep working when the access point is down: 0x00 turns the
+// led off, 0x01 on and 0x02 toggles it.
+//
+// ESP-NOW shares the radio with the station, so it receives on the channel
+// of the access point, and the remotes must send on that same channel. While
+// the station scans for a network, packets sent on other channels are lost.
+//
+// Every packet carries a counter, which must grow from one packet to the
+// next so recorded packets cannot be replayed. The counters are kept in
+// memory, so the first packet of each peer after a restart is accepted.
+#[embassy_executor::task]
+pub(crate) async fn espnow_receiver(
+    mut receiver: EspNowReceiver<'static>,
+    peers: Vec<[u8; 6], MAX_PEERS>,
+    key: &'static str,
+) {
+    let mut peers: Vec<Peer, MAX_PEERS> = peers
+        .into_iter()
+        .map(|address| Peer {
+            address,
+            counter: None,
+        })
+        .collect();
+    info!("ESP-NOW receiver listening for {} peers", peers.len());
+
+    loop {
+        let received = receiver.receive_async().await;
+        let source = received.info.src_address;
+        let Some(peer) = peers.iter_mut().find(|peer| peer.address == source) else {
+            warn!("ESP-NOW packet from unknown peer {source:02x?} ignored");
+            continue;
+        };
+        let Some((command, counter)) = parse(received.data(), key.as_bytes()) else {
+            warn!("Invalid ESP-NOW packet from {source:02x?}");
+            continue;
+        };
+        if peer.counter.is_some_and(|last| counter <= last) {
+            warn!("Replayed ESP-NOW packet from {source:02x?} ignored");
+            continue;
+        }
+        peer.counter = Some(counter);
+
+        let led_input = match command {
+            COMMAND_OFF => LedInput::Off { fade_ms: None },
+            COMMAND_ON => LedInput::On {
+                fade_ms: None,
+                auto_off_secs: None,
+            },
+            COMMAND_TOGGLE => LedInput::Toggle,
+            _ => {
+                warn!("Unknown ESP-NOW command {command:#04x} from {source:02x?}");
+                continue;
+            }
+        };
+        if NOTIFY_LED
+            .try_send(LedCommand::new(Source::EspNow, led_input))
+            .is_err()
+        {
+            warn!("Led channel is full, ESP-NOW command dropped!");
+        }
+    }
+}
+
+#[cfg(feature = "espnow-remote")]
+pub(crate) use remote::{espnow_remote, send};
+
+// Remote side, sending commands to the peers instead of driving a led.
+#[cfg(feature = "espnow-remote")]
+mod remote {
+    use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+    use embassy_sync::signal::Signal;
+
+    use esp_storage::FlashStorage;
+
+    use esp_wifi::esp_now::{EspNowManager, EspNowSender, EspNowWifiInterface, PeerInfo};
+
+    use button_led_logic::espnow::{packet, reservation};
+
+    use super::*;
+    use crate::append_log::{self, AppendLog};
+
+    // Value marking the counter as written by this firmware.
+    const COUNTER_MAGIC: u32 = 0x4e4f_5743;
+    // Size of a reservation record: the reserved counter and its complement,
+    // which tells a complete record apart from an interrupted write.
+    const RECORD_SIZE: usize = 8;
+    // Counter reservations in flash, in the fourth sector of the `nvs`
+    // partition, after the boot counters.
+    const RESERVATIONS: AppendLog<RECORD_SIZE> = AppendLog::new(0xc000);
+
+    // Counter of the last sent packet, kept in RTC RAM so it keeps growing
+    // across deep sleeps and resets without writing the flash.
+    //
+    // The RAM holds random bytes after a power loss, such as a battery swap,
+    // which the magic value detects. The counter then resumes from the
+    // reservation stored in flash, past every counter sent before.
+    #[esp_hal::ram(rtc_fast, persistent)]
+    static mut COUNTER_RECORD: u32 = 0;
+    #[esp_hal::ram(rtc_fast, persistent)]
+    static mut COUNTER: u32 = 0;
+
+    // Command waiting to be sent to the peers.
+    static COMMAND: Signal<CriticalSectionRawMutex, u8> = Signal::new();
+
+    // Sends a command to the peers, without waiting.
+    pub(crate) fn send(command: u8) {
+        COMMAND.signal(command);
+    }
+
+    // Reserved counter stored in a record, if the record is complete.
+    fn decode(record: &[u8; RECORD_SIZE]) -> Option<u32> {
+        let reserved = u32::from_le_bytes([record[0], record[1], record[2], record[3]]);
+        let check = u32::from_le_bytes([record[4], record[5], record[6], record[7]]);
+        (reserved == !check).then_some(reserved)
+    }
+
+    // Record storing the given reserved counter.
+    fn encode(reserved: u32) -> [u8; RECORD_SIZE] {
+        let mut record = [0; RECORD_SIZE];
+        record[..4].copy_from_slice(&reserved.to_le_bytes());
+        record[4..].copy_from_slice(&(!reserved).to_le_bytes());
+        record
+    }
+
+    // Increments the counter, returning its new value, starting from the
+    // given reservation when the RTC RAM lost it.
+    fn next_counter(reserved: u32) -> u32 {
+        // SAFETY: the counter is only accessed by the remote task.
+        unsafe {
+            if COUNTER_RECORD != COUNTER_MAGIC {
+                COUNTER_RECORD = COUNTER_MAGIC;
+                COUNTER = reserved;
+            }
+            COUNTER = COUNTER.wrapping_add(1);
+            COUNTER
+        }
+    }
+
+    // Sends the commands given to `send` to every peer.
+    #[embassy_executor::task]
+    pub(crate) async fn espnow_remote(
+        manager: EspNowManager<'static>,
+        mut sender: EspNowSender<'static>,
+        peers: Vec<[u8; 6], MAX_PEERS>,
+        key: &'static str,
+    ) {
+        for &peer_address in &peers {
+            let peer = PeerInfo {
+                interface: EspNowWifiInterface::Sta,
+                peer_address,
+                lmk: None,
+                channel: None,
+                encrypt: false,
+            };
+            if let Err(e) = manager.add_peer(peer) {
+                error!("Failed to add ESP-NOW peer {peer_address:02x?}: {e:?}");
+            }
+        }
+        info!("ESP-NOW remote sending to {} peers", peers.len());
+
+        let mut flash = FlashStorage::new();
+        let (reserved, mut offset) = match RESERVATIONS.find_last(&mut flash, decode) {
+            Ok(found) => found,
+            Err(e) => {
+                error!("Failed to read the ESP-NOW counter from flash: {e:?}");
+                (None, append_log::FULL)
+            }
+        };
+        let mut reserved = reserved.unwrap_or(0);
+
+        loop {
+            let command = COMMAND.wait().await;
+            let counter = next_counter(reserved);
+            // The reservation is stored before sending, so a power loss never
+            // makes the counter go back. Sending anyway when storing fails
+            // only risks the receivers rejecting the packets after one.
+            if let Some(next) = reservation(counter, reserved) {
+                match RESERVATIONS.store(&mut flash, &encode(next), offset) {
+                    Ok(next_offset) => {
+                        reserved = next;
+                        offset = next_offset;
+                    }
+                    Err(e) => {
+                        error!("Failed to store the ESP-NOW counter: {e:?}");
+                        // Start over from an erased sector at the next attempt.
+                        offset = append_log::FULL;
+                    }
+                }
+            }
+            let packet = packet(command, counter, key.as_bytes());
+            for peer_address in &peers {
+                if let Err(e) = sender.send_async(peer_address, &packet).await {
+                    warn!("Failed to send ESP-NOW command to {peer_address:02x?}: {e:?}");
+                }
+            }
+        }
+    }
+}
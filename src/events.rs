@@ -0,0 +1,120 @@
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::pubsub::{PubSubChannel, Subscriber, WaitResult};
+use embassy_time::Timer;
+
+use picoserve::io::Write;
+use picoserve::response::sse::{EventSource, EventWriter};
+
+use crate::click::Click;
+use crate::heap;
+use crate::led::MAIN_CHANNEL;
+use crate::state::{self, LedState};
+use crate::WEB_TASK_POOL_SIZE;
+
+// Events kept for the streams which are slower than the publishers.
+const EVENTS_CAPACITY: usize = 8;
+// Maximum number of open event streams.
+//
+// Each stream occupies a web task for as long as it is open, so half of the
+// web tasks are kept for the other requests.
+const MAX_EVENT_STREAMS: usize = WEB_TASK_POOL_SIZE / 2;
+// Subscribers besides the event streams.
+#[cfg(feature = "display")]
+const OTHER_SUBSCRIBERS: usize = 1;
+#[cfg(not(feature = "display"))]
+const OTHER_SUBSCRIBERS: usize = 0;
+// Maximum number of subscribers to the events.
+const MAX_SUBSCRIBERS: usize = MAX_EVENT_STREAMS + OTHER_SUBSCRIBERS;
+// Interval between two keep-alive comments, so idle connections are not
+// dropped by NATs and proxies.
+const KEEP_ALIVE_SECS: u64 = 15;
+
+// Event sent to the `/events` streams.
+#[derive(Clone, Copy)]
+pub(crate) enum Event {
+    // The led has been turned on or off.
+    Led(LedState),
+    // The button has been clicked.
+    Button(Click),
+}
+
+pub(crate) type EventSubscriber =
+    Subscriber<'static, CriticalSectionRawMutex, Event, EVENTS_CAPACITY, MAX_SUBSCRIBERS, 0>;
+
+// Channel which broadcasts the events to every open stream.
+static EVENTS: PubSubChannel<CriticalSectionRawMutex, Event, EVENTS_CAPACITY, MAX_SUBSCRIBERS, 0> =
+    PubSubChannel::new();
+
+// Broadcasts an event without waiting for the streams.
+//
+// A stream which falls behind loses the oldest events, and receives the
+// current led state again.
+pub(crate) fn publish(event: Event) {
+    EVENTS.immediate_publisher().publish_immediate(event);
+}
+
+// Subscribes to the events, for the subscribers other than the streams,
+// which must subscribe before the server starts.
+#[cfg(feature = "display")]
+pub(crate) fn subscribe() -> Option<EventSubscriber> {
+    EVENTS.subscriber().ok()
+}
+
+// Server-Sent Events stream of the led and button events.
+pub(crate) struct EventStream {
+    subscriber: EventSubscriber,
+}
+
+impl EventStream {
+    // Opens a stream, unless too many streams are already open or the heap
+    // is critically low.
+    pub(crate) fn open() -> Option<Self> {
+        if heap::low_memory() {
+            return None;
+        }
+        EVENTS
+            .subscriber()
+            .ok()
+            .map(|subscriber| Self { subscriber })
+    }
+}
+
+pub(crate) const fn button_str(click: Click) -> &'static str {
+    match click {
+        Click::Single => "pressed",
+        Click::Double => "double_pressed",
+        Click::Long => "long_pressed",
+    }
+}
+
+impl EventSource for EventStream {
+    async fn write_events<W: Write>(mut self, mut writer: EventWriter<W>) -> Result<(), W::Error> {
+        // The stream starts with the current led state.
+        writer
+            .write_event("led", state::led_state(MAIN_CHANNEL).as_str())
+            .await?;
+
+        loop {
+            match select(
+                self.subscriber.next_message(),
+                Timer::after_secs(KEEP_ALIVE_SECS),
+            )
+            .await
+            {
+                Either::First(WaitResult::Message(Event::Led(led_state))) => {
+                    writer.write_event("led", led_state.as_str()).await?;
+                }
+                Either::First(WaitResult::Message(Event::Button(click))) => {
+                    writer.write_event("button", button_str(click)).await?;
+                }
+                Either::First(WaitResult::Lagged(_)) => {
+                    writer
+                        .write_event("led", state::led_state(MAIN_CHANNEL).as_str())
+                        .await?;
+                }
+                Either::Second(()) => writer.write_keepalive().await?,
+            }
+        }
+    }
+}
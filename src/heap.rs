@@ -0,0 +1,146 @@
+use core::cell::Cell;
+use core::fmt::{self, Write};
+
+use alloc::string::String;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Timer;
+
+use log::{error, info, warn};
+
+use crate::{DEVICE_CONFIG, MAX_HEAP_SIZE};
+
+// A failed allocation cannot be recovered from: the global allocator has no
+// way to report it, so it panics and the device resets. The led and the
+// button never allocate, while the optional subsystems allocate through
+// `try_format`, so running out of heap only disables them for a while.
+//
+// The state of the `/events` streams and the resources of the network stack
+// are not on the heap, so they cannot fail to allocate: every stream is a
+// subscriber slot of a static channel, refused once the slots are taken, and
+// `create_stack` places its resources in static cells, reserved at link
+// time.
+
+// Interval between two samples of the heap usage.
+const HEAP_SAMPLE_SECS: u64 = 10;
+// Share of the heap, in percent, the free heap has to stay above so that
+// `/health` does not report a near miss.
+const NEAR_MISS_PERCENT: usize = 10;
+
+// Largest heap usage sampled since boot, in bytes.
+static HIGH_WATERMARK: Mutex<CriticalSectionRawMutex, Cell<usize>> = Mutex::new(Cell::new(0));
+
+// Whether the free heap is below the critical threshold, so optional load
+// is shed.
+static LOW_MEMORY: Mutex<CriticalSectionRawMutex, Cell<bool>> = Mutex::new(Cell::new(false));
+
+// Whether the free heap was sampled below `NEAR_MISS_PERCENT` since boot.
+static NEAR_MISS: Mutex<CriticalSectionRawMutex, Cell<bool>> = Mutex::new(Cell::new(false));
+
+// Level of the free heap, compared with the configured thresholds.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum HeapLevel {
+    Normal,
+    Warning,
+    Critical,
+}
+
+impl HeapLevel {
+    fn of(free: usize) -> Self {
+        if free < DEVICE_CONFIG.heap_critical_bytes as usize {
+            Self::Critical
+        } else if free < DEVICE_CONFIG.heap_warning_bytes as usize {
+            Self::Warning
+        } else {
+            Self::Normal
+        }
+    }
+}
+
+// Retrieves the largest heap usage sampled since boot.
+pub(crate) fn high_watermark() -> usize {
+    HIGH_WATERMARK.lock(Cell::get)
+}
+
+// Retrieves whether the free heap ever got close to running out since boot.
+pub(crate) fn near_miss() -> bool {
+    NEAR_MISS.lock(Cell::get)
+}
+
+// Retrieves whether the free heap is critically low.
+pub(crate) fn low_memory() -> bool {
+    LOW_MEMORY.lock(Cell::get)
+}
+
+// Waits until the free heap is no longer critically low.
+pub(crate) async fn wait_for_memory() {
+    while low_memory() {
+        Timer::after_secs(HEAP_SAMPLE_SECS).await;
+    }
+}
+
+// Samples the heap usage, returning the used and free bytes.
+fn sample() -> (usize, usize) {
+    let used = esp_alloc::HEAP.used();
+    let free = esp_alloc::HEAP.free();
+    HIGH_WATERMARK.lock(|high_watermark| high_watermark.set(high_watermark.get().max(used)));
+    if free < MAX_HEAP_SIZE * NEAR_MISS_PERCENT / 100 {
+        NEAR_MISS.lock(|near_miss| near_miss.set(true));
+    }
+    (used, free)
+}
+
+// Counts the bytes of a formatted text.
+struct Length(usize);
+
+impl Write for Length {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0 += s.len();
+        Ok(())
+    }
+}
+
+// Formats a text on the heap like `format!`, but returns `None` instead of
+// aborting when the heap cannot hold it.
+pub(crate) fn try_format(args: fmt::Arguments) -> Option<String> {
+    let mut length = Length(0);
+    length.write_fmt(args).ok()?;
+
+    // The whole text is reserved first, so writing it never allocates.
+    let mut text = String::new();
+    let reserved = text.try_reserve_exact(length.0);
+    sample();
+    if reserved.is_err() {
+        error!("Not enough heap for {} bytes", length.0);
+        return None;
+    }
+    text.write_fmt(args).ok()?;
+    Some(text)
+}
+
+// Samples the heap usage, warning when the free heap gets low and shedding
+// optional load when it gets critically low, so allocations do not abort.
+#[embassy_executor::task]
+pub(crate) async fn heap_monitor() {
+    let mut level = HeapLevel::Normal;
+    loop {
+        let (used, free) = sample();
+
+        // Only changes of level are logged, so the log is not flooded.
+        let sampled = HeapLevel::of(free);
+        if sampled != level {
+            match sampled {
+                HeapLevel::Critical => {
+                    error!("Heap critically low, {free} bytes free, shedding optional load");
+                }
+                HeapLevel::Warning => warn!("Heap low, {free} bytes free, {used} bytes used"),
+                HeapLevel::Normal => info!("Heap back to normal, {free} bytes free"),
+            }
+            LOW_MEMORY.lock(|low_memory| low_memory.set(sampled == HeapLevel::Critical));
+            level = sampled;
+        }
+
+        Timer::after_secs(HEAP_SAMPLE_SECS).await;
+    }
+}
@@ -0,0 +1,120 @@
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Instant;
+
+use serde::Serialize;
+
+use crate::click::Click;
+use crate::led::Source;
+use crate::sntp;
+
+// Events kept in the history, the oldest one is overwritten once full.
+pub(crate) const HISTORY_SIZE: usize = 64;
+
+// What happened.
+#[derive(Clone, Copy)]
+pub(crate) enum Action {
+    // The led has been set to the given brightness percentage.
+    On { brightness: u8 },
+    Off,
+    // The button has been clicked.
+    Click(Click),
+}
+
+impl Action {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::On { .. } => "on",
+            Self::Off => "off",
+            Self::Click(Click::Single) => "click",
+            Self::Click(Click::Double) => "double_click",
+            Self::Click(Click::Long) => "long_press",
+        }
+    }
+}
+
+// Event of the history, with the time it happened.
+#[derive(Clone, Copy)]
+struct Event {
+    source: Source,
+    action: Action,
+    at: Instant,
+    // Seconds since the Unix epoch, if the clock was synchronized.
+    unix_time: Option<u64>,
+}
+
+impl Event {
+    const EMPTY: Self = Self {
+        source: Source::System,
+        action: Action::Off,
+        at: Instant::from_ticks(0),
+        unix_time: None,
+    };
+}
+
+// Ring buffer of the last events.
+struct History {
+    events: [Event; HISTORY_SIZE],
+    // Index the next event is written to.
+    next: usize,
+    len: usize,
+}
+
+static HISTORY: Mutex<CriticalSectionRawMutex, RefCell<History>> =
+    Mutex::new(RefCell::new(History {
+        events: [Event::EMPTY; HISTORY_SIZE],
+        next: 0,
+        len: 0,
+    }));
+
+// Event returned by the `/history` route.
+#[derive(Serialize)]
+pub(crate) struct HistoryEntry {
+    source: &'static str,
+    action: &'static str,
+    // Brightness percentage the led has been set to, for `on` events.
+    brightness: Option<u8>,
+    // Time elapsed since the event.
+    ms_ago: u64,
+    unix_time: Option<u64>,
+}
+
+// Records an event.
+pub(crate) fn record(source: Source, action: Action) {
+    let event = Event {
+        source,
+        action,
+        at: Instant::now(),
+        unix_time: sntp::now_unix(),
+    };
+    HISTORY.lock(|history| {
+        let mut history = history.borrow_mut();
+        let next = history.next;
+        history.events[next] = event;
+        history.next = (next + 1) % HISTORY_SIZE;
+        history.len = (history.len + 1).min(HISTORY_SIZE);
+    });
+}
+
+// Up to `limit` events, newest first.
+pub(crate) fn recent(limit: usize) -> heapless::Vec<HistoryEntry, HISTORY_SIZE> {
+    let now = Instant::now();
+    HISTORY.lock(|history| {
+        let history = history.borrow();
+        (1..=history.len.min(limit))
+            .map(|age| history.events[(history.next + HISTORY_SIZE - age) % HISTORY_SIZE])
+            .map(|event| HistoryEntry {
+                source: event.source.as_str(),
+                action: event.action.as_str(),
+                brightness: match event.action {
+                    Action::On { brightness } => Some(brightness),
+                    Action::Off | Action::Click(_) => None,
+                },
+                ms_ago: (now - event.at).as_millis(),
+                unix_time: event.unix_time,
+            })
+            .collect()
+    })
+}
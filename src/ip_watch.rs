@@ -0,0 +1,78 @@
+use core::net::Ipv4Addr;
+
+use embassy_net::Stack;
+use embassy_time::Timer;
+
+use log::info;
+
+#[cfg(feature = "gratuitous-arp")]
+use crate::arp;
+use crate::mqtt::{self, MqttEvent};
+use crate::state;
+use crate::webhook::{self, WebhookEvent};
+
+// Interval between two checks of the IPv4 address.
+const IP_POLL_MS: u64 = 1000;
+// Announcements sent for every address, and the interval between them
+// (RFC 5227).
+#[cfg(feature = "gratuitous-arp")]
+const ANNOUNCE_NUM: u32 = 2;
+#[cfg(feature = "gratuitous-arp")]
+const ANNOUNCE_INTERVAL_SECS: u64 = 2;
+
+// Announces the address on the network with gratuitous ARP requests.
+#[cfg(feature = "gratuitous-arp")]
+async fn announce(ip: Ipv4Addr) {
+    for i in 0..ANNOUNCE_NUM {
+        if i > 0 {
+            Timer::after_secs(ANNOUNCE_INTERVAL_SECS).await;
+        }
+        arp::announce(ip);
+    }
+}
+
+// Without gratuitous ARP, the address is only learnt from the traffic of the
+// device.
+#[cfg(not(feature = "gratuitous-arp"))]
+async fn announce(_ip: Ipv4Addr) {}
+
+// Watches the IPv4 address, announcing it every time the link comes up or the
+// address changes, and notifying the controllers of the changes, so the ones
+// reaching the device by address keep reaching it.
+#[embassy_executor::task]
+pub(crate) async fn ip_watch(stack: Stack<'static>) {
+    // Last address of the device, kept while the link is down.
+    let mut last_ip = None;
+    // Whether the current address has been announced since the link came up.
+    let mut announced = false;
+
+    loop {
+        let ip = stack
+            .is_link_up()
+            .then(|| stack.config_v4())
+            .flatten()
+            .map(|config| config.address.address());
+        match ip {
+            None => announced = false,
+            Some(ip) if !announced || last_ip != Some(ip) => {
+                if last_ip != Some(ip) {
+                    match last_ip {
+                        Some(old) => info!("IP address changed from {old} to {ip}"),
+                        None => info!("IP address acquired: {ip}"),
+                    }
+                    state::set_ip_address(ip);
+                    mqtt::publish(MqttEvent::Ip(ip));
+                    webhook::notify(WebhookEvent::Ip {
+                        old: last_ip,
+                        new: ip,
+                    });
+                    last_ip = Some(ip);
+                }
+                announce(ip).await;
+                announced = true;
+            }
+            Some(_) => {}
+        }
+        Timer::after_millis(IP_POLL_MS).await;
+    }
+}
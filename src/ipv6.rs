@@ -0,0 +1,220 @@
+use core::cell::Cell;
+use core::net::Ipv6Addr;
+use core::task::Context;
+
+use embassy_futures::select::{select, Either};
+use embassy_net::raw::{IpProtocol, IpVersion, PacketMetadata, RawSocket};
+use embassy_net::{Config, ConfigV6, Ipv6Cidr, Stack, StaticConfigV6};
+use embassy_net_driver::{Capabilities, Driver, HardwareAddress, LinkState, RxToken, TxToken};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Timer;
+
+use heapless::Vec;
+
+use log::{info, warn};
+
+use button_led_logic::link_local::LinkLocalAlias;
+use button_led_logic::slaac::{
+    address, link_local_address, router_solicitation, RouterAdvertisement, PREFIX_LEN,
+    ROUTER_SOLICITATION_SIZE,
+};
+
+use crate::state;
+use crate::wifi::NetDevice;
+
+// Size of the raw socket buffers, enough for a Router Advertisement with a
+// few options.
+const RAW_BUFFER_SIZE: usize = 512;
+// Router Solicitations sent before waiting for the periodic advertisements,
+// and the interval between them (RFC 4861).
+const MAX_ROUTER_SOLICITATIONS: u32 = 3;
+const ROUTER_SOLICITATION_INTERVAL_SECS: u64 = 4;
+
+// Link-local address kept next to the global one, once configured.
+static ALIAS: Mutex<CriticalSectionRawMutex, Cell<Option<LinkLocalAlias>>> =
+    Mutex::new(Cell::new(None));
+
+// Adds the link-local IPv6 address to a network configuration, so the device
+// is reachable over IPv6 before a router advertises a global prefix, and
+// stays reachable on it afterwards.
+pub(crate) fn with_link_local(mut config: Config, mac: [u8; 6]) -> Config {
+    let link_local = link_local_address(mac);
+    ALIAS.lock(|alias| alias.set(Some(LinkLocalAlias::new(link_local))));
+    config.ipv6 = ConfigV6::Static(StaticConfigV6 {
+        address: Ipv6Cidr::new(link_local, PREFIX_LEN),
+        gateway: None,
+        dns_servers: Vec::new(),
+    });
+    config
+}
+
+// Passes a frame to the link-local alias, if any.
+fn translate(frame: &mut [u8], translate: fn(&mut LinkLocalAlias, &mut [u8])) {
+    ALIAS.lock(|alias| {
+        if let Some(mut current) = alias.get() {
+            translate(&mut current, frame);
+            alias.set(Some(current));
+        }
+    });
+}
+
+// Network device keeping the link-local address reachable once the global
+// one is configured, since embassy-net holds a single IPv6 address.
+pub(crate) struct LinkLocalDevice<D> {
+    inner: D,
+}
+
+impl<D: Driver> LinkLocalDevice<D> {
+    pub(crate) const fn new(inner: D) -> Self {
+        Self { inner }
+    }
+}
+
+pub(crate) struct LinkLocalRxToken<T>(T);
+
+impl<T: RxToken> RxToken for LinkLocalRxToken<T> {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        self.0.consume(|frame| {
+            translate(frame, LinkLocalAlias::on_received);
+            f(frame)
+        })
+    }
+}
+
+pub(crate) struct LinkLocalTxToken<T>(T);
+
+impl<T: TxToken> TxToken for LinkLocalTxToken<T> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        self.0.consume(len, |frame| {
+            let result = f(frame);
+            translate(frame, LinkLocalAlias::on_sent);
+            result
+        })
+    }
+}
+
+impl<D: Driver> Driver for LinkLocalDevice<D> {
+    type RxToken<'a>
+        = LinkLocalRxToken<D::RxToken<'a>>
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = LinkLocalTxToken<D::TxToken<'a>>
+    where
+        Self: 'a;
+
+    fn receive(&mut self, cx: &mut Context) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let (rx, tx) = self.inner.receive(cx)?;
+        Some((LinkLocalRxToken(rx), LinkLocalTxToken(tx)))
+    }
+
+    fn transmit(&mut self, cx: &mut Context) -> Option<Self::TxToken<'_>> {
+        self.inner.transmit(cx).map(LinkLocalTxToken)
+    }
+
+    fn link_state(&mut self, cx: &mut Context) -> LinkState {
+        self.inner.link_state(cx)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn hardware_address(&self) -> HardwareAddress {
+        self.inner.hardware_address()
+    }
+}
+
+// Global or link-local IPv6 address of the device, if configured.
+pub(crate) fn address_of(stack: Stack<'_>) -> Option<Ipv6Addr> {
+    stack.config_v6().map(|config| config.address.address())
+}
+
+// Configures a global IPv6 address from the prefix advertised by the routers
+// (SLAAC, RFC 4862), along with the default route and the DNS servers.
+//
+// embassy-net only supports static IPv6 configurations, so the Router
+// Advertisements are handled here through a raw ICMPv6 socket. The prefix is
+// kept until another one is advertised, its lifetime is not tracked.
+#[embassy_executor::task]
+pub(crate) async fn slaac(stack: Stack<'static>, mac: [u8; 6]) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; RAW_BUFFER_SIZE];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; ROUTER_SOLICITATION_SIZE];
+    let socket = RawSocket::new::<NetDevice>(
+        stack,
+        IpVersion::Ipv6,
+        IpProtocol::Icmpv6,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+
+    stack.wait_link_up().await;
+    let solicitation = router_solicitation(mac);
+    let mut solicitations = 0;
+    let mut packet = [0; RAW_BUFFER_SIZE];
+    loop {
+        // Solicit the routers a few times, then wait for their periodic
+        // advertisements.
+        let received = if solicitations < MAX_ROUTER_SOLICITATIONS {
+            socket.send(&solicitation).await;
+            solicitations += 1;
+            let timeout = Timer::after_secs(ROUTER_SOLICITATION_INTERVAL_SECS);
+            match select(socket.recv(&mut packet), timeout).await {
+                Either::First(received) => received,
+                Either::Second(()) => continue,
+            }
+        } else {
+            socket.recv(&mut packet).await
+        };
+        let len = match received {
+            Ok(len) => len,
+            Err(e) => {
+                warn!("Failed to receive an ICMPv6 packet: {e:?}");
+                continue;
+            }
+        };
+
+        let Some(advertisement) = RouterAdvertisement::parse(&packet[..len]) else {
+            continue;
+        };
+        let Some(prefix) = advertisement.prefix else {
+            continue;
+        };
+        let global = address(prefix, mac);
+        let config = StaticConfigV6 {
+            address: Ipv6Cidr::new(global, PREFIX_LEN),
+            gateway: advertisement.default_router.then_some(advertisement.router),
+            dns_servers: advertisement.dns_servers,
+        };
+        if stack.config_v6().as_ref() == Some(&config) {
+            continue;
+        }
+
+        if config.dns_servers.is_empty() && stack.config_v4().is_none() {
+            warn!("No DNS server advertised over IPv6 nor DHCPv4, names will not resolve");
+        }
+        info!(
+            "Got IPv6 address {global} from router {}",
+            advertisement.router
+        );
+        ALIAS.lock(|alias| {
+            alias.set(alias.get().map(|mut current| {
+                current.set_global(Some(global));
+                current
+            }))
+        });
+        stack.set_config_v6(ConfigV6::Static(config));
+        state::set_ipv6_address(Some(global));
+    }
+}
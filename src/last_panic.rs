@@ -0,0 +1,129 @@
+use core::cell::RefCell;
+use core::fmt::Write;
+use core::panic::PanicInfo;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+use log::warn;
+
+use crate::boot;
+use crate::crc::crc32;
+use crate::logger::Truncate;
+
+// Size of a stored panic, its message followed by the backtrace.
+pub(crate) const MAX_PANIC_LEN: usize = 256;
+// Size of the panic location and message, longer messages are truncated so
+// the backtrace is always stored.
+const MAX_MESSAGE_LEN: usize = 152;
+// Backtrace addresses stored with the message.
+const MAX_BACKTRACE_FRAMES: usize = 8;
+// Value marking a panic stored in RTC RAM.
+const PANIC_MAGIC: u32 = 0x4c50_4e43;
+
+// Last panic, kept in RTC RAM across every reset but power loss.
+//
+// The RAM holds random bytes after power on, so a panic is only reported when
+// the magic value, the length and the CRC of the text are valid.
+#[esp_hal::ram(rtc_fast, persistent)]
+static mut STORED_MAGIC: u32 = 0;
+#[esp_hal::ram(rtc_fast, persistent)]
+static mut STORED_LEN: u32 = 0;
+#[esp_hal::ram(rtc_fast, persistent)]
+static mut STORED_CRC: u32 = 0;
+#[esp_hal::ram(rtc_fast, persistent)]
+static mut STORED_PANIC: [u8; MAX_PANIC_LEN] = [0; MAX_PANIC_LEN];
+
+// Last panic, copied from RTC RAM at boot and exposed by the `/lastpanic`
+// route.
+static LAST_PANIC: Mutex<
+    CriticalSectionRawMutex,
+    RefCell<Option<heapless::String<MAX_PANIC_LEN>>>,
+> = Mutex::new(RefCell::new(None));
+
+// Stores the text of a panic in RTC RAM.
+fn store(text: &str) {
+    let bytes = text.as_bytes();
+    let len = bytes.len().min(MAX_PANIC_LEN);
+
+    // SAFETY: the record is only written here, while the other tasks are
+    // stopped by the panic, and at boot or by the `/lastpanic` route, which
+    // never run at the same time on this single core.
+    unsafe {
+        STORED_MAGIC = 0;
+        STORED_PANIC[..len].copy_from_slice(&bytes[..len]);
+        // The length is at most `MAX_PANIC_LEN`, which fits an `u32`.
+        STORED_LEN = len as u32;
+        STORED_CRC = crc32(&bytes[..len]);
+        STORED_MAGIC = PANIC_MAGIC;
+    }
+}
+
+// Copies the panic stored before the last reset, if any, into the shared
+// state.
+pub(crate) fn load() {
+    // SAFETY: see `store`.
+    let stored = unsafe {
+        let len = STORED_LEN as usize;
+        if STORED_MAGIC != PANIC_MAGIC || len > MAX_PANIC_LEN {
+            return;
+        }
+        let bytes = &STORED_PANIC[..len];
+        if crc32(bytes) != STORED_CRC {
+            return;
+        }
+        core::str::from_utf8(bytes)
+            .ok()
+            .and_then(|text| heapless::String::try_from(text).ok())
+    };
+
+    if let Some(text) = stored {
+        warn!("Last panic: {text}");
+        LAST_PANIC.lock(|last_panic| *last_panic.borrow_mut() = Some(text));
+    }
+}
+
+// Retrieves the last panic, if any.
+pub(crate) fn last_panic() -> Option<heapless::String<MAX_PANIC_LEN>> {
+    LAST_PANIC.lock(|last_panic| last_panic.borrow().clone())
+}
+
+// Forgets the last panic, also in RTC RAM.
+pub(crate) fn clear() {
+    // SAFETY: see `store`.
+    unsafe {
+        STORED_MAGIC = 0;
+    }
+    LAST_PANIC.lock(|last_panic| *last_panic.borrow_mut() = None);
+}
+
+// Prints a panic on the serial port like `esp-backtrace`, stores it with a
+// truncated backtrace and resets the device.
+//
+// Nothing is allocated, the text is formatted on the stack.
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    esp_println::println!("\n====================== PANIC ======================");
+    esp_println::println!("{info}\n\nBacktrace:\n");
+    let backtrace = esp_backtrace::Backtrace::capture();
+    for frame in backtrace.frames() {
+        esp_println::println!("0x{:x}", frame.program_counter());
+    }
+
+    let mut message = heapless::String::<MAX_MESSAGE_LEN>::new();
+    let mut writer = Truncate(&mut message);
+    if let Some(location) = info.location() {
+        let _ = write!(writer, "{}:{}: ", location.file(), location.line());
+    }
+    let _ = write!(writer, "{}", info.message());
+
+    let mut text = heapless::String::<MAX_PANIC_LEN>::new();
+    let mut writer = Truncate(&mut text);
+    let _ = write!(writer, "{message}\nBacktrace:");
+    for frame in backtrace.frames().iter().take(MAX_BACKTRACE_FRAMES) {
+        let _ = write!(writer, " 0x{:x}", frame.program_counter());
+    }
+    store(&text);
+
+    boot::reset_after_panic()
+}
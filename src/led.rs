@@ -0,0 +1,559 @@
+use embassy_futures::select::{select, Either};
+use embassy_time::{Duration, Instant, Timer};
+
+use esp_hal::gpio::{AnyPin, Level};
+use esp_hal::ledc::channel::ChannelIFace;
+use esp_hal::ledc::timer::TimerIFace;
+use esp_hal::ledc::{self, Ledc, LowSpeed};
+use esp_hal::peripherals::RMT;
+use esp_hal::rmt::{AnyTxChannel, PulseCode, Rmt, TxChannel, TxChannelConfig, TxChannelCreator};
+use esp_hal::time::Rate;
+use esp_hal::Blocking;
+
+use embedded_hal::pwm::SetDutyCycle;
+
+use picoserve::make_static;
+
+use log::{error, info, warn};
+
+pub(crate) use button_led_logic::led::{
+    parse_command, parse_led_body, parse_led_channels, ColorUnsupported, LedCommand, LedDriver,
+    LedInput, LedPolarity, Rgb, Source, DEFAULT_BLINK_PERIOD_MS, MAIN_CHANNEL, MAX_BRIGHTNESS,
+    MAX_LED_CHANNELS,
+};
+
+#[cfg(feature = "ble")]
+use crate::ble;
+use crate::command_throttle::{CommandThrottle, Throttled};
+use crate::error::FirmwareError;
+use crate::events::{self, Event};
+use crate::history::{self, Action};
+use crate::logic::{Admission, BrightnessPresets, LedLogic, LogicConfig};
+use crate::metrics;
+use crate::motion;
+use crate::mqtt::{self, MqttEvent};
+use crate::pattern::LedPattern;
+use crate::quiet_hours::QuietLed;
+use crate::relay;
+use crate::state::{self, LedState, PendingCommand, SharedLedState, NOTIFY_LED};
+use crate::status_led::{self, StatusEvent};
+use crate::temperature;
+use crate::watchdog::{self, Task};
+use crate::webhook::{self, WebhookEvent};
+use crate::DEVICE_CONFIG;
+
+// Frequency of the led PWM signal.
+const LED_PWM_FREQUENCY_KHZ: u32 = 5;
+// LEDC timer and channels of the leds, which must differ from the buzzer ones
+// so the buzzer tones never change the led PWM signals. The main led comes
+// first, followed by the extra leds.
+const LED_LEDC_TIMER: ledc::timer::Number = ledc::timer::Number::Timer0;
+const LED_LEDC_CHANNELS: [ledc::channel::Number; MAX_LED_CHANNELS] = [
+    ledc::channel::Number::Channel0,
+    ledc::channel::Number::Channel2,
+    ledc::channel::Number::Channel3,
+    ledc::channel::Number::Channel4,
+];
+// Settings of the led logic of every channel.
+const LOGIC_CONFIG: LogicConfig = LogicConfig {
+    fade_ms: DEVICE_CONFIG.fade_ms,
+    manual_override_ms: DEVICE_CONFIG.manual_override_secs * 1000,
+    min_switch_interval_ms: DEVICE_CONFIG.min_toggle_interval_ms,
+    reject_early_switches: DEVICE_CONFIG.reject_early_toggles,
+};
+
+// WS2812 bit timings, in ticks of the 80 MHz RMT clock.
+const WS2812_T0H: u16 = 32;
+const WS2812_T0L: u16 = 68;
+const WS2812_T1H: u16 = 64;
+const WS2812_T1L: u16 = 36;
+// Pulse codes of a WS2812 frame: 24 color bits and the end marker.
+const WS2812_CODES: usize = 25;
+
+// Kind of led driven by the firmware.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LedType {
+    // A plain led, dimmed through PWM.
+    Pwm,
+    // An addressable WS2812 RGB led, driven through the RMT peripheral.
+    Ws2812,
+}
+
+impl LedType {
+    // Configured led type, or `None` when the configuration is invalid.
+    pub(crate) fn configured() -> Option<Self> {
+        match DEVICE_CONFIG.led_type {
+            "pwm" => Some(Self::Pwm),
+            "ws2812" => Some(Self::Ws2812),
+            _ => None,
+        }
+    }
+}
+
+// Whether the led can change color, which needs an addressable led.
+pub(crate) fn supports_color() -> bool {
+    LedType::configured() == Some(LedType::Ws2812)
+}
+
+// Scales a value to the given brightness percentage.
+//
+// The brightness is gamma corrected with a quadratic curve, so 50% looks
+// about half as bright as 100% to the human eye.
+fn gamma(max: u32, brightness: u8) -> u32 {
+    let brightness = u32::from(brightness.min(MAX_BRIGHTNESS));
+    let max_brightness = u32::from(MAX_BRIGHTNESS);
+    max * brightness * brightness / (max_brightness * max_brightness)
+}
+
+// Plain led, dimmed by a PWM channel.
+pub(crate) struct PwmLed {
+    channel: ledc::channel::Channel<'static, LowSpeed>,
+    polarity: LedPolarity,
+}
+
+impl PwmLed {
+    pub(crate) const fn new(
+        channel: ledc::channel::Channel<'static, LowSpeed>,
+        polarity: LedPolarity,
+    ) -> Self {
+        Self { channel, polarity }
+    }
+}
+
+impl LedDriver for PwmLed {
+    fn set_level(&mut self, brightness: u8) {
+        let max_duty = self.channel.max_duty_cycle();
+        let duty = u16::try_from(gamma(u32::from(max_duty), brightness)).unwrap_or(max_duty);
+        let duty = match self.polarity {
+            LedPolarity::ActiveLow => max_duty - duty,
+            LedPolarity::ActiveHigh => duty,
+        };
+        if let Err(e) = self.channel.set_duty_cycle(duty) {
+            error!("Failed to set led duty cycle: {e:?}");
+        }
+    }
+
+    fn set_color(&mut self, _color: Rgb) -> Result<(), ColorUnsupported> {
+        Err(ColorUnsupported)
+    }
+}
+
+// Addressable WS2812 RGB led, driven by an RMT channel.
+pub(crate) struct Ws2812Led {
+    // Missing once a transmission fails, since the channel is lost.
+    channel: Option<AnyTxChannel<Blocking>>,
+    color: Rgb,
+    brightness: u8,
+}
+
+impl Ws2812Led {
+    // Drives a WS2812 led through an RMT channel clocked at 80 MHz, white
+    // and off.
+    pub(crate) const fn new(channel: AnyTxChannel<Blocking>) -> Self {
+        Self {
+            channel: Some(channel),
+            color: Rgb::WHITE,
+            brightness: 0,
+        }
+    }
+
+    // Sends the color, scaled to the brightness, to the led.
+    fn show(&mut self) {
+        let Some(channel) = self.channel.take() else {
+            return;
+        };
+
+        // The led expects green, red and blue, most significant bit first.
+        let scale = |value| gamma(u32::from(value), self.brightness);
+        let bits =
+            scale(self.color.green) << 16 | scale(self.color.red) << 8 | scale(self.color.blue);
+        let mut codes = [u32::empty(); WS2812_CODES];
+        for (bit, code) in (0..24).rev().zip(&mut codes) {
+            *code = if bits >> bit & 1 == 1 {
+                PulseCode::new(Level::High, WS2812_T1H, Level::Low, WS2812_T1L)
+            } else {
+                PulseCode::new(Level::High, WS2812_T0H, Level::Low, WS2812_T0L)
+            };
+        }
+
+        // A frame lasts about 30 µs, so waiting for it does not stall the
+        // led task.
+        match channel
+            .transmit(&codes)
+            .map(|transaction| transaction.wait())
+        {
+            Ok(Ok(channel)) => self.channel = Some(channel),
+            Ok(Err((e, channel))) => {
+                error!("Failed to send the led color: {e:?}");
+                self.channel = Some(channel);
+            }
+            Err(e) => error!("Failed to send the led color, led disabled: {e:?}"),
+        }
+    }
+}
+
+impl LedDriver for Ws2812Led {
+    fn set_level(&mut self, brightness: u8) {
+        self.brightness = brightness;
+        self.show();
+    }
+
+    fn set_color(&mut self, color: Rgb) -> Result<(), ColorUnsupported> {
+        self.color = color;
+        self.show();
+        Ok(())
+    }
+}
+
+// Led driven by the firmware, of the configured type.
+pub(crate) enum Led {
+    Pwm(PwmLed),
+    Ws2812(Ws2812Led),
+}
+
+impl LedDriver for Led {
+    fn set_level(&mut self, brightness: u8) {
+        match self {
+            Self::Pwm(led) => led.set_level(brightness),
+            Self::Ws2812(led) => led.set_level(brightness),
+        }
+    }
+
+    fn set_color(&mut self, color: Rgb) -> Result<(), ColorUnsupported> {
+        match self {
+            Self::Pwm(led) => led.set_color(color),
+            Self::Ws2812(led) => led.set_color(color),
+        }
+    }
+}
+
+// Show a network state pattern on the status led when there is one, or on
+// the led otherwise.
+//
+// The status led has no equivalent of some patterns, which are then not
+// shown, so the led stays under the user control.
+pub(crate) fn show_pattern(pattern: LedPattern) {
+    if !status_led::is_present() {
+        let _ = NOTIFY_LED.try_send(LedCommand::new(Source::System, LedInput::Pattern(pattern)));
+    }
+    match pattern {
+        LedPattern::Connecting | LedPattern::WaitingForIp => {
+            status_led::publish(StatusEvent::Connecting);
+        }
+        LedPattern::Connected => status_led::publish(StatusEvent::Connected),
+        LedPattern::SelfTest | LedPattern::WeakSignal | LedPattern::OfflineReboot => {}
+        LedPattern::Fault(_) => status_led::publish(StatusEvent::Error),
+    }
+}
+
+// Receives the next led command, or `None` once the given time is reached.
+//
+// The led task waits here for as long as no command arrives, so the watchdog
+// does not expect it to report meanwhile.
+async fn receive_led_command(wake_at: Option<Instant>) -> Option<LedCommand> {
+    let Some(wake_at) = wake_at else {
+        return Some(watchdog::wait_for(Task::Led, NOTIFY_LED.receive()).await);
+    };
+
+    match watchdog::wait_for(Task::Led, select(NOTIFY_LED.receive(), Timer::at(wake_at))).await {
+        Either::First(command) => Some(command),
+        Either::Second(()) => None,
+    }
+}
+
+// Led channel driven by the led task, with its own logic and throttle.
+struct LedChannel {
+    index: usize,
+    led: QuietLed<Led>,
+    logic: LedLogic<SharedLedState>,
+    throttle: CommandThrottle<LedCommand>,
+    // Time of the next step of the running override, fade or blinking, if
+    // any.
+    step_at: Option<Instant>,
+}
+
+impl LedChannel {
+    const fn new(index: usize, led: Led, presets: BrightnessPresets) -> Self {
+        Self {
+            index,
+            led: QuietLed(led),
+            logic: LedLogic::new(index, presets, LOGIC_CONFIG, SharedLedState),
+            throttle: CommandThrottle::new(DEVICE_CONFIG.min_command_interval_ms),
+            step_at: None,
+        }
+    }
+
+    // Time at which the channel needs the led task: the next step, the end of
+    // the interval or the delay of the held command or the auto-off deadline.
+    fn wake_at(&self) -> Option<Instant> {
+        let release_at = self
+            .throttle
+            .release_at_ms()
+            .filter(|_| self.throttle.is_holding())
+            .map(Instant::from_millis);
+        [self.step_at, release_at, self.logic.auto_off_at()]
+            .into_iter()
+            .flatten()
+            .min()
+    }
+
+    // Handles whatever is due once the led task wakes up.
+    async fn on_wake(&mut self, now: Instant) {
+        if self.logic.auto_off_at().is_some_and(|at| at <= now) {
+            info!("Auto-off timer of led {} expired!", self.index);
+            self.apply(LedCommand::on_channel(
+                self.index,
+                Source::System,
+                LedInput::Off { fade_ms: None },
+            ))
+            .await;
+        }
+        if let Some(command) = self.throttle.release(now.as_millis()) {
+            self.apply_throttled(command).await;
+        }
+        if self.step_at.is_some_and(|at| at <= now) {
+            self.logic.on_step(&mut self.led, now);
+            self.step_at = self.next_step_at(now);
+        }
+        self.publish_pending();
+    }
+
+    // Publishes the command held back, if any, so the routes tell when it is
+    // applied rather than reporting it as applied.
+    fn publish_pending(&self) {
+        let pending =
+            self.throttle
+                .held()
+                .zip(self.throttle.release_at_ms())
+                .map(|(command, at_ms)| PendingCommand {
+                    at: Instant::from_millis(at_ms),
+                    led_state: command.input.brightness().map(LedState::from_brightness),
+                });
+        state::set_pending_command(self.index, pending);
+    }
+
+    // Time of the next step, when one is running.
+    fn next_step_at(&self, now: Instant) -> Option<Instant> {
+        self.logic
+            .step_delay_ms()
+            .map(|delay_ms| now + Duration::from_millis(delay_ms))
+    }
+
+    // Applies a received command, unless the throttle holds it.
+    async fn receive(&mut self, command: LedCommand) {
+        let now_ms = Instant::now().as_millis();
+        match self.throttle.receive(command, &self.logic, now_ms) {
+            Throttled::Bypass(command) => self.apply(command).await,
+            Throttled::Apply(command) => self.apply_throttled(command).await,
+            Throttled::Hold => {}
+            Throttled::Coalesce => metrics::count_led_coalesced(),
+        }
+        self.publish_pending();
+    }
+
+    // Applies a command let through by the throttle, starting its interval
+    // unless the logic ignores the command.
+    async fn apply_throttled(&mut self, command: LedCommand) {
+        let admission = self.admit(&command);
+        self.throttle
+            .on_admission(&admission, Instant::now().as_millis());
+        self.apply_admitted(command, admission).await;
+    }
+
+    // Decides whether the logic applies a command.
+    fn admit(&self, command: &LedCommand) -> Admission {
+        self.logic.admit(
+            &command.input,
+            command.source,
+            temperature::overheated(),
+            relay::switch_delay(),
+            Instant::now(),
+        )
+    }
+
+    // Applies a command through the led logic, publishing the change.
+    async fn apply(&mut self, command: LedCommand) {
+        let admission = self.admit(&command);
+        self.apply_admitted(command, admission).await;
+    }
+
+    // Applies a command the logic admitted, publishing the change.
+    //
+    // Only the main led is known to the subsystems describing a single led,
+    // such as the history, the events or the webhook.
+    async fn apply_admitted(&mut self, command: LedCommand, admission: Admission) {
+        match admission {
+            Admission::Accept => {}
+            Admission::Ignore => return,
+            // The command waits for the relay in the throttle, so the led task
+            // keeps serving the other commands and channels meanwhile.
+            Admission::Delay(until) => {
+                // Rounded up, so the relay can switch once the command is
+                // released.
+                let until_ms = until.as_micros().div_ceil(1000);
+                self.throttle.defer(command, &self.logic, until_ms);
+                return;
+            }
+        }
+
+        let LedCommand {
+            input: led_input,
+            source,
+            channel,
+        } = command;
+
+        let now = Instant::now();
+        let changed = self.logic.on_input(&mut self.led, led_input, source, now);
+        // Any input may start or stop a step, even when the state is the same.
+        self.step_at = self.next_step_at(now);
+        if !changed {
+            return;
+        }
+        info!("Led {channel} changed by {}", source.as_str());
+
+        state::set_auto_off_at(channel, self.logic.auto_off_at());
+
+        // Publish the brightness the led has or is fading to.
+        let target_brightness = self.logic.target_brightness();
+        mqtt::publish(MqttEvent::Led {
+            channel,
+            brightness: target_brightness,
+            source,
+        });
+        if channel != MAIN_CHANNEL {
+            return;
+        }
+        let led_state = if target_brightness > 0 {
+            history::record(
+                source,
+                Action::On {
+                    brightness: target_brightness,
+                },
+            );
+            LedState::On
+        } else {
+            history::record(source, Action::Off);
+            // Keep motion from turning the led on again right away, however
+            // it was turned off by hand.
+            if source.is_manual() {
+                motion::lock_out();
+            }
+            LedState::Off
+        };
+        events::publish(Event::Led(led_state));
+        #[cfg(feature = "ble")]
+        ble::publish(led_state);
+        webhook::notify(WebhookEvent::Led {
+            state: led_state,
+            brightness: target_brightness,
+        });
+    }
+}
+
+// Applies the led inputs through the logic of their channel, waking up for
+// every step of the running overrides, fades or blinkings.
+//
+// The steps are never throttled, only the commands, so a blink keeps its
+// period whatever the minimum command interval.
+//
+// The task owns the leds, the LEDC channels or the RMT channel driving them,
+// so the other tasks change the leds by sending inputs to `NOTIFY_LED`.
+#[embassy_executor::task]
+pub(crate) async fn change_led(leds: heapless::Vec<Led, MAX_LED_CHANNELS>) {
+    let presets = BrightnessPresets::parse(DEVICE_CONFIG.brightness_presets);
+    let mut channels: heapless::Vec<LedChannel, MAX_LED_CHANNELS> = leds
+        .into_iter()
+        .enumerate()
+        .map(|(index, led)| LedChannel::new(index, led, presets.clone()))
+        .collect();
+
+    loop {
+        let wake_at = channels.iter().filter_map(LedChannel::wake_at).min();
+        let Some(command) = receive_led_command(wake_at).await else {
+            let now = Instant::now();
+            for channel in &mut channels {
+                channel.on_wake(now).await;
+            }
+            continue;
+        };
+
+        // The quiet hours change the output of every led.
+        if matches!(command.input, LedInput::Reconcile) {
+            for channel in &mut channels {
+                channel
+                    .apply(LedCommand::on_channel(
+                        channel.index,
+                        command.source,
+                        LedInput::Reconcile,
+                    ))
+                    .await;
+            }
+            continue;
+        }
+
+        let Some(channel) = channels.get_mut(command.channel) else {
+            warn!("Led {} does not exist, input ignored", command.channel);
+            continue;
+        };
+        channel.receive(command).await;
+    }
+}
+
+// Configures the LEDC timer shared by the PWM leds.
+pub(crate) fn pwm_timer(
+    ledc: &'static Ledc<'static>,
+) -> Result<&'static ledc::timer::Timer<'static, LowSpeed>, FirmwareError> {
+    // Led PWM timer, it must outlive the led channels which reference it.
+    let led_timer = make_static!(
+        ledc::timer::Timer<'static, LowSpeed>,
+        ledc.timer(LED_LEDC_TIMER)
+    );
+    led_timer
+        .configure(ledc::timer::config::Config {
+            duty: ledc::timer::config::Duty::Duty10Bit,
+            clock_source: ledc::timer::LSClockSource::APBClk,
+            frequency: Rate::from_khz(LED_PWM_FREQUENCY_KHZ),
+        })
+        .map_err(FirmwareError::LedTimer)?;
+    Ok(led_timer)
+}
+
+// Drives the plain led of the given led channel through a PWM channel.
+pub(crate) fn pwm_led(
+    ledc: &'static Ledc<'static>,
+    timer: &'static ledc::timer::Timer<'static, LowSpeed>,
+    channel: usize,
+    pin: AnyPin<'static>,
+    polarity: LedPolarity,
+) -> Result<PwmLed, FirmwareError> {
+    let mut led_channel = ledc.channel(LED_LEDC_CHANNELS[channel], pin);
+    led_channel
+        .configure(ledc::channel::config::Config {
+            timer,
+            duty_pct: 0,
+            pin_config: ledc::channel::config::PinConfig::PushPull,
+        })
+        .map_err(FirmwareError::LedChannel)?;
+
+    Ok(PwmLed::new(led_channel, polarity))
+}
+
+// Drives an addressable WS2812 led through an RMT channel.
+pub(crate) fn ws2812_led(
+    rmt: RMT<'static>,
+    pin: AnyPin<'static>,
+) -> Result<Ws2812Led, FirmwareError> {
+    let rmt = Rmt::new(rmt, Rate::from_mhz(80)).map_err(FirmwareError::LedRmt)?;
+    let channel = rmt
+        .channel0
+        .configure_tx(
+            pin,
+            TxChannelConfig::default()
+                .with_clk_divider(1)
+                .with_idle_output_level(Level::Low)
+                .with_idle_output(true),
+        )
+        .map_err(FirmwareError::LedRmt)?;
+
+    Ok(Ws2812Led::new(channel.degrade()))
+}
@@ -0,0 +1,53 @@
+use core::cell::RefCell;
+use core::fmt::Write;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Instant;
+
+use log::Record;
+
+use button_led_logic::log_buffer::LogBuffer;
+pub(crate) use button_led_logic::log_buffer::{LogCursor, LogLine, LINE_SIZE};
+
+use crate::logger::{self, Truncate};
+
+// Size of the log buffer, enough for about a hundred lines.
+const LOG_BUFFER_SIZE: usize = 8192;
+
+// Lines are copied one at a time under the lock, so readers streaming the
+// buffer never see a line being overwritten.
+static LOG_BUFFER: Mutex<CriticalSectionRawMutex, RefCell<LogBuffer<LOG_BUFFER_SIZE>>> =
+    Mutex::new(RefCell::new(LogBuffer::new()));
+
+// Stores a log record in the buffer, prefixed by the time since boot, or as a
+// JSON line when configured.
+pub(crate) fn push(record: &Record) {
+    let mut line = heapless::String::<LINE_SIZE>::new();
+    if logger::is_json() {
+        logger::write_json(&mut line, record);
+    } else {
+        let uptime_ms = Instant::now().as_millis();
+        let _ = write!(
+            Truncate(&mut line),
+            "[{}.{:03}] {} - {}",
+            uptime_ms / 1000,
+            uptime_ms % 1000,
+            record.level(),
+            record.args()
+        );
+    }
+
+    LOG_BUFFER.lock(|buffer| buffer.borrow_mut().push(record.level(), line.as_bytes()));
+}
+
+// Copies the line at the cursor, if any, and moves the cursor to the next
+// line.
+pub(crate) fn read(cursor: &mut LogCursor, out: &mut [u8; LINE_SIZE]) -> Option<LogLine> {
+    LOG_BUFFER.lock(|buffer| buffer.borrow().read(cursor, out))
+}
+
+// Removes the lines older than the given sequence number.
+pub(crate) fn clear_until(seq: u32) {
+    LOG_BUFFER.lock(|buffer| buffer.borrow_mut().clear_until(seq));
+}
@@ -0,0 +1,359 @@
+use core::cell::RefCell;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Instant;
+
+use log::{error, Level, LevelFilter, Log, Metadata, Record};
+
+use crate::{log_buffer, sntp, syslog, DEVICE_CONFIG};
+
+// Maximum number of per-target levels.
+const MAX_DIRECTIVES: usize = 8;
+// Longest target of a per-target level.
+const MAX_TARGET_LEN: usize = 32;
+// Longest list of per-target levels, such as `esp_wifi=warn,server=debug`.
+pub(crate) const MAX_FILTER_LEN: usize = MAX_DIRECTIVES * (MAX_TARGET_LEN + 7);
+// Prefix of the targets of this firmware, which per-target levels can omit.
+const CRATE_PREFIX: &str = concat!(env!("CARGO_CRATE_NAME"), "::");
+// Size of a JSON log line printed on the serial port.
+const JSON_LINE_SIZE: usize = 256;
+// Marks a message truncated to fit a JSON log line.
+const ELLIPSIS: &str = "...";
+// End of a JSON log line, following the message.
+const JSON_END: &str = "\"}";
+
+// Writer truncating the text which does not fit the string.
+pub(crate) struct Truncate<'a, const N: usize>(pub(crate) &'a mut heapless::String<N>);
+
+impl<const N: usize> Write for Truncate<'_, N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.0.push(c).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Writer of a JSON log line, escaping the text written as the content of a
+// JSON string, and dropping the text past `limit` bytes.
+struct JsonLine<'a, const N: usize> {
+    line: &'a mut heapless::String<N>,
+    limit: usize,
+    truncated: bool,
+}
+
+impl<const N: usize> JsonLine<'_, N> {
+    // Writes text as is, such as the JSON syntax around the strings.
+    fn raw(&mut self, text: &str) {
+        if self.truncated || self.line.len() + text.len() > self.limit {
+            self.truncated = true;
+            return;
+        }
+        // The length has just been checked.
+        let _ = self.line.push_str(text);
+    }
+}
+
+impl<const N: usize> Write for JsonLine<'_, N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let mut escaped = heapless::String::<6>::new();
+            // Every escape sequence fits six bytes.
+            let _ = match c {
+                '"' => escaped.push_str("\\\""),
+                '\\' => escaped.push_str("\\\\"),
+                '\n' => escaped.push_str("\\n"),
+                '\r' => escaped.push_str("\\r"),
+                '\t' => escaped.push_str("\\t"),
+                c if c.is_control() => write!(escaped, "\\u{:04x}", u32::from(c)).map_err(drop),
+                c => escaped.push(c).map_err(drop),
+            };
+            self.raw(&escaped);
+            if self.truncated {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Formats a record as a one-line JSON object, such as
+// `{"ts":1234,"clock":"uptime","lvl":"INFO","target":"server","msg":"..."}`.
+//
+// The timestamp is the Unix time in seconds once the clock is synchronized,
+// with `clock` set to `unix`, and the time since boot in milliseconds before,
+// with `clock` set to `uptime`. A message too long for the line ends with an
+// ellipsis.
+pub(crate) fn write_json<const N: usize>(line: &mut heapless::String<N>, record: &Record) {
+    let (ts, clock) = match sntp::now_unix() {
+        Some(unix_secs) => (unix_secs, "unix"),
+        None => (Instant::now().as_millis(), "uptime"),
+    };
+    let mut header = heapless::String::<80>::new();
+    // The longest header is 68 bytes long, which always fits.
+    let _ = write!(
+        header,
+        "{{\"ts\":{ts},\"clock\":\"{clock}\",\"lvl\":\"{}\",\"target\":\"",
+        record.level()
+    );
+
+    line.clear();
+    let mut json = JsonLine {
+        line,
+        // Room is left for the ellipsis and the end of the line.
+        limit: N.saturating_sub(ELLIPSIS.len() + JSON_END.len()),
+        truncated: false,
+    };
+    json.raw(&header);
+    let _ = json.write_str(
+        record
+            .target()
+            .strip_prefix(CRATE_PREFIX)
+            .unwrap_or(record.target()),
+    );
+    json.raw("\",\"msg\":\"");
+    let _ = write!(json, "{}", record.args());
+
+    if json.truncated {
+        let _ = line.push_str(ELLIPSIS);
+    }
+    let _ = line.push_str(JSON_END);
+}
+
+// Whether the log lines are JSON objects rather than plain text.
+pub(crate) fn is_json() -> bool {
+    DEVICE_CONFIG.log_json
+}
+
+// Colors of the serial log lines, as printed by the esp-println logger.
+const RESET: &str = "\u{001B}[0m";
+
+const fn color(level: Level) -> &'static str {
+    match level {
+        Level::Error => "\u{001B}[31m",
+        Level::Warn => "\u{001B}[33m",
+        Level::Info => "\u{001B}[32m",
+        Level::Debug => "\u{001B}[34m",
+        Level::Trace => "\u{001B}[35m",
+    }
+}
+
+// Level of a target, such as `esp_wifi=warn`.
+struct Directive {
+    target: heapless::String<MAX_TARGET_LEN>,
+    level: LevelFilter,
+}
+
+impl Directive {
+    // Whether the directive applies to the given target or to its parent
+    // module.
+    fn matches(&self, target: &str) -> bool {
+        let matches = |target: &str| {
+            target
+                .strip_prefix(self.target.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+        };
+        matches(target) || target.strip_prefix(CRATE_PREFIX).is_some_and(matches)
+    }
+}
+
+// Levels of the logged records: a global level, and the levels of some
+// targets, such as `info,esp_wifi=warn,server=debug`.
+pub(crate) struct LogFilter {
+    level: Option<LevelFilter>,
+    directives: heapless::Vec<Directive, MAX_DIRECTIVES>,
+}
+
+impl LogFilter {
+    // Parses comma-separated levels, each one applying to every target or,
+    // when preceded by `target=`, to the given target only.
+    pub(crate) fn parse(spec: &str) -> Result<Self, &'static str> {
+        let mut filter = Self {
+            level: None,
+            directives: heapless::Vec::new(),
+        };
+        for item in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+        {
+            let (target, level) = match item.split_once('=') {
+                Some((target, level)) => (Some(target.trim()), level.trim()),
+                None => (None, item),
+            };
+            let level = level
+                .parse()
+                .map_err(|_| "expected off, error, warn, info, debug or trace")?;
+            let Some(target) = target else {
+                filter.level = Some(level);
+                continue;
+            };
+            let target = heapless::String::try_from(target).map_err(|()| "target too long")?;
+            if target.is_empty() {
+                return Err("empty target");
+            }
+            filter
+                .directives
+                .push(Directive { target, level })
+                .map_err(|_| "too many targets")?;
+        }
+        Ok(filter)
+    }
+
+    // Replaces the global level.
+    pub(crate) fn set_level(&mut self, level: LevelFilter) {
+        self.level = Some(level);
+    }
+}
+
+// Global level, consulted for every record whose target has no level.
+static LEVEL: AtomicU8 = AtomicU8::new(LevelFilter::Info as u8);
+// Per-target levels.
+static DIRECTIVES: Mutex<
+    CriticalSectionRawMutex,
+    RefCell<heapless::Vec<Directive, MAX_DIRECTIVES>>,
+> = Mutex::new(RefCell::new(heapless::Vec::new()));
+
+// Global level.
+pub(crate) fn level() -> LevelFilter {
+    let level = usize::from(LEVEL.load(Ordering::Relaxed));
+    LevelFilter::iter().nth(level).unwrap_or(LevelFilter::Info)
+}
+
+// Level of the records of the given target.
+fn target_level(target: &str) -> LevelFilter {
+    DIRECTIVES
+        .lock(|directives| {
+            directives
+                .borrow()
+                .iter()
+                .filter(|directive| directive.matches(target))
+                // The most specific target wins.
+                .max_by_key(|directive| directive.target.len())
+                .map(|directive| directive.level)
+        })
+        .unwrap_or_else(level)
+}
+
+// Per-target levels, such as `esp_wifi=warn,server=debug`.
+pub(crate) fn filter() -> heapless::String<MAX_FILTER_LEN> {
+    let mut filter = heapless::String::new();
+    DIRECTIVES.lock(|directives| {
+        for (i, directive) in directives.borrow().iter().enumerate() {
+            let separator = if i > 0 { "," } else { "" };
+            // The longest targets and levels always fit.
+            let _ = write!(
+                filter,
+                "{separator}{}={}",
+                directive.target,
+                level_name(directive.level)
+            );
+        }
+    });
+    filter
+}
+
+// Lowercase name of a level, as accepted by `LogFilter::parse`.
+pub(crate) const fn level_name(level: LevelFilter) -> &'static str {
+    match level {
+        LevelFilter::Off => "off",
+        LevelFilter::Error => "error",
+        LevelFilter::Warn => "warn",
+        LevelFilter::Info => "info",
+        LevelFilter::Debug => "debug",
+        LevelFilter::Trace => "trace",
+    }
+}
+
+// Applies new levels. A missing global level keeps the current one, while
+// the per-target levels are always replaced.
+pub(crate) fn set_filter(filter: LogFilter) {
+    let global = filter.level.unwrap_or_else(level);
+    LEVEL.store(global as u8, Ordering::Relaxed);
+    let max_level = filter
+        .directives
+        .iter()
+        .map(|directive| directive.level)
+        .fold(global, LevelFilter::max);
+    DIRECTIVES.lock(|directives| *directives.borrow_mut() = filter.directives);
+
+    // The `log` macros skip the records above the highest level, without
+    // calling the logger.
+    //
+    // SAFETY: the firmware runs on a single core, where writing the level
+    // cannot race with reading it.
+    unsafe {
+        log::set_max_level_racy(max_level);
+    }
+}
+
+// Logger printing records on the serial port, like the esp-println logger,
+// storing them in the log buffer and forwarding them to the syslog server when
+// configured.
+//
+// Records are written as plain text or, when configured, as JSON lines.
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= target_level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        if is_json() {
+            let mut line = heapless::String::<JSON_LINE_SIZE>::new();
+            write_json(&mut line, record);
+            esp_println::println!("{line}");
+        } else {
+            esp_println::println!(
+                "{}{} - {}{}",
+                color(record.level()),
+                record.level(),
+                record.args(),
+                RESET
+            );
+        }
+        log_buffer::push(record);
+        syslog::forward(record);
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: Logger = Logger;
+
+// Installs the logger, keeping records up to the configured levels, or to the
+// ones set by the `ESP_LOG` environment variable at build time.
+//
+// Invalid levels fall back to the `info` level.
+pub(crate) fn init_logger() {
+    // SAFETY: the logger is installed once at boot, before any other task
+    // runs. The target lacks the atomics required by `log::set_logger`.
+    unsafe {
+        log::set_logger_racy(&LOGGER).expect("Logger already installed");
+    }
+
+    let spec = match DEVICE_CONFIG.log_level {
+        "" => option_env!("ESP_LOG").unwrap_or("info"),
+        spec => spec,
+    };
+    match LogFilter::parse(spec) {
+        Ok(filter) => set_filter(filter),
+        Err(e) => {
+            set_filter(LogFilter {
+                level: Some(LevelFilter::Info),
+                directives: heapless::Vec::new(),
+            });
+            error!("Invalid log level {spec}: {e}, using `info`");
+        }
+    }
+}
@@ -6,46 +6,162 @@
     holding buffers for the duration of a data transfer."
 )]
 #![feature(impl_trait_in_assoc_type)]
+// The router type nests one level for each route.
+#![recursion_limit = "256"]
 
 extern crate alloc;
 
+mod adc;
+mod api_version;
+mod append_log;
+#[cfg(feature = "gratuitous-arp")]
+mod arp;
+mod auth;
+#[cfg(feature = "ble")]
+mod ble;
+mod board;
+mod boot;
+mod boot_count;
+mod button;
+mod button_actions;
+mod buzzer;
+#[cfg(feature = "coap")]
+mod coap;
+mod cors;
+mod dhcp;
+#[cfg(feature = "display")]
+mod display;
+mod encoder;
+mod error;
+#[cfg(feature = "espnow")]
+mod espnow;
+mod events;
+mod heap;
+mod history;
+mod ip_watch;
+#[cfg(feature = "ipv6")]
+mod ipv6;
+mod last_panic;
+mod led;
+mod log_buffer;
+mod logger;
+mod mdns;
+mod metrics;
+mod motion;
+mod mqtt;
+mod net_watchdog;
+mod ota;
+mod outputs;
+mod power_save;
+mod quiet_hours;
+mod rate_limit;
+mod relay;
+mod request_log;
+mod schedule;
 mod server;
+mod settings;
+mod sleep;
+mod sntp;
+mod state;
+mod status_led;
+mod syslog;
+mod temperature;
+mod touch;
+mod udp_control;
+mod watchdog;
+mod weak_signal;
+mod web_pool;
+mod webhook;
+mod wifi;
+mod wifi_networks;
 
 use core::net::Ipv4Addr;
 
-use alloc::boxed::Box;
-
-use log::{error, info};
+use log::{error, info, warn};
 
 use embassy_executor::Spawner;
-use embassy_net::{Config, DhcpConfig, Runner, Stack, StackResources};
-use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
-use embassy_sync::signal::Signal;
+use embassy_net::{Config, Ipv4Cidr, StaticConfigV4};
 use embassy_time::{Duration, Timer};
 
 use esp_hal::clock::CpuClock;
 use esp_hal::gpio::{Input, InputConfig, Level, Output, OutputConfig, Pull};
-use esp_hal::rng::Rng;
-use esp_hal::timer::systimer::SystemTimer;
+use esp_hal::ledc::{self, LSGlobalClkSource, Ledc};
+use esp_hal::rtc_cntl::Rtc;
 use esp_hal::timer::timg::TimerGroup;
+#[cfg(not(feature = "esp32s3"))]
+use esp_hal::tsens::{Config as TsensConfig, TemperatureSensor};
 
-use esp_wifi::wifi::{
-    ClientConfiguration, Configuration, WifiController, WifiDevice, WifiEvent, WifiState,
-};
 use esp_wifi::EspWifiController;
 
-use picoserve::{make_static, AppBuilder, AppRouter};
+use picoserve::{make_static, AppRouter, AppWithStateBuilder};
 
 use esp_backtrace as _;
 
+// Logic tested on the host in its own crate, imported at the crate root so
+// its modules are used like the other ones.
+use button_led_logic::{
+    api, backoff, click, command_throttle, crc, debounce, factory_reset, gesture, logic, morse,
+    pattern, quadrature, sha256,
+};
+
+use crate::board::Board;
+use crate::button::press_button;
+use crate::buzzer::BeepPattern;
+use crate::dhcp::dhcp_server;
+use crate::error::FirmwareError;
+use crate::ip_watch::ip_watch;
+use crate::led::{
+    change_led, parse_led_channels, pwm_led, pwm_timer, show_pattern, ws2812_led, Led, LedCommand,
+    LedInput, LedPolarity, LedType, Source, MAIN_CHANNEL,
+};
+use crate::mdns::mdns_responder;
+use crate::mqtt::{mqtt_task, MqttBuffers};
+use crate::net_watchdog::net_watchdog;
+use crate::outputs::ExtraOutput;
+use crate::pattern::LedPattern;
+use crate::power_save::PowerSave;
+use crate::quiet_hours::quiet_hours_task;
+use crate::schedule::scheduler;
 use crate::server::{run_server, AppProps};
+use crate::settings::{load_settings, MAX_HOSTNAME_LEN};
+use crate::sntp::sntp_task;
+use crate::state::{LedState, SharedLedState, NOTIFY_LED, REBOOT};
+use crate::status_led::status_led;
+use crate::syslog::syslog_task;
+use crate::udp_control::{udp_control, SECRET_LEN};
+use crate::watchdog::watchdog;
+use crate::webhook::{webhook_task, WebhookUrl};
+use crate::wifi::{
+    access_point, connect, connect_station, create_stack, net_task, start_access_point,
+    station_net_config, wait_for_ip, PROVISIONING_IP, PROVISIONING_SSID, SIM_NET_SSID,
+};
+use crate::wifi_networks::WifiNetworks;
 
-const MAX_HEAP_SIZE: usize = 64 * 1024;
+// Heap shared by the Wi-Fi driver, the network buffers and the optional
+// subsystems, such as MQTT, as configured, grown by the features which
+// allocate more.
+pub(crate) const MAX_HEAP_SIZE: usize = DEVICE_CONFIG.heap_kib as usize * 1024 + BLE_HEAP_SIZE;
+// The Bluetooth controller takes about 30 KiB of heap.
+const BLE_HEAP_SIZE: usize = if cfg!(feature = "ble") { 32 * 1024 } else { 0 };
 const MILLISECONDS_TO_WAIT: u64 = 100;
-const SECONDS_TO_WAIT_FOR_RECONNECTION: u64 = 5;
+// Port of the HTTP server when the configured one is invalid.
+const DEFAULT_HTTP_PORT: u16 = 80;
+// Blinking period which shows the device is waiting to be provisioned.
+const PROVISIONING_BLINK_PERIOD_MS: u64 = 100;
+// Delay before rebooting, so pending responses can be sent.
+const REBOOT_DELAY_MS: u64 = 500;
+// LEDC timer and channel of the buzzer, which must differ from the led ones so
+// the buzzer tones never change the led PWM signal.
+const BUZZER_LEDC_TIMER: ledc::timer::Number = ledc::timer::Number::Timer1;
+const BUZZER_LEDC_CHANNEL: ledc::channel::Number = ledc::channel::Number::Channel1;
 
-// Signal which notifies the led change of state.
-static NOTIFY_LED: Signal<CriticalSectionRawMutex, LedInput> = Signal::new();
+// Number of tasks serving HTTP connections.
+//
+// Each open `/events` stream occupies one of them, so at most half of them
+// serve streams and the others stay available to the other routes.
+// One of them is reserved for refusing the connections arriving while every
+// other one is busy.
+pub(crate) const WEB_TASK_POOL_SIZE: usize = 8;
 
 #[toml_cfg::toml_config]
 struct DeviceConfig {
@@ -53,13 +169,276 @@ struct DeviceConfig {
     ssid: &'static str,
     #[default("")]
     password: &'static str,
-}
-
-#[derive(Clone, Copy)]
-enum LedInput {
-    On,
-    Off,
-    Button,
+    // Additional Wi-Fi networks, tried when the first one is not visible or
+    // connecting to it keeps failing.
+    #[default("")]
+    ssid2: &'static str,
+    #[default("")]
+    password2: &'static str,
+    #[default("")]
+    ssid3: &'static str,
+    #[default("")]
+    password3: &'static str,
+    // Connection attempts to each network before falling back to provisioning
+    // mode.
+    #[default(5)]
+    wifi_attempts: u32,
+    // Wi-Fi power-save mode in station mode: `none` keeps the radio always
+    // on, `minimum` lets the modem sleep between DTIM beacons and `maximum`
+    // for several beacons, lowering the power draw at the cost of up to a
+    // few hundred milliseconds of latency on every request.
+    #[default("none")]
+    wifi_power_save: &'static str,
+    // Time allowed to read an HTTP request or write a response, raised to
+    // 3 seconds with the `maximum` power-save mode.
+    #[default(1000)]
+    http_timeout_ms: u64,
+    // Time allowed to a client to send its first request once connected.
+    #[default(5000)]
+    http_first_request_timeout_ms: u64,
+    // Whether connections are kept open after a response, so browsers reuse
+    // them. Each open connection holds a web task, so many browser tabs can
+    // leave none to the other clients.
+    #[default(true)]
+    http_keep_alive: bool,
+    // Time a kept-alive connection waits for its next request before being
+    // closed.
+    #[default(1000)]
+    http_idle_timeout_ms: u64,
+    // Requests served on a kept-alive connection before closing it, freeing
+    // its web task, or 0 for no limit.
+    #[default(0)]
+    http_max_requests: u32,
+    // Consecutive failed connections after which the next network is tried.
+    #[default(3)]
+    wifi_network_attempts: u32,
+    // Static IPv4 configuration, DHCP is used when `static_ip` is empty.
+    #[default("")]
+    static_ip: &'static str,
+    #[default("")]
+    gateway: &'static str,
+    #[default("255.255.255.0")]
+    netmask: &'static str,
+    #[default("")]
+    dns: &'static str,
+    // Hostname sent to the DHCP server and answered over mDNS, as
+    // `<hostname>.local`. When empty, it is `button-led-` followed by the
+    // last three bytes of the MAC address, so devices are told apart.
+    #[default("")]
+    hostname: &'static str,
+    // API key required by the routes changing the device, through the
+    // `X-Api-Key` header or the `key` query parameter. Routes are open when
+    // empty.
+    #[default("")]
+    api_key: &'static str,
+    // Credentials required by every route through HTTP Basic authentication,
+    // except in provisioning mode. Authentication is disabled when the user is
+    // empty. The user is at most 32 bytes long, the password 64.
+    #[default("")]
+    http_user: &'static str,
+    #[default("")]
+    http_pass: &'static str,
+    // Origin allowed to call the routes from a browser page, such as
+    // `http://nas.local:8080`, or `*` for any origin. CORS is disabled when
+    // empty.
+    #[default("")]
+    cors_origin: &'static str,
+    // Port of the HTTP server, advertised over mDNS.
+    #[default(80)]
+    http_port: u16,
+    #[default(true)]
+    led_active_low: bool,
+    #[default(board::DEFAULT_BUTTON_GPIO)]
+    button_gpio: u8,
+    #[default(board::DEFAULT_LED_GPIO)]
+    led_gpio: u8,
+    // Led wired to the led GPIO: `pwm` for a plain led, or `ws2812` for an
+    // addressable RGB led, whose polarity is ignored.
+    #[default(board::DEFAULT_LED_TYPE)]
+    led_type: &'static str,
+    // GPIO of a second led showing the system state, which is missing when
+    // negative.
+    #[default(-1)]
+    status_led_gpio: i8,
+    // Comma-separated extra plain leds, dimmed through PWM, as
+    // `gpio[:high|low]`, where the level turning them on is high by default.
+    // They are addressed as led channels 1, 2 and 3, the led above being
+    // channel 0.
+    #[default("")]
+    led_channels: &'static str,
+    // Led channel toggled by the button, the main led by default.
+    #[default(0)]
+    button_led_channel: usize,
+    // Whether a click cycles the led channel of the button through the
+    // brightness presets, rather than toggling it, unless the button actions
+    // are changed through the `/config` route.
+    #[default(false)]
+    button_cycles_presets: bool,
+    // Comma-separated brightness percentages cycled through, in this order,
+    // starting over after the last one.
+    #[default("0,25,50,100")]
+    brightness_presets: &'static str,
+    #[default(false)]
+    status_led_active_low: bool,
+    // Comma-separated extra outputs controlled through the `/gpio` routes,
+    // such as relays or fans, as `name:gpio[:high|low]`, where the level
+    // turning them on is high by default. Outputs on the led GPIO are
+    // ignored.
+    #[default("")]
+    extra_outputs: &'static str,
+    // GPIO of an analog sensor read through the `/adc` route, one of the
+    // ADC1 GPIOs of the chip, which is missing when negative.
+    #[default(-1)]
+    adc_gpio: i8,
+    // GPIOs of the I2C bus of an SSD1306 display, with the `display` feature,
+    // which is missing when either is negative.
+    #[default(-1)]
+    display_sda_gpio: i8,
+    #[default(-1)]
+    display_scl_gpio: i8,
+    // GPIOs of the contacts of a rotary encoder changing the led brightness,
+    // which is missing when either is negative, and of its push switch,
+    // acting like the button, which is missing when negative.
+    #[default(-1)]
+    encoder_a_gpio: i8,
+    #[default(-1)]
+    encoder_b_gpio: i8,
+    #[default(-1)]
+    encoder_switch_gpio: i8,
+    // GPIO of a capacitive touch pad acting like the button, with the `touch`
+    // feature, one of GPIO1 to GPIO14, which is missing when negative. It is
+    // touched once its reading exceeds the idle one by
+    // `touch_threshold_percent`, and the raw readings are returned by the
+    // `/touch` route to tune it.
+    #[default(-1)]
+    touch_gpio: i8,
+    #[default(5)]
+    touch_threshold_percent: u8,
+    // GPIO of a PIR motion sensor turning the led on, which is missing when
+    // negative. The led turns off `motion_hold_secs` after the last motion,
+    // and motion is ignored for `motion_lockout_secs` after the led has been
+    // turned off through the `/off` route.
+    #[default(-1)]
+    pir_gpio: i8,
+    // GPIO of a piezo buzzer beeping on button presses, Wi-Fi connections and
+    // failures, which is missing when negative. It is muted through the
+    // `/config` route.
+    #[default(-1)]
+    buzzer_gpio: i8,
+    // Whether motion turns the led on at boot, changed at runtime through the
+    // `/automation` route.
+    #[default(true)]
+    motion_enabled: bool,
+    #[default(300)]
+    motion_hold_secs: u64,
+    #[default(60)]
+    motion_lockout_secs: u64,
+    #[default(2000)]
+    long_press_ms: u64,
+    #[default(30)]
+    debounce_ms: u64,
+    #[default(400)]
+    double_click_ms: u64,
+    #[default(300)]
+    fade_ms: u64,
+    // Shortest time between two switches of the led, for a relay wired in
+    // its place, which wears out when switched too fast. Blinking is slowed
+    // down accordingly, and patterns and Morse messages are not shown.
+    // Disabled when 0.
+    #[default(0)]
+    min_toggle_interval_ms: u64,
+    // Whether the switches arriving too early are rejected, rather than
+    // delayed until the minimum interval elapses.
+    #[default(false)]
+    reject_early_toggles: bool,
+    // Shortest time between two commands applied to the led, whatever sends
+    // them. The commands arriving faster are coalesced, and the most recent
+    // one is applied once the interval elapses. Blinking, fades and the
+    // inputs of the firmware itself are not affected. Disabled when 0.
+    #[default(0)]
+    min_command_interval_ms: u64,
+    // Time, in seconds, during which the schedule and the motion sensor do
+    // not change the led after it was changed by hand, such as switched off
+    // with the button. Disabled when 0.
+    #[default(0)]
+    manual_override_secs: u64,
+    // Signal strength, in dBm, below which the Wi-Fi signal is weak.
+    #[default(-80)]
+    rssi_warning_dbm: i32,
+    // Whether the led flashes while the Wi-Fi signal is weak.
+    #[default(true)]
+    rssi_warning_blink: bool,
+    // NTP server, either a hostname or an IPv4 address.
+    #[default("pool.ntp.org")]
+    ntp_server: &'static str,
+    // Offset of the local time from UTC, used by the led schedule and
+    // the quiet hours.
+    #[default(0)]
+    timezone_offset_minutes: i32,
+    // Syslog server IPv4 address, log forwarding is disabled when empty.
+    #[default("")]
+    syslog_host: &'static str,
+    #[default(514)]
+    syslog_port: u16,
+    // Log level, such as `debug`, optionally followed by per-target levels,
+    // such as `info,esp_wifi=warn`. The `ESP_LOG` build variable is used when
+    // empty. It can be changed at runtime through the `/loglevel` route.
+    #[default("")]
+    log_level: &'static str,
+    // Whether the log lines are JSON objects, on the serial port, in the log
+    // buffer and to the syslog server, rather than plain text.
+    #[default(false)]
+    log_json: bool,
+    // MQTT broker IPv4 address, MQTT is disabled when empty.
+    #[default("")]
+    mqtt_host: &'static str,
+    #[default(1883)]
+    mqtt_port: u16,
+    // MQTT client identifier, the hostname when empty.
+    #[default("")]
+    mqtt_client_id: &'static str,
+    // Whether the led can be controlled through the MQTT command topic.
+    #[default(true)]
+    mqtt_commands: bool,
+    // Size of the heap, in KiB, shared by the Wi-Fi driver, the network
+    // buffers and the optional subsystems, such as MQTT and mDNS. It grows by
+    // 32 KiB with the `ble` feature, and should be raised when `/health`
+    // reports a `heap_warning`.
+    #[default(64)]
+    heap_kib: u32,
+    // Free heap, in bytes, below which a warning is logged, and below which
+    // optional load is shed.
+    #[default(16384)]
+    heap_warning_bytes: u32,
+    #[default(8192)]
+    heap_critical_bytes: u32,
+    // Minutes offline after which the device restarts, 0 disables the
+    // restart.
+    #[default(15)]
+    offline_reboot_minutes: u32,
+    // Port receiving single-byte led commands over UDP, disabled when 0.
+    #[default(0)]
+    udp_port: u16,
+    // Secret of 4 characters prefixing the UDP commands, not required when
+    // empty.
+    #[default("")]
+    udp_secret: &'static str,
+    // URL receiving the button and led events as JSON, such as
+    // `http://192.168.1.10:8080/hook`, disabled when empty.
+    #[default("")]
+    webhook_url: &'static str,
+    // Comma-separated MAC addresses of the ESP-NOW peers, such as
+    // `aa:bb:cc:dd:ee:ff`: the remotes allowed to control the led, or the
+    // devices controlled by a remote.
+    #[default("")]
+    espnow_peers: &'static str,
+    // Key shared with the ESP-NOW peers, authenticating their packets.
+    #[default("")]
+    espnow_key: &'static str,
+    // Chip temperature in degrees Celsius above which the led is forced off,
+    // until it drops 5 degrees below. Disabled when 0.
+    #[default(0)]
+    over_temperature_celsius: u8,
 }
 
 // This creates a default app-descriptor required by the esp-idf bootloader.
@@ -67,220 +446,618 @@ enum LedInput {
 esp_bootloader_esp_idf::esp_app_desc!();
 
 #[embassy_executor::task]
-pub async fn connect(mut wifi_controller: WifiController<'static>) {
-    info!("Wi-Fi connection task started");
-    loop {
-        if esp_wifi::wifi::wifi_state() == WifiState::StaConnected {
-            wifi_controller
-                .wait_for_event(WifiEvent::StaDisconnected)
-                .await;
-            Timer::after_secs(SECONDS_TO_WAIT_FOR_RECONNECTION).await;
-        }
+async fn reboot_task() {
+    let reason = REBOOT.wait().await;
+    info!("Rebooting: {reason}...");
 
-        if !matches!(wifi_controller.is_started(), Ok(true)) {
-            info!("Starting Wi-Fi...");
-            wifi_controller.start_async().await.unwrap();
-            info!("Wi-Fi started");
-        }
+    // Let pending responses be sent before going offline.
+    Timer::after_millis(REBOOT_DELAY_MS).await;
+    mqtt::go_offline().await;
 
-        info!("Attempting to connect...");
-        if let Err(e) = wifi_controller.connect_async().await {
-            error!("Wi-Fi connect failed: {e:?}");
-            Timer::after_secs(SECONDS_TO_WAIT_FOR_RECONNECTION).await;
-        } else {
-            info!("Wi-Fi connected!");
-        }
-    }
+    esp_hal::system::software_reset();
 }
 
-#[embassy_executor::task]
-pub async fn net_task(mut runner: Runner<'static, WifiDevice<'static>>) {
-    runner.run().await;
+// Starts the optional display. The device works without it, so its failures
+// are only logged.
+#[cfg(feature = "display")]
+async fn spawn_display(
+    spawner: Spawner,
+    i2c: esp_hal::peripherals::I2C0<'static>,
+    gpios: &mut board::Gpios,
+    device_config: &DeviceConfig,
+) -> Result<(), FirmwareError> {
+    let (Ok(sda), Ok(scl)) = (
+        u8::try_from(device_config.display_sda_gpio),
+        u8::try_from(device_config.display_scl_gpio),
+    ) else {
+        info!("Display GPIOs not configured, running without display");
+        return Ok(());
+    };
+    let (sda, scl) = match (
+        gpios.take(sda, "display SDA"),
+        gpios.take(scl, "display SCL"),
+    ) {
+        (Ok(sda), Ok(scl)) => (sda, scl),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("{e}, running without display");
+            buzzer::beep(BeepPattern::Error);
+            return Ok(());
+        }
+    };
+    let Some(display) = display::init(i2c, sda, scl).await else {
+        buzzer::beep(BeepPattern::Error);
+        return Ok(());
+    };
+    let Some(events) = events::subscribe() else {
+        error!("No event subscriber left for the display");
+        return Ok(());
+    };
+    spawner
+        .spawn(display::display_task(display, events))
+        .map_err(FirmwareError::spawn("display"))
 }
 
-#[embassy_executor::task]
-async fn press_button(mut button: Input<'static>) {
-    loop {
-        // Wait for Button Press
-        button.wait_for_rising_edge().await;
-        info!("Button Pressed!");
-
-        // Notify led to change its state.
-        NOTIFY_LED.signal(LedInput::Button);
-
-        // Wait for some time before starting the loop again.
-        Timer::after_millis(MILLISECONDS_TO_WAIT).await;
+// Starts the ESP-NOW receiver, and the sender of a remote, when peers and a
+// key are configured.
+#[cfg(feature = "espnow")]
+fn spawn_espnow(
+    spawner: Spawner,
+    esp_now: esp_wifi::esp_now::EspNow<'static>,
+    device_config: &DeviceConfig,
+) -> Result<(), FirmwareError> {
+    let peers = espnow::parse_peers(device_config.espnow_peers);
+    if peers.is_empty() {
+        return Ok(());
+    }
+    if device_config.espnow_key.is_empty() {
+        error!("Missing ESP-NOW key, ESP-NOW is disabled");
+        return Ok(());
     }
-}
 
-// Set led to on.
-fn led_on(led: &mut Output<'static>) {
-    led.set_low();
-    info!("Led is on!");
+    #[cfg_attr(not(feature = "espnow-remote"), expect(unused_variables))]
+    let (manager, sender, receiver) = esp_now.split();
+    #[cfg(feature = "espnow-remote")]
+    spawner
+        .spawn(espnow::espnow_remote(
+            manager,
+            sender,
+            peers.clone(),
+            device_config.espnow_key,
+        ))
+        .map_err(FirmwareError::spawn("ESP-NOW remote"))?;
+    spawner
+        .spawn(espnow::espnow_receiver(
+            receiver,
+            peers,
+            device_config.espnow_key,
+        ))
+        .map_err(FirmwareError::spawn("ESP-NOW receiver"))
 }
 
-// Set led to off.
-fn led_off(led: &mut Output<'static>) {
-    led.set_high();
-    info!("Led is off!");
-}
+async fn run(spawner: Spawner) {
+    logger::init_logger();
+    let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
+    let peripherals = esp_hal::init(config);
 
-#[embassy_executor::task]
-async fn change_led(mut led: Output<'static>) {
-    loop {
-        // Wait for until a signal is received.
-        let led_input = NOTIFY_LED.wait().await;
-
-        match led_input {
-            LedInput::On => {
-                led_on(&mut led);
-            }
-            LedInput::Off => {
-                led_off(&mut led);
-            }
-            LedInput::Button => {
-                // Switch on or off the led.
-                //
-                // Check whether the led is on.
-                if led.is_set_high() {
-                    led_on(&mut led);
-                } else {
-                    led_off(&mut led);
-                }
-            }
-        }
+    let safe_mode = boot::check_reset_reason(ota::load_running_image());
+    last_panic::load();
+    boot_count::record(state::boot_reason());
 
-        // TODO: We should insert here the `embassy-events` notifier code that
-        // writes the event over the network using mqtt.
+    esp_alloc::heap_allocator!(size: MAX_HEAP_SIZE);
 
-        // Wait for some time before starting the loop again.
-        Timer::after_millis(MILLISECONDS_TO_WAIT).await;
+    // A failed start leaves the device running what already started, with
+    // the led showing which subsystem failed.
+    if let Err(e) = start(spawner, Board::new(peripherals), safe_mode).await {
+        error!(
+            "Firmware failed to start during {} (fault code {}): {e}",
+            e.step(),
+            e.flashes()
+        );
+        show_pattern(LedPattern::Fault(e.flashes()));
     }
 }
 
-fn create_stack<const SOCKET_STACK_SIZE: usize>(
-    mut rng: Rng,
-    wifi_interface: WifiDevice<'static>,
-) -> (Stack<'static>, Runner<'static, WifiDevice<'static>>) {
-    let config = Config::dhcpv4(DhcpConfig::default());
-    let seed = u64::from(rng.random()) << 32 | u64::from(rng.random());
-
-    // FIXME: We need to use `Box::leak` and then `Box::new` because
-    // `make_static` does not accept **ANY** kind of generic, not even const
-    // generics.
-    let resources = Box::leak(Box::new(StackResources::<SOCKET_STACK_SIZE>::new()));
+// Starts the subsystems of the firmware.
+async fn start(spawner: Spawner, board: Board, safe_mode: bool) -> Result<(), FirmwareError> {
+    esp_hal_embassy::init(board.embassy_alarm);
 
-    let (stack, runner) = embassy_net::new(wifi_interface, config, resources, seed);
+    info!("Embassy initialized!");
 
-    (stack, runner)
-}
+    spawner
+        .spawn(heap::heap_monitor())
+        .map_err(FirmwareError::spawn("heap monitor"))?;
 
-async fn get_ip(stack: Stack<'_>) -> Ipv4Addr {
-    info!("Waiting till the link is up...");
-    loop {
-        if stack.is_link_up() {
-            break;
+    #[cfg(not(feature = "esp32s3"))]
+    match TemperatureSensor::new(board.tsens, TsensConfig::default()) {
+        Ok(sensor) => spawner
+            .spawn(temperature::temperature_task(sensor))
+            .map_err(FirmwareError::spawn("temperature"))?,
+        Err(e) => {
+            error!("Failed to initialize the temperature sensor: {e:?}");
+            buzzer::beep(BeepPattern::Error);
         }
-        Timer::after_millis(MILLISECONDS_TO_WAIT).await;
     }
 
-    info!("Waiting to get IP address...");
-    loop {
-        if let Some(config) = stack.config_v4() {
-            info!("Got IP: {}", config.address);
-            return config.address.address();
-        }
-        Timer::after_millis(MILLISECONDS_TO_WAIT).await;
-    }
-}
+    let rng = board.rng;
+    let timer1 = TimerGroup::new(board.wifi_timer);
 
-async fn run<const WEB_TASK_POOL_SIZE: usize>(spawner: Spawner) {
-    esp_println::logger::init_logger_from_env();
+    // Reset the device when the supervised tasks stop making progress.
+    spawner
+        .spawn(watchdog(TimerGroup::new(board.watchdog_timer).wdt))
+        .map_err(FirmwareError::spawn("watchdog"))?;
 
-    let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
-    let peripherals = esp_hal::init(config);
+    let mut gpios = board.gpios;
 
-    esp_alloc::heap_allocator!(size: MAX_HEAP_SIZE);
+    // Output led, started first so it can show the failures of the other
+    // subsystems.
+    let led_pin = board.led?;
+    let power_save = PowerSave::configured().unwrap_or_else(|| {
+        error!(
+            "Invalid Wi-Fi power-save mode {}, expected `none`, `minimum` or `maximum`, using `none`",
+            DEVICE_CONFIG.wifi_power_save
+        );
+        PowerSave::None
+    });
+    let led_type = LedType::configured().unwrap_or_else(|| {
+        error!(
+            "Invalid led type {}, expected `pwm` or `ws2812`, using a PWM led",
+            DEVICE_CONFIG.led_type
+        );
+        LedType::Pwm
+    });
+    // The LEDC peripheral is shared by the led and the buzzer.
+    let ledc = make_static!(Ledc<'static>, Ledc::new(board.ledc));
+    ledc.set_global_slow_clock(LSGlobalClkSource::APBClk);
+    let ledc = &*ledc;
+    let led_timer = pwm_timer(ledc)?;
+    let polarity = if DEVICE_CONFIG.led_active_low {
+        LedPolarity::ActiveLow
+    } else {
+        LedPolarity::ActiveHigh
+    };
+    let mut leds = heapless::Vec::new();
+    let led = match led_type {
+        LedType::Pwm => Led::Pwm(pwm_led(ledc, led_timer, MAIN_CHANNEL, led_pin, polarity)?),
+        LedType::Ws2812 => Led::Ws2812(ws2812_led(board.rmt, led_pin)?),
+    };
+    // The leds are at most as many as the channels.
+    let _ = leds.push(led);
+    // Extra led channels, numbered after the main led.
+    for spec in parse_led_channels(DEVICE_CONFIG.led_channels) {
+        let pin = gpios.take(spec.gpio, "led channel")?;
+        let led = pwm_led(ledc, led_timer, leds.len(), pin, spec.polarity)?;
+        let _ = leds.push(Led::Pwm(led));
+    }
+    state::set_led_channels(leds.len());
 
-    let timer0 = SystemTimer::new(peripherals.SYSTIMER);
-    esp_hal_embassy::init(timer0.alarm0);
+    // Start with the leds off, then blink the main one so the device can be
+    // seen alive before the network comes up. Waking from deep sleep skips
+    // the self-test, the led shows the button press instead.
+    for (channel, led) in leds.iter_mut().enumerate() {
+        logic::set_led(led, &mut SharedLedState, channel, 0);
+    }
+    spawner
+        .spawn(change_led(leds))
+        .map_err(FirmwareError::spawn("led"))?;
+    let woke_by_button = sleep::woke_by_button();
+    if !woke_by_button {
+        info!("Led self-test");
+        show_pattern(LedPattern::SelfTest);
+        // Wait for the self-test to end, so the next pattern does not cut it.
+        if let Some(duration_ms) = LedPattern::SelfTest.duration_ms() {
+            Timer::after_millis(duration_ms).await;
+        }
+    }
+    spawner
+        .spawn(relay::toggle_counter_task())
+        .map_err(FirmwareError::spawn("toggle counter"))?;
 
-    info!("Embassy initialized!");
+    // Optional buzzer, started early so it can report the failures of the
+    // other subsystems.
+    if let Ok(buzzer_gpio) = u8::try_from(DEVICE_CONFIG.buzzer_gpio) {
+        let buzzer_pin = gpios.take(buzzer_gpio, "buzzer")?;
+        spawner
+            .spawn(buzzer::buzzer_task(
+                ledc.timer(BUZZER_LEDC_TIMER),
+                BUZZER_LEDC_CHANNEL,
+                buzzer_pin,
+            ))
+            .map_err(FirmwareError::spawn("buzzer"))?;
+    }
 
-    let rng = esp_hal::rng::Rng::new(peripherals.RNG);
-    let timer1 = TimerGroup::new(peripherals.TIMG0);
+    // A button press woke the device from deep sleep, which toggles the led
+    // it had before sleeping, on the channel of the button.
+    if woke_by_button {
+        info!("Woken from deep sleep by the button");
+        let led_input = match sleep::led_state_before_sleep() {
+            LedState::On => LedInput::Off { fade_ms: None },
+            LedState::Off => LedInput::On {
+                fade_ms: None,
+                auto_off_secs: None,
+            },
+        };
+        let _ = NOTIFY_LED.try_send(LedCommand::on_channel(
+            button::led_channel(),
+            Source::Button,
+            led_input,
+        ));
+    }
+    spawner
+        .spawn(sleep::sleep_task(Rtc::new(board.lpwr)))
+        .map_err(FirmwareError::spawn("sleep"))?;
 
     let wifi_init = &*make_static!(
         EspWifiController<'static>,
-        esp_wifi::init(timer1.timer0, rng).expect("Failed to initialize Wi-Fi/BLE controller")
+        esp_wifi::init(timer1.timer0, rng)?
     );
 
-    let (mut wifi_controller, interfaces) = esp_wifi::wifi::new(wifi_init, peripherals.WIFI)
-        .expect("Failed to initialize WIFI controller");
+    let (mut wifi_controller, interfaces) = esp_wifi::wifi::new(wifi_init, board.wifi)?;
+    #[cfg(feature = "espnow")]
+    let esp_now = interfaces.esp_now;
+
+    // The MAC address is only available once the controller is initialized.
+    let mut mac = [0; 6];
+    esp_wifi::wifi::sta_mac(&mut mac);
+    state::set_mac_address(mac);
 
     // Retrieve device configuration
     let device_config = DEVICE_CONFIG;
+    let settings = load_settings();
+    buzzer::set_muted(settings.buzzer_muted);
+    quiet_hours::set_quiet_hours(settings.quiet_hours);
+    button_actions::set_button_actions(settings.button_actions);
+    let hostname = make_static!(
+        heapless::String<MAX_HOSTNAME_LEN>,
+        settings.hostname.clone()
+    )
+    .as_str();
+    state::set_hostname(hostname);
+    info!("Hostname: {hostname}");
 
-    assert!(!device_config.ssid.is_empty(), "Missing Wi-Fi SSID");
+    // The Bluetooth controller shares the radio with Wi-Fi.
+    #[cfg(feature = "ble")]
+    spawner
+        .spawn(ble::ble_task(
+            esp_wifi::ble::controller::BleConnector::new(wifi_init, board.bt),
+            hostname,
+            mac,
+        ))
+        .map_err(FirmwareError::spawn("BLE"))?;
 
-    assert!(!device_config.password.is_empty(), "Missing Wi-Fi password");
+    let http_port = if device_config.http_port == 0 {
+        error!("Invalid HTTP port 0, using port {DEFAULT_HTTP_PORT}");
+        DEFAULT_HTTP_PORT
+    } else {
+        device_config.http_port
+    };
 
-    let client_config = Configuration::Client(ClientConfiguration {
-        ssid: device_config.ssid.into(),
-        password: device_config.password.into(),
-        ..Default::default()
-    });
+    // Input button
+    let button = Input::new(board.button?, InputConfig::default().with_pull(Pull::Up));
+
+    // Optional status led, also starting off.
+    if let Ok(status_led_gpio) = u8::try_from(device_config.status_led_gpio) {
+        let active_low = device_config.status_led_active_low;
+        let status_led_pin = Output::new(
+            gpios.take(status_led_gpio, "status led")?,
+            if active_low { Level::High } else { Level::Low },
+            OutputConfig::default(),
+        );
+        spawner
+            .spawn(status_led(status_led_pin, active_low))
+            .map_err(FirmwareError::spawn("status led"))?;
+    }
+
+    // Extra outputs, skipping the ones whose GPIO is unavailable.
+    let mut extra_outputs = heapless::Vec::new();
+    for spec in outputs::parse_outputs(device_config.extra_outputs, device_config.led_gpio) {
+        match gpios.take(spec.gpio, spec.name) {
+            Ok(pin) => {
+                // The outputs are at most as many as the specs.
+                let _ = extra_outputs.push(ExtraOutput::new(spec, pin));
+            }
+            Err(e) => {
+                error!("{e}");
+                buzzer::beep(BeepPattern::Error);
+            }
+        }
+    }
+    if !extra_outputs.is_empty() {
+        spawner
+            .spawn(outputs::outputs_task(extra_outputs))
+            .map_err(FirmwareError::spawn("outputs"))?;
+    }
+
+    // Optional analog sensor, sampled on demand. The device works without it,
+    // so its failures are only logged.
+    if let Ok(adc_gpio) = u8::try_from(device_config.adc_gpio) {
+        // The GPIO is taken so nothing else uses it, and given to the ADC.
+        if let Err(e) = gpios.take(adc_gpio, "analog sensor") {
+            error!("{e}, running without analog sensor");
+            buzzer::beep(BeepPattern::Error);
+        } else if !adc::init(board.adc1, adc_gpio).await {
+            error!(
+                "GPIO{adc_gpio} is not an ADC pin, expected {}",
+                adc::GPIO_RANGE
+            );
+            buzzer::beep(BeepPattern::Error);
+        }
+    }
+
+    #[cfg(feature = "display")]
+    spawn_display(spawner, board.i2c0, &mut gpios, &device_config).await?;
+
+    spawner
+        .spawn(button_actions::dispatch_actions())
+        .map_err(FirmwareError::spawn("button actions"))?;
+    spawner
+        .spawn(press_button(button, Source::Button, woke_by_button))
+        .map_err(FirmwareError::spawn("button"))?;
+
+    // Optional rotary encoder, with its push switch.
+    if let (Ok(a_gpio), Ok(b_gpio)) = (
+        u8::try_from(device_config.encoder_a_gpio),
+        u8::try_from(device_config.encoder_b_gpio),
+    ) {
+        let config = InputConfig::default().with_pull(Pull::Up);
+        let a = Input::new(gpios.take(a_gpio, "encoder A")?, config);
+        let b = Input::new(gpios.take(b_gpio, "encoder B")?, config);
+        spawner
+            .spawn(encoder::encoder_task(a, b))
+            .map_err(FirmwareError::spawn("encoder"))?;
+    }
+    if let Ok(switch_gpio) = u8::try_from(device_config.encoder_switch_gpio) {
+        let switch = Input::new(
+            gpios.take(switch_gpio, "encoder switch")?,
+            InputConfig::default().with_pull(Pull::Up),
+        );
+        spawner
+            .spawn(press_button(switch, Source::Encoder, false))
+            .map_err(FirmwareError::spawn("encoder switch"))?;
+    }
+
+    // Optional touch pad, acting like the button.
+    #[cfg(feature = "touch")]
+    if let Ok(touch_gpio) = u8::try_from(device_config.touch_gpio) {
+        let pin = gpios.take(touch_gpio, "touch pad")?;
+        let threshold_percent = device_config.touch_threshold_percent;
+        if let Some(pad) = touch::TouchPad::new(pin, touch_gpio, threshold_percent).await {
+            spawner
+                .spawn(touch::touch_task(pad))
+                .map_err(FirmwareError::spawn("touch pad"))?;
+        } else {
+            error!("GPIO{touch_gpio} is not a touch pad, expected GPIO1 to GPIO14");
+            buzzer::beep(BeepPattern::Error);
+        }
+    }
+
+    // Optional motion sensor, whose output is high while it detects motion.
+    if let Ok(pir_gpio) = u8::try_from(device_config.pir_gpio) {
+        let pir = Input::new(
+            gpios.take(pir_gpio, "motion sensor")?,
+            InputConfig::default().with_pull(Pull::Down),
+        );
+        spawner
+            .spawn(motion::motion_task(pir))
+            .map_err(FirmwareError::spawn("motion"))?;
+    }
+
+    // Safe mode skips the optional subsystems, which could be the ones
+    // resetting the device.
+    if !settings.schedule_enabled {
+        info!("Schedule disabled by the settings");
+    } else if !safe_mode {
+        schedule::set_schedule(settings.schedule.clone());
+        spawner
+            .spawn(scheduler(device_config.timezone_offset_minutes))
+            .map_err(FirmwareError::spawn("scheduler"))?;
+    }
+    if !safe_mode {
+        spawner
+            .spawn(quiet_hours_task(device_config.timezone_offset_minutes))
+            .map_err(FirmwareError::spawn("quiet hours"))?;
+    }
+    spawner
+        .spawn(boot::clear_abnormal_resets())
+        .map_err(FirmwareError::spawn("boot"))?;
 
-    wifi_controller.set_configuration(&client_config).unwrap();
-
-    // We need to pass this value in this way because it is not possible
-    // to increment a const value coming from outside.
-    let (stack, runner) = match WEB_TASK_POOL_SIZE.max(1) {
-        1 => create_stack::<2>(rng, interfaces.sta),
-        2 => create_stack::<3>(rng, interfaces.sta),
-        3 => create_stack::<4>(rng, interfaces.sta),
-        4 => create_stack::<5>(rng, interfaces.sta),
-        5 => create_stack::<6>(rng, interfaces.sta),
-        6 => create_stack::<7>(rng, interfaces.sta),
-        7 => create_stack::<8>(rng, interfaces.sta),
-        _ => create_stack::<9>(rng, interfaces.sta),
+    // Fall back to provisioning mode when the credentials are missing or
+    // wrong. The simulation mode never connects to a network, and serves the
+    // whole web app on its own access point instead.
+    let mut networks = WifiNetworks::new(&settings);
+    let simulation = cfg!(feature = "sim-net");
+    let provisioning = if simulation {
+        info!("Simulation mode, Wi-Fi credentials are ignored");
+        false
+    } else if networks.is_empty() {
+        warn!("Missing Wi-Fi credentials");
+        true
+    } else {
+        // The led shows the network state while connecting.
+        !connect_station(
+            &mut wifi_controller,
+            &mut networks,
+            device_config.wifi_attempts,
+            !woke_by_button,
+        )
+        .await?
     };
 
-    spawner.spawn(connect(wifi_controller)).unwrap();
-    spawner.spawn(net_task(runner)).unwrap();
+    let access_point_mode = provisioning || simulation;
+    let (wifi_interface, net_config) = if access_point_mode {
+        let ssid = if simulation {
+            SIM_NET_SSID
+        } else {
+            PROVISIONING_SSID
+        };
+        start_access_point(&mut wifi_controller, ssid).await?;
+        let net_config = Config::ipv4_static(StaticConfigV4 {
+            address: Ipv4Cidr::new(PROVISIONING_IP, 24),
+            gateway: None,
+            dns_servers: Default::default(),
+        });
+        (interfaces.ap, net_config)
+    } else {
+        let net_config = station_net_config(&device_config, hostname);
+        #[cfg(feature = "ipv6")]
+        let net_config = ipv6::with_link_local(net_config, mac);
+        (interfaces.sta, net_config)
+    };
 
-    let ip = get_ip(stack).await;
-    info!("Got IP Address: {ip}");
+    let (stack, runner) = create_stack(rng, wifi_interface, net_config);
 
-    // Input button
-    let button = Input::new(
-        peripherals.GPIO9,
-        InputConfig::default().with_pull(Pull::Up),
-    );
+    spawner
+        .spawn(net_task(runner))
+        .map_err(FirmwareError::Stack)?;
+    spawner
+        .spawn(reboot_task())
+        .map_err(FirmwareError::spawn("reboot"))?;
+    spawner
+        .spawn(ota::verify_image())
+        .map_err(FirmwareError::spawn("image verification"))?;
+
+    if access_point_mode {
+        spawner
+            .spawn(access_point(wifi_controller, http_port, provisioning))
+            .map_err(FirmwareError::spawn("access point"))?;
+        spawner
+            .spawn(dhcp_server(stack, PROVISIONING_IP))
+            .map_err(FirmwareError::spawn("DHCP server"))?;
+        state::set_ip_address(PROVISIONING_IP);
+
+        // Blink fast until the device is provisioned.
+        if provisioning {
+            let _ = NOTIFY_LED.try_send(LedCommand::new(
+                Source::Startup,
+                LedInput::Blink {
+                    period_ms: PROVISIONING_BLINK_PERIOD_MS,
+                },
+            ));
+        }
+    } else {
+        if let Err(e) = wifi_controller.set_power_saving(power_save.mode()) {
+            error!("Failed to set the Wi-Fi power-save mode: {e:?}");
+        } else {
+            state::set_wifi_power_save(power_save.as_str());
+        }
+        spawner
+            .spawn(connect(wifi_controller, networks, stack, rng))
+            .map_err(FirmwareError::spawn("Wi-Fi connection"))?;
+        spawner
+            .spawn(wait_for_ip(stack))
+            .map_err(FirmwareError::spawn("IP address"))?;
+        spawner
+            .spawn(ip_watch(stack))
+            .map_err(FirmwareError::spawn("IP watch"))?;
+        if device_config.offline_reboot_minutes > 0 {
+            spawner
+                .spawn(net_watchdog(stack, device_config.offline_reboot_minutes))
+                .map_err(FirmwareError::spawn("network watchdog"))?;
+        }
+        #[cfg(feature = "ipv6")]
+        spawner
+            .spawn(ipv6::slaac(stack, mac))
+            .map_err(FirmwareError::spawn("IPv6 autoconfiguration"))?;
+        if device_config.udp_port > 0 {
+            if matches!(device_config.udp_secret.len(), 0 | SECRET_LEN) {
+                spawner
+                    .spawn(udp_control(
+                        stack,
+                        device_config.udp_port,
+                        device_config.udp_secret,
+                    ))
+                    .map_err(FirmwareError::spawn("UDP control"))?;
+            } else {
+                error!("UDP secret must be {SECRET_LEN} characters long, UDP control is disabled");
+            }
+        }
+        #[cfg(feature = "espnow")]
+        spawn_espnow(spawner, esp_now, &device_config)?;
+        #[cfg(feature = "coap")]
+        spawner
+            .spawn(coap::coap_server(stack))
+            .map_err(FirmwareError::spawn("CoAP server"))?;
+        if !device_config.webhook_url.is_empty()
+            && let Some(url) = WebhookUrl::parse(device_config.webhook_url)
+        {
+            spawner
+                .spawn(webhook_task(stack, url, rng))
+                .map_err(FirmwareError::spawn("webhook"))?;
+        }
+
+        // Network services wait for the IP address by themselves.
+        spawner
+            .spawn(mdns_responder(stack, hostname, http_port))
+            .map_err(FirmwareError::spawn("mDNS responder"))?;
+        spawner
+            .spawn(sntp_task(stack, device_config.ntp_server))
+            .map_err(FirmwareError::spawn("SNTP"))?;
 
-    // Output led.
-    let led = Output::new(peripherals.GPIO8, Level::High, OutputConfig::default());
+        // Forward logs to the syslog server, when configured.
+        if !device_config.syslog_host.is_empty() {
+            match device_config.syslog_host.parse::<Ipv4Addr>() {
+                Ok(server) => spawner
+                    .spawn(syslog_task(
+                        stack,
+                        server,
+                        device_config.syslog_port,
+                        hostname,
+                    ))
+                    .map_err(FirmwareError::spawn("syslog"))?,
+                Err(_) => error!(
+                    "Invalid syslog server address {}, log forwarding is disabled",
+                    device_config.syslog_host
+                ),
+            }
+        }
 
-    spawner.spawn(press_button(button)).unwrap();
-    spawner.spawn(change_led(led)).unwrap();
+        // Publish led and button events to the MQTT broker, when configured.
+        if !safe_mode && !settings.mqtt_host.is_empty() {
+            match settings.mqtt_host.parse::<Ipv4Addr>() {
+                Ok(broker) => {
+                    let buffers = make_static!(MqttBuffers, MqttBuffers::new());
+                    spawner
+                        .spawn(mqtt_task(stack, broker, settings.mqtt_port, buffers))
+                        .map_err(FirmwareError::spawn("MQTT"))?;
+                }
+                Err(_) => error!(
+                    "Invalid MQTT broker address {}, MQTT is disabled",
+                    settings.mqtt_host
+                ),
+            }
+        }
+    }
 
-    let app = make_static!(AppRouter<AppProps>, AppProps.build_app());
+    let app = make_static!(AppRouter<AppProps>, AppProps { provisioning }.build_app());
 
+    // The access point never sleeps.
+    let http_timeout = if access_point_mode {
+        PowerSave::None.http_timeout()
+    } else {
+        power_save.http_timeout()
+    };
+    let config = picoserve::Config::new(picoserve::Timeouts {
+        start_read_request: Some(Duration::from_millis(
+            device_config.http_first_request_timeout_ms,
+        )),
+        persistent_start_read_request: Some(Duration::from_millis(
+            device_config.http_idle_timeout_ms,
+        )),
+        read_request: Some(http_timeout),
+        write: Some(http_timeout),
+    });
     let config = make_static!(
         picoserve::Config<Duration>,
-        picoserve::Config::new(picoserve::Timeouts {
-            start_read_request: Some(Duration::from_secs(5)),
-            persistent_start_read_request: Some(Duration::from_secs(1)),
-            read_request: Some(Duration::from_secs(1)),
-            write: Some(Duration::from_secs(1)),
-        })
-        .keep_connection_alive()
+        if device_config.http_keep_alive {
+            config.keep_connection_alive()
+        } else {
+            config
+        }
     );
 
-    run_server::<WEB_TASK_POOL_SIZE>(spawner, stack, app, config).await;
+    run_server(spawner, stack, http_port, app, config)
 }
 
 #[esp_hal_embassy::main]
 async fn main(spawner: Spawner) {
-    run::<8>(spawner).await;
+    run(spawner).await;
 }
@@ -0,0 +1,399 @@
+use core::net::Ipv4Addr;
+
+use alloc::string::String;
+
+use embassy_futures::select::{select, Either};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::Stack;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::Timer;
+
+use log::{error, info, warn};
+
+use crate::heap;
+
+// mDNS multicast group and port.
+const MDNS_ADDRESS: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+// Size of the mDNS socket buffers, enough for a single message.
+const MDNS_BUFFER_SIZE: usize = 512;
+// Time to live of the host and service records.
+const HOST_TTL_SECS: u32 = 120;
+const SERVICE_TTL_SECS: u32 = 4500;
+// Announcements are repeated once after this delay, as mandated by RFC 6762.
+const ANNOUNCEMENT_DELAY_SECS: u64 = 1;
+// Delay before allocating the names again when the heap could not hold them.
+const NAMES_RETRY_SECS: u64 = 60;
+
+// Size of the DNS message header.
+const HEADER_SIZE: usize = 12;
+// Flags of an authoritative response.
+const RESPONSE_FLAGS: u16 = 0x8400;
+// Record types.
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+// Internet class, with the cache-flush bit set for unique records.
+const CLASS_IN: u16 = 1;
+const CLASS_IN_FLUSH: u16 = 0x8001;
+// Maximum number of compression pointers followed while reading a name.
+const MAX_NAME_JUMPS: usize = 8;
+
+// Signal which makes the responder announce the device again.
+static MDNS_ANNOUNCE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+// Announces the device again, e.g. after a Wi-Fi reconnection.
+pub(crate) fn announce() {
+    MDNS_ANNOUNCE.signal(());
+}
+
+// Names answered by the responder, lowercase and without the trailing dot.
+struct Names {
+    hostname: String,
+    // `<hostname>.local`
+    host: String,
+    // `_http._tcp.local`
+    service: String,
+    // `<hostname>._http._tcp.local`
+    instance: String,
+    // Port of the advertised HTTP service.
+    http_port: u16,
+}
+
+impl Names {
+    // Allocates the names, returning `None` when the heap cannot hold them.
+    fn new(hostname: &str, http_port: u16) -> Option<Self> {
+        let mut hostname = heap::try_format(format_args!("{hostname}"))?;
+        hostname.make_ascii_lowercase();
+        Some(Self {
+            host: heap::try_format(format_args!("{hostname}.local"))?,
+            service: heap::try_format(format_args!("_http._tcp.local"))?,
+            instance: heap::try_format(format_args!("{hostname}._http._tcp.local"))?,
+            hostname,
+            http_port,
+        })
+    }
+}
+
+// Records included in a response.
+#[derive(Default)]
+struct Records {
+    a: bool,
+    ptr: bool,
+    srv: bool,
+    txt: bool,
+}
+
+impl Records {
+    const ALL: Self = Self {
+        a: true,
+        ptr: true,
+        srv: true,
+        txt: true,
+    };
+
+    const fn count(&self) -> u16 {
+        self.a as u16 + self.ptr as u16 + self.srv as u16 + self.txt as u16
+    }
+}
+
+// Writes a DNS message into a buffer.
+struct Writer<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    const fn new(buffer: &'a mut [u8]) -> Self {
+        Self { buffer, len: 0 }
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) -> Option<()> {
+        let end = self.len + bytes.len();
+        self.buffer.get_mut(self.len..end)?.copy_from_slice(bytes);
+        self.len = end;
+        Some(())
+    }
+
+    fn u16(&mut self, value: u16) -> Option<()> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    fn u32(&mut self, value: u32) -> Option<()> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    // Writes a dotted name, without compression.
+    fn name(&mut self, name: &str) -> Option<()> {
+        for label in name.split('.') {
+            self.bytes(&[u8::try_from(label.len()).ok()?])?;
+            self.bytes(label.as_bytes())?;
+        }
+        self.bytes(&[0])
+    }
+
+    // Writes the header of a record, up to its type-specific data.
+    fn record(&mut self, name: &str, record_type: u16, class: u16, ttl: u32) -> Option<()> {
+        self.name(name)?;
+        self.u16(record_type)?;
+        self.u16(class)?;
+        self.u32(ttl)
+    }
+
+    // Writes the type-specific data of a record, preceded by its length.
+    fn data(&mut self, write: impl FnOnce(&mut Self) -> Option<()>) -> Option<()> {
+        let len_offset = self.len;
+        self.u16(0)?;
+        write(self)?;
+        let len = u16::try_from(self.len - len_offset - 2).ok()?;
+        self.buffer[len_offset..len_offset + 2].copy_from_slice(&len.to_be_bytes());
+        Some(())
+    }
+}
+
+// Reads the name at the given offset of a message, following compression
+// pointers, returning it lowercase together with the offset following it.
+fn read_name(message: &[u8], offset: usize) -> Option<(String, usize)> {
+    let mut name = String::new();
+    let mut position = offset;
+    let mut end = None;
+    let mut jumps = 0;
+
+    loop {
+        let len = *message.get(position)?;
+        if len & 0xC0 == 0xC0 {
+            jumps += 1;
+            if jumps > MAX_NAME_JUMPS {
+                return None;
+            }
+            end.get_or_insert(position + 2);
+            position = usize::from(len & 0x3F) << 8 | usize::from(*message.get(position + 1)?);
+            continue;
+        }
+
+        if len == 0 {
+            return Some((name, end.unwrap_or(position + 1)));
+        }
+
+        let label = message.get(position + 1..position + 1 + usize::from(len))?;
+        if !name.is_empty() {
+            name.push('.');
+        }
+        name.extend(
+            label
+                .iter()
+                .map(|byte| char::from(byte.to_ascii_lowercase())),
+        );
+        position += 1 + usize::from(len);
+    }
+}
+
+// Reads a big endian `u16` at the given offset of a message.
+fn read_u16(message: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        message.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+// Retrieves the records answering the questions of a query.
+fn answered_records(query: &[u8], names: &Names) -> Option<Records> {
+    // Responses of other responders are ignored.
+    if read_u16(query, 2)? & 0x8000 != 0 {
+        return None;
+    }
+
+    let mut records = Records::default();
+    let mut offset = HEADER_SIZE;
+    for _ in 0..read_u16(query, 4)? {
+        let (name, next) = read_name(query, offset)?;
+        let question_type = read_u16(query, next)?;
+        offset = next + 4;
+
+        let matches = |record_type| question_type == record_type || question_type == TYPE_ANY;
+        if name == names.host {
+            records.a |= matches(TYPE_A);
+        } else if name == names.service && matches(TYPE_PTR) {
+            // Resolving the service needs all the other records too.
+            records = Records::ALL;
+        } else if name == names.instance {
+            records.srv |= matches(TYPE_SRV);
+            records.txt |= matches(TYPE_TXT);
+            records.a |= matches(TYPE_SRV);
+        }
+    }
+
+    (records.count() > 0).then_some(records)
+}
+
+// Writes a response containing the given records, returning its size.
+fn write_response(
+    buffer: &mut [u8],
+    id: u16,
+    records: &Records,
+    names: &Names,
+    ip: Ipv4Addr,
+) -> Option<usize> {
+    let mut writer = Writer::new(buffer);
+    writer.u16(id)?;
+    writer.u16(RESPONSE_FLAGS)?;
+    // No questions, only answers.
+    writer.u16(0)?;
+    writer.u16(records.count())?;
+    writer.u16(0)?;
+    writer.u16(0)?;
+
+    if records.ptr {
+        writer.record(&names.service, TYPE_PTR, CLASS_IN, SERVICE_TTL_SECS)?;
+        writer.data(|writer| writer.name(&names.instance))?;
+    }
+    if records.srv {
+        writer.record(&names.instance, TYPE_SRV, CLASS_IN_FLUSH, HOST_TTL_SECS)?;
+        writer.data(|writer| {
+            // Priority and weight.
+            writer.u16(0)?;
+            writer.u16(0)?;
+            writer.u16(names.http_port)?;
+            writer.name(&names.host)
+        })?;
+    }
+    if records.txt {
+        writer.record(&names.instance, TYPE_TXT, CLASS_IN_FLUSH, SERVICE_TTL_SECS)?;
+        // A single empty string.
+        writer.data(|writer| writer.bytes(&[0]))?;
+    }
+    if records.a {
+        writer.record(&names.host, TYPE_A, CLASS_IN_FLUSH, HOST_TTL_SECS)?;
+        writer.data(|writer| writer.bytes(&ip.octets()))?;
+    }
+
+    Some(writer.len)
+}
+
+// Sends an unsolicited response announcing all the records.
+async fn send_announcement(
+    socket: &mut UdpSocket<'_>,
+    buffer: &mut [u8],
+    names: &Names,
+    ip: Ipv4Addr,
+) {
+    let Some(len) = write_response(buffer, 0, &Records::ALL, names, ip) else {
+        return;
+    };
+
+    if let Err(e) = socket
+        .send_to(&buffer[..len], (MDNS_ADDRESS, MDNS_PORT))
+        .await
+    {
+        error!("Failed to send mDNS announcement: {e:?}");
+    }
+}
+
+// Joins the mDNS group and announces the device twice.
+async fn announce_device(
+    stack: Stack<'static>,
+    socket: &mut UdpSocket<'_>,
+    buffer: &mut [u8],
+    names: &Names,
+) {
+    stack.wait_config_up().await;
+    let Some(config) = stack.config_v4() else {
+        return;
+    };
+
+    // Joining a group which is already joined is harmless.
+    if let Err(e) = stack.join_multicast_group(MDNS_ADDRESS) {
+        warn!("Failed to join mDNS group: {e:?}");
+    }
+
+    let ip = config.address.address();
+    send_announcement(socket, buffer, names, ip).await;
+    Timer::after_secs(ANNOUNCEMENT_DELAY_SECS).await;
+    send_announcement(socket, buffer, names, ip).await;
+
+    info!("Device announced as {}", names.host);
+}
+
+// Minimal mDNS responder, answering queries for the device hostname and
+// advertising its HTTP service.
+#[embassy_executor::task]
+pub(crate) async fn mdns_responder(stack: Stack<'static>, hostname: &'static str, http_port: u16) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; MDNS_BUFFER_SIZE];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; MDNS_BUFFER_SIZE];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+
+    if let Err(e) = socket.bind(MDNS_PORT) {
+        error!("Failed to bind mDNS socket: {e:?}");
+        return;
+    }
+
+    // The responder is disabled until the heap holds the names.
+    let names = loop {
+        if let Some(names) = Names::new(hostname, http_port) {
+            break names;
+        }
+        error!("mDNS disabled, retrying in {NAMES_RETRY_SECS} s");
+        Timer::after_secs(NAMES_RETRY_SECS).await;
+        heap::wait_for_memory().await;
+    };
+    info!("mDNS responder started for {}", names.hostname);
+
+    let mut query = [0; MDNS_BUFFER_SIZE];
+    let mut response = [0; MDNS_BUFFER_SIZE];
+    announce_device(stack, &mut socket, &mut response, &names).await;
+
+    loop {
+        let (len, metadata) = match select(socket.recv_from(&mut query), MDNS_ANNOUNCE.wait()).await
+        {
+            Either::First(Ok(received)) => received,
+            Either::First(Err(e)) => {
+                error!("Failed to receive mDNS query: {e:?}");
+                continue;
+            }
+            Either::Second(()) => {
+                announce_device(stack, &mut socket, &mut response, &names).await;
+                continue;
+            }
+        };
+
+        let Some(records) = answered_records(&query[..len], &names) else {
+            continue;
+        };
+        let Some(config) = stack.config_v4() else {
+            continue;
+        };
+
+        // Legacy unicast queries, not sent from the mDNS port, are answered
+        // directly with the query identifier. Other queries are answered
+        // over multicast.
+        let (id, destination) = if metadata.endpoint.port == MDNS_PORT {
+            (0, (MDNS_ADDRESS, MDNS_PORT).into())
+        } else {
+            (read_u16(&query, 0).unwrap_or(0), metadata.endpoint)
+        };
+
+        let Some(len) = write_response(
+            &mut response,
+            id,
+            &records,
+            &names,
+            config.address.address(),
+        ) else {
+            continue;
+        };
+
+        if let Err(e) = socket.send_to(&response[..len], destination).await {
+            error!("Failed to send mDNS response: {e:?}");
+        }
+    }
+}
@@ -0,0 +1,285 @@
+use core::cell::{Cell, RefCell};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant};
+
+use crate::api_version::unversioned;
+
+// Routes labelling the HTTP requests counter, any other path is counted as
+// `other`.
+const ROUTES: [&str; 32] = [
+    "/",
+    "/on",
+    "/off",
+    "/toggle",
+    "/blink",
+    "/morse",
+    "/color",
+    "/brightness",
+    "/led",
+    "/gpio",
+    "/button",
+    "/button/reset",
+    "/history",
+    "/setup",
+    "/schedule",
+    "/config",
+    "/restart",
+    "/sleep",
+    "/update",
+    "/status",
+    "/health",
+    "/temperature",
+    "/adc",
+    "/info",
+    "/logs",
+    "/loglevel",
+    "/lastpanic",
+    "/metrics",
+    "/stats",
+    "/api",
+    "/events",
+    "/automation",
+];
+const OTHER_ROUTE: &str = "other";
+
+// Upper bounds of the HTTP request latency buckets, in milliseconds, the
+// requests taking longer fall in a last, unbounded, bucket.
+pub(crate) const LATENCY_BOUNDS_MS: [u64; 4] = [5, 20, 100, 500];
+pub(crate) const LATENCY_BUCKETS: usize = LATENCY_BOUNDS_MS.len() + 1;
+
+// Counters updated by the tasks and exposed by the `/metrics` route.
+#[derive(Clone, Copy)]
+pub(crate) struct Metrics {
+    // Debounced button presses since boot or the last reset.
+    pub(crate) button_presses: u32,
+    // Time of the last button press, if any.
+    pub(crate) last_press: Option<Instant>,
+    // Motions detected since boot.
+    pub(crate) motion_events: u32,
+    // Wi-Fi reconnections since boot.
+    pub(crate) wifi_reconnects: u32,
+    // Log messages which could not be sent to the syslog server.
+    pub(crate) syslog_drops: u32,
+    // Malformed or unauthenticated UDP control datagrams.
+    pub(crate) udp_drops: u32,
+    // Events which could not be sent to the webhook.
+    pub(crate) webhook_drops: u32,
+    // HTTP connections refused because every web task was busy.
+    pub(crate) http_saturation_refusals: u32,
+    // Led commands dropped in favour of a more recent one, because they
+    // arrived within the minimum command interval.
+    pub(crate) led_commands_coalesced: u32,
+    // HTTP requests since boot, indexed like the routes, followed by the
+    // requests to other paths.
+    http_requests: [u32; ROUTES.len() + 1],
+    // Time spent serving the HTTP requests, in milliseconds, indexed like
+    // the requests.
+    http_request_ms: [u32; ROUTES.len() + 1],
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            button_presses: 0,
+            last_press: None,
+            motion_events: 0,
+            wifi_reconnects: 0,
+            syslog_drops: 0,
+            udp_drops: 0,
+            webhook_drops: 0,
+            http_saturation_refusals: 0,
+            led_commands_coalesced: 0,
+            http_requests: [0; ROUTES.len() + 1],
+            http_request_ms: [0; ROUTES.len() + 1],
+        }
+    }
+
+    // HTTP requests since boot, together with their route label.
+    pub(crate) fn http_requests(&self) -> impl Iterator<Item = (&'static str, u32)> + '_ {
+        ROUTES
+            .iter()
+            .copied()
+            .chain([OTHER_ROUTE])
+            .zip(self.http_requests.iter().copied())
+    }
+
+    // Time spent serving the HTTP requests since boot, in milliseconds,
+    // together with their route label.
+    pub(crate) fn http_request_ms(&self) -> impl Iterator<Item = (&'static str, u32)> + '_ {
+        ROUTES
+            .iter()
+            .copied()
+            .chain([OTHER_ROUTE])
+            .zip(self.http_request_ms.iter().copied())
+    }
+}
+
+// A blocking mutex is used so readers never wait on the counting tasks.
+static METRICS: Mutex<CriticalSectionRawMutex, Cell<Metrics>> =
+    Mutex::new(Cell::new(Metrics::new()));
+
+fn update(change: impl FnOnce(&mut Metrics)) {
+    METRICS.lock(|metrics| {
+        let mut updated = metrics.get();
+        change(&mut updated);
+        metrics.set(updated);
+    });
+}
+
+// Retrieves a copy of the current counters.
+pub(crate) fn metrics() -> Metrics {
+    METRICS.lock(Cell::get)
+}
+
+// Latency histogram of the requests to a route, each bucket counting only
+// the requests within its bounds.
+#[derive(Clone, Copy)]
+pub(crate) struct Latency {
+    pub(crate) route: &'static str,
+    pub(crate) buckets: [u32; LATENCY_BUCKETS],
+    // Time spent serving the requests, in milliseconds.
+    pub(crate) total_ms: u32,
+}
+
+impl Latency {
+    // Requests served.
+    pub(crate) fn count(&self) -> u32 {
+        self.buckets
+            .iter()
+            .fold(0, |count, requests| count.wrapping_add(*requests))
+    }
+
+    // Requests served within each bound, followed by all of them, as in a
+    // Prometheus histogram.
+    pub(crate) fn cumulative(&self) -> [u32; LATENCY_BUCKETS] {
+        let mut count = 0;
+        self.buckets.map(|requests| {
+            count = requests.wrapping_add(count);
+            count
+        })
+    }
+}
+
+// Latency histograms indexed like the HTTP requests counters.
+//
+// They are kept apart from the other counters, so updating the counters does
+// not copy them.
+type Histograms = [[u32; LATENCY_BUCKETS]; ROUTES.len() + 1];
+
+static LATENCIES: Mutex<CriticalSectionRawMutex, RefCell<Histograms>> =
+    Mutex::new(RefCell::new([[0; LATENCY_BUCKETS]; ROUTES.len() + 1]));
+
+// Retrieves a copy of the latency histogram of every route, the requests to
+// other paths last.
+pub(crate) fn latencies() -> [Latency; ROUTES.len() + 1] {
+    let buckets = LATENCIES.lock(|latencies| *latencies.borrow());
+    let total_ms = metrics().http_request_ms;
+    let mut index = 0;
+    buckets.map(|buckets| {
+        let latency = Latency {
+            route: ROUTES.get(index).copied().unwrap_or(OTHER_ROUTE),
+            buckets,
+            total_ms: total_ms[index],
+        };
+        index += 1;
+        latency
+    })
+}
+
+// Counts a button press, happened at the given time.
+pub(crate) fn count_button_press(at: Instant) {
+    update(|metrics| {
+        metrics.button_presses = metrics.button_presses.wrapping_add(1);
+        metrics.last_press = Some(at);
+    });
+}
+
+// Resets the button presses counter.
+pub(crate) fn reset_button_presses() {
+    update(|metrics| metrics.button_presses = 0);
+}
+
+// Counts a motion detected by the motion sensor.
+pub(crate) fn count_motion() {
+    update(|metrics| metrics.motion_events = metrics.motion_events.wrapping_add(1));
+}
+
+// Counts a Wi-Fi reconnection.
+pub(crate) fn count_wifi_reconnect() {
+    update(|metrics| metrics.wifi_reconnects = metrics.wifi_reconnects.wrapping_add(1));
+}
+
+// Counts a log message which could not be sent to the syslog server.
+pub(crate) fn count_syslog_drop() {
+    update(|metrics| metrics.syslog_drops = metrics.syslog_drops.wrapping_add(1));
+}
+
+// Counts a UDP control datagram which was dropped.
+pub(crate) fn count_udp_drop() {
+    update(|metrics| metrics.udp_drops = metrics.udp_drops.wrapping_add(1));
+}
+
+// Counts an event which could not be sent to the webhook, either because the
+// queue overflowed or because the delivery failed.
+pub(crate) fn count_webhook_drop() {
+    update(|metrics| metrics.webhook_drops = metrics.webhook_drops.wrapping_add(1));
+}
+
+// Counts an HTTP connection refused because every web task was busy.
+pub(crate) fn count_http_saturation() {
+    update(|metrics| {
+        metrics.http_saturation_refusals = metrics.http_saturation_refusals.wrapping_add(1);
+    });
+}
+
+// Counts a led command dropped in favour of a more recent one.
+pub(crate) fn count_led_coalesced() {
+    update(|metrics| {
+        metrics.led_commands_coalesced = metrics.led_commands_coalesced.wrapping_add(1);
+    });
+}
+
+// Index of the counters of the given path. The outputs share the counters of
+// `/gpio` and the led channels the ones of `/led`, so their ids do not add
+// labels, and the routes under the API prefix share the counters of their
+// bare alias.
+fn route_index(path: &str) -> usize {
+    let path = unversioned(path);
+    let path = if path.starts_with("/gpio/") {
+        "/gpio"
+    } else if path.starts_with("/led/") {
+        "/led"
+    } else {
+        path
+    };
+    ROUTES
+        .iter()
+        .position(|route| *route == path)
+        .unwrap_or(ROUTES.len())
+}
+
+// Counts an HTTP request to the given path.
+pub(crate) fn count_http_request(path: &str) {
+    let index = route_index(path);
+    update(|metrics| metrics.http_requests[index] = metrics.http_requests[index].wrapping_add(1));
+}
+
+// Adds the time spent serving a request to the given path.
+pub(crate) fn add_http_request_time(path: &str, elapsed: Duration) {
+    let index = route_index(path);
+    let elapsed_ms = u32::try_from(elapsed.as_millis()).unwrap_or(u32::MAX);
+    update(|metrics| {
+        metrics.http_request_ms[index] = metrics.http_request_ms[index].wrapping_add(elapsed_ms);
+    });
+
+    let bucket = LATENCY_BOUNDS_MS
+        .iter()
+        .position(|bound_ms| elapsed.as_millis() < *bound_ms)
+        .unwrap_or(LATENCY_BOUNDS_MS.len());
+    LATENCIES.lock(|latencies| {
+        let counter = &mut latencies.borrow_mut()[index][bucket];
+        *counter = counter.wrapping_add(1);
+    });
+}
@@ -0,0 +1,104 @@
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant};
+
+use esp_hal::gpio::Input;
+
+use log::{info, warn};
+
+use crate::led::{LedCommand, LedInput, Source, MAIN_CHANNEL};
+use crate::mqtt::{self, MqttEvent};
+use crate::state::{self, LedState, NOTIFY_LED};
+use crate::webhook::{self, WebhookEvent};
+use crate::{metrics, DEVICE_CONFIG};
+
+// Whether motion turns the led on, changed through the `/automation` route.
+static ENABLED: Mutex<CriticalSectionRawMutex, Cell<bool>> =
+    Mutex::new(Cell::new(DEVICE_CONFIG.motion_enabled));
+// Time until which motion is ignored, after the led has been turned off by
+// hand.
+static LOCKED_UNTIL: Mutex<CriticalSectionRawMutex, Cell<Option<Instant>>> =
+    Mutex::new(Cell::new(None));
+
+// Whether motion turns the led on.
+pub(crate) fn is_enabled() -> bool {
+    ENABLED.lock(Cell::get)
+}
+
+// Enables or disables turning the led on with motion.
+pub(crate) fn set_enabled(enabled: bool) {
+    ENABLED.lock(|cell| cell.set(enabled));
+}
+
+// Ignores motion for the lockout period, so the led turned off by hand does
+// not turn on again right away.
+pub(crate) fn lock_out() {
+    let until = Instant::now() + Duration::from_secs(DEVICE_CONFIG.motion_lockout_secs);
+    LOCKED_UNTIL.lock(|locked_until| locked_until.set(Some(until)));
+}
+
+// Whether motion is ignored after the led has been turned off by hand.
+fn is_locked_out() -> bool {
+    LOCKED_UNTIL
+        .lock(Cell::get)
+        .is_some_and(|until| Instant::now() < until)
+}
+
+// Turns the led on, turning it off after the hold time.
+fn turn_on(hold_secs: u64) {
+    let input = LedInput::On {
+        fade_ms: None,
+        auto_off_secs: Some(hold_secs),
+    };
+    if NOTIFY_LED
+        .try_send(LedCommand::new(Source::Motion, input))
+        .is_err()
+    {
+        warn!("Led channel is full, motion dropped!");
+    }
+}
+
+// Turns the led on while a PIR sensor detects motion, and off once the hold
+// time has elapsed since the last motion.
+//
+// The led turned on by other inputs is left alone, so motion never turns it
+// off.
+#[embassy_executor::task]
+pub(crate) async fn motion_task(mut pir: Input<'static>) {
+    info!("Motion sensor initialized");
+    let hold_secs = DEVICE_CONFIG.motion_hold_secs;
+    let mut lit_by_motion = false;
+
+    loop {
+        // The sensor output stays high as long as it detects motion.
+        pir.wait_for_high().await;
+        metrics::count_motion();
+        info!("Motion detected!");
+        mqtt::publish(MqttEvent::Motion);
+        webhook::notify(WebhookEvent::Motion {
+            count: metrics::metrics().motion_events,
+        });
+
+        let is_on = state::led_state(MAIN_CHANNEL) == LedState::On;
+        if !is_on {
+            lit_by_motion = false;
+        }
+        if !is_enabled() || is_locked_out() || (is_on && !lit_by_motion) {
+            pir.wait_for_low().await;
+            continue;
+        }
+
+        // Every motion turns the led on again, restarting the hold time.
+        turn_on(hold_secs);
+        lit_by_motion = true;
+
+        // The hold time starts over once the motion ends, unless the led has
+        // been turned off in the meantime.
+        pir.wait_for_low().await;
+        if is_enabled() && !is_locked_out() && state::led_state(MAIN_CHANNEL) == LedState::On {
+            turn_on(hold_secs);
+        }
+    }
+}
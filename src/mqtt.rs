@@ -0,0 +1,636 @@
+use core::fmt::Write as _;
+use core::net::Ipv4Addr;
+
+use alloc::string::String;
+
+use embassy_futures::select::{select3, Either3};
+use embassy_net::tcp::{self, TcpSocket};
+use embassy_net::Stack;
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex};
+use embassy_sync::channel::Channel;
+use embassy_sync::mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration, Timer};
+
+use embedded_io_async::{ErrorType, Read, Write};
+
+use heapless::Vec;
+
+use log::{error, info, warn};
+
+use serde::Serialize;
+
+use rust_mqtt::client::client_config::{ClientConfig, MqttVersion};
+use rust_mqtt::client::raw_client::{Event, RawMqttClient};
+use rust_mqtt::packet::v5::publish_packet::QualityOfService;
+use rust_mqtt::packet::v5::reason_codes::ReasonCode;
+use rust_mqtt::utils::rng_generator::CountingRng;
+
+use crate::board;
+use crate::click::Click;
+use crate::heap;
+use crate::led::{
+    parse_command, LedCommand, Source, MAIN_CHANNEL, MAX_BRIGHTNESS, MAX_LED_CHANNELS,
+};
+use crate::net_watchdog;
+use crate::state::{self, NOTIFY_LED};
+use crate::{DEVICE_CONFIG, ESP_APP_DESC};
+
+// Size of the TCP socket buffers.
+const MQTT_SOCKET_BUFFER_SIZE: usize = 1024;
+// Size of the buffers used to encode and decode MQTT packets.
+const MQTT_PACKET_BUFFER_SIZE: usize = 1024;
+// Maximum size of the Home Assistant discovery payload.
+const DISCOVERY_PAYLOAD_SIZE: usize = 768;
+// Maximum number of MQTT properties sent with a packet.
+const MQTT_MAX_PROPERTIES: usize = 5;
+// Maximum number of events waiting to be published.
+const MQTT_EVENTS_SIZE: usize = 8;
+// Keep alive interval negotiated with the broker.
+const MQTT_KEEP_ALIVE_SECS: u16 = 60;
+// Range of the delay between two connection attempts, doubled after every
+// failure.
+const MIN_RECONNECTION_DELAY_SECS: u64 = 1;
+const MAX_RECONNECTION_DELAY_SECS: u64 = 60;
+// Length of the unique identifier of the device, `button-led-` followed by
+// its MAC address.
+const UNIQUE_ID_LEN: usize = 23;
+// Longest time waited for the MQTT task to go offline.
+const OFFLINE_TIMEOUT_SECS: u64 = 2;
+// Availability payloads.
+const ONLINE_PAYLOAD: &str = "online";
+const OFFLINE_PAYLOAD: &str = "offline";
+
+// Events published to the MQTT broker.
+#[derive(Clone, Copy)]
+pub(crate) enum MqttEvent {
+    // A led channel has been set to the given brightness percentage, by the
+    // given subsystem.
+    Led {
+        channel: usize,
+        brightness: u8,
+        source: Source,
+    },
+    // The button has been clicked.
+    Button(Click),
+    // The motion sensor has detected motion.
+    Motion,
+    // The device has a new IPv4 address.
+    Ip(Ipv4Addr),
+    // Disconnect from the broker, publishing the offline availability.
+    Offline,
+}
+
+// Channel which carries the events to the MQTT task.
+static MQTT_EVENTS: Channel<CriticalSectionRawMutex, MqttEvent, MQTT_EVENTS_SIZE> = Channel::new();
+
+// Queues an event for publishing without waiting for the network.
+//
+// Events are dropped while the channel is full, which happens when the broker
+// is unreachable or MQTT is disabled. The current led state is published again
+// after every connection, so nothing is lost for good.
+pub(crate) fn publish(event: MqttEvent) {
+    let _ = MQTT_EVENTS.try_send(event);
+}
+
+// Signal raised by the MQTT task once it is offline.
+static MQTT_OFFLINE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+// Gracefully disconnects from the broker, so the device never appears online
+// while rebooting.
+//
+// The last will is only published when the connection is lost, so the
+// offline availability is published explicitly before disconnecting.
+pub(crate) async fn go_offline() {
+    MQTT_OFFLINE.reset();
+    if MQTT_EVENTS.try_send(MqttEvent::Offline).is_ok() {
+        let _ = with_timeout(
+            Duration::from_secs(OFFLINE_TIMEOUT_SECS),
+            MQTT_OFFLINE.wait(),
+        )
+        .await;
+    }
+}
+
+// Buffers used by the MQTT task, they are too large to live on its stack.
+pub(crate) struct MqttBuffers {
+    socket_rx: [u8; MQTT_SOCKET_BUFFER_SIZE],
+    socket_tx: [u8; MQTT_SOCKET_BUFFER_SIZE],
+    packet_rx: [u8; MQTT_PACKET_BUFFER_SIZE],
+    packet_tx: [u8; MQTT_PACKET_BUFFER_SIZE],
+}
+
+impl MqttBuffers {
+    pub(crate) const fn new() -> Self {
+        Self {
+            socket_rx: [0; MQTT_SOCKET_BUFFER_SIZE],
+            socket_tx: [0; MQTT_SOCKET_BUFFER_SIZE],
+            packet_rx: [0; MQTT_PACKET_BUFFER_SIZE],
+            packet_tx: [0; MQTT_PACKET_BUFFER_SIZE],
+        }
+    }
+}
+
+// Topics of a led channel.
+struct LedTopics {
+    state: String,
+    brightness: String,
+    // Subsystem which changed the led last, so automations can ignore their
+    // own changes.
+    source: String,
+    set: String,
+}
+
+// Topics the device publishes and subscribes to.
+struct Topics {
+    discovery: String,
+    availability: String,
+    // Topics of every led channel, indexed by channel. The main led keeps the
+    // topics it had before the channels, the other leds have theirs under
+    // `led/<channel>`.
+    leds: Vec<LedTopics, MAX_LED_CHANNELS>,
+    button: String,
+    motion: String,
+    // Address of the device, for controllers reaching it by address.
+    ip: String,
+}
+
+impl Topics {
+    // Allocates the topics of the given number of led channels, if the heap
+    // can hold them.
+    fn new(client_id: &str, channels: usize) -> Option<Self> {
+        let topic = |name| heap::try_format(format_args!("button-led/{client_id}/{name}"));
+        let mut leds = Vec::new();
+        for channel in 0..channels.min(MAX_LED_CHANNELS) {
+            let led_topic = |name| {
+                if channel == MAIN_CHANNEL {
+                    topic(name)
+                } else {
+                    heap::try_format(format_args!("button-led/{client_id}/led/{channel}/{name}"))
+                }
+            };
+            let _ = leds.push(LedTopics {
+                state: led_topic("state")?,
+                brightness: led_topic("brightness")?,
+                source: led_topic("source")?,
+                set: led_topic("set")?,
+            });
+        }
+        Some(Self {
+            discovery: heap::try_format(format_args!("homeassistant/light/{client_id}/config"))?,
+            availability: topic("availability")?,
+            leds,
+            button: topic("button")?,
+            motion: topic("motion")?,
+            ip: topic("ip")?,
+        })
+    }
+}
+
+// Home Assistant discovery payload of the led.
+//
+// Brightness commands are sent to the command topic too, since it accepts
+// brightness percentages.
+#[derive(Serialize)]
+struct Discovery<'a> {
+    name: &'a str,
+    unique_id: &'a str,
+    availability_topic: &'a str,
+    state_topic: &'a str,
+    command_topic: &'a str,
+    brightness_state_topic: &'a str,
+    brightness_command_topic: &'a str,
+    brightness_scale: u8,
+    device: DiscoveryDevice<'a>,
+}
+
+// Device described by the Home Assistant discovery payload.
+#[derive(Serialize)]
+struct DiscoveryDevice<'a> {
+    identifiers: [&'a str; 1],
+    name: &'a str,
+    model: &'a str,
+    sw_version: &'a str,
+}
+
+type Client<'a, 'b> = RawMqttClient<'a, Connection<'a, 'b>, MQTT_MAX_PROPERTIES, CountingRng>;
+
+// Socket connected to the broker, shared by the client and the wait for the
+// incoming packets.
+type SharedSocket<'a> = Mutex<NoopRawMutex, TcpSocket<'a>>;
+
+// Connection of the client to the broker.
+struct Connection<'a, 'b> {
+    socket: &'b SharedSocket<'a>,
+}
+
+impl ErrorType for Connection<'_, '_> {
+    type Error = tcp::Error;
+}
+
+impl Read for Connection<'_, '_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.socket.lock().await.read(buf).await
+    }
+}
+
+impl Write for Connection<'_, '_> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.socket.lock().await.write(buf).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.socket.lock().await.flush().await
+    }
+}
+
+// Waits until a packet starts arriving or the connection is closed.
+//
+// Unlike reading a packet, waiting can be cancelled without losing any byte.
+async fn wait_readable(socket: &SharedSocket<'_>) {
+    socket.lock().await.wait_read_ready().await;
+}
+
+// Forwards a command message to the led task.
+fn handle_command(channel: usize, payload: &[u8]) {
+    let Some(led_input) = parse_command(payload) else {
+        warn!(
+            "Unknown MQTT command {:?}, ignored",
+            core::str::from_utf8(payload).unwrap_or("<binary>")
+        );
+        return;
+    };
+
+    info!("Led changed through MQTT!");
+    if NOTIFY_LED
+        .try_send(LedCommand::on_channel(channel, Source::Mqtt, led_input))
+        .is_err()
+    {
+        warn!("Led channel is full, MQTT command dropped!");
+    }
+}
+
+// Waits for the packet matched by `is_ack`, ignoring any other packet.
+async fn wait_ack(
+    client: &mut Client<'_, '_>,
+    is_ack: fn(&Event<'_>) -> bool,
+) -> Result<(), ReasonCode> {
+    loop {
+        match client.poll::<1>().await? {
+            Event::Disconnect(reason) => return Err(reason),
+            event if is_ack(&event) => return Ok(()),
+            _ => {}
+        }
+    }
+}
+
+// Connects to the broker and subscribes to the command topic, when enabled.
+async fn start_session(client: &mut Client<'_, '_>, topics: &Topics) -> Result<(), ReasonCode> {
+    client.connect_to_broker().await?;
+    wait_ack(client, |event| matches!(event, Event::Connack)).await?;
+
+    if DEVICE_CONFIG.mqtt_commands {
+        let topic_names: Vec<&str, MAX_LED_CHANNELS> =
+            topics.leds.iter().map(|led| led.set.as_str()).collect();
+        client.subscribe_to_topics(&topic_names).await?;
+        wait_ack(client, |event| matches!(event, Event::Suback(_))).await?;
+    }
+
+    Ok(())
+}
+
+// Publishes the state and brightness of a led channel as retained messages,
+// preceded by the subsystem which changed them, if known.
+async fn publish_led(
+    client: &mut Client<'_, '_>,
+    topics: &LedTopics,
+    brightness: u8,
+    source: Option<Source>,
+) -> Result<(), ReasonCode> {
+    if let Some(source) = source {
+        client
+            .send_message(
+                &topics.source,
+                source.as_str().as_bytes(),
+                QualityOfService::QoS0,
+                true,
+            )
+            .await?;
+    }
+
+    let payload = if brightness > 0 { "ON" } else { "OFF" };
+    client
+        .send_message(
+            &topics.state,
+            payload.as_bytes(),
+            QualityOfService::QoS0,
+            true,
+        )
+        .await?;
+
+    // A brightness percentage is at most 3 characters long.
+    let mut payload = heapless::String::<3>::new();
+    let _ = write!(payload, "{brightness}");
+    client
+        .send_message(
+            &topics.brightness,
+            payload.as_bytes(),
+            QualityOfService::QoS0,
+            true,
+        )
+        .await
+        .map(drop)
+}
+
+// Publishes the availability of the device as a retained message.
+async fn publish_availability(
+    client: &mut Client<'_, '_>,
+    topics: &Topics,
+    payload: &str,
+) -> Result<(), ReasonCode> {
+    client
+        .send_message(
+            &topics.availability,
+            payload.as_bytes(),
+            QualityOfService::QoS0,
+            true,
+        )
+        .await
+        .map(drop)
+}
+
+// MQTT client identifier, which defaults to the hostname.
+fn client_id() -> &'static str {
+    match DEVICE_CONFIG.mqtt_client_id {
+        "" => state::hostname(),
+        client_id => client_id,
+    }
+}
+
+// Publishes the Home Assistant discovery payload as a retained message.
+//
+// Only the main led is announced.
+async fn publish_discovery(
+    client: &mut Client<'_, '_>,
+    topics: &Topics,
+    unique_id: &str,
+) -> Result<(), ReasonCode> {
+    let Some(led) = topics.leds.get(MAIN_CHANNEL) else {
+        return Ok(());
+    };
+    let client_id = client_id();
+    let discovery = Discovery {
+        name: "Led",
+        unique_id,
+        availability_topic: &topics.availability,
+        state_topic: &led.state,
+        command_topic: &led.set,
+        brightness_state_topic: &led.brightness,
+        brightness_command_topic: &led.set,
+        brightness_scale: MAX_BRIGHTNESS,
+        device: DiscoveryDevice {
+            identifiers: [unique_id],
+            name: client_id,
+            model: board::MODEL,
+            sw_version: ESP_APP_DESC.version(),
+        },
+    };
+
+    let Ok(payload) = serde_json_core::to_vec::<_, DISCOVERY_PAYLOAD_SIZE>(&discovery) else {
+        error!("Home Assistant discovery payload is too large, not published");
+        return Ok(());
+    };
+
+    client
+        .send_message(&topics.discovery, &payload, QualityOfService::QoS0, true)
+        .await
+        .map(drop)
+}
+
+// Publishes a button click.
+async fn publish_button(
+    client: &mut Client<'_, '_>,
+    topics: &Topics,
+    click: Click,
+) -> Result<(), ReasonCode> {
+    let payload = match click {
+        Click::Single => "single",
+        Click::Double => "double",
+        Click::Long => "long",
+    };
+    client
+        .send_message(
+            &topics.button,
+            payload.as_bytes(),
+            QualityOfService::QoS0,
+            false,
+        )
+        .await
+        .map(drop)
+}
+
+async fn publish_motion(client: &mut Client<'_, '_>, topics: &Topics) -> Result<(), ReasonCode> {
+    client
+        .send_message(&topics.motion, b"detected", QualityOfService::QoS0, false)
+        .await
+        .map(drop)
+}
+
+// Publishes the IPv4 address of the device, retained so controllers learn it
+// as soon as they subscribe.
+async fn publish_ip(
+    client: &mut Client<'_, '_>,
+    topics: &Topics,
+    ip: Ipv4Addr,
+) -> Result<(), ReasonCode> {
+    let mut payload = heapless::String::<15>::new();
+    // A dotted IPv4 address is at most 15 characters long.
+    let _ = write!(payload, "{ip}");
+    client
+        .send_message(&topics.ip, payload.as_bytes(), QualityOfService::QoS0, true)
+        .await
+        .map(drop)
+}
+
+// Actions performed by the MQTT task.
+enum Action {
+    Publish(MqttEvent),
+    Receive,
+    Ping,
+}
+
+// Publishes the queued events and handles the incoming commands until the
+// connection with the broker fails.
+async fn serve(
+    client: &mut Client<'_, '_>,
+    socket: &SharedSocket<'_>,
+    topics: &Topics,
+    unique_id: &str,
+) -> ReasonCode {
+    if let Err(e) = publish_availability(client, topics, ONLINE_PAYLOAD).await {
+        return e;
+    }
+    if let Err(e) = publish_discovery(client, topics, unique_id).await {
+        return e;
+    }
+
+    // Events queued while disconnected are stale, the current state is
+    // published right away instead.
+    MQTT_EVENTS.clear();
+    for (channel, led) in topics.leds.iter().enumerate() {
+        if let Err(e) = publish_led(client, led, state::led_brightness(channel), None).await {
+            return e;
+        }
+    }
+    if let Some(ip) = state::ip_address()
+        && let Err(e) = publish_ip(client, topics, ip).await
+    {
+        return e;
+    }
+
+    loop {
+        // Ping the broker when no event is published for half the keep alive
+        // interval.
+        let keep_alive = Timer::after_secs(u64::from(MQTT_KEEP_ALIVE_SECS / 2));
+        // A packet is read only once it starts arriving, and then until it is
+        // complete, since a read dropped halfway would lose part of it.
+        let action = match select3(MQTT_EVENTS.receive(), wait_readable(socket), keep_alive).await {
+            Either3::First(event) => Action::Publish(event),
+            Either3::Second(()) => Action::Receive,
+            Either3::Third(()) => Action::Ping,
+        };
+
+        let result = match action {
+            Action::Publish(MqttEvent::Led {
+                channel,
+                brightness,
+                source,
+            }) => match topics.leds.get(channel) {
+                Some(led) => publish_led(client, led, brightness, Some(source)).await,
+                None => Ok(()),
+            },
+            Action::Publish(MqttEvent::Button(click)) => {
+                publish_button(client, topics, click).await
+            }
+            Action::Publish(MqttEvent::Motion) => publish_motion(client, topics).await,
+            Action::Publish(MqttEvent::Ip(ip)) => publish_ip(client, topics, ip).await,
+            Action::Publish(MqttEvent::Offline) => {
+                if let Err(e) = publish_availability(client, topics, OFFLINE_PAYLOAD).await {
+                    error!("Failed to publish MQTT offline availability: {e:?}");
+                }
+                let _ = client.disconnect().await;
+                info!("Disconnected from MQTT broker!");
+
+                // The device is about to reboot, so never reconnect.
+                MQTT_OFFLINE.signal(());
+                core::future::pending().await
+            }
+            Action::Receive => match client.poll::<1>().await {
+                Ok(Event::Message(topic, payload)) => {
+                    net_watchdog::online();
+                    if let Some(channel) = topics.leds.iter().position(|led| led.set == topic) {
+                        handle_command(channel, payload);
+                    }
+                    Ok(())
+                }
+                Ok(Event::Disconnect(reason)) | Err(reason) => Err(reason),
+                // Acknowledgements of QoS 0 messages and pings carry no
+                // information.
+                Ok(_) => {
+                    net_watchdog::online();
+                    Ok(())
+                }
+            },
+            Action::Ping => client.send_ping().await,
+        };
+
+        if let Err(e) = result {
+            return e;
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub(crate) async fn mqtt_task(
+    stack: Stack<'static>,
+    broker: Ipv4Addr,
+    port: u16,
+    buffers: &'static mut MqttBuffers,
+) {
+    let client_id = client_id();
+    // Without memory for the topics, MQTT stays down until there is, while
+    // the rest of the firmware keeps running.
+    let topics = loop {
+        if let Some(topics) = Topics::new(client_id, state::led_channels()) {
+            break topics;
+        }
+        error!("MQTT disabled, retrying in {MAX_RECONNECTION_DELAY_SECS} s");
+        Timer::after_secs(MAX_RECONNECTION_DELAY_SECS).await;
+        heap::wait_for_memory().await;
+    };
+
+    // Identifier which is unique to this device, derived from its MAC
+    // address.
+    let mut mac = [0; 6];
+    esp_wifi::wifi::sta_mac(&mut mac);
+    let mut unique_id = heapless::String::<UNIQUE_ID_LEN>::new();
+    let _ = write!(
+        unique_id,
+        "button-led-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+        mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+    );
+
+    let mut delay_secs = MIN_RECONNECTION_DELAY_SECS;
+    loop {
+        // A session allocates, so it waits while the heap is critically low.
+        heap::wait_for_memory().await;
+
+        let mut socket = SharedSocket::new(TcpSocket::new(
+            stack,
+            &mut buffers.socket_rx,
+            &mut buffers.socket_tx,
+        ));
+        let tcp_socket = socket.get_mut();
+        tcp_socket.set_timeout(Some(Duration::from_secs(u64::from(MQTT_KEEP_ALIVE_SECS))));
+
+        info!("Connecting to MQTT broker {broker}:{port}...");
+        if let Err(e) = tcp_socket.connect((broker, port)).await {
+            error!("MQTT broker connection failed: {e:?}");
+        } else {
+            let mut config = ClientConfig::new(MqttVersion::MQTTv5, CountingRng(0));
+            config.add_client_id(client_id);
+            // The broker publishes the offline availability when the
+            // connection is lost without a graceful disconnection.
+            config.add_will(&topics.availability, OFFLINE_PAYLOAD.as_bytes(), true);
+            config.keep_alive = MQTT_KEEP_ALIVE_SECS;
+            config.max_packet_size = MQTT_PACKET_BUFFER_SIZE as u32;
+
+            let mut client = RawMqttClient::<_, MQTT_MAX_PROPERTIES, _>::new(
+                Connection { socket: &socket },
+                &mut buffers.packet_tx,
+                MQTT_PACKET_BUFFER_SIZE,
+                &mut buffers.packet_rx,
+                MQTT_PACKET_BUFFER_SIZE,
+                config,
+            );
+
+            match start_session(&mut client, &topics).await {
+                Ok(()) => {
+                    info!("Connected to MQTT broker!");
+                    net_watchdog::online();
+                    delay_secs = MIN_RECONNECTION_DELAY_SECS;
+                    let e = serve(&mut client, &socket, &topics, &unique_id).await;
+                    error!("MQTT connection lost: {e:?}");
+                }
+                Err(e) => error!("MQTT broker refused the connection: {e:?}"),
+            }
+        }
+        socket.get_mut().abort();
+
+        // Wait longer after every failure, so an unreachable broker is not
+        // hammered with connection attempts.
+        info!("Reconnecting to MQTT broker in {delay_secs} s...");
+        Timer::after_secs(delay_secs).await;
+        delay_secs = (delay_secs * 2).min(MAX_RECONNECTION_DELAY_SECS);
+    }
+}
@@ -0,0 +1,63 @@
+use core::cell::Cell;
+
+use embassy_net::Stack;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant, Timer};
+
+use log::{error, info};
+
+use crate::boot;
+use crate::led::show_pattern;
+use crate::pattern::LedPattern;
+use crate::state::REBOOT;
+
+// Interval between two checks of the network state.
+const CHECK_INTERVAL_SECS: u64 = 10;
+// Time left to the warning pattern before the device is restarted.
+const WARNING_MS: u64 = 2000;
+
+// Last time the device was known to be online.
+static LAST_ONLINE: Mutex<CriticalSectionRawMutex, Cell<Instant>> =
+    Mutex::new(Cell::new(Instant::from_ticks(0)));
+
+// Records that the device is online, because the network answered.
+pub(crate) fn online() {
+    LAST_ONLINE.lock(|last_online| last_online.set(Instant::now()));
+}
+
+// Restarts the device once it stays offline for the given time, since the
+// Wi-Fi stack sometimes gets stuck until it is reset.
+//
+// The device is online while it has an IPv4 address, or while HTTP clients
+// and the MQTT broker talk to it. The task is not spawned in provisioning
+// mode, where the device is offline on purpose.
+#[embassy_executor::task]
+pub(crate) async fn net_watchdog(stack: Stack<'static>, timeout_minutes: u32) {
+    let timeout = Duration::from_secs(u64::from(timeout_minutes) * 60);
+    info!("Network watchdog enabled, restarting after {timeout_minutes} minutes offline");
+    online();
+
+    loop {
+        Timer::after_secs(CHECK_INTERVAL_SECS).await;
+
+        if stack.is_link_up() && stack.config_v4().is_some() {
+            online();
+            continue;
+        }
+        let offline = LAST_ONLINE.lock(Cell::get).elapsed();
+        if offline < timeout {
+            continue;
+        }
+
+        error!(
+            "Offline for {} minutes, restarting the device",
+            offline.as_secs() / 60
+        );
+        boot::count_offline_reboot();
+        show_pattern(LedPattern::OfflineReboot);
+        Timer::after_millis(WARNING_MS).await;
+        REBOOT.signal("offline for too long");
+        return;
+    }
+}
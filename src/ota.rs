@@ -0,0 +1,434 @@
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_time::{Duration, Instant, Timer};
+
+use embedded_storage::Storage;
+
+use esp_bootloader_esp_idf::ota::{Ota, OtaImageState, Slot};
+use esp_bootloader_esp_idf::partitions::{
+    self, AppPartitionSubType, DataPartitionSubType, PartitionTable, PartitionType,
+    PARTITION_TABLE_MAX_LEN,
+};
+
+use esp_storage::FlashStorage;
+
+use picoserve::io::Read;
+use picoserve::response::StatusCode;
+
+use esp_wifi::wifi::WifiState;
+
+use log::{error, info, warn};
+
+use crate::sha256::{Sha256, DIGEST_SIZE};
+use crate::state::{self, REBOOT};
+use crate::status_led::{self, StatusEvent};
+
+// Size of a flash sector, firmware is written one sector at a time.
+const SECTOR_SIZE: usize = 4096;
+// Amount of written firmware between two progress logs.
+const PROGRESS_LOG_BYTES: usize = 64 * 1024;
+// First byte of every ESP firmware image.
+const IMAGE_MAGIC: u8 = 0xE9;
+
+// Time a new image has to pass its self-check before being rolled back.
+const VERIFY_TIMEOUT_SECS: u64 = 120;
+// Interval between two self-checks of a new image.
+const VERIFY_INTERVAL_SECS: u64 = 5;
+// Free heap required by the self-check.
+const MIN_FREE_HEAP: usize = 8 * 1024;
+
+// Buffer collecting a flash sector, shared by the web tasks.
+//
+// It is too large for the web tasks stacks, and holding its lock also
+// prevents concurrent updates.
+static SECTOR_BUFFER: Mutex<CriticalSectionRawMutex, [u8; SECTOR_SIZE]> =
+    Mutex::new([0; SECTOR_SIZE]);
+
+// Errors arising while updating the firmware.
+#[derive(Debug)]
+pub(crate) enum OtaError {
+    // Another update is in progress.
+    Busy,
+    // The partition table lacks two OTA slots and the OTA data partition.
+    MissingPartitions,
+    // The body does not start with an ESP firmware image, or it is too short
+    // to contain the image and its digest.
+    InvalidImage,
+    // The image does not fit the update partition.
+    TooLarge,
+    // The body could not be read completely.
+    Read,
+    // Flash could not be read or written.
+    Flash,
+    // The image does not match its digest.
+    DigestMismatch,
+    // The running image has not been verified yet, so the other slot still
+    // holds the image to roll back to.
+    Unverified,
+}
+
+impl OtaError {
+    // Response returned to the client.
+    pub(crate) const fn response(&self) -> (StatusCode, &'static str) {
+        match self {
+            Self::Busy => (StatusCode::CONFLICT, "Update already in progress\n"),
+            Self::MissingPartitions => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Partition table does not support updates\n",
+            ),
+            Self::InvalidImage => (StatusCode::BAD_REQUEST, "Invalid firmware image\n"),
+            Self::TooLarge => (StatusCode::PAYLOAD_TOO_LARGE, "Firmware image too large\n"),
+            Self::Read => (StatusCode::BAD_REQUEST, "Failed to read firmware image\n"),
+            Self::Flash => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to write firmware image\n",
+            ),
+            Self::DigestMismatch => (
+                StatusCode::BAD_REQUEST,
+                "Firmware image does not match its SHA-256 digest\n",
+            ),
+            Self::Unverified => (
+                StatusCode::CONFLICT,
+                "Running firmware has not been verified yet\n",
+            ),
+        }
+    }
+}
+
+impl From<partitions::Error> for OtaError {
+    fn from(_: partitions::Error) -> Self {
+        Self::Flash
+    }
+}
+
+// Running image, as described by the OTA data partition.
+#[derive(Clone, Copy)]
+pub(crate) struct RunningImage {
+    // Name of the running partition.
+    pub(crate) partition: &'static str,
+    // State of the image, missing when no OTA slot is selected.
+    pub(crate) state: Option<OtaImageState>,
+}
+
+impl RunningImage {
+    // Whether the image still has to pass its self-check, which is the case
+    // from its first boot until it is marked valid.
+    pub(crate) fn is_unverified(&self) -> bool {
+        matches!(
+            self.state,
+            Some(OtaImageState::New | OtaImageState::PendingVerify)
+        )
+    }
+}
+
+// Running image, read at boot.
+static RUNNING_IMAGE: BlockingMutex<CriticalSectionRawMutex, Cell<Option<RunningImage>>> =
+    BlockingMutex::new(Cell::new(None));
+
+// Retrieves the running image, if it has been read.
+pub(crate) fn running_image() -> Option<RunningImage> {
+    RUNNING_IMAGE.lock(Cell::get)
+}
+
+// Image state as a lowercase string.
+pub(crate) const fn image_state_str(state: OtaImageState) -> &'static str {
+    match state {
+        OtaImageState::New => "new",
+        OtaImageState::PendingVerify => "pending_verify",
+        OtaImageState::Valid => "valid",
+        OtaImageState::Invalid => "invalid",
+        OtaImageState::Aborted => "aborted",
+        OtaImageState::Undefined => "undefined",
+    }
+}
+
+// Update partition, where the image is written.
+struct UpdatePartition {
+    slot: Slot,
+    offset: u32,
+    len: usize,
+}
+
+// Reads the partition table and the OTA data partition, passing them to the
+// given function.
+fn with_ota<T>(
+    f: impl FnOnce(&PartitionTable<'_>, &mut Ota<'_, FlashStorage>) -> Result<T, OtaError>,
+) -> Result<T, OtaError> {
+    let mut table_buffer = [0; PARTITION_TABLE_MAX_LEN];
+    let table = partitions::read_partition_table(&mut FlashStorage::new(), &mut table_buffer)?;
+    let ota_data = table
+        .find_partition(PartitionType::Data(DataPartitionSubType::Ota))?
+        .ok_or(OtaError::MissingPartitions)?;
+
+    let mut flash = FlashStorage::new();
+    let mut region = ota_data.as_embedded_storage(&mut flash);
+    let mut ota = Ota::new(&mut region).map_err(|_| OtaError::MissingPartitions)?;
+    f(&table, &mut ota)
+}
+
+// Finds the OTA slot which is not running.
+fn update_partition() -> Result<UpdatePartition, OtaError> {
+    with_ota(|table, ota| {
+        // Without a selected slot, the bootloader runs the factory partition
+        // if present, or the first OTA slot otherwise.
+        let slot = match ota.current_slot()? {
+            Slot::None
+                if table
+                    .find_partition(PartitionType::App(AppPartitionSubType::Factory))?
+                    .is_none() =>
+            {
+                Slot::Slot1
+            }
+            current => current.next(),
+        };
+
+        let subtype = match slot {
+            Slot::Slot1 => AppPartitionSubType::Ota1,
+            Slot::None | Slot::Slot0 => AppPartitionSubType::Ota0,
+        };
+        let partition = table
+            .find_partition(PartitionType::App(subtype))?
+            .ok_or(OtaError::MissingPartitions)?;
+
+        Ok(UpdatePartition {
+            slot,
+            offset: partition.offset(),
+            len: partition.len() as usize,
+        })
+    })
+}
+
+// Reads the running image from the OTA data partition.
+fn read_running_image() -> Result<RunningImage, OtaError> {
+    with_ota(|table, ota| {
+        let slot = ota.current_slot()?;
+        let partition = match slot {
+            Slot::None
+                if table
+                    .find_partition(PartitionType::App(AppPartitionSubType::Factory))?
+                    .is_some() =>
+            {
+                "factory"
+            }
+            Slot::None | Slot::Slot0 => "ota_0",
+            Slot::Slot1 => "ota_1",
+        };
+        let state = match slot {
+            Slot::None => None,
+            Slot::Slot0 | Slot::Slot1 => Some(ota.current_ota_state()?),
+        };
+
+        Ok(RunningImage { partition, state })
+    })
+}
+
+// Reads the running image at boot, keeping it for the `verify_image` task
+// and the routes.
+pub(crate) fn load_running_image() -> Option<RunningImage> {
+    match read_running_image() {
+        Ok(image) => {
+            RUNNING_IMAGE.lock(|running_image| running_image.set(Some(image)));
+            Some(image)
+        }
+        Err(e) => {
+            error!("Failed to read the running image state: {e:?}");
+            None
+        }
+    }
+}
+
+// Sets the state of the running image.
+fn set_image_state(state: OtaImageState) -> Result<(), OtaError> {
+    with_ota(|_, ota| {
+        ota.set_current_ota_state(state)?;
+        Ok(())
+    })?;
+    RUNNING_IMAGE.lock(|image| {
+        image.set(image.get().map(|image| RunningImage {
+            state: Some(state),
+            ..image
+        }));
+    });
+    Ok(())
+}
+
+// Marks the running image as invalid, making the bootloader run the other
+// slot at the next boot.
+fn roll_back() -> Result<(), OtaError> {
+    with_ota(|_, ota| {
+        let slot = ota.current_slot()?;
+        ota.set_current_ota_state(OtaImageState::Invalid)?;
+        ota.set_current_slot(slot.next())?;
+        Ok(())
+    })
+}
+
+// Outcome of the self-check of a new image.
+struct SelfCheck {
+    wifi_connected: bool,
+    server_listening: bool,
+    heap_free: usize,
+}
+
+impl SelfCheck {
+    fn run() -> Self {
+        Self {
+            wifi_connected: esp_wifi::wifi::wifi_state() == WifiState::StaConnected
+                && state::ip_address().is_some(),
+            server_listening: state::server_listening(),
+            heap_free: esp_alloc::HEAP.free(),
+        }
+    }
+
+    fn passed(&self) -> bool {
+        self.wifi_connected && self.server_listening && self.heap_free >= MIN_FREE_HEAP
+    }
+}
+
+// Verifies a newly installed image, which the bootloader runs once.
+//
+// The image is marked valid once Wi-Fi is connected, the HTTP server is
+// listening and enough heap is free. When that does not happen within two
+// minutes, the device reboots into the previous image.
+#[embassy_executor::task]
+pub(crate) async fn verify_image() {
+    let Some(image) = running_image().filter(RunningImage::is_unverified) else {
+        return;
+    };
+    info!("Verifying new firmware image in {}...", image.partition);
+
+    let deadline = Instant::now() + Duration::from_secs(VERIFY_TIMEOUT_SECS);
+    loop {
+        let check = SelfCheck::run();
+        if check.passed() {
+            match set_image_state(OtaImageState::Valid) {
+                Ok(()) => info!("Firmware image verified and marked valid"),
+                Err(e) => error!("Failed to mark the firmware image valid: {e:?}"),
+            }
+            return;
+        }
+
+        if Instant::now() >= deadline {
+            warn!(
+                "Firmware image failed its self-check (Wi-Fi connected: {}, HTTP server \
+                 listening: {}, free heap: {} bytes), rolling back",
+                check.wifi_connected, check.server_listening, check.heap_free
+            );
+            status_led::publish(StatusEvent::Error);
+            match roll_back() {
+                Ok(()) => REBOOT.signal("firmware rollback"),
+                Err(e) => error!("Failed to roll back the firmware image: {e:?}"),
+            }
+            return;
+        }
+
+        Timer::after_secs(VERIFY_INTERVAL_SECS).await;
+    }
+}
+
+// Makes the bootloader run the given slot at the next boot.
+fn activate(slot: Slot) -> Result<(), OtaError> {
+    with_ota(|_, ota| {
+        ota.set_current_slot(slot)?;
+        ota.set_current_ota_state(OtaImageState::New)?;
+        Ok(())
+    })
+}
+
+// Writes and verifies an image read from the request body.
+async fn write_image(
+    mut body: impl Read,
+    partition: &UpdatePartition,
+    image_len: usize,
+    sector: &mut [u8; SECTOR_SIZE],
+) -> Result<(), OtaError> {
+    let mut flash = FlashStorage::new();
+    let mut hasher = Sha256::new();
+    let mut written = 0;
+    while written < image_len {
+        // Fill a sector, or the end of the image.
+        let sector_len = SECTOR_SIZE.min(image_len - written);
+        body.read_exact(&mut sector[..sector_len])
+            .await
+            .map_err(|_| OtaError::Read)?;
+
+        if written == 0 && sector[0] != IMAGE_MAGIC {
+            return Err(OtaError::InvalidImage);
+        }
+
+        hasher.update(&sector[..sector_len]);
+        // The offset is bounded by the partition size.
+        flash
+            .write(partition.offset + written as u32, &sector[..sector_len])
+            .map_err(|e| {
+                error!("Failed to write firmware: {e:?}");
+                OtaError::Flash
+            })?;
+
+        let previous = written;
+        written += sector_len;
+        if written / PROGRESS_LOG_BYTES != previous / PROGRESS_LOG_BYTES {
+            info!("Firmware update: {written}/{image_len} bytes written");
+        }
+    }
+
+    let mut digest = [0; DIGEST_SIZE];
+    body.read_exact(&mut digest)
+        .await
+        .map_err(|_| OtaError::Read)?;
+    if hasher.finalize() != digest {
+        return Err(OtaError::DigestMismatch);
+    }
+
+    Ok(())
+}
+
+// Writes the firmware image contained in a request body to the OTA slot which
+// is not running, then makes the bootloader run it at the next boot.
+//
+// The body is the image followed by its SHA-256 digest. The running firmware
+// and the OTA data are only changed once the whole image has been written and
+// verified, so a failure never prevents the device from booting.
+//
+// The partition table must contain the `otadata`, `ota_0` and `ota_1`
+// partitions, and the bootloader must support OTA updates.
+pub(crate) async fn update_firmware(
+    body: impl Read,
+    content_length: usize,
+) -> Result<(), OtaError> {
+    // Updating an unverified image would overwrite the image to roll back to.
+    if running_image().is_some_and(|image| image.is_unverified()) {
+        return Err(OtaError::Unverified);
+    }
+
+    let mut sector = SECTOR_BUFFER.try_lock().map_err(|_| OtaError::Busy)?;
+
+    let image_len = content_length
+        .checked_sub(DIGEST_SIZE)
+        .filter(|len| *len > 0)
+        .ok_or(OtaError::InvalidImage)?;
+
+    let partition = update_partition()?;
+    if image_len > partition.len {
+        return Err(OtaError::TooLarge);
+    }
+    info!(
+        "Updating firmware in slot {} with a {image_len} bytes image",
+        partition.slot.number()
+    );
+
+    status_led::publish(StatusEvent::OtaInProgress);
+    let result = write_image(body, &partition, image_len, &mut sector)
+        .await
+        .and_then(|()| activate(partition.slot));
+    if result.is_err() {
+        status_led::publish(StatusEvent::Error);
+    }
+    result?;
+    info!("Firmware update completed");
+
+    Ok(())
+}
@@ -0,0 +1,179 @@
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::channel::Channel;
+
+use esp_hal::gpio::{AnyPin, Level, Output, OutputConfig};
+
+use heapless::Vec;
+
+use log::{error, info};
+
+use serde::Serialize;
+
+use crate::state::LedState;
+
+// Maximum number of extra outputs.
+pub(crate) const MAX_OUTPUTS: usize = 4;
+// Maximum length of an output name.
+pub(crate) const MAX_OUTPUT_NAME_LEN: usize = 16;
+// Commands waiting to be applied to the outputs.
+const COMMAND_QUEUE_SIZE: usize = 4;
+
+// Extra output, as configured.
+#[derive(Clone, Copy)]
+pub(crate) struct OutputSpec {
+    pub(crate) name: &'static str,
+    pub(crate) gpio: u8,
+    pub(crate) active_low: bool,
+}
+
+// Parses a `name:gpio[:high|low]` output, active high by default.
+fn parse_output(output: &'static str) -> Option<OutputSpec> {
+    let mut fields = output.split(':');
+    let name = fields.next()?;
+    let gpio = fields.next()?.parse().ok()?;
+    let active_low = match fields.next() {
+        None | Some("high") => false,
+        Some("low") => true,
+        Some(_) => return None,
+    };
+    let valid_name = !name.is_empty()
+        && name.len() <= MAX_OUTPUT_NAME_LEN
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_');
+    (valid_name && fields.next().is_none()).then_some(OutputSpec {
+        name,
+        gpio,
+        active_low,
+    })
+}
+
+// Parses a comma-separated list of outputs, logging the invalid ones and the
+// ones clashing with the led, whose GPIO the led task owns and whose name
+// the `/gpio` routes reject.
+pub(crate) fn parse_outputs(outputs: &'static str, led_gpio: u8) -> Vec<OutputSpec, MAX_OUTPUTS> {
+    let mut specs: Vec<OutputSpec, MAX_OUTPUTS> = Vec::new();
+    for output in outputs
+        .split(',')
+        .map(str::trim)
+        .filter(|output| !output.is_empty())
+    {
+        let Some(spec) = parse_output(output) else {
+            error!("Invalid output {output}, expected `name:gpio[:high|low]`");
+            continue;
+        };
+        if spec.gpio == led_gpio || spec.name == "led" {
+            error!("Output {} clashes with the led, ignored", spec.name);
+        } else if specs.iter().any(|other| other.name == spec.name) {
+            error!("Duplicate output {}, ignored", spec.name);
+        } else if specs.push(spec).is_err() {
+            error!("Too many outputs, {} is ignored", spec.name);
+        }
+    }
+    specs
+}
+
+// Extra output with the pin driving it.
+pub(crate) struct ExtraOutput {
+    spec: OutputSpec,
+    pin: Output<'static>,
+}
+
+impl ExtraOutput {
+    // Configures the pin of an output, starting off.
+    pub(crate) fn new(spec: OutputSpec, pin: AnyPin<'static>) -> Self {
+        let pin = Output::new(pin, level(&spec, LedState::Off), OutputConfig::default());
+        Self { spec, pin }
+    }
+}
+
+// Pin level of an output in the given state.
+const fn level(spec: &OutputSpec, state: LedState) -> Level {
+    match (state, spec.active_low) {
+        (LedState::On, false) | (LedState::Off, true) => Level::High,
+        (LedState::On, true) | (LedState::Off, false) => Level::Low,
+    }
+}
+
+// State of an output, returned by the `/gpio` routes.
+#[derive(Clone, Copy, Serialize)]
+pub(crate) struct OutputState {
+    pub(crate) name: &'static str,
+    pub(crate) gpio: u8,
+    pub(crate) state: LedState,
+}
+
+// Change requested to an output.
+#[derive(Clone, Copy)]
+pub(crate) enum OutputAction {
+    Set(LedState),
+    Toggle,
+}
+
+// States of the registered outputs, in the order of the configuration.
+static STATES: Mutex<CriticalSectionRawMutex, RefCell<Vec<OutputState, MAX_OUTPUTS>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+// Changes waiting to be applied, with the index of their output.
+static COMMANDS: Channel<CriticalSectionRawMutex, (usize, OutputAction), COMMAND_QUEUE_SIZE> =
+    Channel::new();
+
+// States of all the outputs.
+pub(crate) fn states() -> Vec<OutputState, MAX_OUTPUTS> {
+    STATES.lock(|states| states.borrow().clone())
+}
+
+// Index and state of the output with the given name, if registered.
+pub(crate) fn find(name: &str) -> Option<(usize, OutputState)> {
+    STATES.lock(|states| {
+        states
+            .borrow()
+            .iter()
+            .enumerate()
+            .find(|(_, output)| output.name == name)
+            .map(|(index, output)| (index, *output))
+    })
+}
+
+// Requests a change to an output, returning `false` when too many changes
+// are pending.
+pub(crate) fn request(index: usize, action: OutputAction) -> bool {
+    COMMANDS.try_send((index, action)).is_ok()
+}
+
+// Owns the extra output pins, such as relays or fans, and applies the
+// requested changes, so the HTTP handlers never touch the pins.
+#[embassy_executor::task]
+pub(crate) async fn outputs_task(mut outputs: Vec<ExtraOutput, MAX_OUTPUTS>) {
+    STATES.lock(|states| {
+        *states.borrow_mut() = outputs
+            .iter()
+            .map(|output| OutputState {
+                name: output.spec.name,
+                gpio: output.spec.gpio,
+                state: LedState::Off,
+            })
+            .collect();
+    });
+    info!("{} extra outputs registered", outputs.len());
+
+    loop {
+        let (index, action) = COMMANDS.receive().await;
+        let Some(output) = outputs.get_mut(index) else {
+            continue;
+        };
+        let led_state = STATES.lock(|states| {
+            let mut states = states.borrow_mut();
+            let state = &mut states[index].state;
+            *state = match action {
+                OutputAction::Set(led_state) => led_state,
+                OutputAction::Toggle => state.toggled(),
+            };
+            *state
+        });
+        output.pin.set_level(level(&output.spec, led_state));
+        info!("Output {} is {}!", output.spec.name, led_state.as_str());
+    }
+}
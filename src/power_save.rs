@@ -0,0 +1,62 @@
+use embassy_time::Duration;
+
+use esp_wifi::config::PowerSaveMode;
+
+use crate::DEVICE_CONFIG;
+
+// Shortest time allowed to read a request or write a response when the modem
+// sleeps as much as possible, since it only wakes up every few beacons.
+const MAXIMUM_HTTP_TIMEOUT_MS: u64 = 3000;
+
+// Wi-Fi power-save mode of the station.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PowerSave {
+    // The radio is always on.
+    None,
+    // The modem sleeps between DTIM beacons.
+    Minimum,
+    // The modem sleeps for several beacons, as set by the listen interval.
+    Maximum,
+}
+
+impl PowerSave {
+    // Configured power-save mode, or `None` when the configuration is
+    // invalid.
+    pub(crate) fn configured() -> Option<Self> {
+        match DEVICE_CONFIG.wifi_power_save {
+            "none" => Some(Self::None),
+            "minimum" => Some(Self::Minimum),
+            "maximum" => Some(Self::Maximum),
+            _ => None,
+        }
+    }
+
+    // Power-save mode as a lowercase string.
+    pub(crate) const fn as_str(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Minimum => "minimum",
+            Self::Maximum => "maximum",
+        }
+    }
+
+    // Power-save mode of the Wi-Fi driver.
+    pub(crate) const fn mode(self) -> PowerSaveMode {
+        match self {
+            Self::None => PowerSaveMode::None,
+            Self::Minimum => PowerSaveMode::Minimum,
+            Self::Maximum => PowerSaveMode::Maximum,
+        }
+    }
+
+    // Time allowed to read a request or write a response, raised when the
+    // modem sleeps for several beacons, so its wake-up latency does not time
+    // requests out.
+    pub(crate) fn http_timeout(self) -> Duration {
+        let timeout_ms = match self {
+            Self::Maximum => DEVICE_CONFIG.http_timeout_ms.max(MAXIMUM_HTTP_TIMEOUT_MS),
+            Self::None | Self::Minimum => DEVICE_CONFIG.http_timeout_ms,
+        };
+        Duration::from_millis(timeout_ms)
+    }
+}
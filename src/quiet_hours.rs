@@ -0,0 +1,97 @@
+use core::cell::Cell;
+
+use embassy_futures::select::select;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::Timer;
+
+use log::info;
+
+pub(crate) use button_led_logic::quiet_hours::QuietHours;
+
+use crate::led::{ColorUnsupported, LedCommand, LedDriver, LedInput, Rgb, Source};
+use crate::schedule::TimeOfDay;
+use crate::sntp;
+use crate::state::NOTIFY_LED;
+use crate::status_led;
+
+// Interval between two checks of the clock, while it is not synchronized.
+const TIME_SYNC_POLL_SECS: u64 = 10;
+
+static QUIET_HOURS: Mutex<CriticalSectionRawMutex, Cell<QuietHours>> =
+    Mutex::new(Cell::new(QuietHours::DISABLED));
+
+// Highest brightness shown, set only during the quiet hours.
+static BRIGHTNESS_LIMIT: Mutex<CriticalSectionRawMutex, Cell<Option<u8>>> =
+    Mutex::new(Cell::new(None));
+
+// Signalled when the quiet hours change, so they are checked again.
+static QUIET_HOURS_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+// Replaces the quiet hours.
+pub(crate) fn set_quiet_hours(quiet_hours: QuietHours) {
+    QUIET_HOURS.lock(|current| current.set(quiet_hours));
+    QUIET_HOURS_CHANGED.signal(());
+}
+
+// Whether the quiet hours are running.
+pub(crate) fn is_active() -> bool {
+    BRIGHTNESS_LIMIT.lock(Cell::get).is_some()
+}
+
+// Brightness actually shown for the given led brightness.
+pub(crate) fn limit(brightness: u8) -> u8 {
+    BRIGHTNESS_LIMIT
+        .lock(Cell::get)
+        .map_or(brightness, |limit| brightness.min(limit))
+}
+
+// Led driver keeping the output within the quiet hours brightness, while the
+// led logic still sees the brightness it asked for.
+pub(crate) struct QuietLed<L>(pub(crate) L);
+
+impl<L: LedDriver> LedDriver for QuietLed<L> {
+    fn set_level(&mut self, brightness: u8) {
+        self.0.set_level(limit(brightness));
+    }
+
+    fn set_color(&mut self, color: Rgb) -> Result<(), ColorUnsupported> {
+        self.0.set_color(color)
+    }
+}
+
+// Starts and ends the quiet hours, bringing the led output back in line with
+// the led state every time.
+//
+// The quiet hours never start until the clock is synchronized.
+#[embassy_executor::task]
+pub(crate) async fn quiet_hours_task(timezone_offset_minutes: i32) {
+    loop {
+        let quiet_hours = QUIET_HOURS.lock(Cell::get);
+        let now = sntp::now_unix();
+        let limit = now
+            .filter(|now| {
+                quiet_hours.enabled
+                    && quiet_hours.contains(TimeOfDay::at(*now, timezone_offset_minutes))
+            })
+            .map(|_| quiet_hours.brightness);
+
+        let previous = BRIGHTNESS_LIMIT.lock(|current| current.replace(limit));
+        if previous != limit {
+            match (previous, limit) {
+                (None, Some(_)) => info!("Quiet hours started"),
+                (Some(_), None) => info!("Quiet hours ended"),
+                _ => info!("Quiet hours brightness changed"),
+            }
+            NOTIFY_LED
+                .send(LedCommand::new(Source::System, LedInput::Reconcile))
+                .await;
+            status_led::refresh();
+        }
+
+        // Check again at the start of the next minute.
+        let delay_secs = now.map_or(TIME_SYNC_POLL_SECS, |now| 60 - now % 60);
+        select(Timer::after_secs(delay_secs), QUIET_HOURS_CHANGED.wait()).await;
+    }
+}
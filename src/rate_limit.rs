@@ -0,0 +1,82 @@
+use core::cell::RefCell;
+use core::net::IpAddr;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant};
+
+// Requests a client can send in a burst.
+const BUCKET_SIZE: u32 = 10;
+// Time needed to earn back a request, so clients are limited to 4 requests
+// per second on average.
+const REFILL_INTERVAL: Duration = Duration::from_millis(250);
+// Number of clients tracked at once, the least recently seen one is evicted.
+const MAX_CLIENTS: usize = 8;
+
+// Token bucket of a client.
+struct Bucket {
+    client: IpAddr,
+    tokens: u32,
+    // Time the last token was added.
+    refilled_at: Instant,
+    last_seen: Instant,
+}
+
+impl Bucket {
+    const fn new(client: IpAddr, now: Instant) -> Self {
+        Self {
+            client,
+            tokens: BUCKET_SIZE,
+            refilled_at: now,
+            last_seen: now,
+        }
+    }
+
+    // Takes a token for a request, returning the time to wait for the next
+    // one when the bucket is empty.
+    fn take(&mut self, now: Instant) -> Result<(), Duration> {
+        self.last_seen = now;
+
+        // A full bucket does not earn tokens, so waiting starts now.
+        if self.tokens == BUCKET_SIZE {
+            self.refilled_at = now;
+        }
+        let earned = ((now - self.refilled_at).as_ticks() / REFILL_INTERVAL.as_ticks())
+            .min(u64::from(BUCKET_SIZE - self.tokens));
+        // At most `BUCKET_SIZE` tokens are earned, which fits an `u32`.
+        self.tokens += earned as u32;
+        self.refilled_at += Duration::from_ticks(REFILL_INTERVAL.as_ticks() * earned);
+
+        if self.tokens == 0 {
+            return Err(REFILL_INTERVAL - (now - self.refilled_at));
+        }
+        self.tokens -= 1;
+        Ok(())
+    }
+}
+
+// Buckets of the recent clients, shared by all the web tasks.
+static BUCKETS: Mutex<CriticalSectionRawMutex, RefCell<heapless::Vec<Bucket, MAX_CLIENTS>>> =
+    Mutex::new(RefCell::new(heapless::Vec::new()));
+
+// Accounts for a request of the given client, returning the time to wait
+// before retrying when the client exceeded its rate.
+pub(crate) fn check(client: IpAddr) -> Result<(), Duration> {
+    let now = Instant::now();
+    BUCKETS.lock(|buckets| {
+        let mut buckets = buckets.borrow_mut();
+        if let Some(bucket) = buckets.iter_mut().find(|bucket| bucket.client == client) {
+            return bucket.take(now);
+        }
+
+        let mut bucket = Bucket::new(client, now);
+        let result = bucket.take(now);
+        if let Err(bucket) = buckets.push(bucket) {
+            // The table is full, so the least recently seen client is evicted.
+            if let Some(oldest) = buckets.iter_mut().min_by_key(|bucket| bucket.last_seen) {
+                *oldest = bucket;
+            }
+        }
+        result
+    })
+}
@@ -0,0 +1,114 @@
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant, Timer};
+
+use esp_storage::FlashStorage;
+
+use log::{error, info};
+
+use crate::append_log::{self, AppendLog};
+use crate::DEVICE_CONFIG;
+
+// Size of a counter record: the counter and its complement, which tells a
+// complete record apart from an interrupted write.
+const RECORD_SIZE: usize = 8;
+// Toggle counter in flash, in the second sector of the `nvs` partition, after
+// the settings.
+const TOGGLE_LOG: AppendLog<RECORD_SIZE> = AppendLog::new(0xa000);
+// Interval between two writes of the toggle counter, limiting flash wear.
+const FLUSH_INTERVAL_SECS: u64 = 60;
+
+// Lifetime count of the led switches, including the ones not stored yet.
+static TOGGLES: Mutex<CriticalSectionRawMutex, Cell<u32>> = Mutex::new(Cell::new(0));
+// Time of the last switch of the led, if it switched since boot.
+static LAST_SWITCH: Mutex<CriticalSectionRawMutex, Cell<Option<Instant>>> =
+    Mutex::new(Cell::new(None));
+
+// Shortest time between two switches of the led.
+const fn min_interval_ms() -> u64 {
+    DEVICE_CONFIG.min_toggle_interval_ms
+}
+
+// Whether the led drives a relay, which must not switch faster than the
+// minimum interval.
+pub(crate) const fn is_enabled() -> bool {
+    min_interval_ms() != 0
+}
+
+// Whether early switches are rejected rather than deferred.
+pub(crate) const fn rejects_early_switches() -> bool {
+    DEVICE_CONFIG.reject_early_toggles
+}
+
+// Time left before the led can switch again, if it cannot switch yet.
+pub(crate) fn switch_delay() -> Option<Duration> {
+    let allowed_at = LAST_SWITCH.lock(Cell::get)? + Duration::from_millis(min_interval_ms());
+    let now = Instant::now();
+    (allowed_at > now).then(|| allowed_at - now)
+}
+
+// Records a switch of the led.
+pub(crate) fn count_switch() {
+    LAST_SWITCH.lock(|last_switch| last_switch.set(Some(Instant::now())));
+    TOGGLES.lock(|toggles| toggles.set(toggles.get().wrapping_add(1)));
+}
+
+// Lifetime count of the led switches.
+pub(crate) fn toggles() -> u32 {
+    TOGGLES.lock(Cell::get)
+}
+
+// Counter stored in a record, if the record is complete.
+fn decode(record: &[u8; RECORD_SIZE]) -> Option<u32> {
+    let count = u32::from_le_bytes([record[0], record[1], record[2], record[3]]);
+    let check = u32::from_le_bytes([record[4], record[5], record[6], record[7]]);
+    (count == !check).then_some(count)
+}
+
+// Record storing the given counter.
+fn encode(count: u32) -> [u8; RECORD_SIZE] {
+    let mut record = [0; RECORD_SIZE];
+    record[..4].copy_from_slice(&count.to_le_bytes());
+    record[4..].copy_from_slice(&(!count).to_le_bytes());
+    record
+}
+
+// Loads the toggle counter from flash, and stores its changes at most once
+// per minute, so a few switches may be lost at reboot.
+#[embassy_executor::task]
+pub(crate) async fn toggle_counter_task() {
+    let mut flash = FlashStorage::new();
+    let (mut stored, mut offset) = match TOGGLE_LOG.find_last(&mut flash, decode) {
+        Ok(found) => found,
+        Err(e) => {
+            error!("Failed to read the toggle counter from flash: {e:?}");
+            return;
+        }
+    };
+    // Switches counted before loading are added to the stored counter.
+    let loaded = stored.unwrap_or(0);
+    TOGGLES.lock(|toggles| toggles.set(toggles.get().wrapping_add(loaded)));
+    info!("Led switched {loaded} times before this boot");
+
+    loop {
+        Timer::after_secs(FLUSH_INTERVAL_SECS).await;
+
+        let count = toggles();
+        if stored == Some(count) {
+            continue;
+        }
+        match TOGGLE_LOG.store(&mut flash, &encode(count), offset) {
+            Ok(next) => {
+                stored = Some(count);
+                offset = next;
+            }
+            Err(e) => {
+                error!("Failed to store the toggle counter: {e:?}");
+                // Start over from an erased sector at the next attempt.
+                offset = append_log::FULL;
+            }
+        }
+    }
+}
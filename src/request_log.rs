@@ -0,0 +1,137 @@
+use core::cell::Cell;
+use core::net::{IpAddr, Ipv4Addr};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant};
+
+use picoserve::io::Read;
+use picoserve::request::RequestParts;
+use picoserve::response::{Body, Connection, HeadersIter, Response, ResponseWriter};
+use picoserve::routing::{Layer, Next};
+use picoserve::ResponseSent;
+
+use log::info;
+
+use crate::metrics;
+use crate::server::Client;
+
+// Requests logged in each window, the others are only counted, so a flood of
+// requests does not saturate the serial port.
+const MAX_LOGGED_REQUESTS: u32 = 10;
+const LOG_WINDOW: Duration = Duration::from_secs(1);
+
+// Requests logged in the current window.
+#[derive(Clone, Copy)]
+struct LogWindow {
+    started_at: Instant,
+    logged: u32,
+    // Requests not logged since the last logged one.
+    skipped: u32,
+}
+
+static LOG_WINDOW_STATE: Mutex<CriticalSectionRawMutex, Cell<LogWindow>> =
+    Mutex::new(Cell::new(LogWindow {
+        started_at: Instant::from_ticks(0),
+        logged: 0,
+        skipped: 0,
+    }));
+
+// Whether a request can be logged, returning the number of requests which
+// were not logged since the previous one.
+fn admit(now: Instant) -> Option<u32> {
+    LOG_WINDOW_STATE.lock(|window| {
+        let mut current = window.get();
+        if now - current.started_at >= LOG_WINDOW {
+            current.started_at = now;
+            current.logged = 0;
+        }
+
+        let admitted = if current.logged < MAX_LOGGED_REQUESTS {
+            current.logged += 1;
+            Some(core::mem::take(&mut current.skipped))
+        } else {
+            current.skipped = current.skipped.saturating_add(1);
+            None
+        };
+        window.set(current);
+        admitted
+    })
+}
+
+// Response writer recording the status code of the response.
+struct RecordStatus<'s, W> {
+    writer: W,
+    status: &'s Cell<u16>,
+}
+
+impl<W: ResponseWriter> ResponseWriter for RecordStatus<'_, W> {
+    type Error = W::Error;
+
+    async fn write_response<R: Read<Error = Self::Error>, H: HeadersIter, B: Body>(
+        self,
+        connection: Connection<'_, R>,
+        response: Response<H, B>,
+    ) -> Result<ResponseSent, Self::Error> {
+        self.status.set(response.status_code().as_u16());
+        self.writer.write_response(connection, response).await
+    }
+}
+
+// Layer logging every request with the client address, its method, path,
+// response status and duration, and counting them in the metrics.
+pub(crate) struct LogRequests;
+
+impl<PathParameters> Layer<Client, PathParameters> for LogRequests {
+    type NextState = Client;
+    type NextPathParameters = PathParameters;
+
+    async fn call_layer<
+        'a,
+        R: Read + 'a,
+        NextLayer: Next<'a, R, Client, PathParameters>,
+        W: ResponseWriter<Error = R::Error>,
+    >(
+        &self,
+        next: NextLayer,
+        client: &Client,
+        path_parameters: PathParameters,
+        request_parts: RequestParts<'_>,
+        response_writer: W,
+    ) -> Result<ResponseSent, W::Error> {
+        let started_at = Instant::now();
+        let path = request_parts.path();
+        metrics::count_http_request(path.encoded());
+
+        // No status is recorded when the connection fails before a response.
+        let status = Cell::new(0);
+        let result = next
+            .run(
+                client,
+                path_parameters,
+                RecordStatus {
+                    writer: response_writer,
+                    status: &status,
+                },
+            )
+            .await;
+
+        let elapsed = Instant::now() - started_at;
+        metrics::add_http_request_time(path.encoded(), elapsed);
+
+        if let Some(skipped) = admit(started_at) {
+            if skipped > 0 {
+                info!("{skipped} requests not logged");
+            }
+            let address = client.address.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+            info!(
+                "{address} {} {path} {} {} ms",
+                request_parts.method(),
+                status.get(),
+                elapsed.as_millis()
+            );
+        }
+
+        result
+    }
+}
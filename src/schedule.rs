@@ -0,0 +1,86 @@
+use core::cell::RefCell;
+
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::Timer;
+
+use log::info;
+
+pub(crate) use button_led_logic::schedule::{Schedule, TimeOfDay};
+
+use crate::led::{LedCommand, Source};
+use crate::sntp;
+use crate::state::NOTIFY_LED;
+
+// Interval between two checks of the clock, while it is not synchronized.
+const TIME_SYNC_POLL_SECS: u64 = 10;
+
+static SCHEDULE: Mutex<CriticalSectionRawMutex, RefCell<Schedule>> =
+    Mutex::new(RefCell::new(Schedule::new()));
+
+// Signalled when the schedule changes, so the next event is computed again.
+static SCHEDULE_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+// Retrieves a copy of the current schedule.
+pub(crate) fn schedule() -> Schedule {
+    SCHEDULE.lock(|schedule| schedule.borrow().clone())
+}
+
+// Replaces the current schedule.
+pub(crate) fn set_schedule(new_schedule: Schedule) {
+    SCHEDULE.lock(|schedule| *schedule.borrow_mut() = new_schedule);
+    SCHEDULE_CHANGED.signal(());
+}
+
+// Sends the led inputs of the schedule entries once they are due.
+//
+// The scheduler is paused until the clock is synchronized.
+#[embassy_executor::task]
+pub(crate) async fn scheduler(timezone_offset_minutes: i32) {
+    // Time of the last event, so it is not performed twice when the clock
+    // moves back.
+    let mut last_event_unix = None;
+    let mut paused = false;
+
+    loop {
+        let Some(now) = sntp::now_unix() else {
+            if !paused {
+                info!("Scheduler paused until the clock is synchronized");
+                paused = true;
+            }
+            select(
+                Timer::after_secs(TIME_SYNC_POLL_SECS),
+                SCHEDULE_CHANGED.wait(),
+            )
+            .await;
+            continue;
+        };
+        paused = false;
+
+        let after = last_event_unix.map_or(now, |last: u64| last.max(now));
+        let Some(event) = schedule().next_event(after, timezone_offset_minutes) else {
+            SCHEDULE_CHANGED.wait().await;
+            continue;
+        };
+
+        let delay_secs = event.at_unix - now;
+        match select(Timer::after_secs(delay_secs), SCHEDULE_CHANGED.wait()).await {
+            // Compute the next event again, it may be earlier.
+            Either::Second(()) => continue,
+            Either::First(()) => {}
+        }
+
+        // The clock may have been synchronized while waiting.
+        if sntp::now_unix().is_none_or(|now| now < event.at_unix) {
+            continue;
+        }
+
+        info!("Scheduled event: led {}", event.action.as_str());
+        NOTIFY_LED
+            .send(LedCommand::new(Source::Schedule, event.action.led_input()))
+            .await;
+        last_event_unix = Some(event.at_unix);
+    }
+}
@@ -1,131 +1,2008 @@
+use core::fmt::Write as _;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
 use embassy_executor::Spawner;
 
+use embassy_futures::select::{select, Either};
+
+use embassy_net::tcp::TcpSocket;
 use embassy_net::Stack;
-use embassy_time::{Duration, Timer};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+
+use esp_wifi::wifi::WifiState;
 
 use picoserve::{
-    listen_and_serve,
-    routing::{get, PathRouter, Router},
-    AppBuilder, AppRouter, Config,
+    extract::{Form, FromRequest, Query},
+    io::{Read, Write},
+    request::{ReadAllBodyError, RequestBody, RequestParts},
+    response::{
+        chunked::{ChunkWriter, ChunkedResponse, Chunks, ChunksWritten},
+        sse, File, IntoResponse, Json, ResponseWriter, StatusCode,
+    },
+    routing::{get, get_service, parse_path_segment, post, Layer, Next, PathRouter, Router},
+    serve_with_state, AppRouter, AppWithStateBuilder, Config, ResponseSent,
 };
 
-use crate::{LedInput, MILLISECONDS_TO_WAIT, NOTIFY_LED};
+use log::Level;
+
+use serde::{Deserialize, Serialize};
+
+use crate::adc;
+use crate::api::{check_routes, documented, ApiRoute, Route, API_ROUTES};
+use crate::api_version::{unversioned, ApiVersion, API_PREFIX, API_VERSION};
+use crate::auth::{ApiKey, Authorized, BasicAuth};
+use crate::boot::BootReason;
+use crate::boot_count::{self, BootCounts};
+use crate::button_actions;
+use crate::buzzer;
+use crate::cors::Cors;
+use crate::error::FirmwareError;
+use crate::events::EventStream;
+use crate::heap;
+use crate::history::{self, HISTORY_SIZE};
+use crate::last_panic;
+use crate::led::{
+    self, parse_led_body, LedCommand, LedInput, Rgb, Source, DEFAULT_BLINK_PERIOD_MS, MAIN_CHANNEL,
+    MAX_BRIGHTNESS,
+};
+use crate::log_buffer::{self, LogCursor};
+use crate::logger::{self, LogFilter, MAX_FILTER_LEN};
+use crate::metrics::{self, LATENCY_BOUNDS_MS};
+use crate::morse::{MorseMessage, MAX_MORSE_LEN};
+use crate::motion;
+use crate::net_watchdog;
+use crate::ota;
+use crate::outputs::{self, OutputAction, OutputState, MAX_OUTPUT_NAME_LEN};
+use crate::quiet_hours;
+use crate::rate_limit;
+use crate::relay;
+use crate::request_log::LogRequests;
+use crate::schedule::{self, Schedule};
+use crate::settings::{self, ConfigUpdate, SettingsUpdate, MAX_SSID_LEN};
+use crate::sleep;
+use crate::sntp;
+use crate::state::{self, LedState, NOTIFY_LED, REBOOT};
+use crate::temperature;
+use crate::touch;
+use crate::watchdog::{self, Task};
+use crate::web_pool::{self, TrackRequests, WorkerStatus};
+use crate::{ESP_APP_DESC, MAX_HEAP_SIZE, MILLISECONDS_TO_WAIT, WEB_TASK_POOL_SIZE};
+
+// Control page served by the `/` route.
+//
+// The page is kept in its own file so it can be edited without touching
+// the server logic.
+const INDEX_PAGE: &str = include_str!("index.html");
+
+// Provisioning page served by the `/setup` route.
+const SETUP_PAGE: &str = include_str!("setup.html");
+
+// Sizes, in bytes, of the buffers of each web task.
+//
+// Every web task keeps its buffers in its own future, so they cost
+// `WEB_TASK_POOL_SIZE` times their sum of RAM, 48 KB with these sizes, taken
+// from the task arena. Responses are streamed through the TCP transmit
+// buffer, so pages of any size are served.
+const TCP_RX_BUFFER_SIZE: usize = 1024;
+const TCP_TX_BUFFER_SIZE: usize = 2048;
+// Holds the request line and headers, followed by the bodies read at once.
+const HTTP_BUFFER_SIZE: usize = 3072;
+
+// Response of the connections refused while every other web task is busy.
+const SATURATED_RESPONSE: &[u8] = b"HTTP/1.1 503 Service Unavailable\r\n\
+    Retry-After: 1\r\n\
+    X-Api-Version: 1\r\n\
+    Content-Length: 0\r\n\
+    Connection: close\r\n\r\n";
+// The response is written before any layer runs, so it carries the API
+// version itself.
+const _: () = assert!(API_VERSION == 1, "update the version of SATURATED_RESPONSE");
+// Time given to a refused client to receive the response.
+const REFUSE_TIMEOUT_MS: u64 = 1000;
+
+// Longest `msg` query parameter of the `/morse` route, longer than the
+// messages played, so their length is checked with a clear error.
+const MAX_MORSE_QUERY_LEN: usize = 2 * MAX_MORSE_LEN;
+
+// Maximum size, in bytes, of a `/led` request body.
+const MAX_LED_BODY_SIZE: usize = 256;
+
+// Maximum size, in bytes, of a `/schedule` request body.
+const MAX_SCHEDULE_BODY_SIZE: usize = 512;
+// Largest `/config` request body accepted.
+const MAX_CONFIG_BODY_SIZE: usize = 512;
+// Largest `/automation` request body accepted.
+const MAX_AUTOMATION_BODY_SIZE: usize = 64;
+// Largest `/loglevel` request body accepted, enough for the longest filter.
+const MAX_LOG_LEVEL_BODY_SIZE: usize = 512;
+
+// The accepted bodies leave room for the request headers in the HTTP buffer.
+const _: () = assert!(
+    MAX_LED_BODY_SIZE <= HTTP_BUFFER_SIZE / 2
+        && MAX_SCHEDULE_BODY_SIZE <= HTTP_BUFFER_SIZE / 2
+        && MAX_CONFIG_BODY_SIZE <= HTTP_BUFFER_SIZE / 2
+        && MAX_LOG_LEVEL_BODY_SIZE <= HTTP_BUFFER_SIZE / 2
+);
+
+// Range of blinking periods, in milliseconds, accepted by the `/blink` route.
+const MIN_BLINK_PERIOD_MS: u64 = 50;
+const MAX_BLINK_PERIOD_MS: u64 = 10_000;
+// Longest fade duration, in milliseconds, accepted by the led routes.
+const MAX_FADE_MS: u64 = 10_000;
+// Longest auto-off duration, in seconds, accepted by the `/on` route.
+const MAX_AUTO_OFF_SECS: u64 = 24 * 60 * 60;
+
+// Device status returned by the `/status` route.
+#[derive(Serialize)]
+struct Status {
+    led: LedState,
+    brightness: u8,
+    // What the led actually shows, which differs from the led state during
+    // the quiet hours.
+    physical_led: LedState,
+    physical_brightness: u8,
+    quiet_hours: bool,
+    uptime_ms: u64,
+    ip: Option<Ipv4Addr>,
+    ipv6: Option<Ipv6Addr>,
+    // SSID of the connected Wi-Fi network.
+    wifi_ssid: Option<heapless::String<MAX_SSID_LEN>>,
+    // Consecutive failed Wi-Fi connection attempts.
+    wifi_failures: u32,
+    // Seconds left before the led is automatically turned off, if scheduled.
+    auto_off_secs: Option<u64>,
+    // Current, weakest and strongest Wi-Fi signal since boot, in dBm.
+    rssi: Option<i32>,
+    rssi_min: Option<i32>,
+    rssi_max: Option<i32>,
+    // Lifetime count of the led switches, to estimate the wear of a relay.
+    toggles: u32,
+}
+
+// Device health returned by the `/health` route.
+//
+// It only holds numbers and static strings, so it is serialized without
+// allocating on the heap.
+#[derive(Serialize)]
+struct Health {
+    uptime_ms: u64,
+    heap_free: usize,
+    heap_used: usize,
+    // Largest heap usage sampled since boot.
+    heap_high_watermark: usize,
+    // Whether the free heap was ever sampled below 10% of the heap, a warning
+    // that the device came close to running out of memory.
+    heap_warning: bool,
+    wifi: &'static str,
+    // Signal strength in dBm, if connected.
+    rssi: Option<i32>,
+    wifi_reconnects: u32,
+    // Time elapsed since the clock was synchronized, if it ever was.
+    time_sync_age_secs: Option<u64>,
+    // Activity of each web task, to tell when persistent connections hold
+    // all of them.
+    web_tasks: [WorkerStatus; WEB_TASK_POOL_SIZE],
+}
+
+// Chip temperature returned by the `/temperature` route.
+#[derive(Serialize)]
+struct Temperature {
+    // Degrees Celsius, with one decimal.
+    celsius: f32,
+}
+
+// Automations returned and changed by the `/automation` route.
+#[derive(Serialize, Deserialize)]
+struct Automation {
+    // Whether motion turns the led on.
+    motion: bool,
+}
+
+// Log levels returned by the `/loglevel` route.
+#[derive(Serialize)]
+struct LogLevel {
+    level: &'static str,
+    // Per-target levels, such as `esp_wifi=warn,server=debug`.
+    filter: heapless::String<MAX_FILTER_LEN>,
+}
+
+impl LogLevel {
+    fn current() -> Self {
+        Self {
+            level: logger::level_name(logger::level()),
+            filter: logger::filter(),
+        }
+    }
+}
+
+// Log levels changed through the `/loglevel` route. A missing level is kept,
+// while a missing filter keeps the per-target levels.
+#[derive(Deserialize)]
+struct LogLevelUpdate {
+    level: Option<heapless::String<8>>,
+    filter: Option<heapless::String<MAX_FILTER_LEN>>,
+}
+
+// Button statistics returned by the `/button` route.
+#[derive(Serialize)]
+struct ButtonStats {
+    count: u32,
+    // Time elapsed since the last press, if any.
+    last_press_ms_ago: Option<u64>,
+}
+
+// Firmware and hardware details returned by the `/info` route.
+#[derive(Serialize)]
+struct Info {
+    name: &'static str,
+    version: &'static str,
+    build_date: &'static str,
+    build_time: &'static str,
+    chip: &'static str,
+    // Wi-Fi station MAC address, as colon-separated hex digits.
+    mac: Option<heapless::String<17>>,
+    hostname: &'static str,
+    ip: Option<Ipv4Addr>,
+    ipv6: Option<Ipv6Addr>,
+    heap_size: usize,
+    // Running partition and the state of its image, once read at boot.
+    partition: Option<&'static str>,
+    image_state: Option<&'static str>,
+    // Reason of the last reset, and whether the device booted in safe mode
+    // because it keeps resetting.
+    reset_reason: Option<&'static str>,
+    safe_mode: bool,
+    // Restarts caused by the device staying offline, since power on.
+    offline_reboots: u32,
+    // Boots counted in flash, missing when it cannot be read.
+    boots: Option<BootCounts>,
+    // Active Wi-Fi power-save mode, missing in provisioning mode.
+    wifi_power_save: Option<&'static str>,
+}
+
+// Extra output with the given name, answering `404 Not Found` when missing.
+//
+// The led has its own routes, so it is never an extra output.
+fn find_output(name: &str) -> Result<(usize, OutputState), (StatusCode, &'static str)> {
+    if name == "led" {
+        return Err((
+            StatusCode::CONFLICT,
+            "The led is controlled through the `/led` routes\n",
+        ));
+    }
+    outputs::find(name).ok_or((StatusCode::NOT_FOUND, "Unknown output\n"))
+}
+
+// Formats a MAC address as colon-separated hex digits.
+fn format_mac(mac: [u8; 6]) -> heapless::String<17> {
+    let mut formatted = heapless::String::new();
+    // The string is large enough for six bytes and five separators.
+    let _ = write!(
+        formatted,
+        "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+        mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+    );
+    formatted
+}
+
+// Wi-Fi state as a lowercase string.
+const fn wifi_state_str(wifi_state: WifiState) -> &'static str {
+    match wifi_state {
+        WifiState::StaStarted | WifiState::StaDisconnected => "disconnected",
+        WifiState::StaConnected => "connected",
+        WifiState::StaStopped | WifiState::ApStopped => "stopped",
+        WifiState::ApStarted => "access_point",
+        WifiState::Invalid => "invalid",
+    }
+}
+
+// Change extracted from a `/gpio/<name>` request body, which is the same as
+// a `/led` one.
+struct OutputCommand(OutputAction);
 
-macro_rules! web_task {
-    ($pool_size_ident:ident, $pool_size_value:tt) => {
-        #[embassy_executor::task(pool_size = $pool_size_value)]
-        async fn $pool_size_ident(
-            id: usize,
-            stack: Stack<'static>,
-            app: &'static AppRouter<AppProps>,
-            config: &'static Config<Duration>,
-        ) {
-            web_task(id, stack, app, config).await
+impl<'r, State> FromRequest<'r, State> for OutputCommand {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request<R: Read>(
+        _state: &'r State,
+        _request_parts: RequestParts<'r>,
+        request_body: RequestBody<'r, R>,
+    ) -> Result<Self, Self::Rejection> {
+        if request_body.content_length() > MAX_LED_BODY_SIZE {
+            return Err((StatusCode::PAYLOAD_TOO_LARGE, "Request body too large\n"));
+        }
+
+        let body = read_body(request_body).await?;
+
+        let action = match parse_led_body(body) {
+            Ok(LedInput::On { .. }) => OutputAction::Set(LedState::On),
+            Ok(LedInput::Off { .. }) => OutputAction::Set(LedState::Off),
+            Ok(_) => OutputAction::Toggle,
+            Err(e) => return Err((StatusCode::BAD_REQUEST, e.message())),
+        };
+        Ok(Self(action))
+    }
+}
+
+// Reads a whole request body into the HTTP buffer.
+//
+// A body which does not fit the buffer left by the headers is an error of the
+// device, not of the client, so it is logged and answered with `500 Internal
+// Server Error` rather than truncated.
+async fn read_body<R: Read>(
+    request_body: RequestBody<'_, R>,
+) -> Result<&mut [u8], (StatusCode, &'static str)> {
+    let content_length = request_body.content_length();
+    request_body.read_all().await.map_err(|e| match e {
+        ReadAllBodyError::BufferIsTooSmall => {
+            log::error!(
+                "Request body of {content_length} bytes does not fit the \
+                 {HTTP_BUFFER_SIZE} bytes HTTP buffer"
+            );
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Request does not fit the server buffer\n",
+            )
+        }
+        ReadAllBodyError::UnexpectedEof | ReadAllBodyError::IO(_) => {
+            (StatusCode::BAD_REQUEST, "Failed to read request body\n")
+        }
+    })
+}
+
+// Led input extracted from a `/led` request body.
+struct LedUpdate(LedInput);
+
+impl<'r, State> FromRequest<'r, State> for LedUpdate {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request<R: Read>(
+        _state: &'r State,
+        _request_parts: RequestParts<'r>,
+        request_body: RequestBody<'r, R>,
+    ) -> Result<Self, Self::Rejection> {
+        if request_body.content_length() > MAX_LED_BODY_SIZE {
+            return Err((StatusCode::PAYLOAD_TOO_LARGE, "Request body too large\n"));
+        }
+
+        let body = read_body(request_body).await?;
+
+        parse_led_body(body)
+            .map(Self)
+            .map_err(|e| (StatusCode::BAD_REQUEST, e.message()))
+    }
+}
+
+// Schedule extracted from a `/schedule` request body.
+struct ScheduleUpdate(Schedule);
+
+impl<'r, State> FromRequest<'r, State> for ScheduleUpdate {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request<R: Read>(
+        _state: &'r State,
+        _request_parts: RequestParts<'r>,
+        request_body: RequestBody<'r, R>,
+    ) -> Result<Self, Self::Rejection> {
+        if request_body.content_length() > MAX_SCHEDULE_BODY_SIZE {
+            return Err((StatusCode::PAYLOAD_TOO_LARGE, "Request body too large\n"));
         }
+
+        let body = read_body(request_body).await?;
+
+        let (schedule, _) = serde_json_core::from_slice::<Schedule>(body).map_err(|_| {
+            (
+                StatusCode::BAD_REQUEST,
+                "Invalid schedule, expected at most 8 entries such as \
+                 `{\"time\":\"18:00\",\"action\":\"on\"}`\n",
+            )
+        })?;
+
+        Ok(Self(schedule))
+    }
+}
+
+// Automations extracted from an `/automation` request body.
+struct AutomationBody(Automation);
+
+impl<'r, State> FromRequest<'r, State> for AutomationBody {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request<R: Read>(
+        _state: &'r State,
+        _request_parts: RequestParts<'r>,
+        request_body: RequestBody<'r, R>,
+    ) -> Result<Self, Self::Rejection> {
+        if request_body.content_length() > MAX_AUTOMATION_BODY_SIZE {
+            return Err((StatusCode::PAYLOAD_TOO_LARGE, "Request body too large\n"));
+        }
+
+        let body = read_body(request_body).await?;
+
+        let (automation, _) = serde_json_core::from_slice::<Automation>(body).map_err(|_| {
+            (
+                StatusCode::BAD_REQUEST,
+                "Invalid automation, expected `{\"motion\":true}`\n",
+            )
+        })?;
+
+        Ok(Self(automation))
+    }
+}
+
+// Log levels extracted from a `/loglevel` request body.
+struct LogLevelBody(LogLevelUpdate);
+
+impl<'r, State> FromRequest<'r, State> for LogLevelBody {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request<R: Read>(
+        _state: &'r State,
+        _request_parts: RequestParts<'r>,
+        request_body: RequestBody<'r, R>,
+    ) -> Result<Self, Self::Rejection> {
+        if request_body.content_length() > MAX_LOG_LEVEL_BODY_SIZE {
+            return Err((StatusCode::PAYLOAD_TOO_LARGE, "Request body too large\n"));
+        }
+
+        let body = read_body(request_body).await?;
+
+        let (update, _) = serde_json_core::from_slice::<LogLevelUpdate>(body).map_err(|_| {
+            (
+                StatusCode::BAD_REQUEST,
+                "Invalid log level, expected a JSON object such as \
+                 `{\"level\":\"debug\",\"filter\":\"esp_wifi=warn\"}`\n",
+            )
+        })?;
+
+        Ok(Self(update))
+    }
+}
+
+// Settings update extracted from a `/config` request body.
+struct ConfigBody(ConfigUpdate);
+
+impl<'r, State> FromRequest<'r, State> for ConfigBody {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request<R: Read>(
+        _state: &'r State,
+        _request_parts: RequestParts<'r>,
+        request_body: RequestBody<'r, R>,
+    ) -> Result<Self, Self::Rejection> {
+        if request_body.content_length() > MAX_CONFIG_BODY_SIZE {
+            return Err((StatusCode::PAYLOAD_TOO_LARGE, "Request body too large\n"));
+        }
+
+        let body = read_body(request_body).await?;
+
+        let (update, _) = serde_json_core::from_slice::<ConfigUpdate>(body).map_err(|_| {
+            (
+                StatusCode::BAD_REQUEST,
+                "Invalid config, expected a JSON object of settings such as \
+                 `{\"hostname\":\"button-led\"}`\n",
+            )
+        })?;
+
+        Ok(Self(update))
+    }
+}
+
+// Response of a `/config` update.
+#[derive(Serialize)]
+struct ConfigChanged {
+    // Settings are applied at boot, so any change needs a reboot.
+    reboot_required: bool,
+}
+
+// Query parameters of the `/blink` route.
+#[derive(Deserialize)]
+struct BlinkQuery {
+    period: Option<u64>,
+}
+
+// Query parameters of the `/color` route.
+#[derive(Deserialize)]
+struct ColorQuery {
+    rgb: Rgb,
+}
+
+// Query parameters of the `/morse` route.
+#[derive(Deserialize)]
+struct MorseQuery {
+    msg: heapless::String<MAX_MORSE_QUERY_LEN>,
+}
+
+// Query parameters of the `/off` route.
+//
+// The `fade` parameter overrides the fade duration in milliseconds, so
+// `fade=0` switches the led at once.
+#[derive(Deserialize)]
+struct FadeQuery {
+    fade: Option<u64>,
+}
+
+// Query parameters of the `/on` route.
+//
+// Besides `fade`, the `duration` parameter turns the led off after the given
+// number of seconds.
+#[derive(Deserialize)]
+struct OnQuery {
+    fade: Option<u64>,
+    duration: Option<u64>,
+}
+
+// Response returned when the led channel is full.
+type LedBusy = (StatusCode, &'static str);
+
+// Sends an input for the main led to the led task.
+fn notify_led(led_input: LedInput) -> Result<(), LedBusy> {
+    notify_channel(MAIN_CHANNEL, led_input)
+}
+
+// Sends an input for a led channel to the led task.
+//
+// When the led channel is full, the input is rejected and the client is asked
+// to retry later with a `503 Service Unavailable` response.
+fn notify_channel(channel: usize, led_input: LedInput) -> Result<(), LedBusy> {
+    NOTIFY_LED
+        .try_send(LedCommand::on_channel(channel, Source::Http, led_input))
+        .map_err(|_| {
+            log::warn!("Led channel is full, input rejected!");
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Led is busy, retry later\n",
+            )
+        })
+}
+
+// Firmware update extracted from a `/update` request body.
+//
+// The image is written to flash while the body is read, so there is nothing
+// left to do but rebooting once the extraction succeeds.
+struct FirmwareUpdate;
+
+impl<'r, State> FromRequest<'r, State> for FirmwareUpdate {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request<R: Read>(
+        _state: &'r State,
+        _request_parts: RequestParts<'r>,
+        request_body: RequestBody<'r, R>,
+    ) -> Result<Self, Self::Rejection> {
+        let content_length = request_body.content_length();
+        ota::update_firmware(request_body.reader(), content_length)
+            .await
+            .map(|()| Self)
+            .map_err(|e| {
+                log::error!("Firmware update failed: {e:?}");
+                e.response()
+            })
+    }
+}
+
+// Query parameters of the `/restart` route.
+//
+// Restarts must be confirmed with `confirm=yes`, so crawlers following links
+// never reboot the device.
+#[derive(Deserialize)]
+struct RestartQuery {
+    confirm: Option<heapless::String<3>>,
+}
+
+// Query parameters of the `/logs` route.
+//
+// `level` only keeps the lines at least as severe, and `clear=1` removes the
+// returned lines from the buffer.
+#[derive(Deserialize)]
+struct LogsQuery {
+    level: Option<heapless::String<5>>,
+    clear: Option<u8>,
+}
+
+// Query parameters of the `/history` route.
+//
+// `limit` keeps only the given number of the newest events.
+#[derive(Deserialize)]
+struct HistoryQuery {
+    limit: Option<usize>,
+}
+
+// Query parameters of the `/brightness` route.
+#[derive(Deserialize)]
+struct BrightnessQuery {
+    level: u8,
+    fade: Option<u64>,
+}
+
+// Routes exempted from rate limiting, so diagnostics work while a client is
+// flooding the device.
+const RATE_LIMIT_EXEMPT_ROUTES: [&str; 2] = ["/health", "/metrics"];
+
+// Document returned by the `/api` route.
+#[derive(Serialize)]
+struct ApiDescription {
+    version: u32,
+    prefix: &'static str,
+    routes: &'static [ApiRoute],
+}
+
+// Client of a connection, the state of the router.
+pub(crate) struct Client {
+    pub(crate) address: Option<IpAddr>,
+    // Web task serving the connection.
+    pub(crate) worker: usize,
+}
+
+// Layer limiting the rate of the requests of each client, answering
+// `429 Too Many Requests` when it is exceeded.
+struct RateLimit;
+
+impl<PathParameters> Layer<Client, PathParameters> for RateLimit {
+    type NextState = Client;
+    type NextPathParameters = PathParameters;
+
+    async fn call_layer<
+        'a,
+        R: Read + 'a,
+        NextLayer: Next<'a, R, Client, PathParameters>,
+        W: ResponseWriter<Error = R::Error>,
+    >(
+        &self,
+        next: NextLayer,
+        client: &Client,
+        path_parameters: PathParameters,
+        request_parts: RequestParts<'_>,
+        response_writer: W,
+    ) -> Result<ResponseSent, W::Error> {
+        let path = request_parts.path();
+        let exempt = RATE_LIMIT_EXEMPT_ROUTES.contains(&unversioned(path.encoded()));
+        let Some(address) = client.address.filter(|_| !exempt) else {
+            return next.run(client, path_parameters, response_writer).await;
+        };
+
+        match rate_limit::check(address) {
+            Ok(()) => next.run(client, path_parameters, response_writer).await,
+            Err(retry_after) => {
+                log::warn!("Request to {path} from {address} rejected, rate limit exceeded");
+                // Clients are told to retry after at least a second.
+                let retry_after_secs = retry_after.as_millis().div_ceil(1000).max(1);
+                let connection = next.into_connection().await?;
+                (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    ("Retry-After", retry_after_secs),
+                    "Too many requests\n",
+                )
+                    .write_to(connection, response_writer)
+                    .await
+            }
+        }
+    }
+}
+
+// Metrics returned by the `/metrics` route in the Prometheus text format.
+//
+// Every line is written as its own chunk, so the response is never built in
+// memory.
+struct PrometheusMetrics;
+
+impl Chunks for PrometheusMetrics {
+    fn content_type(&self) -> &'static str {
+        "text/plain; version=0.0.4"
+    }
+
+    async fn write_chunks<W: Write>(
+        self,
+        mut chunk_writer: ChunkWriter<W>,
+    ) -> Result<ChunksWritten, W::Error> {
+        let metrics = metrics::metrics();
+
+        write!(
+            chunk_writer,
+            "# HELP buttonled_led_state Whether the led is on.\n\
+             # TYPE buttonled_led_state gauge\n"
+        )
+        .await?;
+        for channel in 0..state::led_channels() {
+            write!(
+                chunk_writer,
+                "buttonled_led_state{{channel=\"{channel}\"}} {}\n",
+                u8::from(state::led_state(channel) == LedState::On)
+            )
+            .await?;
+        }
+        write!(
+            chunk_writer,
+            "# HELP buttonled_led_brightness Brightness percentage of the led.\n\
+             # TYPE buttonled_led_brightness gauge\n"
+        )
+        .await?;
+        for channel in 0..state::led_channels() {
+            write!(
+                chunk_writer,
+                "buttonled_led_brightness{{channel=\"{channel}\"}} {}\n",
+                state::led_brightness(channel)
+            )
+            .await?;
+        }
+        write!(
+            chunk_writer,
+            "# HELP buttonled_button_presses_total Button presses since boot.\n\
+             # TYPE buttonled_button_presses_total counter\n\
+             buttonled_button_presses_total {}\n",
+            metrics.button_presses
+        )
+        .await?;
+        write!(
+            chunk_writer,
+            "# HELP buttonled_motion_events_total Motions detected since boot.\n\
+             # TYPE buttonled_motion_events_total counter\n\
+             buttonled_motion_events_total {}\n",
+            metrics.motion_events
+        )
+        .await?;
+
+        chunk_writer
+            .write_chunk(
+                b"# HELP buttonled_http_requests_total HTTP requests since boot.\n\
+                  # TYPE buttonled_http_requests_total counter\n",
+            )
+            .await?;
+        for (route, requests) in metrics.http_requests() {
+            writeln!(
+                chunk_writer,
+                "buttonled_http_requests_total{{route=\"{route}\"}} {requests}"
+            )
+            .await?;
+        }
+
+        chunk_writer
+            .write_chunk(
+                b"# HELP buttonled_http_request_milliseconds_total Time spent serving HTTP requests since boot.\n\
+                  # TYPE buttonled_http_request_milliseconds_total counter\n",
+            )
+            .await?;
+        for (route, elapsed_ms) in metrics.http_request_ms() {
+            writeln!(
+                chunk_writer,
+                "buttonled_http_request_milliseconds_total{{route=\"{route}\"}} {elapsed_ms}"
+            )
+            .await?;
+        }
+
+        chunk_writer
+            .write_chunk(
+                b"# HELP buttonled_http_request_duration_milliseconds Time spent serving each HTTP request.\n\
+                  # TYPE buttonled_http_request_duration_milliseconds histogram\n",
+            )
+            .await?;
+        for latency in metrics::latencies() {
+            let route = latency.route;
+            let cumulative = latency.cumulative();
+            for (bound_ms, requests) in LATENCY_BOUNDS_MS.iter().zip(cumulative) {
+                writeln!(
+                    chunk_writer,
+                    "buttonled_http_request_duration_milliseconds_bucket{{route=\"{route}\",le=\"{bound_ms}\"}} {requests}"
+                )
+                .await?;
+            }
+            write!(
+                chunk_writer,
+                "buttonled_http_request_duration_milliseconds_bucket{{route=\"{route}\",le=\"+Inf\"}} {}\n\
+                 buttonled_http_request_duration_milliseconds_sum{{route=\"{route}\"}} {}\n\
+                 buttonled_http_request_duration_milliseconds_count{{route=\"{route}\"}} {}\n",
+                latency.count(),
+                latency.total_ms,
+                latency.count()
+            )
+            .await?;
+        }
+
+        write!(
+            chunk_writer,
+            "# HELP buttonled_wifi_reconnects_total Wi-Fi reconnections since boot.\n\
+             # TYPE buttonled_wifi_reconnects_total counter\n\
+             buttonled_wifi_reconnects_total {}\n",
+            metrics.wifi_reconnects
+        )
+        .await?;
+        write!(
+            chunk_writer,
+            "# HELP buttonled_syslog_dropped_total Log messages not sent to the syslog server.\n\
+             # TYPE buttonled_syslog_dropped_total counter\n\
+             buttonled_syslog_dropped_total {}\n",
+            metrics.syslog_drops
+        )
+        .await?;
+        write!(
+            chunk_writer,
+            "# HELP buttonled_udp_dropped_total Malformed or unauthenticated UDP commands.\n\
+             # TYPE buttonled_udp_dropped_total counter\n\
+             buttonled_udp_dropped_total {}\n",
+            metrics.udp_drops
+        )
+        .await?;
+        write!(
+            chunk_writer,
+            "# HELP buttonled_webhook_dropped_total Events not sent to the webhook.\n\
+             # TYPE buttonled_webhook_dropped_total counter\n\
+             buttonled_webhook_dropped_total {}\n",
+            metrics.webhook_drops
+        )
+        .await?;
+        write!(
+            chunk_writer,
+            "# HELP buttonled_http_saturated_total Connections refused because every web task was busy.\n\
+             # TYPE buttonled_http_saturated_total counter\n\
+             buttonled_http_saturated_total {}\n",
+            metrics.http_saturation_refusals
+        )
+        .await?;
+        write!(
+            chunk_writer,
+            "# HELP buttonled_led_commands_coalesced_total Led commands dropped for a more recent one within the minimum interval.\n\
+             # TYPE buttonled_led_commands_coalesced_total counter\n\
+             buttonled_led_commands_coalesced_total {}\n",
+            metrics.led_commands_coalesced
+        )
+        .await?;
+        // The boot counters are missing when flash cannot be read.
+        if let Some(boots) = boot_count::boot_counts() {
+            write!(
+                chunk_writer,
+                "# HELP buttonled_boots_total Boots since the counters were first stored.\n\
+                 # TYPE buttonled_boots_total counter\n\
+                 buttonled_boots_total {}\n\
+                 # HELP buttonled_boot_resets_total Boots caused by watchdogs, panics and firmware updates.\n\
+                 # TYPE buttonled_boot_resets_total counter\n\
+                 buttonled_boot_resets_total{{reason=\"watchdog\"}} {}\n\
+                 buttonled_boot_resets_total{{reason=\"panic\"}} {}\n\
+                 buttonled_boot_resets_total{{reason=\"update\"}} {}\n",
+                boots.total, boots.watchdog, boots.panic, boots.update
+            )
+            .await?;
+        }
+        write!(
+            chunk_writer,
+            "# HELP buttonled_uptime_seconds Time since boot.\n\
+             # TYPE buttonled_uptime_seconds gauge\n\
+             buttonled_uptime_seconds {}\n",
+            Instant::now().as_secs()
+        )
+        .await?;
+        write!(
+            chunk_writer,
+            "# HELP buttonled_heap_free_bytes Free heap memory.\n\
+             # TYPE buttonled_heap_free_bytes gauge\n\
+             buttonled_heap_free_bytes {}\n",
+            esp_alloc::HEAP.free()
+        )
+        .await?;
+        write!(
+            chunk_writer,
+            "# HELP buttonled_heap_high_watermark_bytes Largest heap usage since boot.\n\
+             # TYPE buttonled_heap_high_watermark_bytes gauge\n\
+             buttonled_heap_high_watermark_bytes {}\n",
+            heap::high_watermark()
+        )
+        .await?;
+
+        // The temperature gauge is missing while the sensor is unavailable.
+        if let Some(celsius) = temperature::celsius() {
+            write!(
+                chunk_writer,
+                "# HELP buttonled_temperature_celsius Chip temperature.\n\
+                 # TYPE buttonled_temperature_celsius gauge\n\
+                 buttonled_temperature_celsius {celsius:.1}\n"
+            )
+            .await?;
+        }
+
+        // The analog sensor is sampled on every scrape, and its gauges are
+        // missing when it is not configured.
+        if let Some(reading) = adc::read().await {
+            write!(
+                chunk_writer,
+                "# HELP buttonled_adc_raw Raw reading of the analog sensor.\n\
+                 # TYPE buttonled_adc_raw gauge\n\
+                 buttonled_adc_raw {}\n\
+                 # HELP buttonled_adc_millivolts Voltage of the analog sensor.\n\
+                 # TYPE buttonled_adc_millivolts gauge\n\
+                 buttonled_adc_millivolts {}\n",
+                reading.raw, reading.millivolts
+            )
+            .await?;
+        }
+
+        // Signal strength gauges are missing until sampled.
+        if let Some(rssi) = state::wifi_rssi() {
+            write!(
+                chunk_writer,
+                "# HELP buttonled_wifi_rssi_dbm Wi-Fi signal strength.\n\
+                 # TYPE buttonled_wifi_rssi_dbm gauge\n\
+                 buttonled_wifi_rssi_dbm {rssi}\n"
+            )
+            .await?;
+        }
+        if let Some(range) = state::wifi_rssi_range() {
+            write!(
+                chunk_writer,
+                "# HELP buttonled_wifi_rssi_min_dbm Weakest Wi-Fi signal since boot.\n\
+                 # TYPE buttonled_wifi_rssi_min_dbm gauge\n\
+                 buttonled_wifi_rssi_min_dbm {}\n\
+                 # HELP buttonled_wifi_rssi_max_dbm Strongest Wi-Fi signal since boot.\n\
+                 # TYPE buttonled_wifi_rssi_max_dbm gauge\n\
+                 buttonled_wifi_rssi_max_dbm {}\n",
+                range.min, range.max
+            )
+            .await?;
+        }
+
+        chunk_writer.finalize().await
+    }
+}
+
+// Request counts and latencies of the routes which were requested, returned
+// by the `/stats` route as a table.
+struct RequestStats;
+
+impl Chunks for RequestStats {
+    fn content_type(&self) -> &'static str {
+        "text/plain"
+    }
+
+    async fn write_chunks<W: Write>(
+        self,
+        mut chunk_writer: ChunkWriter<W>,
+    ) -> Result<ChunksWritten, W::Error> {
+        write!(
+            chunk_writer,
+            "{:<16} {:>8} {:>8}",
+            "route", "requests", "avg ms"
+        )
+        .await?;
+        // Labels are formatted apart, so they are padded like the counts.
+        let mut label = heapless::String::<16>::new();
+        let mut lower_ms = 0;
+        for bound_ms in LATENCY_BOUNDS_MS {
+            label.clear();
+            let _ = write!(label, "{lower_ms}-{bound_ms}ms");
+            write!(chunk_writer, " {label:>10}").await?;
+            lower_ms = bound_ms;
+        }
+        label.clear();
+        let _ = write!(label, ">={lower_ms}ms");
+        writeln!(chunk_writer, " {label:>10}").await?;
+
+        for latency in metrics::latencies() {
+            let count = latency.count();
+            if count == 0 {
+                continue;
+            }
+            write!(
+                chunk_writer,
+                "{:<16} {count:>8} {:>8}",
+                latency.route,
+                latency.total_ms / count
+            )
+            .await?;
+            for requests in latency.buckets {
+                write!(chunk_writer, " {requests:>10}").await?;
+            }
+            chunk_writer.write_chunk(b"\n").await?;
+        }
+
+        chunk_writer.finalize().await
+    }
+}
+
+// Log lines streamed oldest-first by the `/logs` route.
+struct LogLines {
+    max_level: Level,
+    clear: bool,
+}
+
+impl Chunks for LogLines {
+    fn content_type(&self) -> &'static str {
+        "text/plain"
+    }
+
+    async fn write_chunks<W: Write>(
+        self,
+        mut chunk_writer: ChunkWriter<W>,
+    ) -> Result<ChunksWritten, W::Error> {
+        // Lines are copied one at a time, so lines logged while streaming are
+        // returned too.
+        let mut line = [0; log_buffer::LINE_SIZE];
+        let mut cursor = LogCursor::new();
+        let mut seq = 0;
+        while let Some(read) = log_buffer::read(&mut cursor, &mut line) {
+            seq = read.seq.wrapping_add(1);
+            if read.level <= self.max_level {
+                chunk_writer.write_chunk(&line[..read.len]).await?;
+                chunk_writer.write_chunk(b"\n").await?;
+            }
+        }
+
+        if self.clear {
+            log_buffer::clear_until(seq);
+        }
+
+        chunk_writer.finalize().await
+    }
+}
+
+// New state of the led, returned by the `/on`, `/off` and `/toggle` routes
+// under the API prefix.
+#[derive(Serialize)]
+struct LedChanged {
+    led: LedState,
+    // Time before the change is applied, when the throttle or a relay
+    // switched too recently holds it back.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delay_ms: Option<u64>,
+}
+
+// Led channel with the given id, or `404 Not Found` when it does not exist.
+fn led_channel(id: usize) -> Result<usize, (StatusCode, &'static str)> {
+    if id < state::led_channels() {
+        Ok(id)
+    } else {
+        Err((StatusCode::NOT_FOUND, "No such led\n"))
+    }
+}
+
+// State of a led channel, returned by the `/led/<id>` route.
+#[derive(Serialize)]
+struct LedChannelStatus {
+    channel: usize,
+    led: LedState,
+    brightness: u8,
+    auto_off_secs: Option<u64>,
+}
+
+impl LedChannelStatus {
+    fn current(channel: usize) -> Self {
+        Self {
+            channel,
+            led: state::led_state(channel),
+            brightness: state::led_brightness(channel),
+            auto_off_secs: auto_off_secs(channel),
+        }
+    }
+}
+
+// Seconds left before a led channel turns off by itself, if it does.
+fn auto_off_secs(channel: usize) -> Option<u64> {
+    state::auto_off_at(channel)
+        .map(|deadline| deadline.saturating_duration_since(Instant::now()).as_secs())
+}
+
+// Turns a led channel on, for the `/on` route and its aliases.
+async fn turn_on(channel: usize, OnQuery { fade, duration }: OnQuery) -> Result<(), LedBusy> {
+    if duration.is_some_and(|secs| secs > MAX_AUTO_OFF_SECS) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Duration must be at most 86400 seconds\n",
+        ));
+    }
+
+    // Notify led to turn led on.
+    notify_channel(
+        channel,
+        LedInput::On {
+            fade_ms: fade.map(|fade_ms| fade_ms.min(MAX_FADE_MS)),
+            auto_off_secs: duration,
+        },
+    )?;
+
+    log::info!("Led {channel} turned on through GET route!");
+
+    // Wait for some time before starting the loop again.
+    Timer::after_millis(MILLISECONDS_TO_WAIT).await;
+
+    Ok(())
+}
+
+// Turns a led channel off, for the `/off` route and its aliases.
+async fn turn_off(channel: usize, FadeQuery { fade }: FadeQuery) -> Result<(), LedBusy> {
+    // Notify led to turn led off.
+    notify_channel(
+        channel,
+        LedInput::Off {
+            fade_ms: fade.map(|fade_ms| fade_ms.min(MAX_FADE_MS)),
+        },
+    )?;
+    log::info!("Led {channel} turned off through GET route!");
+
+    // Wait for some time before starting the loop again.
+    Timer::after_millis(MILLISECONDS_TO_WAIT).await;
+
+    Ok(())
+}
+
+// Toggles a led channel, for the `/toggle` route and its aliases, returning
+// the resulting state and the time before it is applied, if the throttle or
+// a relay holds the toggle back.
+async fn toggle(channel: usize) -> Result<(LedState, Option<Duration>), LedBusy> {
+    // A relay, only wired in place of the main led, switched too recently
+    // rejects the toggle when configured so, which is reported to the client.
+    if channel == MAIN_CHANNEL
+        && relay::is_enabled()
+        && relay::rejects_early_switches()
+        && relay::switch_delay().is_some()
+    {
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            "Led switched too recently, retry later\n",
+        ));
+    }
+
+    // Notify led to switch its state.
+    notify_channel(channel, LedInput::Toggle)?;
+
+    log::info!("Led {channel} toggled through GET route!");
+
+    // Wait for some time before starting the loop again.
+    Timer::after_millis(MILLISECONDS_TO_WAIT).await;
+
+    // The led task has handled the toggle meanwhile, either applying it or
+    // holding it back, as the absolute state it amounts to.
+    let result = match state::pending_command(channel) {
+        Some(pending) => (
+            pending
+                .led_state
+                .unwrap_or_else(|| state::led_state(channel)),
+            Some(pending.at.saturating_duration_since(Instant::now())),
+        ),
+        None => (state::led_state(channel), None),
     };
+    Ok(result)
 }
 
-pub(crate) struct AppProps;
+// Paths and methods of the routes registered by `build_app` and
+// `json_routes`, checked against the API description when building, so a
+// route added to the router and to the description must be listed here too.
+// The routes under the API prefix share the paths of their bare aliases.
+const ROUTES: [Route; 45] = [
+    ("/", "GET"),
+    ("/on", "GET"),
+    ("/off", "GET"),
+    ("/toggle", "GET"),
+    ("/blink", "GET"),
+    ("/color", "GET"),
+    ("/morse", "GET"),
+    ("/brightness", "GET"),
+    ("/button/reset", "POST"),
+    ("/setup", "GET"),
+    ("/setup", "POST"),
+    ("/update", "POST"),
+    ("/restart", "POST"),
+    ("/sleep", "POST"),
+    ("/lastpanic", "GET"),
+    ("/lastpanic", "DELETE"),
+    ("/logs", "GET"),
+    ("/metrics", "GET"),
+    ("/stats", "GET"),
+    ("/events", "GET"),
+    ("/led", "POST"),
+    ("/led/{id}", "GET"),
+    ("/led/{id}/on", "GET"),
+    ("/led/{id}/off", "GET"),
+    ("/led/{id}/toggle", "GET"),
+    ("/gpio", "GET"),
+    ("/gpio/{name}", "GET"),
+    ("/gpio/{name}", "POST"),
+    ("/button", "GET"),
+    ("/history", "GET"),
+    ("/config", "GET"),
+    ("/config", "POST"),
+    ("/schedule", "GET"),
+    ("/schedule", "POST"),
+    ("/automation", "GET"),
+    ("/automation", "POST"),
+    ("/health", "GET"),
+    ("/temperature", "GET"),
+    ("/adc", "GET"),
+    ("/touch", "GET"),
+    ("/info", "GET"),
+    ("/loglevel", "GET"),
+    ("/loglevel", "POST"),
+    ("/api", "GET"),
+    ("/status", "GET"),
+];
 
-impl AppBuilder for AppProps {
-    type PathRouter = impl PathRouter;
+const _: () = check_routes(&ROUTES);
 
-    fn build_app(self) -> Router<Self::PathRouter> {
-        Router::new()
+pub(crate) struct AppProps {
+    // Whether the device is waiting to be provisioned.
+    pub(crate) provisioning: bool,
+}
+
+impl AppWithStateBuilder for AppProps {
+    type State = Client;
+    type PathRouter = impl PathRouter<Client>;
+
+    fn build_app(self) -> Router<Self::PathRouter, Client> {
+        // Routes served only under the API prefix, answering JSON where
+        // their bare aliases answer plain text.
+        let versioned = Router::new()
             .route(
-                "/on",
-                get(|| async move {
-                    // Notify led to turn led on.
-                    NOTIFY_LED.signal(LedInput::On);
+                const { documented("/on") },
+                get(|_: Authorized, Query(query): Query<OnQuery>| async move {
+                    turn_on(MAIN_CHANNEL, query).await?;
+                    Ok::<_, LedBusy>(Json(LedChanged {
+                        led: LedState::On,
+                        delay_ms: None,
+                    }))
+                }),
+            )
+            .route(
+                const { documented("/off") },
+                get(|_: Authorized, Query(query): Query<FadeQuery>| async move {
+                    turn_off(MAIN_CHANNEL, query).await?;
+                    Ok::<_, LedBusy>(Json(LedChanged {
+                        led: LedState::Off,
+                        delay_ms: None,
+                    }))
+                }),
+            )
+            .route(
+                const { documented("/toggle") },
+                get(|_: Authorized| async move {
+                    let (led, delay) = toggle(MAIN_CHANNEL).await?;
+                    let status = if delay.is_some() {
+                        StatusCode::ACCEPTED
+                    } else {
+                        StatusCode::OK
+                    };
+                    Ok::<_, LedBusy>((
+                        status,
+                        Json(LedChanged {
+                            led,
+                            delay_ms: delay.map(|delay| delay.as_millis()),
+                        }),
+                    ))
+                }),
+            );
 
-                    log::info!("Led turned on through GET route!");
+        let router = Router::new()
+            .route(
+                const { documented("/") },
+                get_service(File::html(INDEX_PAGE)),
+            )
+            .route(
+                const { documented("/on") },
+                get(|_: Authorized, Query(query): Query<OnQuery>| turn_on(MAIN_CHANNEL, query)),
+            )
+            .route(
+                const { documented("/off") },
+                get(|_: Authorized, Query(query): Query<FadeQuery>| turn_off(MAIN_CHANNEL, query)),
+            )
+            .route(
+                const { documented("/toggle") },
+                get(|_: Authorized| async move {
+                    let (led_state, delay) = toggle(MAIN_CHANNEL).await?;
 
-                    // Wait for some time before starting the loop again.
-                    Timer::after_millis(MILLISECONDS_TO_WAIT).await;
+                    let mut body = heapless::String::<32>::new();
+                    // The body is large enough for the state and the delay.
+                    let status = match delay {
+                        Some(delay) => {
+                            let _ = write!(
+                                body,
+                                "{} in {} ms",
+                                led_state.as_str(),
+                                delay.as_millis()
+                            );
+                            StatusCode::ACCEPTED
+                        }
+                        None => {
+                            let _ = write!(body, "{}", led_state.as_str());
+                            StatusCode::OK
+                        }
+                    };
+                    Ok::<_, LedBusy>((status, body))
                 }),
             )
             .route(
-                "/off",
-                get(|| async move {
-                    // Notify led to turn led off.
-                    NOTIFY_LED.signal(LedInput::Off);
+                const { documented("/blink") },
+                get(
+                    |_: Authorized, Query(BlinkQuery { period }): Query<BlinkQuery>| async move {
+                        // Use the default period when the `period` query
+                        // parameter is missing.
+                        let period_ms = period
+                            .unwrap_or(DEFAULT_BLINK_PERIOD_MS)
+                            .clamp(MIN_BLINK_PERIOD_MS, MAX_BLINK_PERIOD_MS);
+
+                        // Notify led to start blinking.
+                        notify_led(LedInput::Blink { period_ms })?;
+
+                        log::info!("Led blinking through GET route!");
+
+                        // Wait for some time before starting the loop again.
+                        Timer::after_millis(MILLISECONDS_TO_WAIT).await;
 
-                    log::info!("Led turned off through GET route!");
+                        Ok::<_, LedBusy>(())
+                    },
+                ),
+            )
+            .route(
+                const { documented("/color") },
+                get(
+                    |_: Authorized, Query(ColorQuery { rgb }): Query<ColorQuery>| async move {
+                        if !led::supports_color() {
+                            return Err((
+                                StatusCode::NOT_IMPLEMENTED,
+                                "Colors need an addressable led\n",
+                            ));
+                        }
+
+                        // Notify led to change its color.
+                        notify_led(LedInput::Color(rgb))?;
+
+                        log::info!("Led color set to {rgb} through GET route!");
+
+                        Ok(())
+                    },
+                ),
+            )
+            .route(
+                const { documented("/morse") },
+                get(
+                    |_: Authorized, Query(MorseQuery { msg }): Query<MorseQuery>| async move {
+                        let message = MorseMessage::parse(&msg).map_err(|e| {
+                            let mut body = heapless::String::<128>::new();
+                            // Error messages always fit the string.
+                            let _ = writeln!(body, "{e}");
+                            (StatusCode::BAD_REQUEST, body)
+                        })?;
+
+                        // Notify led to play the message, replacing the one
+                        // being played.
+                        notify_led(LedInput::Morse(message)).map_err(|(status, message)| {
+                            (status, heapless::String::try_from(message).unwrap_or_default())
+                        })?;
+
+                        log::info!("Led playing Morse message {msg:?} through GET route!");
+
+                        Ok::<_, (StatusCode, heapless::String<128>)>(())
+                    },
+                ),
+            )
+            .route(
+                const { documented("/brightness") },
+                get(
+                    |_: Authorized, Query(BrightnessQuery { level, fade }): Query<BrightnessQuery>| async move {
+                        if level > MAX_BRIGHTNESS {
+                            return Err((
+                                StatusCode::BAD_REQUEST,
+                                "Brightness level must be between 0 and 100\n",
+                            ));
+                        }
+
+                        // Notify led to change its brightness.
+                        notify_led(LedInput::Brightness {
+                            level,
+                            fade_ms: fade.map(|fade_ms| fade_ms.min(MAX_FADE_MS)),
+                        })?;
+
+                        log::info!("Led brightness changed through GET route!");
+
+                        // Wait for some time before starting the loop again.
+                        Timer::after_millis(MILLISECONDS_TO_WAIT).await;
+
+                        Ok(())
+                    },
+                ),
+            )
+            .route(
+                const { documented("/button/reset") },
+                post(|_: Authorized| async move {
+                    metrics::reset_button_presses();
+                    log::info!("Button presses counter reset through POST route!");
+                }),
+            )
+            .route(
+                const { documented("/setup") },
+                get_service(File::html(SETUP_PAGE)).post(
+                    |_: Authorized, Form(update): Form<SettingsUpdate>| async move {
+                        let _lock = settings::lock().await;
+                        let mut settings = settings::load_settings();
+                        settings.update(update);
+
+                        if settings.ssid.is_empty() {
+                            return Err((StatusCode::BAD_REQUEST, "Missing Wi-Fi SSID\n"));
+                        }
 
-                    // Wait for some time before starting the loop again.
-                    Timer::after_millis(MILLISECONDS_TO_WAIT).await;
+                        settings::store_settings(&settings).map_err(|e| {
+                            log::error!("Failed to store settings: {e:?}");
+                            (
+                                StatusCode::INTERNAL_SERVER_ERROR,
+                                "Failed to store settings\n",
+                            )
+                        })?;
+
+                        log::info!("Settings stored through POST route!");
+
+                        // Reboot to apply the new settings.
+                        REBOOT.signal("settings updated");
+
+                        Ok("Settings stored, rebooting...\n")
+                    },
+                ),
+            )
+            .route(
+                const { documented("/update") },
+                post(|_: Authorized, FirmwareUpdate| async move {
+                    log::info!("Firmware updated through POST route!");
+                    REBOOT.signal("firmware updated");
+
+                    "Firmware updated, rebooting...\n"
+                }),
+            )
+            .route(
+                const { documented("/restart") },
+                post(
+                    |_: Authorized, Query(RestartQuery { confirm }): Query<RestartQuery>| async move {
+                        if confirm.as_deref() != Some("yes") {
+                            return Err((
+                                StatusCode::BAD_REQUEST,
+                                "Restart must be confirmed with `confirm=yes`\n",
+                            ));
+                        }
+
+                        log::info!("Restart requested through POST route!");
+                        REBOOT.signal("restart requested");
+
+                        Ok("Restarting...\n")
+                    },
+                ),
+            )
+            .route(
+                const { documented("/sleep") },
+                post(|_: Authorized| async move {
+                    if !sleep::request() {
+                        return Err((
+                            StatusCode::CONFLICT,
+                            "Deep sleep needs the button on a GPIO from 0 to 5 to wake up\n",
+                        ));
+                    }
+
+                    log::info!("Deep sleep requested through POST route!");
+                    Ok("Going to sleep, press the button to wake up...\n")
                 }),
             )
+            .route(
+                const { documented("/lastpanic") },
+                get(|| async move {
+                    let mut body = heapless::String::<{ last_panic::MAX_PANIC_LEN + 1 }>::new();
+                    // The body is large enough for the panic and a newline.
+                    let _ = match last_panic::last_panic() {
+                        Some(text) => writeln!(body, "{text}"),
+                        None => writeln!(body, "none"),
+                    };
+                    body
+                })
+                .delete(|_: Authorized| async move {
+                    last_panic::clear();
+                    log::info!("Last panic cleared through DELETE route!");
+                    StatusCode::NO_CONTENT
+                }),
+            )
+            .route(
+                const { documented("/logs") },
+                get(
+                    |ApiKey { authorized }: ApiKey,
+                     Query(LogsQuery { level, clear }): Query<LogsQuery>| async move {
+                        // Clearing the logs requires the API key, unlike reading them.
+                        let clear = clear == Some(1);
+                        if clear && !authorized {
+                            return Err((
+                                StatusCode::UNAUTHORIZED,
+                                "Missing or invalid API key\n",
+                            ));
+                        }
+
+                        let max_level = match level.as_deref().map(str::parse) {
+                            None => Level::Trace,
+                            Some(Ok(level)) => level,
+                            Some(Err(_)) => {
+                                return Err((
+                                    StatusCode::BAD_REQUEST,
+                                    "Unknown level, expected `error`, `warn`, `info`, `debug` or \
+                                     `trace`\n",
+                                ));
+                            }
+                        };
+
+                        Ok(ChunkedResponse::new(LogLines {
+                            max_level,
+                            clear,
+                        }))
+                    },
+                ),
+            )
+            .route(
+                const { documented("/metrics") },
+                get(|| async move { ChunkedResponse::new(PrometheusMetrics) }),
+            )
+            .route(
+                const { documented("/stats") },
+                get(|| async move { ChunkedResponse::new(RequestStats) }),
+            )
+            .route(
+                const { documented("/events") },
+                get(|| async move {
+                    EventStream::open().map(sse::EventStream).ok_or((
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        "Too many event streams open or low memory\n",
+                    ))
+                }),
+            );
+
+        // The JSON routes are served both under the API prefix and bare, so
+        // the clients predating the prefix keep working.
+        json_routes(router)
+            .nest(API_PREFIX, json_routes(versioned))
+            .layer(BasicAuth::new(self.provisioning))
+            .layer(RateLimit)
+            .layer(Cors::new())
+            .layer(ApiVersion)
+            .layer(LogRequests)
+            .layer(TrackRequests)
     }
 }
 
-pub(crate) async fn run_server<const WEB_TASK_POOL_SIZE: usize>(
+// Adds the routes answering JSON, shared by the bare routes and the ones under
+// the API prefix.
+fn json_routes(
+    router: Router<impl PathRouter<Client>, Client>,
+) -> Router<impl PathRouter<Client>, Client> {
+    router
+        .route(
+            const { documented("/led") },
+            post(|_: Authorized, LedUpdate(led_input)| async move {
+                // Notify led to change its state.
+                notify_led(led_input)?;
+
+                log::info!("Led changed through POST route!");
+
+                // Wait for some time before starting the loop again.
+                Timer::after_millis(MILLISECONDS_TO_WAIT).await;
+
+                Ok::<_, LedBusy>(())
+            }),
+        )
+        .route(
+            (const { documented("/led") }, parse_path_segment::<usize>()),
+            get(|id: usize| async move {
+                led_channel(id).map(|channel| Json(LedChannelStatus::current(channel)))
+            }),
+        )
+        .route(
+            (
+                const { documented("/led") },
+                parse_path_segment::<usize>(),
+                const { documented("/on") },
+            ),
+            get(
+                |id: usize, _: Authorized, Query(query): Query<OnQuery>| async move {
+                    let channel = led_channel(id)?;
+                    turn_on(channel, query).await?;
+                    Ok::<_, LedBusy>(Json(LedChanged {
+                        led: LedState::On,
+                        delay_ms: None,
+                    }))
+                },
+            ),
+        )
+        .route(
+            (
+                const { documented("/led") },
+                parse_path_segment::<usize>(),
+                const { documented("/off") },
+            ),
+            get(
+                |id: usize, _: Authorized, Query(query): Query<FadeQuery>| async move {
+                    let channel = led_channel(id)?;
+                    turn_off(channel, query).await?;
+                    Ok::<_, LedBusy>(Json(LedChanged {
+                        led: LedState::Off,
+                        delay_ms: None,
+                    }))
+                },
+            ),
+        )
+        .route(
+            (
+                const { documented("/led") },
+                parse_path_segment::<usize>(),
+                const { documented("/toggle") },
+            ),
+            get(|id: usize, _: Authorized| async move {
+                let (led, delay) = toggle(led_channel(id)?).await?;
+                let status = if delay.is_some() {
+                    StatusCode::ACCEPTED
+                } else {
+                    StatusCode::OK
+                };
+                Ok::<_, LedBusy>((
+                    status,
+                    Json(LedChanged {
+                        led,
+                        delay_ms: delay.map(|delay| delay.as_millis()),
+                    }),
+                ))
+            }),
+        )
+        .route(
+            const { documented("/gpio") },
+            get(|| async move { Json(outputs::states()) }),
+        )
+        .route(
+            (
+                const { documented("/gpio") },
+                parse_path_segment::<heapless::String<MAX_OUTPUT_NAME_LEN>>(),
+            ),
+            get(|name: heapless::String<MAX_OUTPUT_NAME_LEN>| async move {
+                find_output(&name).map(|(_, output)| Json(output))
+            })
+            .post(
+                |name: heapless::String<MAX_OUTPUT_NAME_LEN>,
+                 _: Authorized,
+                 OutputCommand(action)| async move {
+                    let (index, mut output) = find_output(&name)?;
+                    if !outputs::request(index, action) {
+                        log::warn!("Output channel is full, change rejected!");
+                        return Err((
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "Output is busy, retry later\n",
+                        ));
+                    }
+
+                    // The outputs task has not applied the change yet, so
+                    // the resulting state is computed here.
+                    output.state = match action {
+                        OutputAction::Set(led_state) => led_state,
+                        OutputAction::Toggle => output.state.toggled(),
+                    };
+                    log::info!("Output {name} changed through POST route!");
+
+                    Ok(Json(output))
+                },
+            ),
+        )
+        .route(
+            const { documented("/button") },
+            get(|| async move {
+                let metrics = metrics::metrics();
+                Json(ButtonStats {
+                    count: metrics.button_presses,
+                    last_press_ms_ago: metrics
+                        .last_press
+                        .map(|last_press| last_press.elapsed().as_millis()),
+                })
+            }),
+        )
+        .route(
+            const { documented("/history") },
+            get(
+                |Query(HistoryQuery { limit }): Query<HistoryQuery>| async move {
+                    Json(history::recent(limit.unwrap_or(HISTORY_SIZE)))
+                },
+            ),
+        )
+        .route(
+            const { documented("/config") },
+            get(|_: Authorized| async move { Json(settings::load_settings().into_config()) }).post(
+                |_: Authorized, ConfigBody(update)| async move {
+                    let _lock = settings::lock().await;
+                    let mut settings = settings::load_settings();
+                    let changed = settings.apply(update, state::led_channels()).map_err(|e| {
+                        let mut message = heapless::String::<128>::new();
+                        // The message is short, so it always fits.
+                        let _ = writeln!(message, "{e}");
+                        (StatusCode::BAD_REQUEST, message)
+                    })?;
+
+                    if changed {
+                        settings::store_settings(&settings).map_err(|e| {
+                            log::error!("Failed to store settings: {e:?}");
+                            (
+                                StatusCode::INTERNAL_SERVER_ERROR,
+                                heapless::String::try_from("Failed to store settings\n")
+                                    .unwrap_or_default(),
+                            )
+                        })?;
+                        // Muting the buzzer, the quiet hours and the button
+                        // actions do not wait for the reboot.
+                        buzzer::set_muted(settings.buzzer_muted);
+                        quiet_hours::set_quiet_hours(settings.quiet_hours);
+                        button_actions::set_button_actions(settings.button_actions);
+                        log::info!("Settings changed through POST route!");
+                    }
+
+                    Ok::<_, (StatusCode, heapless::String<128>)>(Json(ConfigChanged {
+                        reboot_required: changed,
+                    }))
+                },
+            ),
+        )
+        .route(
+            const { documented("/schedule") },
+            get(|| async move { Json(schedule::schedule()) }).post(
+                |_: Authorized, ScheduleUpdate(schedule)| async move {
+                    let _lock = settings::lock().await;
+                    let mut settings = settings::load_settings();
+                    settings.schedule = schedule.clone();
+                    settings::store_settings(&settings).map_err(|e| {
+                        log::error!("Failed to store schedule: {e:?}");
+                        (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            "Failed to store schedule\n",
+                        )
+                    })?;
+
+                    schedule::set_schedule(schedule);
+                    log::info!("Schedule changed through POST route!");
+
+                    Ok::<_, (StatusCode, &str)>("Schedule stored\n")
+                },
+            ),
+        )
+        .route(
+            const { documented("/automation") },
+            get(|| async move {
+                Json(Automation {
+                    motion: motion::is_enabled(),
+                })
+            })
+            .post(|_: Authorized, AutomationBody(automation)| async move {
+                motion::set_enabled(automation.motion);
+                log::info!(
+                    "Motion automation {} through POST route!",
+                    if automation.motion {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    }
+                );
+
+                Json(automation)
+            }),
+        )
+        .route(
+            const { documented("/health") },
+            get(|| async move {
+                Json(Health {
+                    uptime_ms: Instant::now().as_millis(),
+                    heap_free: esp_alloc::HEAP.free(),
+                    heap_used: esp_alloc::HEAP.used(),
+                    heap_high_watermark: heap::high_watermark(),
+                    heap_warning: heap::near_miss(),
+                    wifi: wifi_state_str(esp_wifi::wifi::wifi_state()),
+                    rssi: state::wifi_rssi(),
+                    wifi_reconnects: metrics::metrics().wifi_reconnects,
+                    time_sync_age_secs: sntp::last_sync_age().map(|age| age.as_secs()),
+                    web_tasks: web_pool::status(),
+                })
+            }),
+        )
+        .route(
+            const { documented("/temperature") },
+            get(|| async move {
+                temperature::celsius()
+                    .map(|celsius| Json(Temperature { celsius }))
+                    .ok_or((StatusCode::SERVICE_UNAVAILABLE, "Temperature unavailable\n"))
+            }),
+        )
+        .route(
+            const { documented("/adc") },
+            get(|| async move {
+                adc::read()
+                    .await
+                    .map(Json)
+                    .ok_or((StatusCode::NOT_IMPLEMENTED, "No analog sensor configured\n"))
+            }),
+        )
+        .route(
+            const { documented("/touch") },
+            get(|| async move {
+                touch::reading()
+                    .map(Json)
+                    .ok_or((StatusCode::NOT_IMPLEMENTED, "No touch pad configured\n"))
+            }),
+        )
+        .route(
+            const { documented("/info") },
+            get(|| async move {
+                let running_image = ota::running_image();
+                Json(Info {
+                    name: ESP_APP_DESC.project_name(),
+                    version: ESP_APP_DESC.version(),
+                    build_date: ESP_APP_DESC.date(),
+                    build_time: ESP_APP_DESC.time(),
+                    chip: esp_hal::chip!(),
+                    mac: state::mac_address().map(format_mac),
+                    hostname: state::hostname(),
+                    ip: state::ip_address(),
+                    ipv6: state::ipv6_address(),
+                    heap_size: MAX_HEAP_SIZE,
+                    partition: running_image.map(|image| image.partition),
+                    image_state: running_image
+                        .and_then(|image| image.state)
+                        .map(ota::image_state_str),
+                    reset_reason: state::boot_reason().map(BootReason::as_str),
+                    safe_mode: state::safe_mode(),
+                    offline_reboots: state::offline_reboots(),
+                    boots: boot_count::boot_counts(),
+                    wifi_power_save: state::wifi_power_save(),
+                })
+            }),
+        )
+        .route(
+            const { documented("/loglevel") },
+            get(|| async move { Json(LogLevel::current()) }).post(
+                |_: Authorized, LogLevelBody(update)| async move {
+                    let invalid = |e| {
+                        let mut message = heapless::String::<128>::new();
+                        // The message is short, so it always fits.
+                        let _ = writeln!(message, "Invalid log level: {e}");
+                        (StatusCode::BAD_REQUEST, message)
+                    };
+                    // The filter parses into per-target levels, the level
+                    // into the global one.
+                    let mut filter = match update.filter {
+                        Some(filter) => LogFilter::parse(&filter).map_err(invalid)?,
+                        None => LogFilter::parse(&logger::filter()).map_err(invalid)?,
+                    };
+                    if let Some(level) = update.level {
+                        filter.set_level(level.parse().map_err(|_| {
+                            invalid("expected off, error, warn, info, debug or trace")
+                        })?);
+                    }
+                    logger::set_filter(filter);
+
+                    let current = LogLevel::current();
+                    log::info!(
+                        "Log level changed to {} {} through POST route!",
+                        current.level,
+                        current.filter
+                    );
+                    Ok::<_, (StatusCode, heapless::String<128>)>(Json(current))
+                },
+            ),
+        )
+        .route(
+            const { documented("/api") },
+            get(|| async move {
+                Json(ApiDescription {
+                    version: API_VERSION,
+                    prefix: API_PREFIX,
+                    routes: &API_ROUTES,
+                })
+            }),
+        )
+        .route(
+            const { documented("/status") },
+            get(|| async move {
+                let rssi_range = state::wifi_rssi_range();
+                let physical_brightness = quiet_hours::limit(state::led_brightness(MAIN_CHANNEL));
+                Json(Status {
+                    led: state::led_state(MAIN_CHANNEL),
+                    brightness: state::led_brightness(MAIN_CHANNEL),
+                    physical_led: if physical_brightness > 0 {
+                        LedState::On
+                    } else {
+                        LedState::Off
+                    },
+                    physical_brightness,
+                    quiet_hours: quiet_hours::is_active(),
+                    uptime_ms: Instant::now().as_millis(),
+                    ip: state::ip_address(),
+                    ipv6: state::ipv6_address(),
+                    wifi_ssid: state::wifi_ssid(),
+                    wifi_failures: state::wifi_failures(),
+                    auto_off_secs: auto_off_secs(MAIN_CHANNEL),
+                    rssi: state::wifi_rssi(),
+                    rssi_min: rssi_range.map(|range| range.min),
+                    rssi_max: rssi_range.map(|range| range.max),
+                    toggles: relay::toggles(),
+                })
+            }),
+        )
+}
+
+pub(crate) fn run_server(
     spawner: Spawner,
     stack: Stack<'static>,
+    port: u16,
     app: &'static AppRouter<AppProps>,
     config: &'static Config<Duration>,
-) {
-    for id in 0..WEB_TASK_POOL_SIZE.max(1) {
-        match WEB_TASK_POOL_SIZE.max(1) {
-            1 => {
-                spawner.spawn(web_task1(id, stack, app, config)).unwrap();
-            }
-            2 => {
-                spawner.spawn(web_task2(id, stack, app, config)).unwrap();
-            }
-            3 => {
-                spawner.spawn(web_task3(id, stack, app, config)).unwrap();
-            }
-            4 => {
-                spawner.spawn(web_task4(id, stack, app, config)).unwrap();
-            }
-            5 => {
-                spawner.spawn(web_task5(id, stack, app, config)).unwrap();
-            }
-            6 => {
-                spawner.spawn(web_task6(id, stack, app, config)).unwrap();
-            }
-            7 => {
-                spawner.spawn(web_task7(id, stack, app, config)).unwrap();
-            }
-            _ => {
-                spawner.spawn(web_task8(id, stack, app, config)).unwrap();
-            }
-        }
+) -> Result<(), FirmwareError> {
+    for id in 0..WEB_TASK_POOL_SIZE {
+        spawner
+            .spawn(web_task(id, stack, port, app, config))
+            .map_err(FirmwareError::spawn("web"))?;
     }
+    Ok(())
 }
 
-#[inline]
+// Answers `503 Service Unavailable` and closes the connection, so clients
+// know right away that the server is busy rather than waiting for a task.
+async fn refuse(socket: &mut TcpSocket<'_>) {
+    metrics::count_http_saturation();
+    if let Err(e) = socket.write_all(SATURATED_RESPONSE).await {
+        log::warn!("Failed to refuse a connection: {e:?}");
+        return;
+    }
+    socket.close();
+    // The response is lost if the socket is dropped before it is sent.
+    let _ = with_timeout(Duration::from_millis(REFUSE_TIMEOUT_MS), socket.flush()).await;
+}
+
+#[embassy_executor::task(pool_size = WEB_TASK_POOL_SIZE)]
 #[allow(clippy::similar_names)]
 async fn web_task(
     id: usize,
     stack: Stack<'static>,
+    port: u16,
     app: &'static AppRouter<AppProps>,
     config: &'static Config<Duration>,
 ) {
-    let port = 80;
-    let mut tcp_rx_buffer = [0; 1024];
-    let mut tcp_tx_buffer = [0; 1024];
-    let mut http_buffer = [0; 2048];
-
-    listen_and_serve(
-        id,
-        app,
-        config,
-        stack,
-        port,
-        &mut tcp_rx_buffer,
-        &mut tcp_tx_buffer,
-        &mut http_buffer,
-    )
-    .await;
-}
-
-web_task!(web_task1, 1);
-web_task!(web_task2, 2);
-web_task!(web_task3, 3);
-web_task!(web_task4, 4);
-web_task!(web_task5, 5);
-web_task!(web_task6, 6);
-web_task!(web_task7, 7);
-web_task!(web_task8, 8);
+    let mut tcp_rx_buffer = [0; TCP_RX_BUFFER_SIZE];
+    let mut tcp_tx_buffer = [0; TCP_TX_BUFFER_SIZE];
+    let mut http_buffer = [0; HTTP_BUFFER_SIZE];
+
+    // Serve requests once the IP address is available.
+    //
+    // The task reports to the watchdog whenever a connection arrives or ends,
+    // and whenever a request starts or ends, see `TrackRequests`. Waiting for
+    // the address, the connections and their requests lasts as long as the
+    // network and the clients decide, so the watchdog does not expect a
+    // report meanwhile, while a request taking too long drops its connection.
+    let task = Task::Web(id);
+    watchdog::wait_for(task, stack.wait_config_up()).await;
+    state::set_server_listening();
+
+    // Connections are accepted here rather than by `listen_and_serve`, so the
+    // routes know the address of the client.
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut tcp_rx_buffer, &mut tcp_tx_buffer);
+        if let Err(e) = watchdog::wait_for(task, socket.accept(port)).await {
+            log::warn!("Web task {id}: accept error: {e:?}");
+            continue;
+        }
+
+        // The last listening task refuses the connections, so they are not
+        // left hanging while every other task is busy.
+        if web_pool::connected(id) {
+            log::warn!("Web task {id}: every web task is busy, connection refused");
+            web_pool::closing(id);
+            refuse(&mut socket).await;
+            watchdog::alive(task);
+            web_pool::listening(id);
+            continue;
+        }
+
+        let client = Client {
+            address: socket
+                .remote_endpoint()
+                .map(|endpoint| IpAddr::from(endpoint.addr)),
+            worker: id,
+        };
+        // Waiting for the first request is not supervised.
+        watchdog::idle(task);
+        let served = serve_with_state(app, config, &mut http_buffer, socket, &client);
+        match select(served, web_pool::overrun(id)).await {
+            Either::First(Ok(_)) => net_watchdog::online(),
+            Either::First(Err(e)) => log::error!("Web task {id}: {e:?}"),
+            Either::Second(()) => log::error!(
+                "Web task {id}: request not handled within {} s, connection dropped",
+                web_pool::HANDLER_TIMEOUT_SECS
+            ),
+        }
+        watchdog::alive(task);
+        web_pool::listening(id);
+    }
+}
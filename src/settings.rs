@@ -0,0 +1,129 @@
+use core::fmt::Write;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::{Mutex, MutexGuard};
+
+use embedded_storage::{ReadStorage, Storage};
+
+use esp_hal::efuse::Efuse;
+
+use esp_storage::{FlashStorage, FlashStorageError};
+
+use heapless::String;
+
+use log::{error, info, warn};
+
+use button_led_logic::settings::SETTINGS_SIZE;
+pub(crate) use button_led_logic::settings::{
+    ConfigUpdate, Settings, SettingsUpdate, MAX_HOSTNAME_LEN, MAX_PASSWORD_LEN, MAX_SSID_LEN,
+};
+
+use crate::button;
+use crate::button_actions::ButtonActions;
+use crate::quiet_hours::QuietHours;
+use crate::schedule::Schedule;
+use crate::state;
+use crate::DEVICE_CONFIG;
+
+// Offset of the settings in flash, at the start of the `nvs` partition of the
+// default partition table, which is otherwise unused.
+const SETTINGS_OFFSET: u32 = 0x9000;
+// Prefix of the hostname derived from the MAC address.
+const DEFAULT_HOSTNAME_PREFIX: &str = "button-led-";
+
+// Serializes the changes of the settings stored in flash, so concurrent
+// requests do not overwrite each other.
+static SETTINGS_LOCK: Mutex<CriticalSectionRawMutex, ()> = Mutex::new(());
+
+// Hostname derived from the MAC address, such as `button-led-a1b2c3`.
+fn default_hostname() -> String<MAX_HOSTNAME_LEN> {
+    let mac = Efuse::mac_address();
+    let mut hostname = String::new();
+    // The hostname is 17 characters long, which always fits.
+    let _ = write!(
+        hostname,
+        "{DEFAULT_HOSTNAME_PREFIX}{:02x}{:02x}{:02x}",
+        mac[3], mac[4], mac[5]
+    );
+    hostname
+}
+
+// Configured hostname, or the one derived from the MAC address when missing.
+fn configured_hostname() -> String<MAX_HOSTNAME_LEN> {
+    match config_string("hostname", DEVICE_CONFIG.hostname) {
+        hostname if hostname.is_empty() => default_hostname(),
+        hostname => hostname,
+    }
+}
+
+// Settings from the device configuration.
+//
+// Settings which are too long are discarded.
+fn configured_settings() -> Settings {
+    Settings {
+        ssid: config_string("Wi-Fi SSID", DEVICE_CONFIG.ssid),
+        password: config_string("Wi-Fi password", DEVICE_CONFIG.password),
+        hostname: configured_hostname(),
+        mqtt_host: config_string("MQTT host", DEVICE_CONFIG.mqtt_host),
+        mqtt_port: DEVICE_CONFIG.mqtt_port,
+        schedule: Schedule::default(),
+        schedule_enabled: true,
+        buzzer_muted: false,
+        quiet_hours: QuietHours::DISABLED,
+        button_actions: ButtonActions::defaults(
+            button::led_channel(),
+            DEVICE_CONFIG.button_cycles_presets,
+        ),
+    }
+}
+
+// Converts a configuration value, discarding it when it is too long.
+pub(crate) fn config_string<const N: usize>(name: &str, value: &str) -> String<N> {
+    String::try_from(value).unwrap_or_else(|()| {
+        error!("Configured {name} is too long, ignored");
+        String::new()
+    })
+}
+
+// Loads the settings.
+//
+// The settings stored in flash take precedence over the device configuration,
+// which is used when flash is empty or its content is corrupted.
+pub(crate) fn load_settings() -> Settings {
+    let defaults = configured_settings();
+    let mut bytes = [0; SETTINGS_SIZE];
+    if let Err(e) = FlashStorage::new().read(SETTINGS_OFFSET, &mut bytes) {
+        error!("Failed to read settings from flash: {e:?}");
+        return defaults;
+    }
+
+    if let Some(settings) = Settings::decode(&bytes, &defaults, state::led_channels()) {
+        info!("Settings loaded from flash");
+        settings
+    } else {
+        // Erased flash is filled with ones.
+        if bytes.iter().any(|byte| *byte != 0xFF) {
+            warn!("Settings in flash are corrupted, using the configured ones");
+        }
+        defaults
+    }
+}
+
+// Waits until no other request changes the stored settings.
+//
+// The lock must be held from loading the settings until storing them.
+pub(crate) async fn lock() -> MutexGuard<'static, CriticalSectionRawMutex, ()> {
+    SETTINGS_LOCK.lock().await
+}
+
+// Stores the settings in flash.
+pub(crate) fn store_settings(settings: &Settings) -> Result<(), FlashStorageError> {
+    FlashStorage::new().write(SETTINGS_OFFSET, &settings.encode())
+}
+
+// Erases the settings stored in flash, so the device configuration is used
+// again.
+pub(crate) fn erase_settings() -> Result<(), FlashStorageError> {
+    // Erased flash is filled with ones.
+    FlashStorage::new().write(SETTINGS_OFFSET, &[0xFF; SETTINGS_SIZE])
+}
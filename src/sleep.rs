@@ -0,0 +1,131 @@
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration, Timer};
+
+use esp_hal::gpio::AnyPin;
+#[cfg(not(feature = "esp32s3"))]
+use esp_hal::gpio::RtcPinWithResistors;
+#[cfg(feature = "esp32s3")]
+use esp_hal::rtc_cntl::sleep::Ext0WakeupSource;
+#[cfg(feature = "esp32c6")]
+use esp_hal::rtc_cntl::sleep::Ext1WakeupSource;
+#[cfg(feature = "esp32c3")]
+use esp_hal::rtc_cntl::sleep::RtcioWakeupSource;
+use esp_hal::rtc_cntl::sleep::WakeupLevel;
+use esp_hal::rtc_cntl::{wakeup_cause, Rtc};
+use esp_hal::system::SleepSource;
+
+use log::{info, warn};
+
+use crate::board::MAX_WAKE_GPIO;
+use crate::button;
+use crate::state::{self, LedState};
+use crate::{mqtt, DEVICE_CONFIG};
+
+// Time left to pending responses before going offline.
+const SLEEP_DELAY_MS: u64 = 500;
+// Longest time waited for the Wi-Fi connection to be shut down.
+const WIFI_STOP_TIMEOUT_MS: u64 = 2000;
+// Value marking the led as on when the device went to sleep.
+const LED_ON_MAGIC: u32 = 0x4c45_444f;
+
+// State of the led of the button before the device went to sleep, kept in
+// RTC RAM across the sleep.
+#[esp_hal::ram(rtc_fast, persistent)]
+static mut LED_STATE: u32 = 0;
+
+// Signal raised to put the device to sleep.
+static SLEEP: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+// Signal raised to shut down the Wi-Fi connection before sleeping, and once
+// it is shut down.
+static STOP_WIFI: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+static WIFI_STOPPED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+// Whether the button can wake the chip, since only the RTC GPIOs can.
+pub(crate) const fn is_available() -> bool {
+    DEVICE_CONFIG.button_gpio <= MAX_WAKE_GPIO
+}
+
+// Puts the device to sleep, returning whether the button can wake it.
+pub(crate) fn request() -> bool {
+    if !is_available() {
+        return false;
+    }
+    SLEEP.signal(());
+    true
+}
+
+// Waits until the Wi-Fi connection must be shut down.
+pub(crate) async fn wifi_stop_requested() {
+    STOP_WIFI.wait().await;
+}
+
+// Reports that the Wi-Fi connection is shut down.
+pub(crate) fn wifi_stopped() {
+    WIFI_STOPPED.signal(());
+}
+
+// Whether the button woke the device from deep sleep.
+pub(crate) fn woke_by_button() -> bool {
+    matches!(
+        wakeup_cause(),
+        SleepSource::Gpio | SleepSource::Ext0 | SleepSource::Ext1
+    )
+}
+
+// Led state the device had before going to sleep.
+pub(crate) fn led_state_before_sleep() -> LedState {
+    // SAFETY: the led state is only read at boot, before the sleep task runs.
+    if unsafe { LED_STATE } == LED_ON_MAGIC {
+        LedState::On
+    } else {
+        LedState::Off
+    }
+}
+
+// Puts the device in deep sleep once requested, shutting down MQTT and Wi-Fi
+// first. Pressing the button wakes the device, which boots again.
+#[embassy_executor::task]
+pub(crate) async fn sleep_task(mut rtc: Rtc<'static>) {
+    SLEEP.wait().await;
+    info!("Going to deep sleep...");
+
+    // SAFETY: the led state is only written here, once the boot is over.
+    unsafe {
+        LED_STATE = match state::led_state(button::led_channel()) {
+            LedState::On => LED_ON_MAGIC,
+            LedState::Off => 0,
+        };
+    }
+
+    Timer::after_millis(SLEEP_DELAY_MS).await;
+    mqtt::go_offline().await;
+
+    // The connection task is not running in provisioning mode.
+    STOP_WIFI.signal(());
+    if with_timeout(
+        Duration::from_millis(WIFI_STOP_TIMEOUT_MS),
+        WIFI_STOPPED.wait(),
+    )
+    .await
+    .is_err()
+    {
+        warn!("Wi-Fi not stopped, going to sleep anyway");
+    }
+
+    // SAFETY: the button task is not reading the pin anymore, since the chip
+    // stops right after configuring it.
+    let mut button = unsafe { AnyPin::steal(DEVICE_CONFIG.button_gpio) };
+    // The button is pulled up, so it is pressed while its level is low. Every
+    // chip wakes from deep sleep through a different source.
+    #[cfg(not(feature = "esp32s3"))]
+    let mut wake_pins: [(&mut dyn RtcPinWithResistors, WakeupLevel); 1] =
+        [(&mut button, WakeupLevel::Low)];
+    #[cfg(feature = "esp32c3")]
+    let wake_source = RtcioWakeupSource::new(&mut wake_pins);
+    #[cfg(feature = "esp32c6")]
+    let wake_source = Ext1WakeupSource::new(&mut wake_pins);
+    #[cfg(feature = "esp32s3")]
+    let wake_source = Ext0WakeupSource::new(button.reborrow(), WakeupLevel::Low);
+    rtc.sleep_deep(&[&wake_source]);
+}
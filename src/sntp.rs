@@ -0,0 +1,246 @@
+use core::cell::Cell;
+use core::fmt;
+use core::net::{IpAddr, Ipv4Addr};
+
+use embassy_futures::select::select;
+use embassy_net::dns::DnsQueryType;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::Stack;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+
+use log::{error, info, warn};
+
+use button_led_logic::sntp::{parse_response, NTP_PACKET_SIZE};
+
+// NTP server port.
+const NTP_PORT: u16 = 123;
+// First byte of a request: no leap second warning, version 3, client mode.
+const NTP_CLIENT_HEADER: u8 = 0x1B;
+
+// Time between two synchronizations.
+const SYNC_INTERVAL_SECS: u64 = 3600;
+// Time before retrying a failed synchronization.
+const RETRY_INTERVAL_SECS: u64 = 60;
+// Requests sent before a synchronization fails, and time waited for each
+// response.
+const REQUEST_ATTEMPTS: u32 = 3;
+const RESPONSE_TIMEOUT_SECS: u64 = 5;
+
+// Wall clock, as the Unix time at boot.
+#[derive(Clone, Copy)]
+struct Clock {
+    boot_unix_us: u64,
+    synced_at: Instant,
+}
+
+static CLOCK: Mutex<CriticalSectionRawMutex, Cell<Option<Clock>>> = Mutex::new(Cell::new(None));
+
+// Signalled to synchronize the clock right away.
+static SNTP_RESYNC: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+// Synchronizes the clock again, after the device reconnects to the network.
+pub(crate) fn resync() {
+    SNTP_RESYNC.signal(());
+}
+
+// Current Unix time in seconds, or `None` until the clock is synchronized.
+pub(crate) fn now_unix() -> Option<u64> {
+    CLOCK
+        .lock(Cell::get)
+        .map(|clock| (clock.boot_unix_us + Instant::now().as_micros()) / 1_000_000)
+}
+
+// Time elapsed since the last synchronization, if any.
+pub(crate) fn last_sync_age() -> Option<Duration> {
+    CLOCK
+        .lock(Cell::get)
+        .map(|clock| Instant::now() - clock.synced_at)
+}
+
+// UTC date and time.
+pub(crate) struct DateTime {
+    year: u64,
+    month: u64,
+    day: u64,
+    hour: u64,
+    minute: u64,
+    second: u64,
+}
+
+impl DateTime {
+    // Converts Unix time in seconds.
+    pub(crate) const fn from_unix(secs: u64) -> Self {
+        let time = secs % 86400;
+
+        // Civil date from the days since the epoch, computed over eras of 400
+        // years starting on March 1st, from Howard Hinnant's algorithms.
+        let days = secs / 86400 + 719_468;
+        let era = days / 146_097;
+        let day_of_era = days % 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        Self {
+            year,
+            month,
+            day,
+            hour: time / 3600,
+            minute: time % 3600 / 60,
+            second: time % 60,
+        }
+    }
+}
+
+// Formats the date and time as RFC 3339.
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+// Errors arising while synchronizing the clock.
+#[derive(Debug)]
+enum SntpError {
+    // The server name could not be resolved.
+    Dns,
+    // The request could not be sent.
+    Send,
+    // No valid response arrived.
+    NoResponse,
+}
+
+// Resolves the NTP server, which is either an IPv4 address or a hostname.
+async fn resolve(stack: Stack<'static>, server: &str) -> Result<Ipv4Addr, SntpError> {
+    if let Ok(ip) = server.parse() {
+        return Ok(ip);
+    }
+
+    let addresses = stack
+        .dns_query(server, DnsQueryType::A)
+        .await
+        .map_err(|_| SntpError::Dns)?;
+    // Only IPv4 addresses are queried.
+    addresses
+        .iter()
+        .find_map(|&address| match IpAddr::from(address) {
+            IpAddr::V4(ip) => Some(ip),
+            IpAddr::V6(_) => None,
+        })
+        .ok_or(SntpError::Dns)
+}
+
+// Queries the NTP server, storing the obtained time.
+async fn sync(
+    stack: Stack<'static>,
+    socket: &mut UdpSocket<'_>,
+    server: &str,
+) -> Result<(), SntpError> {
+    let server_ip = resolve(stack, server).await?;
+
+    let mut response = [0; NTP_PACKET_SIZE];
+    for attempt in 1..=REQUEST_ATTEMPTS {
+        // The boot time is used as transmit timestamp, to match the response
+        // with its request.
+        let sent_at = Instant::now();
+        let request_timestamp = sent_at.as_micros().to_be_bytes();
+        let mut request = [0; NTP_PACKET_SIZE];
+        request[0] = NTP_CLIENT_HEADER;
+        request[40..48].copy_from_slice(&request_timestamp);
+
+        socket
+            .send_to(&request, (server_ip, NTP_PORT))
+            .await
+            .map_err(|_| SntpError::Send)?;
+
+        let deadline = sent_at + Duration::from_secs(RESPONSE_TIMEOUT_SECS);
+        // Responses to earlier requests are skipped.
+        while let Ok(received) = with_timeout(
+            deadline.saturating_duration_since(Instant::now()),
+            socket.recv_from(&mut response),
+        )
+        .await
+        {
+            let Ok((len, _)) = received else {
+                continue;
+            };
+            let Some(server_unix_us) = parse_response(&response[..len], request_timestamp) else {
+                continue;
+            };
+
+            // The response took about half of the round trip to arrive.
+            let received_at = Instant::now();
+            let delay_us = (received_at - sent_at).as_micros() / 2;
+            let boot_unix_us = (server_unix_us + delay_us).saturating_sub(received_at.as_micros());
+            CLOCK.lock(|clock| {
+                clock.set(Some(Clock {
+                    boot_unix_us,
+                    synced_at: received_at,
+                }));
+            });
+
+            info!(
+                "Clock synchronized with {server} ({server_ip}), Unix time {}",
+                (server_unix_us + delay_us) / 1_000_000
+            );
+            return Ok(());
+        }
+
+        warn!("No response from NTP server {server} ({attempt}/{REQUEST_ATTEMPTS})");
+    }
+
+    Err(SntpError::NoResponse)
+}
+
+// Synchronizes the clock with an NTP server every hour, and after each
+// reconnection.
+#[embassy_executor::task]
+pub(crate) async fn sntp_task(stack: Stack<'static>, server: &'static str) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; NTP_PACKET_SIZE * 2];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; NTP_PACKET_SIZE * 2];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+
+    // Bind to an ephemeral port.
+    if let Err(e) = socket.bind(0) {
+        error!("Failed to bind SNTP socket: {e:?}");
+        return;
+    }
+
+    loop {
+        stack.wait_config_up().await;
+        SNTP_RESYNC.reset();
+
+        let delay_secs = match sync(stack, &mut socket, server).await {
+            Ok(()) => SYNC_INTERVAL_SECS,
+            Err(e) => {
+                warn!("Failed to synchronize the clock with {server}: {e:?}");
+                RETRY_INTERVAL_SECS
+            }
+        };
+
+        select(Timer::after_secs(delay_secs), SNTP_RESYNC.wait()).await;
+    }
+}
@@ -0,0 +1,417 @@
+use core::cell::{Cell, RefCell};
+use core::net::{Ipv4Addr, Ipv6Addr};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::signal::Signal;
+use embassy_time::Instant;
+
+use serde::Serialize;
+
+use crate::boot::BootReason;
+use crate::led::{LedCommand, MAX_LED_CHANNELS};
+use crate::logic::LedStore;
+use crate::relay;
+use crate::settings::MAX_SSID_LEN;
+
+// Logical led state.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum LedState {
+    On,
+    Off,
+}
+
+impl LedState {
+    // Led state shown at the given brightness percentage.
+    pub(crate) const fn from_brightness(brightness: u8) -> Self {
+        if brightness > 0 {
+            Self::On
+        } else {
+            Self::Off
+        }
+    }
+
+    // Led state obtained by switching the current one.
+    pub(crate) const fn toggled(self) -> Self {
+        match self {
+            Self::On => Self::Off,
+            Self::Off => Self::On,
+        }
+    }
+
+    // Led state as a lowercase string.
+    pub(crate) const fn as_str(self) -> &'static str {
+        match self {
+            Self::On => "on",
+            Self::Off => "off",
+        }
+    }
+}
+
+// Maximum number of led inputs waiting to be processed.
+const LED_CHANNEL_SIZE: usize = 8;
+
+// Channel which notifies the led change of state. Every subsystem may send
+// inputs, to any led channel, only the `change_led` task receives them.
+//
+// Inputs are queued, so rapid events are not lost. When the channel is full,
+// new inputs are rejected: button presses are dropped and server routes reply
+// with `503 Service Unavailable`.
+pub(crate) static NOTIFY_LED: Channel<CriticalSectionRawMutex, LedCommand, LED_CHANNEL_SIZE> =
+    Channel::new();
+
+// Signal which reboots the device, carrying the reason of the reboot. Any task
+// may signal it, only the reboot task waits for it.
+pub(crate) static REBOOT: Signal<CriticalSectionRawMutex, &'static str> = Signal::new();
+
+// Brightness percentage of every led channel, updated by the `change_led`
+// task and read by the server routes. A brightness of 0 means the led is off.
+//
+// A blocking mutex is used so readers never wait on the led task.
+static LED_BRIGHTNESS: Mutex<CriticalSectionRawMutex, Cell<[u8; MAX_LED_CHANNELS]>> =
+    Mutex::new(Cell::new([0; MAX_LED_CHANNELS]));
+
+// Brightness preset of every led channel the button cycle is at, moved to the
+// nearest one by any other change, so the next press continues from what the
+// led shows.
+static PRESET_INDEX: Mutex<CriticalSectionRawMutex, Cell<[usize; MAX_LED_CHANNELS]>> =
+    Mutex::new(Cell::new([0; MAX_LED_CHANNELS]));
+
+// Number of configured led channels, the main led included.
+static LED_CHANNELS: Mutex<CriticalSectionRawMutex, Cell<usize>> = Mutex::new(Cell::new(1));
+
+// IP address assigned to the device, if any.
+static IP_ADDRESS: Mutex<CriticalSectionRawMutex, Cell<Option<Ipv4Addr>>> =
+    Mutex::new(Cell::new(None));
+
+// Global or link-local IPv6 address of the device, if any.
+static IPV6_ADDRESS: Mutex<CriticalSectionRawMutex, Cell<Option<Ipv6Addr>>> =
+    Mutex::new(Cell::new(None));
+
+// Consecutive failed Wi-Fi connection attempts.
+static WIFI_FAILURES: Mutex<CriticalSectionRawMutex, Cell<u32>> = Mutex::new(Cell::new(0));
+
+// SSID of the connected Wi-Fi network, if any.
+static WIFI_SSID: Mutex<CriticalSectionRawMutex, RefCell<Option<heapless::String<MAX_SSID_LEN>>>> =
+    Mutex::new(RefCell::new(None));
+
+// Signal strength of the Wi-Fi connection, in dBm, if connected.
+static WIFI_RSSI: Mutex<CriticalSectionRawMutex, Cell<Option<i32>>> = Mutex::new(Cell::new(None));
+
+// Weakest and strongest Wi-Fi signal since boot, if any was sampled.
+static WIFI_RSSI_RANGE: Mutex<CriticalSectionRawMutex, Cell<Option<RssiRange>>> =
+    Mutex::new(Cell::new(None));
+
+// Wi-Fi station MAC address, stored once the controller is initialized.
+static MAC_ADDRESS: Mutex<CriticalSectionRawMutex, Cell<Option<[u8; 6]>>> =
+    Mutex::new(Cell::new(None));
+
+// Whether the HTTP server is accepting connections.
+static SERVER_LISTENING: Mutex<CriticalSectionRawMutex, Cell<bool>> = Mutex::new(Cell::new(false));
+
+// Reason of the last reset, stored once read at boot.
+static BOOT_REASON: Mutex<CriticalSectionRawMutex, Cell<Option<BootReason>>> =
+    Mutex::new(Cell::new(None));
+
+// Active Wi-Fi power-save mode, once applied in station mode.
+static WIFI_POWER_SAVE: Mutex<CriticalSectionRawMutex, Cell<Option<&'static str>>> =
+    Mutex::new(Cell::new(None));
+
+// Restarts caused by the device staying offline, since power on.
+static OFFLINE_REBOOTS: Mutex<CriticalSectionRawMutex, Cell<u32>> = Mutex::new(Cell::new(0));
+
+// Whether the device booted in safe mode, without the optional subsystems.
+static SAFE_MODE: Mutex<CriticalSectionRawMutex, Cell<bool>> = Mutex::new(Cell::new(false));
+
+// Hostname announced on the network, from the stored settings.
+static HOSTNAME: Mutex<CriticalSectionRawMutex, Cell<&'static str>> = Mutex::new(Cell::new(""));
+
+// Deadline of the automatic turn off of every led channel, if any.
+static AUTO_OFF_AT: Mutex<CriticalSectionRawMutex, Cell<[Option<Instant>; MAX_LED_CHANNELS]>> =
+    Mutex::new(Cell::new([None; MAX_LED_CHANNELS]));
+
+// Command of every led channel held back by the throttle or by a relay,
+// updated by the `change_led` task.
+static PENDING_COMMANDS: Mutex<
+    CriticalSectionRawMutex,
+    Cell<[Option<PendingCommand>; MAX_LED_CHANNELS]>,
+> = Mutex::new(Cell::new([None; MAX_LED_CHANNELS]));
+
+// Led command held back, until the throttle interval ends or a relay can
+// switch again.
+#[derive(Clone, Copy)]
+pub(crate) struct PendingCommand {
+    // Time at which the command is applied.
+    pub(crate) at: Instant,
+    // Led state the command leads to, unless it leaves the state untouched.
+    pub(crate) led_state: Option<LedState>,
+}
+
+// Range of the Wi-Fi signal strength samples, in dBm.
+#[derive(Clone, Copy)]
+pub(crate) struct RssiRange {
+    pub(crate) min: i32,
+    pub(crate) max: i32,
+}
+
+// Retrieves the current state of a led channel.
+pub(crate) fn led_state(channel: usize) -> LedState {
+    LedState::from_brightness(led_brightness(channel))
+}
+
+// Retrieves the current brightness percentage of a led channel, 0 for a
+// missing channel.
+pub(crate) fn led_brightness(channel: usize) -> u8 {
+    LED_BRIGHTNESS
+        .lock(Cell::get)
+        .get(channel)
+        .copied()
+        .unwrap_or(0)
+}
+
+// Sets the current brightness percentage of a led channel.
+pub(crate) fn set_led_brightness(channel: usize, brightness: u8) {
+    LED_BRIGHTNESS.lock(|led_brightness| {
+        let mut levels = led_brightness.get();
+        if let Some(level) = levels.get_mut(channel) {
+            *level = brightness;
+            led_brightness.set(levels);
+        }
+    });
+}
+
+// Retrieves the index of the brightness preset a led channel is at.
+pub(crate) fn preset_index(channel: usize) -> usize {
+    PRESET_INDEX
+        .lock(Cell::get)
+        .get(channel)
+        .copied()
+        .unwrap_or(0)
+}
+
+// Sets the index of the brightness preset a led channel is at.
+pub(crate) fn set_preset_index(channel: usize, index: usize) {
+    PRESET_INDEX.lock(|preset_index| {
+        let mut indices = preset_index.get();
+        if let Some(current) = indices.get_mut(channel) {
+            *current = index;
+            preset_index.set(indices);
+        }
+    });
+}
+
+// Led state of the firmware, kept in the statics above so the server routes
+// and the other tasks read it while the led logic updates it.
+pub(crate) struct SharedLedState;
+
+impl LedStore for SharedLedState {
+    fn brightness(&self, channel: usize) -> u8 {
+        led_brightness(channel)
+    }
+
+    fn set_brightness(&mut self, channel: usize, brightness: u8) {
+        set_led_brightness(channel, brightness);
+    }
+
+    fn preset_index(&self, channel: usize) -> usize {
+        preset_index(channel)
+    }
+
+    fn set_preset_index(&mut self, channel: usize, index: usize) {
+        set_preset_index(channel, index);
+    }
+
+    fn count_switch(&mut self) {
+        relay::count_switch();
+    }
+}
+
+// Retrieves the number of configured led channels.
+pub(crate) fn led_channels() -> usize {
+    LED_CHANNELS.lock(Cell::get)
+}
+
+// Sets the number of configured led channels.
+pub(crate) fn set_led_channels(channels: usize) {
+    LED_CHANNELS.lock(|led_channels| led_channels.set(channels));
+}
+
+// Retrieves the IP address assigned to the device.
+pub(crate) fn ip_address() -> Option<Ipv4Addr> {
+    IP_ADDRESS.lock(Cell::get)
+}
+
+// Sets the IP address assigned to the device.
+pub(crate) fn set_ip_address(ip: Ipv4Addr) {
+    IP_ADDRESS.lock(|ip_address| ip_address.set(Some(ip)));
+}
+
+// Retrieves the IPv6 address of the device, if any.
+pub(crate) fn ipv6_address() -> Option<Ipv6Addr> {
+    IPV6_ADDRESS.lock(Cell::get)
+}
+
+// Sets the IPv6 address of the device, if any.
+pub(crate) fn set_ipv6_address(ip: Option<Ipv6Addr>) {
+    IPV6_ADDRESS.lock(|ipv6_address| ipv6_address.set(ip));
+}
+
+// Retrieves the number of consecutive failed Wi-Fi connection attempts.
+pub(crate) fn wifi_failures() -> u32 {
+    WIFI_FAILURES.lock(Cell::get)
+}
+
+// Sets the number of consecutive failed Wi-Fi connection attempts.
+pub(crate) fn set_wifi_failures(failures: u32) {
+    WIFI_FAILURES.lock(|wifi_failures| wifi_failures.set(failures));
+}
+
+// Retrieves the SSID of the connected Wi-Fi network, if any.
+pub(crate) fn wifi_ssid() -> Option<heapless::String<MAX_SSID_LEN>> {
+    WIFI_SSID.lock(|wifi_ssid| wifi_ssid.borrow().clone())
+}
+
+// Sets the SSID of the connected Wi-Fi network, if any.
+pub(crate) fn set_wifi_ssid(ssid: Option<&str>) {
+    WIFI_SSID.lock(|wifi_ssid| {
+        *wifi_ssid.borrow_mut() = ssid.and_then(|ssid| heapless::String::try_from(ssid).ok());
+    });
+}
+
+// Retrieves the deadline of the automatic turn off of a led channel, if any.
+pub(crate) fn auto_off_at(channel: usize) -> Option<Instant> {
+    AUTO_OFF_AT.lock(Cell::get).get(channel).copied().flatten()
+}
+
+// Sets the deadline of the automatic turn off of a led channel.
+pub(crate) fn set_auto_off_at(channel: usize, deadline: Option<Instant>) {
+    AUTO_OFF_AT.lock(|auto_off_at| {
+        let mut deadlines = auto_off_at.get();
+        if let Some(current) = deadlines.get_mut(channel) {
+            *current = deadline;
+            auto_off_at.set(deadlines);
+        }
+    });
+}
+
+// Retrieves the command of a led channel held back, if any.
+pub(crate) fn pending_command(channel: usize) -> Option<PendingCommand> {
+    PENDING_COMMANDS
+        .lock(Cell::get)
+        .get(channel)
+        .copied()
+        .flatten()
+}
+
+// Sets the command of a led channel held back.
+pub(crate) fn set_pending_command(channel: usize, command: Option<PendingCommand>) {
+    PENDING_COMMANDS.lock(|pending_commands| {
+        let mut commands = pending_commands.get();
+        if let Some(current) = commands.get_mut(channel) {
+            *current = command;
+            pending_commands.set(commands);
+        }
+    });
+}
+
+// Retrieves the signal strength of the Wi-Fi connection, if connected.
+pub(crate) fn wifi_rssi() -> Option<i32> {
+    WIFI_RSSI.lock(Cell::get)
+}
+
+// Sets the signal strength of the Wi-Fi connection, extending the range of the
+// samples.
+pub(crate) fn set_wifi_rssi(rssi: Option<i32>) {
+    WIFI_RSSI.lock(|wifi_rssi| wifi_rssi.set(rssi));
+    if let Some(rssi) = rssi {
+        WIFI_RSSI_RANGE.lock(|range| {
+            range.set(Some(range.get().map_or(
+                RssiRange {
+                    min: rssi,
+                    max: rssi,
+                },
+                |range| RssiRange {
+                    min: range.min.min(rssi),
+                    max: range.max.max(rssi),
+                },
+            )));
+        });
+    }
+}
+
+// Retrieves the range of the Wi-Fi signal strength since boot, if sampled.
+pub(crate) fn wifi_rssi_range() -> Option<RssiRange> {
+    WIFI_RSSI_RANGE.lock(Cell::get)
+}
+
+// Retrieves the Wi-Fi station MAC address, if the controller is initialized.
+pub(crate) fn mac_address() -> Option<[u8; 6]> {
+    MAC_ADDRESS.lock(Cell::get)
+}
+
+// Sets the Wi-Fi station MAC address.
+pub(crate) fn set_mac_address(mac: [u8; 6]) {
+    MAC_ADDRESS.lock(|mac_address| mac_address.set(Some(mac)));
+}
+
+// Retrieves the reason of the last reset.
+pub(crate) fn boot_reason() -> Option<BootReason> {
+    BOOT_REASON.lock(Cell::get)
+}
+
+// Sets the reason of the last reset.
+pub(crate) fn set_boot_reason(reason: BootReason) {
+    BOOT_REASON.lock(|boot_reason| boot_reason.set(Some(reason)));
+}
+
+// Retrieves the active Wi-Fi power-save mode, if applied.
+pub(crate) fn wifi_power_save() -> Option<&'static str> {
+    WIFI_POWER_SAVE.lock(Cell::get)
+}
+
+// Sets the active Wi-Fi power-save mode.
+pub(crate) fn set_wifi_power_save(mode: &'static str) {
+    WIFI_POWER_SAVE.lock(|wifi_power_save| wifi_power_save.set(Some(mode)));
+}
+
+// Retrieves the number of restarts caused by the device staying offline.
+pub(crate) fn offline_reboots() -> u32 {
+    OFFLINE_REBOOTS.lock(Cell::get)
+}
+
+// Sets the number of restarts caused by the device staying offline.
+pub(crate) fn set_offline_reboots(reboots: u32) {
+    OFFLINE_REBOOTS.lock(|offline_reboots| offline_reboots.set(reboots));
+}
+
+// Retrieves whether the device booted in safe mode.
+pub(crate) fn safe_mode() -> bool {
+    SAFE_MODE.lock(Cell::get)
+}
+
+// Sets whether the device booted in safe mode.
+pub(crate) fn set_safe_mode(enabled: bool) {
+    SAFE_MODE.lock(|safe_mode| safe_mode.set(enabled));
+}
+
+// Retrieves the hostname announced on the network.
+pub(crate) fn hostname() -> &'static str {
+    HOSTNAME.lock(Cell::get)
+}
+
+// Sets the hostname announced on the network.
+pub(crate) fn set_hostname(hostname: &'static str) {
+    HOSTNAME.lock(|current| current.set(hostname));
+}
+
+// Retrieves whether the HTTP server is accepting connections.
+pub(crate) fn server_listening() -> bool {
+    SERVER_LISTENING.lock(Cell::get)
+}
+
+// Records that the HTTP server is accepting connections.
+pub(crate) fn set_server_listening() {
+    SERVER_LISTENING.lock(|server_listening| server_listening.set(true));
+}
@@ -0,0 +1,124 @@
+use core::cell::Cell;
+
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::Timer;
+
+use esp_hal::gpio::{Level, Output};
+
+use crate::quiet_hours;
+
+// System states shown by the status led.
+#[derive(Clone, Copy)]
+pub(crate) enum StatusEvent {
+    // Wi-Fi is connecting or waiting for the IP address.
+    Connecting,
+    // The device is connected to the network.
+    Connected,
+    // An operation failed, such as a Wi-Fi connection or a firmware update.
+    Error,
+    // A firmware update is being written.
+    OtaInProgress,
+    // Nothing to show.
+    Idle,
+}
+
+// Way the status led shows a system state.
+#[derive(Clone, Copy)]
+enum Blink {
+    Off,
+    On,
+    // Switch on and off, staying in each state for the given times.
+    Flash { on_ms: u64, off_ms: u64 },
+}
+
+impl StatusEvent {
+    // Blink patterns of the system states.
+    const fn blink(self) -> Blink {
+        match self {
+            Self::Connecting => Blink::Flash {
+                on_ms: 100,
+                off_ms: 100,
+            },
+            Self::Connected => Blink::On,
+            Self::Error => Blink::Flash {
+                on_ms: 100,
+                off_ms: 1900,
+            },
+            Self::OtaInProgress => Blink::Flash {
+                on_ms: 500,
+                off_ms: 500,
+            },
+            Self::Idle => Blink::Off,
+        }
+    }
+}
+
+// Latest system state, only the last one matters.
+//
+// Without a status led nobody waits on the signal, so the states are
+// silently dropped.
+static STATUS_EVENTS: Signal<CriticalSectionRawMutex, StatusEvent> = Signal::new();
+
+// Latest system state, shown again when the quiet hours start or end.
+static LAST_EVENT: Mutex<CriticalSectionRawMutex, Cell<StatusEvent>> =
+    Mutex::new(Cell::new(StatusEvent::Idle));
+
+// Whether a status led shows the system states, once its task runs.
+static PRESENT: Mutex<CriticalSectionRawMutex, Cell<bool>> = Mutex::new(Cell::new(false));
+
+// Whether a status led shows the system states, so the led is left to the
+// user.
+pub(crate) fn is_present() -> bool {
+    PRESENT.lock(Cell::get)
+}
+
+// Shows a system state on the status led, if any.
+pub(crate) fn publish(event: StatusEvent) {
+    LAST_EVENT.lock(|last| last.set(event));
+    STATUS_EVENTS.signal(event);
+}
+
+// Shows the latest system state again.
+pub(crate) fn refresh() {
+    STATUS_EVENTS.signal(LAST_EVENT.lock(Cell::get));
+}
+
+// Blinks the status led according to the latest system state.
+//
+// The status led stays off during the quiet hours.
+#[embassy_executor::task]
+pub(crate) async fn status_led(mut led: Output<'static>, active_low: bool) {
+    let (on, off) = if active_low {
+        (Level::Low, Level::High)
+    } else {
+        (Level::High, Level::Low)
+    };
+    PRESENT.lock(|present| present.set(true));
+
+    let mut blink = StatusEvent::Idle.blink();
+    loop {
+        let (on_ms, off_ms) = match blink {
+            // Steady states wait for the next one.
+            Blink::Off | Blink::On => {
+                let lit = matches!(blink, Blink::On) && !quiet_hours::is_active();
+                led.set_level(if lit { on } else { off });
+                blink = STATUS_EVENTS.wait().await.blink();
+                continue;
+            }
+            Blink::Flash { on_ms, off_ms } => (on_ms, off_ms),
+        };
+
+        for (level, duration_ms) in [(on, on_ms), (off, off_ms)] {
+            led.set_level(if quiet_hours::is_active() { off } else { level });
+            if let Either::First(event) =
+                select(STATUS_EVENTS.wait(), Timer::after_millis(duration_ms)).await
+            {
+                blink = event.blink();
+                break;
+            }
+        }
+    }
+}
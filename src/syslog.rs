@@ -0,0 +1,149 @@
+use core::cell::Cell;
+use core::fmt::{self, Write};
+use core::net::Ipv4Addr;
+
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::Stack;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::channel::Channel;
+
+use log::{error, info, Level, Record};
+
+use crate::logger::{self, Truncate};
+use crate::sntp::{self, DateTime};
+use crate::{metrics, ESP_APP_DESC};
+
+// Log messages waiting to be sent, further messages are dropped.
+const SYSLOG_QUEUE_SIZE: usize = 16;
+// Size of a log message, longer messages are truncated.
+const MESSAGE_SIZE: usize = 192;
+// Size of a syslog packet, a message together with its header.
+const PACKET_SIZE: usize = MESSAGE_SIZE + 128;
+// Syslog facility of the messages, user-level messages.
+const FACILITY_USER: u8 = 1;
+
+// Log message waiting to be sent.
+struct LogMessage {
+    level: Level,
+    // Unix time at which the message was logged, if the clock is synchronized.
+    unix_secs: Option<u64>,
+    text: heapless::String<MESSAGE_SIZE>,
+}
+
+static MESSAGES: Channel<CriticalSectionRawMutex, LogMessage, SYSLOG_QUEUE_SIZE> = Channel::new();
+
+// Whether log messages are forwarded, set once the syslog task starts.
+static ENABLED: Mutex<CriticalSectionRawMutex, Cell<bool>> = Mutex::new(Cell::new(false));
+
+// Syslog severity of a log level.
+const fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+// Queues a log record for the syslog server, without waiting.
+//
+// The record is dropped when the queue is full.
+pub(crate) fn forward(record: &Record) {
+    if !ENABLED.lock(Cell::get) {
+        return;
+    }
+
+    let mut text = heapless::String::new();
+    if logger::is_json() {
+        logger::write_json(&mut text, record);
+    } else {
+        let _ = write!(Truncate(&mut text), "{}", record.args());
+    }
+
+    let message = LogMessage {
+        level: record.level(),
+        unix_secs: sntp::now_unix(),
+        text,
+    };
+    if MESSAGES.try_send(message).is_err() {
+        metrics::count_syslog_drop();
+    }
+}
+
+// Formats a log message as an RFC 5424 syslog packet.
+//
+// The timestamp is left empty until the clock is synchronized.
+fn write_packet(
+    packet: &mut heapless::String<PACKET_SIZE>,
+    hostname: &str,
+    message: &LogMessage,
+) -> fmt::Result {
+    let priority = FACILITY_USER * 8 + severity(message.level);
+    write!(packet, "<{priority}>1 ")?;
+    match message.unix_secs {
+        Some(secs) => write!(packet, "{}", DateTime::from_unix(secs))?,
+        None => packet.push('-').map_err(|()| fmt::Error)?,
+    }
+    write!(
+        packet,
+        " {hostname} {} - - - {}",
+        ESP_APP_DESC.project_name(),
+        message.text
+    )
+}
+
+// Sends the log messages to a syslog server over UDP.
+//
+// Messages logged while the network is down are dropped.
+#[embassy_executor::task]
+pub(crate) async fn syslog_task(
+    stack: Stack<'static>,
+    server: Ipv4Addr,
+    port: u16,
+    hostname: &'static str,
+) {
+    // Nothing is received on the socket.
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0; 0];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; PACKET_SIZE * 4];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+
+    // Bind to an ephemeral port.
+    if let Err(e) = socket.bind(0) {
+        error!("Failed to bind syslog socket: {e:?}");
+        return;
+    }
+
+    ENABLED.lock(|enabled| enabled.set(true));
+    info!("Forwarding logs to syslog server {server}:{port}");
+
+    let mut packet = heapless::String::new();
+    loop {
+        let message = MESSAGES.receive().await;
+        if !stack.is_config_up() {
+            metrics::count_syslog_drop();
+            continue;
+        }
+
+        packet.clear();
+        // The packet is large enough for any message.
+        let _ = write_packet(&mut packet, hostname, &message);
+
+        // Failures are not logged, since they would be forwarded again.
+        if socket
+            .send_to(packet.as_bytes(), (server, port))
+            .await
+            .is_err()
+        {
+            metrics::count_syslog_drop();
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Timer;
+
+#[cfg(not(feature = "esp32s3"))]
+use esp_hal::tsens::TemperatureSensor;
+
+use log::{info, warn};
+
+use crate::led::{LedCommand, LedInput, Source};
+use crate::state::NOTIFY_LED;
+use crate::DEVICE_CONFIG;
+
+// Interval between two temperature samples.
+const SAMPLE_SECS: u64 = 10;
+// Time left to the sensor to stabilize once powered up.
+const STABILIZE_MS: u64 = 1;
+// Measuring range of the sensor, in degrees Celsius, beyond which samples are
+// read errors.
+const MIN_CELSIUS: f32 = -40.0;
+const MAX_CELSIUS: f32 = 125.0;
+// Degrees below the over-temperature threshold at which the led can be
+// turned on again.
+const HYSTERESIS_CELSIUS: f32 = 5.0;
+
+// Last temperature sample, in tenths of a degree Celsius, missing until the
+// first sample or after a read error.
+static TEMPERATURE: Mutex<CriticalSectionRawMutex, Cell<Option<i16>>> = Mutex::new(Cell::new(None));
+// Whether the chip is above the over-temperature threshold, so the led is
+// kept off.
+static OVERHEATED: Mutex<CriticalSectionRawMutex, Cell<bool>> = Mutex::new(Cell::new(false));
+
+// Last chip temperature in degrees Celsius, with one decimal, if available.
+pub(crate) fn celsius() -> Option<f32> {
+    TEMPERATURE
+        .lock(Cell::get)
+        .map(|tenths| f32::from(tenths) / 10.0)
+}
+
+// Whether the chip is too hot for the led to be turned on.
+pub(crate) fn overheated() -> bool {
+    OVERHEATED.lock(Cell::get)
+}
+
+// Samples the chip temperature, and keeps the led off while it is above the
+// over-temperature threshold, if configured. esp-hal has no driver for the
+// ESP32-S3 sensor, so the temperature is never available there.
+#[cfg(not(feature = "esp32s3"))]
+#[embassy_executor::task]
+pub(crate) async fn temperature_task(sensor: TemperatureSensor<'static>) {
+    let threshold = DEVICE_CONFIG.over_temperature_celsius as f32;
+    Timer::after_millis(STABILIZE_MS).await;
+
+    loop {
+        let celsius = sensor.get_temperature().to_celsius();
+        if (MIN_CELSIUS..=MAX_CELSIUS).contains(&celsius) {
+            // The range fits, even in tenths of a degree. Casting truncates,
+            // so half a tenth is added away from zero to round.
+            let half = if celsius < 0.0 { -0.5 } else { 0.5 };
+            let tenths = (celsius * 10.0 + half) as i16;
+            TEMPERATURE.lock(|temperature| temperature.set(Some(tenths)));
+
+            if DEVICE_CONFIG.over_temperature_celsius != 0 {
+                let overheated = overheated();
+                if !overheated && celsius > threshold {
+                    warn!("Chip temperature {celsius:.1} °C above {threshold} °C, led forced off");
+                    OVERHEATED.lock(|overheated| overheated.set(true));
+                    let _ = NOTIFY_LED.try_send(LedCommand::new(
+                        Source::System,
+                        LedInput::Off { fade_ms: None },
+                    ));
+                } else if overheated && celsius < threshold - HYSTERESIS_CELSIUS {
+                    info!("Chip temperature back to {celsius:.1} °C, led allowed on");
+                    OVERHEATED.lock(|overheated| overheated.set(false));
+                }
+            }
+        } else {
+            warn!("Invalid chip temperature {celsius} °C");
+            TEMPERATURE.lock(|temperature| temperature.set(None));
+        }
+
+        Timer::after_secs(SAMPLE_SECS).await;
+    }
+}
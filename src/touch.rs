@@ -0,0 +1,206 @@
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+#[cfg(feature = "touch")]
+use embassy_time::Timer;
+
+#[cfg(feature = "touch")]
+use esp_hal::gpio::AnyPin;
+#[cfg(feature = "touch")]
+use esp_hal::peripherals::{LPWR, RTC_IO, SENS};
+
+#[cfg(feature = "touch")]
+use log::{error, info};
+
+use serde::Serialize;
+
+#[cfg(feature = "touch")]
+use button_led_logic::touch::TouchDetector;
+
+#[cfg(feature = "touch")]
+use crate::button::{self, ButtonEvents};
+#[cfg(feature = "touch")]
+use crate::led::Source;
+
+// Interval between two readings of the pad.
+#[cfg(feature = "touch")]
+const SAMPLE_MS: u64 = 10;
+// Readings averaged at boot into the idle reading of the pad, which must not
+// be touched meanwhile.
+#[cfg(feature = "touch")]
+const CALIBRATION_SAMPLES: u32 = 32;
+// Time after which a touch is considered a drift of the idle reading, longer
+// than the hold resetting the device.
+#[cfg(feature = "touch")]
+const MAX_TOUCH_MS: u64 = 30_000;
+// Touch channels, numbered after their GPIO.
+#[cfg(feature = "touch")]
+const CHANNELS: core::ops::RangeInclusive<u8> = 1..=14;
+// Charge and discharge cycles of every measurement, and sleep cycles of the
+// 150 kHz clock between two measurements, the ESP-IDF defaults.
+#[cfg(feature = "touch")]
+const MEASURE_CYCLES: u16 = 500;
+#[cfg(feature = "touch")]
+const SLEEP_CYCLES: u16 = 15;
+// Fastest charge and discharge slope of the pad.
+#[cfg(feature = "touch")]
+const CHARGE_SLOPE: u8 = 7;
+// Selects the raw readings, rather than the filtered ones, in the status
+// registers.
+#[cfg(feature = "touch")]
+const RAW_DATA: u8 = 0;
+
+// Last reading of the touch pad, returned by the `/touch` route to tune the
+// threshold.
+#[derive(Clone, Copy, Serialize)]
+pub(crate) struct TouchReading {
+    pub(crate) raw: u32,
+    // Idle reading, which follows the slow drifts.
+    pub(crate) baseline: u32,
+    pub(crate) touched: bool,
+}
+
+// Last reading of the touch pad, missing when there is none.
+static READING: Mutex<CriticalSectionRawMutex, Cell<Option<TouchReading>>> =
+    Mutex::new(Cell::new(None));
+
+// Last reading of the touch pad, if configured.
+pub(crate) fn reading() -> Option<TouchReading> {
+    READING.lock(Cell::get)
+}
+
+// Capacitive touch pad of the ESP32-S3, measured periodically by the touch
+// sensor, whose readings grow while it is touched.
+#[cfg(feature = "touch")]
+pub(crate) struct TouchPad {
+    channel: u8,
+    detector: TouchDetector,
+    // Owned so nothing else uses the GPIO.
+    _pin: AnyPin<'static>,
+}
+
+#[cfg(feature = "touch")]
+impl TouchPad {
+    // Starts measuring the pad on the given GPIO and calibrates its idle
+    // reading, returning `None` when the GPIO is not a touch pad.
+    pub(crate) async fn new(pin: AnyPin<'static>, gpio: u8, threshold_percent: u8) -> Option<Self> {
+        if !CHANNELS.contains(&gpio) {
+            return None;
+        }
+        start(gpio);
+
+        let mut sum = 0;
+        for _ in 0..CALIBRATION_SAMPLES {
+            Timer::after_millis(SAMPLE_MS).await;
+            sum += read_raw(gpio);
+        }
+        let baseline = sum / CALIBRATION_SAMPLES;
+        if baseline == 0 {
+            error!("Touch pad on GPIO{gpio} is not measured");
+        }
+
+        Some(Self {
+            channel: gpio,
+            detector: TouchDetector::new(
+                baseline,
+                threshold_percent,
+                (MAX_TOUCH_MS / SAMPLE_MS) as u32,
+            ),
+            _pin: pin,
+        })
+    }
+
+    // Reads the pad, returning whether it is touched.
+    fn sample(&mut self) -> bool {
+        let raw = read_raw(self.channel);
+        let touched = self.detector.update(raw);
+        READING.lock(|reading| {
+            reading.set(Some(TouchReading {
+                raw,
+                baseline: self.detector.baseline(),
+                touched,
+            }))
+        });
+        touched
+    }
+}
+
+// The touch sensor measures on its own, so the pad is read on every sample.
+#[cfg(feature = "touch")]
+impl ButtonEvents for TouchPad {
+    fn is_pressed(&mut self) -> bool {
+        self.sample()
+    }
+
+    async fn wait_for_state(&mut self, pressed: bool) {
+        while self.sample() != pressed {
+            Timer::after_millis(SAMPLE_MS).await;
+        }
+    }
+}
+
+// Routes the given touch channel to the touch sensor, and starts measuring
+// it periodically. esp-hal has no touch driver for the ESP32-S3, so the
+// registers are programmed as ESP-IDF does.
+#[cfg(feature = "touch")]
+fn start(channel: u8) {
+    // SAFETY: every value fits its field.
+    RTC_IO::regs()
+        .touch_pad(usize::from(channel))
+        .modify(|_, w| unsafe {
+            w.mux_sel().set_bit();
+            w.fun_sel().bits(0);
+            w.fun_ie().clear_bit();
+            w.rue().clear_bit();
+            w.rde().clear_bit();
+            w.tie_opt().clear_bit();
+            w.dac().bits(CHARGE_SLOPE);
+            w.xpd().set_bit()
+        });
+
+    let rtc_cntl = LPWR::regs();
+    // SAFETY: every value fits its field, and only the pad map of the given
+    // channel is added.
+    rtc_cntl.touch_ctrl1().modify(|_, w| unsafe {
+        w.touch_meas_num().bits(MEASURE_CYCLES);
+        w.touch_sleep_cycles().bits(SLEEP_CYCLES)
+    });
+    rtc_cntl.touch_scan_ctrl().modify(|r, w| unsafe {
+        w.touch_scan_pad_map()
+            .bits(r.touch_scan_pad_map().bits() | 1 << channel)
+    });
+    SENS::regs()
+        .sar_touch_conf()
+        .modify(|_, w| unsafe { w.touch_data_sel().bits(RAW_DATA) });
+    rtc_cntl.touch_ctrl2().modify(|_, w| {
+        w.touch_clkgate_en().set_bit();
+        w.touch_xpd_bias().set_bit();
+        // Measure on the sleep timer, rather than on request.
+        w.touch_start_force().clear_bit();
+        w.touch_slp_timer_en().set_bit();
+        w.touch_start_fsm_en().set_bit()
+    });
+}
+
+// Last raw measurement of the given touch channel.
+#[cfg(feature = "touch")]
+fn read_raw(channel: u8) -> u32 {
+    SENS::regs()
+        .sar_touch_status(usize::from(channel) - 1)
+        .read()
+        .touch_pad_data()
+        .bits()
+}
+
+// Handles the touches of the pad, which emit the same gestures as the
+// button.
+#[cfg(feature = "touch")]
+#[embassy_executor::task]
+pub(crate) async fn touch_task(mut pad: TouchPad) {
+    info!(
+        "Touch pad initialized, idle reading {}",
+        pad.detector.baseline()
+    );
+    button::handle_presses(&mut pad, Source::Button).await;
+}
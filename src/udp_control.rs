@@ -0,0 +1,96 @@
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::Stack;
+
+use log::{error, info, warn};
+
+use crate::auth::keys_match;
+use crate::led::{LedCommand, LedInput, Source, MAIN_CHANNEL};
+use crate::metrics;
+use crate::state::{self, LedState, NOTIFY_LED};
+
+// Length of the shared secret prefixing every command, when configured.
+pub(crate) const SECRET_LEN: usize = 4;
+// Size of the socket buffers, commands are a few bytes long.
+const UDP_BUFFER_SIZE: usize = 64;
+
+// Commands, in the last byte of a datagram.
+pub(crate) const COMMAND_OFF: u8 = 0x00;
+pub(crate) const COMMAND_ON: u8 = 0x01;
+pub(crate) const COMMAND_TOGGLE: u8 = 0x02;
+const COMMAND_QUERY: u8 = 0x03;
+
+// Extracts the command of a datagram made of the optional secret followed by
+// a single byte, returning `None` when it is malformed or the secret is
+// wrong.
+fn command(datagram: &[u8], secret: &[u8]) -> Option<u8> {
+    let (&command, prefix) = datagram.split_last()?;
+    keys_match(prefix, secret).then_some(command)
+}
+
+// Listens for single-byte commands, which change the led without the round
+// trips of HTTP: 0x00 turns it off, 0x01 on, 0x02 toggles it and 0x03 asks
+// for its state, answered with a 0x00 or 0x01 byte.
+//
+// When a secret is configured, it prefixes every command.
+#[embassy_executor::task]
+pub(crate) async fn udp_control(stack: Stack<'static>, port: u16, secret: &'static str) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; UDP_BUFFER_SIZE];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; UDP_BUFFER_SIZE];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+
+    if let Err(e) = socket.bind(port) {
+        error!("Failed to bind UDP control socket: {e:?}");
+        return;
+    }
+    info!("UDP control listening on port {port}");
+
+    let mut datagram = [0; UDP_BUFFER_SIZE];
+    loop {
+        let (len, metadata) = match socket.recv_from(&mut datagram).await {
+            Ok(received) => received,
+            Err(e) => {
+                // Datagrams larger than the buffer are truncated.
+                warn!("Failed to receive a UDP command: {e:?}");
+                metrics::count_udp_drop();
+                continue;
+            }
+        };
+
+        let led_input = match command(&datagram[..len], secret.as_bytes()) {
+            Some(COMMAND_OFF) => LedInput::Off { fade_ms: None },
+            Some(COMMAND_ON) => LedInput::On {
+                fade_ms: None,
+                auto_off_secs: None,
+            },
+            Some(COMMAND_TOGGLE) => LedInput::Toggle,
+            Some(COMMAND_QUERY) => {
+                let state = match state::led_state(MAIN_CHANNEL) {
+                    LedState::On => COMMAND_ON,
+                    LedState::Off => COMMAND_OFF,
+                };
+                if let Err(e) = socket.send_to(&[state], metadata.endpoint).await {
+                    warn!("Failed to answer a UDP query: {e:?}");
+                }
+                continue;
+            }
+            _ => {
+                metrics::count_udp_drop();
+                continue;
+            }
+        };
+        if NOTIFY_LED
+            .try_send(LedCommand::new(Source::Udp, led_input))
+            .is_err()
+        {
+            warn!("Led channel is full, UDP command dropped!");
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use core::cell::Cell;
+use core::future::Future;
+use core::task::Context;
+
+use embassy_net_driver::{Capabilities, Driver, HardwareAddress, LinkState};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Timer;
+
+use esp_hal::peripherals::TIMG1;
+use esp_hal::timer::timg::{MwdtStage, Wdt};
+
+use log::info;
+
+use crate::WEB_TASK_POOL_SIZE;
+
+// Time without feeding after which the watchdog resets the device.
+const WATCHDOG_TIMEOUT_SECS: u64 = 10;
+// Interval between two checks of the supervised tasks.
+const FEED_INTERVAL_SECS: u64 = 1;
+// Interval between two polls of the network stack when it has nothing to do,
+// short enough for it to report several times within the watchdog timeout.
+pub(crate) const ALIVE_INTERVAL_SECS: u64 = 2;
+
+// Task whose progress is supervised by the watchdog.
+#[derive(Clone, Copy)]
+pub(crate) enum Task {
+    // The task driving the led.
+    Led,
+    // The task running the network stack.
+    Net,
+    // A web task, by its identifier.
+    Web(usize),
+}
+
+impl Task {
+    const fn bit(self) -> u32 {
+        match self {
+            Self::Led => 1,
+            Self::Net => 1 << 1,
+            Self::Web(id) => 1 << (2 + id),
+        }
+    }
+}
+
+// Bits of all the web tasks.
+const WEB_TASKS: u32 = ((1 << WEB_TASK_POOL_SIZE) - 1) << 2;
+// Tasks which must report before the watchdog is fed.
+const SUPERVISED: u32 = Task::Led.bit() | Task::Net.bit() | WEB_TASKS;
+
+const _: () = assert!(2 + WEB_TASK_POOL_SIZE <= 32, "Too many supervised tasks");
+
+// Supervised tasks which have started, those which reported since the last
+// feed, and those waiting for an event which may take any time.
+//
+// Tasks start at different times, for example the web tasks only once the
+// network is up, so a task is waited for only after its first report.
+static STARTED: Mutex<CriticalSectionRawMutex, Cell<u32>> = Mutex::new(Cell::new(0));
+static ALIVE: Mutex<CriticalSectionRawMutex, Cell<u32>> = Mutex::new(Cell::new(0));
+static IDLE: Mutex<CriticalSectionRawMutex, Cell<u32>> = Mutex::new(Cell::new(0));
+
+// Reports that a task is making progress, from a point it only reaches when
+// it does.
+pub(crate) fn alive(task: Task) {
+    let bit = task.bit() & SUPERVISED;
+    STARTED.lock(|started| started.set(started.get() | bit));
+    ALIVE.lock(|alive| alive.set(alive.get() | bit));
+    IDLE.lock(|idle| idle.set(idle.get() & !bit));
+}
+
+// Reports that a task waits for an event which may take any time, so it is
+// not expected to report until it reports alive again.
+pub(crate) fn idle(task: Task) {
+    let bit = task.bit() & SUPERVISED;
+    STARTED.lock(|started| started.set(started.get() | bit));
+    IDLE.lock(|idle| idle.set(idle.get() | bit));
+}
+
+// Waits for an event which may take any time, such as a led command or a
+// connection, during which the task is not expected to report. The task is
+// reported alive once the event arrives.
+//
+// Any other wait of a supervised task must end within the watchdog timeout.
+pub(crate) async fn wait_for<F: Future>(task: Task, event: F) -> F::Output {
+    idle(task);
+    let output = event.await;
+    alive(task);
+    output
+}
+
+// Network device of the stack, reporting the network task alive whenever the
+// stack polls it, so the report comes from the stack itself.
+pub(crate) struct SupervisedDevice<D> {
+    inner: D,
+}
+
+impl<D: Driver> SupervisedDevice<D> {
+    pub(crate) const fn new(inner: D) -> Self {
+        Self { inner }
+    }
+}
+
+impl<D: Driver> Driver for SupervisedDevice<D> {
+    type RxToken<'a>
+        = D::RxToken<'a>
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = D::TxToken<'a>
+    where
+        Self: 'a;
+
+    fn receive(&mut self, cx: &mut Context) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        self.inner.receive(cx)
+    }
+
+    fn transmit(&mut self, cx: &mut Context) -> Option<Self::TxToken<'_>> {
+        self.inner.transmit(cx)
+    }
+
+    // The stack checks the link on every poll.
+    fn link_state(&mut self, cx: &mut Context) -> LinkState {
+        alive(Task::Net);
+        self.inner.link_state(cx)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn hardware_address(&self) -> HardwareAddress {
+        self.inner.hardware_address()
+    }
+}
+
+// Feeds the watchdog only when every started task reported since the last
+// feed or is waiting for an event, so a stuck task resets the device.
+#[embassy_executor::task]
+pub(crate) async fn watchdog(mut wdt: Wdt<TIMG1<'static>>) {
+    wdt.set_timeout(
+        MwdtStage::Stage0,
+        esp_hal::time::Duration::from_secs(WATCHDOG_TIMEOUT_SECS),
+    );
+    wdt.enable();
+    info!("Watchdog enabled, timeout {WATCHDOG_TIMEOUT_SECS} seconds");
+
+    loop {
+        Timer::after_secs(FEED_INTERVAL_SECS).await;
+
+        let started = STARTED.lock(Cell::get);
+        let idle = IDLE.lock(Cell::get);
+        let fed = ALIVE.lock(|alive| {
+            if (alive.get() | idle) & started != started {
+                return false;
+            }
+            alive.set(0);
+            true
+        });
+        if fed {
+            wdt.feed();
+        }
+    }
+}
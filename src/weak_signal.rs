@@ -0,0 +1,51 @@
+// Consecutive samples below the threshold after which the signal is weak.
+const WEAK_SIGNAL_SAMPLES: u32 = 3;
+
+// Change of the signal quality.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SignalChange {
+    // The signal has been below the threshold for several samples.
+    Weakened,
+    // The signal is above the threshold again.
+    Recovered,
+}
+
+// Detects a Wi-Fi signal which stays below a threshold, so a single bad
+// sample is not reported.
+pub(crate) struct WeakSignal {
+    threshold_dbm: i32,
+    // Consecutive samples below the threshold.
+    weak_samples: u32,
+}
+
+impl WeakSignal {
+    pub(crate) const fn new(threshold_dbm: i32) -> Self {
+        Self {
+            threshold_dbm,
+            weak_samples: 0,
+        }
+    }
+
+    // Whether the signal is currently weak.
+    pub(crate) const fn is_weak(&self) -> bool {
+        self.weak_samples >= WEAK_SIGNAL_SAMPLES
+    }
+
+    // Feeds a signal strength sample, in dBm, returning how the signal
+    // quality changed, if it did.
+    pub(crate) fn sample(&mut self, rssi: i32) -> Option<SignalChange> {
+        if rssi < self.threshold_dbm {
+            self.weak_samples = self.weak_samples.saturating_add(1);
+            (self.weak_samples == WEAK_SIGNAL_SAMPLES).then_some(SignalChange::Weakened)
+        } else {
+            let was_weak = self.is_weak();
+            self.weak_samples = 0;
+            was_weak.then_some(SignalChange::Recovered)
+        }
+    }
+
+    // Forgets the samples, when the connection drops.
+    pub(crate) fn reset(&mut self) {
+        self.weak_samples = 0;
+    }
+}
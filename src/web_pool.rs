@@ -0,0 +1,247 @@
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant, Timer};
+
+use picoserve::io::Read;
+use picoserve::request::RequestParts;
+use picoserve::response::{Body, Connection, HeadersIter, Response, ResponseWriter};
+use picoserve::routing::{Layer, Next};
+use picoserve::ResponseSent;
+
+use serde::Serialize;
+
+use crate::api_version::unversioned;
+use crate::server::Client;
+use crate::watchdog::{self, Task};
+use crate::{DEVICE_CONFIG, WEB_TASK_POOL_SIZE};
+
+// Time allowed to handle a request, well within the watchdog timeout, after
+// which its connection is dropped.
+pub(crate) const HANDLER_TIMEOUT_SECS: u64 = 5;
+// Interval between two checks of the time spent handling the requests.
+const HANDLER_CHECK_SECS: u64 = 1;
+// Routes whose responses last as long as the clients decide, such as the
+// event streams and the firmware uploads, bounded by the HTTP timeouts
+// between two reads or writes rather than by `HANDLER_TIMEOUT_SECS`.
+const LONG_RUNNING_ROUTES: [&str; 2] = ["/events", "/update"];
+
+// What a web task is doing.
+#[derive(Clone, Copy)]
+enum Activity {
+    // Waiting for a connection.
+    Listening,
+    // Connected, waiting for the next request of a persistent connection.
+    Idle,
+    // Handling a request.
+    Serving,
+    // Handling a request of a long-running route.
+    Streaming,
+    // Closing its connection, before listening again.
+    Closing,
+}
+
+impl Activity {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Listening => "listening",
+            Self::Idle => "idle",
+            Self::Serving => "serving",
+            Self::Streaming => "streaming",
+            Self::Closing => "closing",
+        }
+    }
+
+    // Whether the task can take a new connection, right away or once it
+    // closed its own.
+    const fn is_available(self) -> bool {
+        matches!(self, Self::Listening | Self::Closing)
+    }
+}
+
+// Activity of a web task, and when it started.
+#[derive(Clone, Copy)]
+struct Worker {
+    activity: Activity,
+    since: Instant,
+    // Requests served on the current connection.
+    requests: u32,
+}
+
+impl Worker {
+    const LISTENING: Self = Self {
+        activity: Activity::Listening,
+        since: Instant::from_ticks(0),
+        requests: 0,
+    };
+}
+
+static WORKERS: Mutex<CriticalSectionRawMutex, Cell<[Worker; WEB_TASK_POOL_SIZE]>> =
+    Mutex::new(Cell::new([Worker::LISTENING; WEB_TASK_POOL_SIZE]));
+
+// Activity of a web task returned by the `/health` route.
+#[derive(Serialize)]
+pub(crate) struct WorkerStatus {
+    state: &'static str,
+    // Time elapsed since the task entered its state.
+    since_ms_ago: u64,
+    requests: u32,
+}
+
+// Updates the activity of a web task, returning the requests served on its
+// connection.
+fn update(id: usize, change: impl FnOnce(&mut Worker)) -> u32 {
+    WORKERS.lock(|workers| {
+        let mut current = workers.get();
+        let requests = current.get_mut(id).map_or(0, |worker| {
+            change(worker);
+            worker.since = Instant::now();
+            worker.requests
+        });
+        workers.set(current);
+        requests
+    })
+}
+
+// Records that a web task waits for a connection.
+pub(crate) fn listening(id: usize) {
+    update(id, |worker| *worker = Worker::LISTENING);
+}
+
+// Records that a web task accepted a connection, returning whether no other
+// task is available, so the connection must be refused.
+//
+// One task is reserved for refusing the connections while every other task is
+// busy, so at most `WEB_TASK_POOL_SIZE - 1` connections are served at once.
+// Tasks closing their connection count as available, since they listen again
+// right after.
+pub(crate) fn connected(id: usize) -> bool {
+    update(id, |worker| worker.activity = Activity::Idle);
+    WORKERS.lock(|workers| {
+        workers
+            .get()
+            .iter()
+            .all(|worker| !worker.activity.is_available())
+    })
+}
+
+// Records that a web task is closing its connection.
+pub(crate) fn closing(id: usize) {
+    update(id, |worker| worker.activity = Activity::Closing);
+}
+
+// Waits until a web task spent more than `HANDLER_TIMEOUT_SECS` handling a
+// request, other than a long-running one.
+pub(crate) async fn overrun(id: usize) {
+    let timeout = Duration::from_secs(HANDLER_TIMEOUT_SECS);
+    loop {
+        Timer::after_secs(HANDLER_CHECK_SECS).await;
+        let worker = WORKERS.lock(|workers| workers.get().get(id).copied());
+        if worker.is_some_and(|worker| {
+            matches!(worker.activity, Activity::Serving) && worker.since.elapsed() > timeout
+        }) {
+            return;
+        }
+    }
+}
+
+// Activity of every web task, indexed by their identifier.
+pub(crate) fn status() -> [WorkerStatus; WEB_TASK_POOL_SIZE] {
+    let now = Instant::now();
+    let workers = WORKERS.lock(Cell::get);
+    workers.map(|worker| WorkerStatus {
+        state: worker.activity.as_str(),
+        since_ms_ago: (now - worker.since).as_millis(),
+        requests: worker.requests,
+    })
+}
+
+// Response writer asking the client to close the connection after the
+// response, if it is the last one allowed.
+struct LimitRequests<W> {
+    writer: W,
+    last: bool,
+}
+
+impl<W: ResponseWriter> ResponseWriter for LimitRequests<W> {
+    type Error = W::Error;
+
+    async fn write_response<R: Read<Error = Self::Error>, H: HeadersIter, B: Body>(
+        self,
+        connection: Connection<'_, R>,
+        response: Response<H, B>,
+    ) -> Result<ResponseSent, Self::Error> {
+        if self.last {
+            let response = response.with_header("Connection", "close");
+            self.writer.write_response(connection, response).await
+        } else {
+            self.writer.write_response(connection, response).await
+        }
+    }
+}
+
+// Layer tracking the activity of the web tasks, and asking the clients to
+// close their connection once it served `http_max_requests` requests, so
+// persistent connections do not hold a task forever.
+//
+// The web task is supervised by the watchdog while it handles a request,
+// reporting when it starts and ends, and left unsupervised while it waits
+// for the next request or serves a long-running route.
+pub(crate) struct TrackRequests;
+
+impl<PathParameters> Layer<Client, PathParameters> for TrackRequests {
+    type NextState = Client;
+    type NextPathParameters = PathParameters;
+
+    async fn call_layer<
+        'a,
+        R: Read + 'a,
+        NextLayer: Next<'a, R, Client, PathParameters>,
+        W: ResponseWriter<Error = R::Error>,
+    >(
+        &self,
+        next: NextLayer,
+        client: &Client,
+        path_parameters: PathParameters,
+        request_parts: RequestParts<'_>,
+        response_writer: W,
+    ) -> Result<ResponseSent, W::Error> {
+        let task = Task::Web(client.worker);
+        let long_running =
+            LONG_RUNNING_ROUTES.contains(&unversioned(request_parts.path().encoded()));
+        if long_running {
+            watchdog::idle(task);
+        } else {
+            watchdog::alive(task);
+        }
+        let requests = update(client.worker, |worker| {
+            worker.activity = if long_running {
+                Activity::Streaming
+            } else {
+                Activity::Serving
+            };
+            worker.requests = worker.requests.saturating_add(1);
+        });
+        let max_requests = DEVICE_CONFIG.http_max_requests;
+        let last = max_requests > 0 && requests >= max_requests;
+
+        let response_writer = LimitRequests {
+            writer: response_writer,
+            last,
+        };
+        let result = next.run(client, path_parameters, response_writer).await;
+
+        // The client closes the connection after the last response.
+        let activity = if last {
+            Activity::Closing
+        } else {
+            Activity::Idle
+        };
+        update(client.worker, |worker| worker.activity = activity);
+        watchdog::alive(task);
+        watchdog::idle(task);
+
+        result
+    }
+}
@@ -0,0 +1,302 @@
+use core::cell::{Cell, RefCell};
+use core::fmt::Write;
+use core::net::{IpAddr, Ipv4Addr};
+
+use embassy_net::dns::DnsQueryType;
+use embassy_net::tcp::TcpSocket;
+use embassy_net::Stack;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration, Timer};
+
+use esp_hal::rng::Rng;
+
+use heapless::Deque;
+
+use log::{error, info, warn};
+
+use serde::Serialize;
+
+use crate::backoff::Backoff;
+use crate::click::Click;
+use crate::events::button_str;
+use crate::state::LedState;
+use crate::{metrics, ESP_APP_DESC};
+
+// Events waiting to be sent, the oldest ones are dropped beyond this.
+const WEBHOOK_QUEUE_SIZE: usize = 8;
+// Time allowed to each step of a delivery: resolving the host, connecting,
+// sending the request and receiving the response status.
+const WEBHOOK_TIMEOUT_SECS: u64 = 5;
+// Retries of a failed delivery before the event is dropped.
+const WEBHOOK_RETRIES: u32 = 3;
+// Range of the delays between two retries.
+const RETRY_MIN_MS: u64 = 500;
+const RETRY_MAX_MS: u64 = 4000;
+// Size of the socket buffers, enough for a request and a status line.
+const SOCKET_BUFFER_SIZE: usize = 512;
+// Size of a request, its headers and the JSON payload.
+const REQUEST_SIZE: usize = 384;
+const PAYLOAD_SIZE: usize = 96;
+// Default port of `http` URLs.
+const HTTP_PORT: u16 = 80;
+
+// Event notified to the webhook.
+#[derive(Clone, Copy, Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub(crate) enum WebhookEvent {
+    // The button has been clicked, `count` presses since boot.
+    Button {
+        #[serde(serialize_with = "serialize_click")]
+        click: Click,
+        count: u32,
+    },
+    // The led has been turned on or off.
+    Led {
+        state: LedState,
+        brightness: u8,
+    },
+    // The motion sensor has detected motion, `count` times since boot.
+    Motion {
+        count: u32,
+    },
+    // The IPv4 address of the device changed, `old` is missing for the first
+    // address.
+    Ip {
+        old: Option<Ipv4Addr>,
+        new: Ipv4Addr,
+    },
+}
+
+fn serialize_click<S: serde::Serializer>(click: &Click, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(button_str(*click))
+}
+
+// Events waiting to be sent.
+//
+// A deque is used instead of a channel, so the oldest events are dropped when
+// it is full rather than the newest ones.
+static EVENTS: Mutex<CriticalSectionRawMutex, RefCell<Deque<WebhookEvent, WEBHOOK_QUEUE_SIZE>>> =
+    Mutex::new(RefCell::new(Deque::new()));
+// Signal raised when an event is queued.
+static QUEUED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+// Whether events are queued, set once the webhook task starts.
+static ENABLED: Mutex<CriticalSectionRawMutex, Cell<bool>> = Mutex::new(Cell::new(false));
+
+// Queues an event for the webhook, without waiting.
+pub(crate) fn notify(event: WebhookEvent) {
+    if !ENABLED.lock(Cell::get) {
+        return;
+    }
+
+    let dropped = EVENTS.lock(|events| {
+        let mut events = events.borrow_mut();
+        let dropped = events.is_full() && events.pop_front().is_some();
+        // There is room, the oldest event has been dropped otherwise.
+        let _ = events.push_back(event);
+        dropped
+    });
+    if dropped {
+        metrics::count_webhook_drop();
+    }
+    QUEUED.signal(());
+}
+
+// Takes the oldest event waiting to be sent, if any.
+fn next_event() -> Option<WebhookEvent> {
+    EVENTS.lock(|events| events.borrow_mut().pop_front())
+}
+
+// Parts of a webhook URL.
+pub(crate) struct WebhookUrl {
+    host: &'static str,
+    port: u16,
+    path: &'static str,
+}
+
+impl WebhookUrl {
+    // Parses an `http://host[:port][/path]` URL, logging why it is invalid.
+    pub(crate) fn parse(url: &'static str) -> Option<Self> {
+        let Some(rest) = url.strip_prefix("http://") else {
+            error!("Webhook URL {url} must start with http://, HTTPS is not supported");
+            return None;
+        };
+        let (authority, path) = rest
+            .find('/')
+            .map_or((rest, "/"), |slash| (&rest[..slash], &rest[slash..]));
+        let (host, port) = match authority.split_once(':') {
+            Some((host, port)) => {
+                let Ok(port) = port.parse() else {
+                    error!("Invalid webhook port {port}");
+                    return None;
+                };
+                (host, port)
+            }
+            None => (authority, HTTP_PORT),
+        };
+        if host.is_empty() {
+            error!("Missing webhook host in {url}");
+            return None;
+        }
+        Some(Self { host, port, path })
+    }
+}
+
+// Reasons of a failed delivery.
+#[derive(Debug)]
+enum WebhookError {
+    Dns,
+    Timeout,
+    Connect,
+    Write,
+    Read,
+    // The request does not fit its buffer.
+    Request,
+    // The server answered with a status other than 2xx.
+    Status,
+}
+
+// Resolves the host of the webhook, which is either an IPv4 address or a
+// hostname.
+async fn resolve(stack: Stack<'static>, host: &str) -> Result<Ipv4Addr, WebhookError> {
+    if let Ok(ip) = host.parse() {
+        return Ok(ip);
+    }
+
+    let addresses = stack
+        .dns_query(host, DnsQueryType::A)
+        .await
+        .map_err(|_| WebhookError::Dns)?;
+    addresses
+        .iter()
+        .find_map(|&address| match IpAddr::from(address) {
+            IpAddr::V4(ip) => Some(ip),
+            IpAddr::V6(_) => None,
+        })
+        .ok_or(WebhookError::Dns)
+}
+
+// Runs a step of a delivery, failing once it takes too long.
+async fn timeout<T>(
+    step: impl Future<Output = Result<T, WebhookError>>,
+) -> Result<T, WebhookError> {
+    with_timeout(Duration::from_secs(WEBHOOK_TIMEOUT_SECS), step)
+        .await
+        .map_err(|_| WebhookError::Timeout)?
+}
+
+// POSTs an event to the webhook over a new connection.
+async fn deliver(
+    stack: Stack<'static>,
+    url: &WebhookUrl,
+    event: &WebhookEvent,
+) -> Result<(), WebhookError> {
+    let payload =
+        serde_json_core::to_vec::<_, PAYLOAD_SIZE>(event).map_err(|_| WebhookError::Request)?;
+    let mut request = heapless::String::<REQUEST_SIZE>::new();
+    write!(
+        request,
+        "POST {} HTTP/1.1\r\n\
+         Host: {}\r\n\
+         User-Agent: {}/{}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n",
+        url.path,
+        url.host,
+        ESP_APP_DESC.project_name(),
+        ESP_APP_DESC.version(),
+        payload.len()
+    )
+    .map_err(|_| WebhookError::Request)?;
+
+    let address = timeout(resolve(stack, url.host)).await?;
+
+    let mut rx_buffer = [0; SOCKET_BUFFER_SIZE];
+    let mut tx_buffer = [0; SOCKET_BUFFER_SIZE];
+    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+    socket.set_timeout(Some(Duration::from_secs(WEBHOOK_TIMEOUT_SECS)));
+    timeout(async {
+        socket
+            .connect((address, url.port))
+            .await
+            .map_err(|_| WebhookError::Connect)
+    })
+    .await?;
+
+    let status = timeout(async {
+        for mut bytes in [request.as_bytes(), &payload] {
+            while !bytes.is_empty() {
+                let written = socket.write(bytes).await.map_err(|_| WebhookError::Write)?;
+                bytes = &bytes[written..];
+            }
+        }
+        socket.flush().await.map_err(|_| WebhookError::Write)?;
+
+        // Only the status line is read, as `HTTP/1.1 200 OK`.
+        let mut response = [0; 16];
+        let mut len = 0;
+        while len < response.len() {
+            match socket.read(&mut response[len..]).await {
+                Ok(0) => break,
+                Ok(read) => len += read,
+                Err(_) => return Err(WebhookError::Read),
+            }
+        }
+        Ok(core::str::from_utf8(&response[..len])
+            .ok()
+            .and_then(|line| line.split(' ').nth(1))
+            .and_then(|status| status.parse::<u16>().ok()))
+    })
+    .await;
+    socket.close();
+
+    match status? {
+        Some(200..=299) => Ok(()),
+        status => {
+            warn!("Webhook answered with status {status:?}");
+            Err(WebhookError::Status)
+        }
+    }
+}
+
+// Notifies the button and led events to a webhook, POSTing them as JSON, such
+// as `{"event":"button","click":"pressed","count":7}`.
+//
+// A connection is opened for every event. Failed deliveries are retried a
+// few times with backoff, then the event is dropped.
+#[embassy_executor::task]
+pub(crate) async fn webhook_task(stack: Stack<'static>, url: WebhookUrl, mut rng: Rng) {
+    ENABLED.lock(|enabled| enabled.set(true));
+    info!(
+        "Webhook notifications to {}:{}{}",
+        url.host, url.port, url.path
+    );
+
+    let mut backoff = Backoff::new(RETRY_MIN_MS, RETRY_MAX_MS);
+    loop {
+        let Some(event) = next_event() else {
+            QUEUED.wait().await;
+            continue;
+        };
+
+        stack.wait_config_up().await;
+        backoff.reset();
+        loop {
+            match deliver(stack, &url, &event).await {
+                Ok(()) => break,
+                Err(e) if backoff.failures() < WEBHOOK_RETRIES => {
+                    let delay_ms = backoff.next_delay(rng.random());
+                    warn!("Webhook delivery failed, retrying in {delay_ms} ms: {e:?}");
+                    Timer::after_millis(delay_ms).await;
+                }
+                Err(e) => {
+                    error!("Webhook delivery failed, event dropped: {e:?}");
+                    metrics::count_webhook_drop();
+                    break;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,474 @@
+use core::net::{Ipv4Addr, Ipv6Addr};
+use core::pin::pin;
+
+use embassy_futures::select::{select, Either};
+use embassy_net::{Config, DhcpConfig, Ipv4Cidr, Runner, Stack, StackResources, StaticConfigV4};
+use embassy_time::{with_timeout, Duration, Timer};
+
+use esp_hal::rng::Rng;
+
+use esp_wifi::wifi::{
+    AccessPointConfiguration, AuthMethod, Configuration, WifiController, WifiDevice, WifiError,
+    WifiEvent, WifiState,
+};
+
+use picoserve::make_static;
+
+use log::{error, info, warn};
+
+#[cfg(feature = "gratuitous-arp")]
+use crate::arp;
+use crate::backoff::Backoff;
+use crate::buzzer::{self, BeepPattern};
+#[cfg(feature = "ipv6")]
+use crate::ipv6;
+use crate::led::show_pattern;
+use crate::mdns;
+use crate::metrics;
+use crate::pattern::LedPattern;
+use crate::sleep;
+use crate::sntp;
+use crate::state;
+use crate::status_led::{self, StatusEvent};
+use crate::watchdog::{self, SupervisedDevice};
+use crate::weak_signal::{SignalChange, WeakSignal};
+use crate::wifi_networks::WifiNetworks;
+use crate::{DeviceConfig, DEVICE_CONFIG, MILLISECONDS_TO_WAIT, WEB_TASK_POOL_SIZE};
+
+const SECONDS_TO_WAIT_FOR_RECONNECTION: u64 = 5;
+// Attempts to start the Wi-Fi controller at boot before giving up.
+const WIFI_START_ATTEMPTS: u32 = 3;
+// Bounds of the delay between Wi-Fi reconnection attempts.
+const WIFI_BACKOFF_MIN_MS: u64 = 1000;
+const WIFI_BACKOFF_MAX_MS: u64 = 60_000;
+// Time waited for an IP address before logging the failure, the device keeps
+// waiting in the background meanwhile.
+const GET_IP_TIMEOUT_SECS: u64 = 60;
+// Interval between two samples of the Wi-Fi signal strength.
+const RSSI_SAMPLE_SECS: u64 = 30;
+// Access point started when the device cannot connect to a Wi-Fi network.
+pub(crate) const PROVISIONING_SSID: &str = "button-led-setup";
+pub(crate) const PROVISIONING_IP: Ipv4Addr = Ipv4Addr::new(192, 168, 4, 1);
+// Access point serving the web app with the `sim-net` feature, on the same
+// address as the provisioning one.
+pub(crate) const SIM_NET_SSID: &str = "button-led-sim";
+// Sockets of the network stack: one for each web task, one used by DHCP, one
+// by MQTT, one by mDNS, one by syslog, one by SNTP, one by DNS queries, one by
+// IPv6 autoconfiguration, one by the CoAP server, one by the UDP control and
+// one by the webhook notifications.
+const STACK_SOCKETS: usize = WEB_TASK_POOL_SIZE + 10;
+
+// Keeps the station connected, reconnecting with a backoff and sampling the
+// signal strength, until the device goes to sleep.
+//
+// The task owns the Wi-Fi controller once the device is connected, no other
+// task touches the radio in station mode.
+#[embassy_executor::task]
+pub(crate) async fn connect(
+    mut wifi_controller: WifiController<'static>,
+    mut networks: WifiNetworks,
+    stack: Stack<'static>,
+    mut rng: Rng,
+) {
+    info!("Wi-Fi connection task started");
+    let mut backoff = Backoff::new(WIFI_BACKOFF_MIN_MS, WIFI_BACKOFF_MAX_MS);
+    let mut weak_signal = WeakSignal::new(DEVICE_CONFIG.rssi_warning_dbm);
+    // The connection is kept until the device goes to sleep.
+    let stay_connected = async {
+        loop {
+            if esp_wifi::wifi::wifi_state() == WifiState::StaConnected {
+                // Sample the signal strength until the connection drops.
+                while esp_wifi::wifi::wifi_state() == WifiState::StaConnected {
+                    // The sample is skipped when the connection drops meanwhile.
+                    if let Ok(rssi) = wifi_controller.rssi() {
+                        state::set_wifi_rssi(Some(rssi));
+                        match weak_signal.sample(rssi) {
+                            Some(SignalChange::Weakened) => warn!(
+                                "Weak Wi-Fi signal: {rssi} dBm, below {} dBm",
+                                DEVICE_CONFIG.rssi_warning_dbm
+                            ),
+                            Some(SignalChange::Recovered) => {
+                                info!("Wi-Fi signal recovered: {rssi} dBm");
+                            }
+                            None => {}
+                        }
+                        if weak_signal.is_weak() && DEVICE_CONFIG.rssi_warning_blink {
+                            show_pattern(LedPattern::WeakSignal);
+                        }
+                    }
+                    let disconnected = wifi_controller.wait_for_event(WifiEvent::StaDisconnected);
+                    select(disconnected, Timer::after_secs(RSSI_SAMPLE_SECS)).await;
+                }
+                state::set_wifi_rssi(None);
+                state::set_wifi_ssid(None);
+                weak_signal.reset();
+                warn!("Wi-Fi disconnected");
+                show_pattern(LedPattern::Connecting);
+                Timer::after_millis(backoff.next_delay(rng.random())).await;
+            }
+
+            if !matches!(wifi_controller.is_started(), Ok(true)) {
+                info!("Starting Wi-Fi...");
+                if let Err(e) = wifi_controller.start_async().await {
+                    let delay_ms = backoff.next_delay(rng.random());
+                    error!("Wi-Fi start failed, retrying in {delay_ms} ms: {e:?}");
+                    status_led::publish(StatusEvent::Error);
+                    Timer::after_millis(delay_ms).await;
+                    continue;
+                }
+                info!("Wi-Fi started");
+            }
+
+            info!("Attempting to connect...");
+            show_pattern(LedPattern::Connecting);
+            if let Err(e) = wifi_controller.connect_async().await {
+                let delay_ms = backoff.next_delay(rng.random());
+                error!(
+                    "Wi-Fi connect failed ({} consecutive failures), retrying in {delay_ms} ms: {e:?}",
+                    backoff.failures()
+                );
+                state::set_wifi_failures(backoff.failures());
+                status_led::publish(StatusEvent::Error);
+                Timer::after_millis(delay_ms).await;
+                if let Err(e) = networks.failed(&mut wifi_controller).await {
+                    error!(
+                        "Failed to configure Wi-Fi network {}: {e:?}",
+                        networks.ssid()
+                    );
+                }
+            } else {
+                info!("Wi-Fi connected!");
+                buzzer::beep(BeepPattern::Connected);
+                networks.connected();
+                metrics::count_wifi_reconnect();
+                backoff.reset();
+                state::set_wifi_failures(0);
+
+                // Wait for the IP address again, unless the connection drops
+                // in the meantime.
+                let disconnected = wifi_controller.wait_for_event(WifiEvent::StaDisconnected);
+                if let Either::Second((ip, ipv6)) = select(disconnected, get_ip(stack)).await {
+                    state::set_ip_address(ip);
+                    state::set_ipv6_address(ipv6);
+                    mdns::announce();
+                    sntp::resync();
+                }
+            }
+        }
+    };
+    select(stay_connected, sleep::wifi_stop_requested()).await;
+
+    info!("Stopping Wi-Fi...");
+    if let Err(e) = wifi_controller.disconnect_async().await {
+        warn!("Wi-Fi disconnect failed: {e:?}");
+    }
+    if let Err(e) = wifi_controller.stop_async().await {
+        warn!("Wi-Fi stop failed: {e:?}");
+    }
+    sleep::wifi_stopped();
+}
+
+// Starts the Wi-Fi controller, retrying a few times before giving up.
+async fn start_wifi(wifi_controller: &mut WifiController<'static>) -> Result<(), WifiError> {
+    info!("Starting Wi-Fi...");
+    let mut attempt = 1;
+    loop {
+        match wifi_controller.start_async().await {
+            Ok(()) => {
+                info!("Wi-Fi started");
+                return Ok(());
+            }
+            Err(e) if attempt < WIFI_START_ATTEMPTS => {
+                error!("Wi-Fi start failed ({attempt}/{WIFI_START_ATTEMPTS}): {e:?}");
+                attempt += 1;
+                Timer::after_secs(SECONDS_TO_WAIT_FOR_RECONNECTION).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Connects to the configured network with the strongest signal, moving on to
+// the others when it fails, and gives up after the given number of attempts
+// to each network.
+//
+// Without scanning, the networks are tried in the configured order, which
+// saves time when waking from deep sleep.
+pub(crate) async fn connect_station(
+    wifi_controller: &mut WifiController<'static>,
+    networks: &mut WifiNetworks,
+    attempts: u32,
+    scan: bool,
+) -> Result<bool, WifiError> {
+    // Scanning needs the controller started in station mode.
+    networks.configure(wifi_controller)?;
+    start_wifi(wifi_controller).await?;
+    if scan {
+        networks.scan(wifi_controller).await;
+    }
+    info!("Chosen Wi-Fi network {}", networks.ssid());
+    networks.configure(wifi_controller)?;
+
+    // The number of networks is tiny, so the product fits.
+    let attempts = attempts * networks.len() as u32;
+    for attempt in 1..=attempts {
+        info!(
+            "Attempting to connect to {} ({attempt}/{attempts})...",
+            networks.ssid()
+        );
+        show_pattern(LedPattern::Connecting);
+        if let Err(e) = wifi_controller.connect_async().await {
+            error!("Wi-Fi connect failed: {e:?}");
+            status_led::publish(StatusEvent::Error);
+            Timer::after_secs(SECONDS_TO_WAIT_FOR_RECONNECTION).await;
+            networks.failed(wifi_controller).await?;
+        } else {
+            info!("Wi-Fi connected!");
+            buzzer::beep(BeepPattern::Connected);
+            networks.connected();
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+// Switches the Wi-Fi controller to an open access point, so the device can be
+// provisioned with the credentials of a Wi-Fi network, or reached without
+// one in simulation mode.
+pub(crate) async fn start_access_point(
+    wifi_controller: &mut WifiController<'static>,
+    ssid: &str,
+) -> Result<(), WifiError> {
+    if matches!(wifi_controller.is_started(), Ok(true)) {
+        wifi_controller.stop_async().await?;
+    }
+
+    let ap_config = Configuration::AccessPoint(AccessPointConfiguration {
+        ssid: ssid.into(),
+        auth_method: AuthMethod::None,
+        ..Default::default()
+    });
+    wifi_controller.set_configuration(&ap_config)?;
+    start_wifi(wifi_controller).await?;
+
+    info!("Access point {ssid} started");
+    status_led::publish(StatusEvent::Idle);
+    Ok(())
+}
+
+// Logs the clients joining the access point.
+//
+// The task owns the Wi-Fi controller in access point mode.
+#[embassy_executor::task]
+pub(crate) async fn access_point(
+    mut wifi_controller: WifiController<'static>,
+    http_port: u16,
+    provisioning: bool,
+) {
+    loop {
+        wifi_controller
+            .wait_for_event(WifiEvent::ApStaconnected)
+            .await;
+        if provisioning {
+            info!("Device connected, open http://{PROVISIONING_IP}:{http_port}/setup to provision");
+        } else {
+            info!("Device connected, open http://{PROVISIONING_IP}:{http_port}/");
+        }
+    }
+}
+
+// Network device of the stack, wrapped to report to the watchdog, to send
+// gratuitous ARP announcements and to keep the IPv6 link-local address when
+// enabled.
+pub(crate) type NetDevice = SupervisedDevice<Ipv6Device>;
+#[cfg(feature = "ipv6")]
+type Ipv6Device = ipv6::LinkLocalDevice<ArpDevice>;
+#[cfg(not(feature = "ipv6"))]
+type Ipv6Device = ArpDevice;
+#[cfg(feature = "gratuitous-arp")]
+type ArpDevice = arp::AnnouncingDevice<WifiDevice<'static>>;
+#[cfg(not(feature = "gratuitous-arp"))]
+type ArpDevice = WifiDevice<'static>;
+
+// Runs the network stack, the only task driving the Wi-Fi device, while the
+// other tasks use the stack through their sockets.
+#[embassy_executor::task]
+pub(crate) async fn net_task(mut runner: Runner<'static, NetDevice>) {
+    // The stack may have nothing to do for long, so it is polled
+    // periodically to report to the watchdog through its device.
+    let mut run = pin!(runner.run());
+    loop {
+        if let Either::First(never) =
+            select(&mut run, Timer::after_secs(watchdog::ALIVE_INTERVAL_SECS)).await
+        {
+            match never {}
+        }
+    }
+}
+
+// Creates the network stack on top of the Wi-Fi device, which is handed over
+// to the runner of `net_task`.
+pub(crate) fn create_stack(
+    mut rng: Rng,
+    wifi_interface: WifiDevice<'static>,
+    config: Config,
+) -> (Stack<'static>, Runner<'static, NetDevice>) {
+    let seed = u64::from(rng.random()) << 32 | u64::from(rng.random());
+    #[cfg(feature = "gratuitous-arp")]
+    let wifi_interface = arp::AnnouncingDevice::new(wifi_interface);
+    #[cfg(feature = "ipv6")]
+    let wifi_interface = ipv6::LinkLocalDevice::new(wifi_interface);
+    let wifi_interface = SupervisedDevice::new(wifi_interface);
+
+    // Reserved at link time rather than on the heap, so it cannot fail.
+    let resources = make_static!(StackResources<STACK_SOCKETS>, StackResources::new());
+
+    let (stack, runner) = embassy_net::new(wifi_interface, config, resources, seed);
+
+    (stack, runner)
+}
+
+// Parses the static IPv4 configuration of the device, if any.
+//
+// Empty gateway and DNS addresses are omitted. Returns `None`, logging the
+// error, when any field is malformed.
+fn parse_static_config(
+    static_ip: &str,
+    gateway: &str,
+    netmask: &str,
+    dns: &str,
+) -> Option<StaticConfigV4> {
+    // Parses an optional address, `Err` when it is malformed.
+    let parse_optional = |name: &str, address: &str| {
+        if address.is_empty() {
+            Ok(None)
+        } else {
+            address.parse::<Ipv4Addr>().map(Some).map_err(|_| {
+                error!("Invalid {name} address {address}");
+            })
+        }
+    };
+
+    let Ok(address) = static_ip.parse::<Ipv4Addr>() else {
+        error!("Invalid static IP address {static_ip}");
+        return None;
+    };
+
+    // The netmask ones must be contiguous.
+    let Some(prefix_len) = netmask
+        .parse::<Ipv4Addr>()
+        .ok()
+        .map(u32::from)
+        .filter(|mask| mask.leading_ones() + mask.trailing_zeros() == 32)
+        .and_then(|mask| u8::try_from(mask.leading_ones()).ok())
+    else {
+        error!("Invalid netmask {netmask}");
+        return None;
+    };
+
+    let gateway = parse_optional("gateway", gateway).ok()?;
+    let dns = parse_optional("DNS", dns).ok()?;
+
+    Some(StaticConfigV4 {
+        address: Ipv4Cidr::new(address, prefix_len),
+        gateway,
+        dns_servers: dns.into_iter().collect(),
+    })
+}
+
+// Network configuration used in station mode.
+//
+// A static configuration is used when configured, falling back to DHCP when
+// it is malformed.
+pub(crate) fn station_net_config(device_config: &DeviceConfig, hostname: &str) -> Config {
+    let dhcp_config = || {
+        let mut dhcp_config = DhcpConfig::default();
+        // Hostnames are bounded by the settings, so they always fit.
+        dhcp_config.hostname = heapless::String::try_from(hostname).ok();
+        Config::dhcpv4(dhcp_config)
+    };
+    if device_config.static_ip.is_empty() {
+        return dhcp_config();
+    }
+
+    match parse_static_config(
+        device_config.static_ip,
+        device_config.gateway,
+        device_config.netmask,
+        device_config.dns,
+    ) {
+        Some(static_config) => {
+            info!("Using static IP address {}", static_config.address);
+            Config::ipv4_static(static_config)
+        }
+        None => {
+            warn!("Malformed static IP configuration, falling back to DHCP");
+            dhcp_config()
+        }
+    }
+}
+
+// IPv6 address of the device, if any.
+#[cfg(feature = "ipv6")]
+fn ipv6_address(stack: Stack<'_>) -> Option<Ipv6Addr> {
+    ipv6::address_of(stack)
+}
+
+#[cfg(not(feature = "ipv6"))]
+fn ipv6_address(_stack: Stack<'_>) -> Option<Ipv6Addr> {
+    None
+}
+
+// Waits for the IPv4 address of the device, returning it along with the IPv6
+// one, if any.
+//
+// A static address is available as soon as the link is up, while a DHCP one
+// is available once a lease is obtained. The link-local IPv6 address is
+// configured from the start, and replaced by a global one once a router
+// advertises a prefix.
+async fn get_ip(stack: Stack<'_>) -> (Ipv4Addr, Option<Ipv6Addr>) {
+    info!("Waiting till the link is up...");
+    loop {
+        if stack.is_link_up() {
+            break;
+        }
+        Timer::after_millis(MILLISECONDS_TO_WAIT).await;
+    }
+
+    if stack.config_v4().is_none() {
+        info!("Waiting to get IP address...");
+        show_pattern(LedPattern::WaitingForIp);
+    }
+    loop {
+        if let Some(config) = stack.config_v4() {
+            info!("Got IP: {}", config.address);
+            let ipv6 = ipv6_address(stack);
+            if let Some(ipv6) = ipv6 {
+                info!("Got IPv6: {ipv6}");
+            }
+            show_pattern(LedPattern::Connected);
+            return (config.address.address(), ipv6);
+        }
+        Timer::after_millis(MILLISECONDS_TO_WAIT).await;
+    }
+}
+
+// Waits for the first IP address without blocking the other tasks, so the
+// button and the led keep working while the network is unavailable.
+#[embassy_executor::task]
+pub(crate) async fn wait_for_ip(stack: Stack<'static>) {
+    loop {
+        match with_timeout(Duration::from_secs(GET_IP_TIMEOUT_SECS), get_ip(stack)).await {
+            Ok((ip, ipv6)) => {
+                info!("Got IP Address: {ip}");
+                state::set_ip_address(ip);
+                state::set_ipv6_address(ipv6);
+                return;
+            }
+            Err(_) => warn!(
+                "No IP address after {GET_IP_TIMEOUT_SECS} seconds, running locally while retrying"
+            ),
+        }
+    }
+}
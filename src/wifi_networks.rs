@@ -0,0 +1,179 @@
+use core::cmp::Reverse;
+
+use esp_wifi::wifi::{
+    AccessPointInfo, ClientConfiguration, Configuration, WifiController, WifiError,
+};
+
+use heapless::{String, Vec};
+
+use log::{info, warn};
+
+use crate::settings::{config_string, Settings, MAX_PASSWORD_LEN, MAX_SSID_LEN};
+use crate::{state, DEVICE_CONFIG};
+
+// Wi-Fi networks the device can connect to.
+pub(crate) const MAX_WIFI_NETWORKS: usize = 3;
+// Access points read from a scan, enough to find the configured networks in
+// a crowded area.
+const MAX_SCAN_RESULTS: usize = 20;
+
+// Strongest signal, in dBm, of the access points of a network, if visible.
+fn strongest_signal(access_points: &[AccessPointInfo], ssid: &str) -> Option<i8> {
+    access_points
+        .iter()
+        .filter(|access_point| access_point.ssid == ssid)
+        .map(|access_point| access_point.signal_strength)
+        .max()
+}
+
+// Wi-Fi network the device can connect to.
+//
+// The password is never logged, so this type does not implement `Debug`.
+struct WifiNetwork {
+    ssid: String<MAX_SSID_LEN>,
+    password: String<MAX_PASSWORD_LEN>,
+    // Position in the configuration, which breaks ties between networks
+    // with the same signal.
+    priority: usize,
+}
+
+// Configured Wi-Fi networks, tried from the strongest visible signal.
+pub(crate) struct WifiNetworks {
+    networks: Vec<WifiNetwork, MAX_WIFI_NETWORKS>,
+    // Index of the network being tried.
+    current: usize,
+    // Consecutive failed connections to the current network.
+    failures: u32,
+}
+
+impl WifiNetworks {
+    // Collects the network of the stored settings followed by the additional
+    // networks of the configuration, skipping the missing and repeated ones.
+    pub(crate) fn new(settings: &Settings) -> Self {
+        let mut networks = Vec::new();
+        let candidates = [
+            (settings.ssid.clone(), settings.password.clone()),
+            (
+                config_string("Wi-Fi SSID 2", DEVICE_CONFIG.ssid2),
+                config_string("Wi-Fi password 2", DEVICE_CONFIG.password2),
+            ),
+            (
+                config_string("Wi-Fi SSID 3", DEVICE_CONFIG.ssid3),
+                config_string("Wi-Fi password 3", DEVICE_CONFIG.password3),
+            ),
+        ];
+        for (ssid, password) in candidates {
+            if ssid.is_empty() || networks.iter().any(|n: &WifiNetwork| n.ssid == ssid) {
+                continue;
+            }
+            let priority = networks.len();
+            // At most `MAX_WIFI_NETWORKS` candidates, so there is always room.
+            let _ = networks.push(WifiNetwork {
+                ssid,
+                password,
+                priority,
+            });
+        }
+
+        Self {
+            networks,
+            current: 0,
+            failures: 0,
+        }
+    }
+
+    // Whether no Wi-Fi network is configured.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.networks.is_empty()
+    }
+
+    // Number of configured Wi-Fi networks.
+    pub(crate) fn len(&self) -> usize {
+        self.networks.len()
+    }
+
+    // SSID of the network being tried.
+    pub(crate) fn ssid(&self) -> &str {
+        &self.networks[self.current].ssid
+    }
+
+    // Sets the network being tried as the one the controller connects to.
+    pub(crate) fn configure(
+        &self,
+        wifi_controller: &mut WifiController<'static>,
+    ) -> Result<(), WifiError> {
+        let network = &self.networks[self.current];
+        let client_config = Configuration::Client(ClientConfiguration {
+            ssid: network.ssid.as_str().into(),
+            password: network.password.as_str().into(),
+            ..Default::default()
+        });
+        wifi_controller.set_configuration(&client_config)
+    }
+
+    // Scans the visible access points and sorts the networks by their
+    // strongest signal, so the first one is tried next.
+    //
+    // The configured order is kept when the scan fails.
+    pub(crate) async fn scan(&mut self, wifi_controller: &mut WifiController<'static>) {
+        self.current = 0;
+        self.failures = 0;
+        if self.networks.len() == 1 {
+            return;
+        }
+
+        let access_points = match wifi_controller.scan_n_async(MAX_SCAN_RESULTS).await {
+            Ok(access_points) => access_points,
+            Err(e) => {
+                warn!("Wi-Fi scan failed, keeping the configured order: {e:?}");
+                self.networks
+                    .sort_unstable_by_key(|network| network.priority);
+                return;
+            }
+        };
+
+        // Networks which are not visible go last.
+        self.networks.sort_unstable_by_key(|network| {
+            (
+                Reverse(strongest_signal(&access_points, &network.ssid)),
+                network.priority,
+            )
+        });
+        for network in &self.networks {
+            match strongest_signal(&access_points, &network.ssid) {
+                Some(signal) => info!("Wi-Fi network {} visible: {signal} dBm", network.ssid),
+                None => info!("Wi-Fi network {} not visible", network.ssid),
+            }
+        }
+    }
+
+    // Records a failed connection, moving on to the next network after too
+    // many failures in a row.
+    //
+    // Once every network failed, the access points are scanned again.
+    pub(crate) async fn failed(
+        &mut self,
+        wifi_controller: &mut WifiController<'static>,
+    ) -> Result<(), WifiError> {
+        self.failures += 1;
+        if self.networks.len() == 1 || self.failures < DEVICE_CONFIG.wifi_network_attempts {
+            return Ok(());
+        }
+
+        self.failures = 0;
+        if self.current + 1 < self.networks.len() {
+            self.current += 1;
+        } else {
+            self.scan(wifi_controller).await;
+        }
+        info!("Moving on to Wi-Fi network {}", self.ssid());
+        self.configure(wifi_controller)
+    }
+
+    // Records a successful connection to the network being tried.
+    pub(crate) fn connected(&mut self) {
+        self.failures = 0;
+        info!("Connected to Wi-Fi network {}", self.ssid());
+        state::set_wifi_ssid(Some(self.ssid()));
+    }
+}
@@ -0,0 +1,10 @@
+#!/bin/bash
+# Runs the host tests of the logic crate.
+#
+# Cargo runs from outside the repository, so the cross-compilation settings
+# of `.cargo/config.toml` do not apply to the host build.
+set -e
+
+root="$(cd "$(dirname "$0")" && pwd)"
+cd "${TMPDIR:-/tmp}"
+cargo test --manifest-path "$root/logic/Cargo.toml" "$@"